@@ -7,17 +7,194 @@ pub mod epr {
     // Use necessary items from the ink crate.
     use patient::PatientRef;
 
+    use ink::ToAccountId;
+    use ink::codegen::TraitCallBuilder;
+    use ink::env::hash::Blake2x256;
+
     use ink::storage::Mapping;
     use ink::prelude::string::String;
     use ink::prelude::vec::Vec;
 
     // Define a type alias for HealthId to enhance readability.
     pub type HealthId = u32;
+    // Identifies a one-time export grant.
+    pub type ExportGrantId = u64;
+    // A role grant of a batch: the account, its role and, for temporary roles, when the role expires.
+    pub type RoleEntry = (AccountId, Role, Option<Timestamp>);
+    // A reference to a clinical note: the patient and the index of the note among the patient's notes.
+    pub type NoteRef = (AccountId, u32);
+    // A record of an import batch: the health id it had in the legacy contract, the patient's account, the biodata
+    // and, if the legacy record had any, the clinical notes.
+    pub type ImportEntry = (HealthId, AccountId, Biodata, Option<ClinicalNotes>);
+    // A co-authorization of a read of a VIP patient's record: the clinician who gave it and until when it can be used.
+    pub type CoAuthorization = (AccountId, Timestamp);
+    // A reference to an appointment: the patient and the index of the appointment among the patient's appointments.
+    pub type AppointmentRef = (AccountId, u32);
+    // A day of a clinician's schedule: the clinician and the number of the day since the epoch.
+    pub type ScheduleDay = (AccountId, Timestamp);
+    // A usage counter of an organization: the billing period, the organization and the metric.
+    pub type ActivityKey = (u32, AccountId, u8);
     // pub type TokenId = u32;
 
+    // Upper bounds (in bytes) for the fields of a record payload.
+    // Anything larger could exceed block limits and make the record unreadable.
+    pub const MAX_NAME_LEN: usize = 128;
+    pub const MAX_DETAILS_LEN: usize = 2048;
+    pub const MAX_VECTOR_LEN: usize = 8192;
+
+    // Maximum number of groups a single account can belong to, which bounds the access check.
+    pub const MAX_GROUPS_PER_ACCOUNT: usize = 16;
+
+    // Maximum depth of the facility tree, a hospital being at depth 1. It bounds the walk up the tree in the access check.
+    pub const MAX_FACILITY_DEPTH: u32 = 4;
+
+    // Default number of blocks an account has to wait between two calls of the same self-service message.
+    pub const DEFAULT_COOLDOWN: BlockNumber = 100;
+
+    // Default time, in milliseconds, after which clinical notes count as finalized: 30 days.
+    pub const DEFAULT_FINALIZE_AFTER: Timestamp = 30 * 24 * 60 * 60 * 1000;
+
+    // Default number of payload bytes of biodata, clinical notes and imaging reports a single patient may hold: 1 MiB.
+    pub const DEFAULT_QUOTA: u32 = 1024 * 1024;
+
+    // Usage metrics counted per organization for billing.
+    pub const METRIC_PATIENTS_CREATED: u8 = 0;
+    pub const METRIC_NOTES_WRITTEN: u8 = 1;
+
+    // Message kinds subject to rate limiting.
+    pub const CALL_REQUEST_ACCESS: u8 = 0;
+    pub const CALL_REGISTER_PROVIDER: u8 = 1;
+    pub const CALL_SET_RESEARCH_CONSENT: u8 = 2;
+    pub const CALL_REGISTER_SELF: u8 = 3;
+
+    // Parts of a patient's record a consent can cover, combined as a bit mask.
+    pub const CONSENT_BIODATA: u8 = 0b01;
+    pub const CONSENT_CLINICAL_NOTES: u8 = 0b10;
+    pub const CONSENT_IMAGING: u8 = 0b100;
+    pub const CONSENT_ALL: u8 = CONSENT_BIODATA | CONSENT_CLINICAL_NOTES | CONSENT_IMAGING;
+
+    // Kinds of the entries of a patient's change log.
+    pub const CHANGE_CREATED: u8 = 0;
+    pub const CHANGE_BIODATA: u8 = 1;
+    pub const CHANGE_NOTES: u8 = 2;
+    pub const CHANGE_CONSENT_GRANTED: u8 = 3;
+    pub const CHANGE_CONSENT_REVOKED: u8 = 4;
+    pub const CHANGE_ENCOUNTER: u8 = 5;
+    pub const CHANGE_IMAGING: u8 = 6;
+    pub const CHANGE_IMMUNIZATION: u8 = 7;
+    pub const CHANGE_PROBLEM: u8 = 8;
+    pub const CHANGE_MEDICATION: u8 = 9;
+    pub const CHANGE_CRITICAL_INFO: u8 = 10;
+    pub const CHANGE_ARCHIVED: u8 = 11;
+    pub const CHANGE_ERASED: u8 = 12;
+    pub const CHANGE_CLAIMED: u8 = 13;
+
+    // Kinds of data quality issues a data steward can flag on a patient's record.
+    pub const FLAG_POSSIBLE_DUPLICATE: u8 = 0;
+    pub const FLAG_INCONSISTENT_DEMOGRAPHICS: u8 = 1;
+    pub const FLAG_UNVERIFIED_IDENTITY: u8 = 2;
+    pub const FLAG_KINDS: [u8; 3] = [FLAG_POSSIBLE_DUPLICATE, FLAG_INCONSISTENT_DEMOGRAPHICS, FLAG_UNVERIFIED_IDENTITY];
+
+    // Sub-codes telling apart the causes of an access check failing, as returned by denial_reason.
+    pub const DENIAL_NO_ACCESS: u8 = 1;
+    pub const DENIAL_CONSENT_SCOPE: u8 = 2;
+    pub const DENIAL_CONSENT_EXPIRED: u8 = 3;
+    pub const DENIAL_ROLE_EXPIRED: u8 = 4;
+    pub const DENIAL_PERMISSION_REVOKED: u8 = 5;
+    pub const DENIAL_NOT_CLINICIAN: u8 = 6;
+    pub const DENIAL_NOT_GUARDIAN: u8 = 7;
+    pub const DENIAL_RECORD_NOT_FOUND: u8 = 8;
+    pub const DENIAL_RECORD_ARCHIVED: u8 = 9;
+    pub const DENIAL_CO_AUTHORIZATION: u8 = 10;
+    pub const DENIAL_CONSENT_NOT_YET_ACTIVE: u8 = 11;
+
+    // Fields of a record that can be redacted from a provider's view, combined as a bit mask.
+    pub const REDACT_NAME: u8 = 0b001;
+    pub const REDACT_DETAILS: u8 = 0b010;
+    pub const REDACT_VECTOR: u8 = 0b100;
+    pub const REDACT_ALL: u8 = REDACT_NAME | REDACT_DETAILS | REDACT_VECTOR;
+
+    // Default deposit held for every entry created through an open registration message.
+    pub const DEFAULT_STORAGE_DEPOSIT: Balance = 1_000_000_000_000;
+
+    // Maximum number of clinical notes embedded in an exported patient bundle.
+    pub const MAX_BUNDLE_NOTES: usize = 20;
+
+    // Maximum numbers of problems and of active consents embedded in an exported patient bundle.
+    pub const MAX_BUNDLE_PROBLEMS: u32 = 50;
+    pub const MAX_BUNDLE_CONSENTS: u32 = 20;
+
+    // HealthIds derived from an external identifier hash have this bit set, which keeps them
+    // apart from the sequential ids handed out by create_patient.
+    pub const EXTERNAL_ID_FLAG: HealthId = 0x8000_0000;
+
+    // Access-consent tokens are minted on the Patient contract with this bit set, which keeps them apart from
+    // the tokens of patient records. The Patient contract only lets the marketplace move them.
+    pub const CONSENT_TOKEN_FLAG: HealthId = patient::CONSENT_TOKEN_FLAG;
+
+    // Maximum number of guardians, and the number of guardian approvals a destructive admin action needs
+    // (or every guardian, when fewer are configured).
+    pub const MAX_GUARDIANS: usize = 5;
+    pub const GUARDIAN_THRESHOLD: u32 = 2;
+
+    // Maximum number of accounts in a patient's care team.
+    pub const MAX_CARE_TEAM: usize = 16;
+
+    // Maximum number of care teams a revoked account is removed from per call; continue_care_cleanup does the rest.
+    pub const MAX_CARE_CLEANUP: u32 = 10;
+
+    // Default number of blocks after which an unexecuted admin proposal expires.
+    pub const DEFAULT_PROPOSAL_TTL: BlockNumber = 14_400;
+
+    // The inner code of a CrossContract error when the Patient contract trapped or its reply could not be decoded,
+    // rather than returning one of its own errors.
+    pub const CHILD_CALL_FAILED: u8 = u8::MAX;
+
+    // Maximum number of entries in a single batch of role grants or revocations.
+    pub const MAX_BATCH_LEN: usize = 50;
+
+    // Maximum number of diagnosis codes in a discharge summary. Each code is capped at MAX_NAME_LEN bytes.
+    pub const MAX_DIAGNOSIS_CODES: usize = 32;
+
+    // Length of a scheduling day in milliseconds. Booked appointments are indexed by the days they touch.
+    pub const DAY: Timestamp = 24 * 60 * 60 * 1000;
+
+    // Time during which the admin of a patient's new primary institution can read the whole record after a transfer.
+    pub const ONBOARDING_PERIOD: Timestamp = 30 * DAY;
+
+    // Default time during which a clinician can read a patient's record after invoking emergency access.
+    pub const DEFAULT_EMERGENCY_WINDOW: Timestamp = DAY;
+
+    // Default time within which an auditor should review an emergency access before it blocks the clinician.
+    pub const DEFAULT_REVIEW_PERIOD: Timestamp = 7 * DAY;
+
+    // Maximum number of unreviewed emergency accesses a clinician may have at any time.
+    pub const MAX_OPEN_REVIEWS: usize = 20;
+
+    // Time during which a second clinician's co-authorization lets a reader read a VIP patient's record once.
+    pub const CO_AUTHORIZATION_WINDOW: Timestamp = 15 * 60 * 1000;
+
+    // Maximum length of an appointment in minutes, so that an appointment touches at most two days.
+    pub const MAX_APPOINTMENT_MINUTES: u16 = 24 * 60;
+
+    // DIDs anchored for patients have to be shorter than this many bytes.
+    pub const MAX_DID_LEN: usize = 256;
+
+    // Maximum number of notifications kept per patient. Older ones are pruned as new ones arrive.
+    pub const MAX_NOTIFICATIONS: u32 = 500;
+
+    // The first three-character category of each ICD-10 chapter, in code order, with the chapter's roman numeral.
+    // A code belongs to the last chapter that starts at or before its category.
+    const ICD10_CHAPTERS: [(&str, &str); 22] = [
+        ("A00", "I"), ("C00", "II"), ("D50", "III"), ("E00", "IV"), ("F00", "V"), ("G00", "VI"),
+        ("H00", "VII"), ("H60", "VIII"), ("I00", "IX"), ("J00", "X"), ("K00", "XI"), ("L00", "XII"),
+        ("M00", "XIII"), ("N00", "XIV"), ("O00", "XV"), ("P00", "XVI"), ("Q00", "XVII"), ("R00", "XVIII"),
+        ("S00", "XIX"), ("U00", "XXII"), ("V00", "XX"), ("Z00", "XXI")
+    ];
+
     // The Biodata struct is used to represent the biodata of a patient.
     // It contains the patient's name, details, a boolean indicating whether the data is finalized or not, and a vector of bytes.
-    #[derive(Default, scale::Decode, scale::Encode)]
+    #[derive(Default, Clone, scale::Decode, scale::Encode)]
     #[cfg_attr(
         feature = "std",
         derive(
@@ -36,7 +213,7 @@ pub mod epr {
     }
 
     // Similar to the Biodata struct, the ClinicalNotes struct is used to represent the clinical notes of a patient.
-    #[derive(Default, scale::Decode, scale::Encode)]
+    #[derive(Default, Clone, scale::Decode, scale::Encode)]
     #[cfg_attr(
         feature = "std",
         derive(
@@ -54,8 +231,167 @@ pub mod epr {
         vector: Vec<u8>,
     }
 
+    // The RecordMeta struct holds the lightweight metadata of a record.
+    // It is stored under its own key so permission checks and listings never decode the payload.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct RecordMeta {
+        author: AccountId,
+        finalized: bool,
+        created_at: Timestamp,
+        updated_at: Timestamp,
+        name_len: u32,
+        details_len: u32,
+        vector_len: u32
+    }
+
+    // The Group struct represents an access group such as a department or a ward.
+    // Any current member of a group inherits the permissions granted to the group.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct Group {
+        name: String,
+        admin: AccountId
+    }
+
+    // The Facility struct is a node of a hospital's facility tree, such as the hospital itself, a department or a ward.
+    // Access granted to a facility is inherited by the staff of the facility and of every facility below it.
+    // The admin of a facility manages it and the facilities below it.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct Facility {
+        name: String,
+        parent: Option<u32>,
+        admin: AccountId
+    }
+
+    // The Provider struct represents a self-registered care provider.
+    // Providers start unverified.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct Provider {
+        verified: bool,
+        registered_at: BlockNumber,
+        deposit: Balance
+    }
+
+    // The AccessRequest struct represents a pending request to access a patient's record.
+    // The deposit is refunded to the requester when the request is resolved.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct AccessRequest {
+        requested_at: BlockNumber,
+        deposit: Balance
+    }
+
+    // The Template struct is a version of the standard wording of a consent, published by the admin.
+    // hash is the hash of the document found at uri. Inactive templates cannot be used for new consents.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct Template {
+        uri: String,
+        hash: Hash,
+        version: u32,
+        active: bool
+    }
+
+    // The Consent struct represents a patient's active consent for a provider.
+    // terms_hash is the hash of the human-readable consent document found at document_uri, both copied from
+    // the template the consent was granted against.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct Consent {
+        scope: u8,
+        terms_hash: Hash,
+        document_uri: String,
+        template_id: u32,
+        template_version: u32,
+        granted_at: Timestamp,
+        granted_block: BlockNumber,
+        starts_at: Timestamp
+    }
+
+    // The ConsentReceipt struct is the evidence of a consent, kept after it is revoked.
+    // revoked_at is None while the consent is active.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct ConsentReceipt {
+        provider: AccountId,
+        scope: u8,
+        terms_hash: Hash,
+        document_uri: String,
+        template_id: u32,
+        template_version: u32,
+        granted_at: Timestamp,
+        granted_block: BlockNumber,
+        starts_at: Timestamp,
+        revoked_at: Option<Timestamp>
+    }
+
+    // The ConsentToken struct links an access-consent token to the consent it represents.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct ConsentToken {
+        patient: AccountId,
+        holder: AccountId
+    }
+
     // Access controls
-    #[derive(Default, scale::Decode, scale::Encode)]
+    #[derive(Default, Clone, scale::Decode, scale::Encode)]
     #[cfg_attr(
         feature = "std",
         derive(
@@ -67,243 +403,9651 @@ pub mod epr {
         )
     )]
     pub struct Permission {
-        can_access: bool
+        can_access: bool,
+        // The time from which a permission granted with a temporary role stops counting.
+        valid_until: Option<Timestamp>
     }
 
-    // Define an Error enum to handle errors.
+    // The Role enum lists the staff roles an admin can grant. Every role gives access to patient records;
+    // the Provider role is reserved for verified providers. Clinicians and nurses can draft biodata updates,
+    // and clinicians review them. Registrars book appointments.
     #[derive(Debug, Copy, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
     #[cfg_attr(
         feature = "std",
-        derive(ink::storage::traits::StorageLayout, scale_info::TypeInfo)
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
     )]
-    pub enum Error {
-        NotAllowed,
-        CannotFetchValue,
-        PermissionDenied
+    pub enum Role {
+        Staff,
+        Provider,
+        Auditor,
+        Clinician,
+        Nurse,
+        Registrar,
+        DataSteward,
+        Researcher
     }
 
-    /// The initial state is `Adder`.
+    // The RoleGrant struct stores the role of an account and, for temporary roles such as locums, when it expires.
+    // An expired grant is treated as absent until it is pruned.
     #[derive(Debug, Copy, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
     #[cfg_attr(
         feature = "std",
-        derive(ink::storage::traits::StorageLayout, scale_info::TypeInfo)
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
     )]
-    pub enum Which {
-        Patient
+    pub struct RoleGrant {
+        role: Role,
+        valid_until: Option<Timestamp>
     }
 
-    // The EPR (Electronic Patient Record) struct represents the smart contract.
-    #[ink(storage)]
-    pub struct Epr {
-        // The current_id field keeps track of the current patient id.
-        current_id: HealthId,
-        // The record_count mapping stores the account id associated with each health id.
-        record_count: Mapping<HealthId, AccountId>,
-        // The patient_biodata mapping stores the biodata of each patient.
-        patient_biodata: Mapping<AccountId, Biodata>,  
-        // The patient_notes mapping stores the clinical notes of each patient.
-        patient_notes: Mapping<AccountId, ClinicalNotes>,
-        which: Which,
-        patient: PatientRef,
-        permissions: Mapping<AccountId, Permission>
+    // The Draft struct holds a biodata update waiting for a clinician's review.
+    #[derive(Clone, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            Debug,
+            PartialEq,
+            Eq,
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct Draft {
+        biodata: Biodata,
+        author: AccountId,
+        submitted_at: Timestamp
     }
 
-    // The NewPatient event is emitted whenever a new patient is created.
-    #[ink(event)]
-    pub struct NewPatient {
-        #[ink(topic)]
-        id: HealthId,
-        #[ink(topic)]
-        identifier: Option<AccountId>
+    // The RecordOrigin enum tells who registered a patient's record: an institution, or the patient themselves.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub enum RecordOrigin {
+        InstitutionRegistered,
+        SelfRegistered
     }
 
-    // The BiodataUpdate event is emitted whenever the biodata of a patient is updated.
-    #[ink(event)]
-    pub struct BiodataUpdate {
-        #[ink(topic)]
-        identifier: Option<AccountId>,
-        #[ink(topic)]
-        message: Option<Biodata>
+    // The PatientBundle struct aggregates a patient's on-chain state for export in a single call.
+    // At most MAX_BUNDLE_NOTES clinical notes are embedded; notes_truncated is set when note_count exceeds them.
+    // Likewise the first MAX_BUNDLE_PROBLEMS problems and MAX_BUNDLE_CONSENTS active consents are embedded, with
+    // their full counts.
+    #[derive(Clone, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            Debug,
+            PartialEq,
+            Eq,
+            scale_info::TypeInfo
+        )
+    )]
+    pub struct PatientBundle {
+        health_id: HealthId,
+        biodata: Option<Biodata>,
+        note_count: u32,
+        notes: Vec<ClinicalNotes>,
+        notes_truncated: bool,
+        problem_count: u32,
+        problems: Vec<Problem>,
+        consent_count: u32,
+        consents: Vec<ConsentReceipt>,
+        research_consent: bool,
+        quality_flags: Vec<(u8, QualityFlag)>,
+        imported: bool,
+        origin: RecordOrigin
     }
 
-    // The ClinicalNotesUpdate event is emitted whenever the clinical notes of a patient are updated.
-    #[ink(event)]
-    pub struct ClinicalNotesUpdate {
-        #[ink(topic)]
-        identifier: Option<AccountId>,
-        #[ink(topic)]
-        message: Option<ClinicalNotes>
+    // The ExportGrant struct lets a recipient export a patient's bundle once before expires_at, whatever the
+    // patient's other consents say.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct ExportGrant {
+        patient: AccountId,
+        recipient: AccountId,
+        expires_at: Timestamp
     }
 
-    // Define the behavior of the EPR contract.
-    impl Epr {
-        // The constructor initializes an EPR contract with no data.
-        #[ink(constructor, payable)]
-        pub fn new(patient_code_hash: Hash) -> Self {
-            let patient = PatientRef::new(String::from("HealthDOT"), String::from("HDOT"))
-                .endowment(0)
-                .code_hash(patient_code_hash)
-                .salt_bytes([0xDE, 0xAD, 0xBE, 0xEF])
-                .instantiate();
+    // The ExportRecord struct is an entry of a patient's export log: which grant was used, by whom and when.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct ExportRecord {
+        grant_id: ExportGrantId,
+        recipient: AccountId,
+        exported_at: Timestamp
+    }
 
-            Self {
-                current_id: 0,
-                record_count: Default::default(),
-                patient_biodata: Default::default(),
-                patient_notes: Default::default(),
-                which: Which::Patient,
-                patient,
-                permissions: Default::default()
-            }
-        }
+    // The AdminAction enum lists the destructive admin actions that need guardian approval once guardians are configured.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub enum AdminAction {
+        ArchivePatient(AccountId),
+        ErasePatientData(AccountId),
+        Upgrade(Hash)
+    }
 
-        // Function to add a user with permissions
-        #[ink(message)]
-        pub fn add_user_with_permissions(&mut self, user: AccountId, can_access: bool) {
-            let new_permission = Permission {
-                can_access
-            };
-            self.permissions.insert(&user, &new_permission);
-        }
+    // The Proposal struct represents an admin action waiting for guardian approvals.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct Proposal {
+        action: AdminAction,
+        proposer: AccountId,
+        created_at: BlockNumber,
+        approvals: u32,
+        executed: bool
+    }
 
-        #[ink(message)]
-        pub fn get(&mut self) -> String {
-            self.patient.name()
-        }
+    // The SnapshotBundle struct is the part of a patient's record that snapshots hash.
+    // Clinical notes are only included by hash, so a snapshot can be verified without disclosing them.
+    #[derive(Clone, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            Debug,
+            PartialEq,
+            Eq,
+            scale_info::TypeInfo
+        )
+    )]
+    pub struct SnapshotBundle {
+        health_id: HealthId,
+        biodata: Option<Biodata>,
+        notes_hash: Option<Hash>
+    }
+
+    // The Snapshot struct anchors the hash of a SnapshotBundle to the block it was taken in.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct Snapshot {
+        hash: Hash,
+        block_number: BlockNumber,
+        taker: AccountId
+    }
+
+    // The GuardianInfo struct describes an account a patient, or the admin for an incapacitated patient, empowered
+    // to manage the patient's consents and redactions on their behalf. The admin has to record a justification.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct GuardianInfo {
+        relationship: String,
+        appointed_by: AccountId,
+        appointed_at: Timestamp,
+        justification: Option<String>
+    }
+
+    // The ImagingReport struct anchors a radiology report to a DICOM study, identified by the hash of its Study Instance UID.
+    // The images and the full report stay off-chain at report_uri. The contract records who reported and when.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct ImagingReport {
+        modality: String,
+        study_uid_hash: Hash,
+        report_uri: String,
+        impression: String,
+        reported_by: AccountId,
+        reported_at: Timestamp
+    }
+
+    // The Immunization struct records a vaccine dose given to a patient. The contract records who recorded it and when.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct Immunization {
+        vaccine_code: String,
+        recorded_by: AccountId,
+        recorded_at: Timestamp
+    }
+
+    // The Problem struct is an entry of a patient's problem list, coded in ICD-10.
+    // A problem is active until it is resolved.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct Problem {
+        icd_code: String,
+        noted_by: AccountId,
+        noted_at: Timestamp,
+        resolved_at: Option<Timestamp>
+    }
+
+    // The Medication struct is an entry of a patient's current medication list, kept separately from prescriptions.
+    // A medication is active until it is stopped.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct Medication {
+        name: String,
+        dose: String,
+        active: bool,
+        added_by: AccountId,
+        started_at: Timestamp,
+        stopped_at: Option<Timestamp>
+    }
+
+    // The MedicationReconciliation struct records that a clinician reviewed a patient's medication list
+    // during an encounter.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct MedicationReconciliation {
+        encounter_index: u32,
+        clinician: AccountId,
+        reconciled_at: Timestamp
+    }
+
+    // The InstitutionRecord struct is an entry of a patient's institution history: a former primary institution
+    // and when the patient left it.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct InstitutionRecord {
+        org: AccountId,
+        left_at: Timestamp,
+        left_by: AccountId
+    }
+
+    // The QualityFlag struct marks a data quality issue of one of the FLAG_* kinds on a patient's record.
+    // It leaves the clinical content untouched and stays on the record, resolved, once the issue is dealt with.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct QualityFlag {
+        raised_by: AccountId,
+        raised_at: Timestamp,
+        note: String,
+        resolved: bool
+    }
+
+    // The Subscription struct records that a provider system follows a patient's change log, and the sequence
+    // number up to which it has processed the log.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct Subscription {
+        patient: AccountId,
+        cursor: u64,
+        subscribed_at: Timestamp
+    }
+
+    // The ChangeEntry struct is an entry of a patient's change log: what kind of write happened, who made it,
+    // the hash of what was written and when. Entries are numbered per patient from 1 without gaps.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct ChangeEntry {
+        kind: u8,
+        actor: AccountId,
+        ref_hash: Hash,
+        block: BlockNumber,
+        timestamp: Timestamp
+    }
+
+    // The ReviewItem struct records an emergency access for an auditor to review: who accessed whose record,
+    // why and when, and once closed, who reviewed it and whether the access was justified.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct ReviewItem {
+        clinician: AccountId,
+        patient: AccountId,
+        reason: String,
+        opened_at: Timestamp,
+        reviewed_by: Option<AccountId>,
+        justified: Option<bool>
+    }
+
+    // The VipRead struct is an entry of a VIP patient's read log: who read which part of the record,
+    // which clinician co-authorized the read and when it happened.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct VipRead {
+        reader: AccountId,
+        co_authorized_by: AccountId,
+        scope: u8,
+        read_at: Timestamp
+    }
+
+    // The Stats struct holds the anonymized top-level counts published for public health dashboards:
+    // registered patients whose record is not archived, recorded immunizations and active problems.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct Stats {
+        patients: u32,
+        immunizations: u32,
+        active_problems: u32
+    }
+
+    // The Action enum lists what authorize checks an account for on a patient's record.
+    // Read carries the consent scope bits of the part of the record being read.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub enum Action {
+        Read(u8),
+        Write,
+        WriteClinical,
+        ActFor
+    }
+
+    // The AccessPath enum tells through what an account is authorized: being the patient, being one of the
+    // patient's guardians, the patient's consent, a direct permission, the permission of the given group,
+    // the permission of the given facility at or above the account's own,
+    // a sharing agreement between its organization and the given organization of one of the patient's care team,
+    // the onboarding consent of the patient's new primary institution, or a clinician's emergency access. Paths do not chain: a guardian's own organization, for instance, confers nothing on the patient's record.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub enum AccessPath {
+        Patient,
+        Guardian,
+        Consent,
+        Permission,
+        Group(u32),
+        Agreement(AccountId),
+        Onboarding,
+        Emergency,
+        Facility(u32)
+    }
+
+    // The AccessSummary struct describes what an account can do right now, optionally on a given patient's record.
+    // Every flag is computed by authorize, the check the messages themselves use. Without a patient,
+    // the per-record flags are false.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct AccessSummary {
+        role: Option<Role>,
+        role_expiry: Option<Timestamp>,
+        via_group: bool,
+        can_read_biodata: bool,
+        can_read_notes: bool,
+        can_read_imaging: bool,
+        can_write_notes: bool,
+        can_write_clinical: bool,
+        consent_scope: Option<u8>,
+        is_guardian: bool
+    }
+
+    // The Agreement struct is a standing record sharing agreement between two organizations, stored under the pair
+    // (org_a, org_b) ordered so that org_a < org_b. It is active once both organizations approved it, until expires_at.
+    // Members of either organization can then read, within scope, the patients whose care team includes
+    // a member of the other.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct Agreement {
+        scope: u8,
+        expires_at: Timestamp,
+        approved_by_a: bool,
+        approved_by_b: bool
+    }
+
+    // The ApptStatus enum lists the states of an appointment. Only booked appointments hold their slot.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub enum ApptStatus {
+        Booked,
+        Cancelled,
+        Completed,
+        NoShow
+    }
+
+    // The Appointment struct represents an appointment of a patient with a clinician.
+    // It covers the slot from scheduled_at, for duration_minutes.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct Appointment {
+        clinician: AccountId,
+        scheduled_at: Timestamp,
+        duration_minutes: u16,
+        status: ApptStatus
+    }
+
+    // The NotificationKind enum lists what a patient is notified of. The actor of a notification is the requester,
+    // the provider whose consent changed, or the account that wrote to the record.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub enum NotificationKind {
+        AccessRequested,
+        ConsentGranted,
+        ConsentRevoked,
+        BiodataUpdated,
+        NotesUpdated
+    }
+
+    // The Notification struct is an item of a patient's inbox, kept on-chain for clients that missed the event.
+    // ref_index is the index of the revoked consent receipt for ConsentRevoked, the note index for NotesUpdated,
+    // and zero otherwise.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct Notification {
+        kind: NotificationKind,
+        actor: AccountId,
+        timestamp: Timestamp,
+        ref_index: u32
+    }
+
+    // The DidAnchor struct points from a patient to their W3C DID, whose document is kept off-chain.
+    // document_hash lets anyone check the document they resolved against the one the patient anchored.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct DidAnchor {
+        did: String,
+        document_hash: Hash,
+        updated_at: Timestamp
+    }
+
+    // The BloodType enum is the ABO group and Rh factor of a patient, Unknown until it is typed.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub enum BloodType {
+        Unknown,
+        APositive,
+        ANegative,
+        BPositive,
+        BNegative,
+        AbPositive,
+        AbNegative,
+        OPositive,
+        ONegative
+    }
+
+    // The CriticalInfo struct holds the flags of a patient an emergency team needs first. updated_by and
+    // updated_at are set by the contract; whatever the caller passes for them is ignored.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct CriticalInfo {
+        blood_type: BloodType,
+        organ_donor: bool,
+        advance_directive_uri: Option<String>,
+        updated_by: AccountId,
+        updated_at: Timestamp
+    }
+
+    // The RecordView struct is a record as a given reader sees it. Redacted fields are emptied,
+    // and redacted tells which ones, so an empty field can be told apart from a hidden one.
+    #[derive(Clone, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            Debug,
+            PartialEq,
+            Eq,
+            scale_info::TypeInfo
+        )
+    )]
+    pub struct RecordView<T> {
+        record: T,
+        redacted: u8
+    }
+
+    // The Encounter struct represents a patient's visit or admission, opened and closed by clinicians.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct Encounter {
+        opened_by: AccountId,
+        opened_at: Timestamp,
+        closed_at: Option<Timestamp>
+    }
+
+    // The DischargeSummary struct holds the regulatory minimum content of the discharge summary of a closed encounter.
+    // The full document is kept off-chain; content_hash anchors it. The contract sets the author and,
+    // when finalized_at is given, the time of finalization.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(
+            scale_info::TypeInfo,
+            ink::storage::traits::StorageLayout
+        )
+    )]
+    pub struct DischargeSummary {
+        encounter_index: u32,
+        diagnosis_codes: Vec<String>,
+        medications: String,
+        follow_up: String,
+        author: AccountId,
+        finalized_at: Option<Timestamp>,
+        content_hash: Hash
+    }
+
+    // Define an Error enum to handle errors.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout, scale_info::TypeInfo)
+    )]
+    pub enum Error {
+        NotAllowed,
+        CannotFetchValue,
+        PermissionDenied,
+        PayloadTooLarge,
+        RecordNotFound,
+        EmptyPayload,
+        GroupNotFound,
+        AlreadyMember,
+        NotMember,
+        TooManyGroups,
+        RateLimited,
+        AlreadyRequested,
+        AlreadyRegistered,
+        InsufficientDeposit,
+        NothingToWithdraw,
+        TransferFailed,
+        PatientExists,
+        ConsentNotFound,
+        NotGuardian,
+        GuardiansRequired,
+        ProposalNotFound,
+        ProposalExpired,
+        ProposalExecuted,
+        AlreadyApproved,
+        RecordArchived,
+        BatchTooLarge,
+        RoleAlreadyGranted,
+        ProviderNotVerified,
+        InvalidExpiry,
+        SelfApproval,
+        EncounterOpen,
+        EncounterClosed,
+        MissingDiagnosis,
+        SummaryFinalized,
+        InvalidRedaction,
+        JustificationRequired,
+        DuplicateStudy,
+        InvalidCode,
+        OpenQualityFlags,
+        ProblemResolved,
+        MedicationStopped,
+        RecordFinalized,
+        AgreementNotFound,
+        CareTeamFull,
+        InvalidSlot,
+        SlotTaken,
+        AppointmentClosed,
+        InvalidDid,
+        QuotaExceeded,
+        GrantUnavailable,
+        SubscriptionNotFound,
+        PendingReview,
+        FacilityNotFound,
+        FacilityTooDeep,
+        InvalidTemplate,
+        CoAuthorizationRequired,
+        ConsentNotYetActive,
+        AlreadyReviewed,
+        /// A call to the Patient contract failed. `selector` identifies the message (`mint_to`, `owner_of`,
+        /// `transfer_from`, `set_registry` or `set_consent_market`) and `inner` is the discriminant of the Patient
+        /// error it returned, named by `patient::ERROR_NAMES` or the Patient contract's `error_catalog()`.
+        /// `inner` is 255 (CHILD_CALL_FAILED) when the call trapped or its reply could not be decoded.
+        CrossContract {
+            selector: [u8; 4],
+            inner: u8
+        }
+    }
+
+    // The name of each Error variant, indexed by its discriminant. Kept next to the enum and checked against it by the tests.
+    pub const ERROR_NAMES: [&str; 59] = [
+        "NotAllowed", "CannotFetchValue", "PermissionDenied", "PayloadTooLarge", "RecordNotFound",
+        "EmptyPayload", "GroupNotFound", "AlreadyMember", "NotMember", "TooManyGroups", "RateLimited",
+        "AlreadyRequested", "AlreadyRegistered", "InsufficientDeposit", "NothingToWithdraw", "TransferFailed",
+        "PatientExists", "ConsentNotFound", "NotGuardian", "GuardiansRequired", "ProposalNotFound",
+        "ProposalExpired", "ProposalExecuted", "AlreadyApproved", "RecordArchived", "BatchTooLarge",
+        "RoleAlreadyGranted", "ProviderNotVerified", "InvalidExpiry", "SelfApproval", "EncounterOpen",
+        "EncounterClosed", "MissingDiagnosis", "SummaryFinalized", "InvalidRedaction", "JustificationRequired",
+        "DuplicateStudy", "InvalidCode", "OpenQualityFlags", "ProblemResolved", "MedicationStopped",
+        "RecordFinalized", "AgreementNotFound", "CareTeamFull", "InvalidSlot", "SlotTaken", "AppointmentClosed",
+        "InvalidDid", "QuotaExceeded", "GrantUnavailable", "SubscriptionNotFound", "PendingReview",
+        "FacilityNotFound", "FacilityTooDeep", "InvalidTemplate", "CoAuthorizationRequired", "ConsentNotYetActive",
+        "AlreadyReviewed", "CrossContract"
+    ];
+
+    /// The initial state is `Adder`.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(ink::storage::traits::StorageLayout, scale_info::TypeInfo)
+    )]
+    pub enum Which {
+        Patient
+    }
+
+    // The EPR (Electronic Patient Record) struct represents the smart contract.
+    #[ink(storage)]
+    pub struct Epr {
+        // The current_id field keeps track of the current patient id.
+        current_id: HealthId,
+        // The record_count mapping stores the account id associated with each health id.
+        record_count: Mapping<HealthId, AccountId>,
+        // The health_ids mapping stores the health id of each registered patient account.
+        health_ids: Mapping<AccountId, HealthId>,
+        // The patient_biodata mapping stores the biodata payload of each patient.
+        patient_biodata: Mapping<AccountId, Biodata>,
+        // The biodata_meta mapping stores the metadata of each patient's biodata.
+        biodata_meta: Mapping<AccountId, RecordMeta>,
+        // The patient_notes mapping stores the clinical notes payload of each patient.
+        patient_notes: Mapping<AccountId, ClinicalNotes>,
+        // The notes_meta mapping stores the metadata of each patient's clinical notes.
+        notes_meta: Mapping<AccountId, RecordMeta>,
+        // The note_revisions mapping counts the clinical notes written for each patient; the count before a write
+        // is the index of that note.
+        note_revisions: Mapping<AccountId, u32>,
+        // The notes_by_author mapping stores, by author and position, the patient and index of every note written.
+        notes_by_author: Mapping<(AccountId, u32), NoteRef>,
+        // The author_note_count mapping keeps track of the number of notes written by each author.
+        author_note_count: Mapping<AccountId, u32>,
+        which: Which,
+        patient: PatientRef,
+        permissions: Mapping<AccountId, Permission>,
+        // The admin field stores the account that instantiated the contract.
+        admin: AccountId,
+        // The group_count field keeps track of the last group id.
+        group_count: u32,
+        // The groups mapping stores each access group by id.
+        groups: Mapping<u32, Group>,
+        // The group_permissions mapping stores the permission granted to each group.
+        group_permissions: Mapping<u32, Permission>,
+        // The group_members mapping stores the members of each group by position.
+        group_members: Mapping<(u32, u32), AccountId>,
+        // The group_member_count mapping stores the number of members of each group.
+        group_member_count: Mapping<u32, u32>,
+        // The member_index mapping stores the position of an account in a group's member list.
+        member_index: Mapping<(u32, AccountId), u32>,
+        // The account_groups mapping stores the groups each account belongs to.
+        account_groups: Mapping<AccountId, Vec<u32>>,
+        // The access_requests mapping stores pending access requests by (patient, requester).
+        access_requests: Mapping<(AccountId, AccountId), AccessRequest>,
+        // The providers mapping stores the self-registered providers.
+        providers: Mapping<AccountId, Provider>,
+        // The research_consent mapping stores whether a patient opted into research.
+        research_consent: Mapping<AccountId, bool>,
+        // The cooldown field stores the number of blocks between two rate-limited calls of the same kind.
+        cooldown: BlockNumber,
+        // The finalize_after field stores the time, in milliseconds after their creation, from which clinical notes
+        // count as finalized even if the flag was never set.
+        finalize_after: Timestamp,
+        // The quota field stores the number of payload bytes a single patient's record may hold.
+        quota: u32,
+        // The storage_used mapping stores the payload bytes of biodata, clinical notes and imaging reports held for each patient.
+        storage_used: Mapping<AccountId, u32>,
+        // The last_call mapping stores the block of the last rate-limited call per (caller, message kind).
+        last_call: Mapping<(AccountId, u8), BlockNumber>,
+        // The storage_deposit field stores the deposit required for new open-registration entries.
+        storage_deposit: Balance,
+        // The deposits mapping stores the total deposit currently held for each account.
+        deposits: Mapping<AccountId, Balance>,
+        // The pending_withdrawals mapping stores refunds each account can withdraw.
+        pending_withdrawals: Mapping<AccountId, Balance>,
+        // The external_ids mapping stores the health id derived from each external identifier hash.
+        external_ids: Mapping<Hash, HealthId>,
+        // The consents mapping stores the active consent of each (patient, provider).
+        consents: Mapping<(AccountId, AccountId), Consent>,
+        // The revoked_consents mapping stores the receipts of each patient's revoked consents by position.
+        revoked_consents: Mapping<(AccountId, u32), ConsentReceipt>,
+        // The revoked_consent_count mapping stores the number of revoked consents of each patient.
+        revoked_consent_count: Mapping<AccountId, u32>,
+        // The consent_providers mapping stores the providers holding an active consent of each patient,
+        // dense in 0..consent_provider_count.
+        consent_providers: Mapping<(AccountId, u32), AccountId>,
+        // The consent_provider_index mapping stores the position of each provider among a patient's consent_providers.
+        consent_provider_index: Mapping<(AccountId, AccountId), u32>,
+        // The consent_provider_count mapping stores the number of active consents of each patient.
+        consent_provider_count: Mapping<AccountId, u32>,
+        // The guardians field stores the accounts whose approval destructive admin actions need.
+        guardians: Vec<AccountId>,
+        // The proposal_count field keeps track of the last proposal id.
+        proposal_count: u32,
+        // The proposals mapping stores each admin action proposal by id.
+        proposals: Mapping<u32, Proposal>,
+        // The proposal_approvals mapping stores which guardians approved which proposal.
+        proposal_approvals: Mapping<(u32, AccountId), ()>,
+        // The proposal_ttl field stores the number of blocks after which a proposal expires.
+        proposal_ttl: BlockNumber,
+        // The archived mapping stores the patients whose records are archived and no longer writable.
+        archived: Mapping<AccountId, ()>,
+        // The snapshots mapping stores the snapshot log of each patient by index.
+        snapshots: Mapping<(AccountId, u32), Snapshot>,
+        // The snapshot_count mapping keeps track of the length of each patient's snapshot log.
+        snapshot_count: Mapping<AccountId, u32>,
+        // The roles mapping stores the staff role granted to each account.
+        roles: Mapping<AccountId, RoleGrant>,
+        // The marketplace field stores the marketplace allowed to reassign access-consent tokens.
+        marketplace: Option<AccountId>,
+        // The consent_token_count field keeps track of the last access-consent token number.
+        consent_token_count: u32,
+        // The consent_tokens mapping stores the consent behind each access-consent token.
+        consent_tokens: Mapping<HealthId, ConsentToken>,
+        // The consent_token_ids mapping stores the access-consent token of each (patient, provider) consent.
+        consent_token_ids: Mapping<(AccountId, AccountId), HealthId>,
+        // The biodata_drafts mapping stores the pending biodata drafts of each patient by index.
+        biodata_drafts: Mapping<(AccountId, u32), Draft>,
+        // The biodata_draft_count mapping keeps track of the number of drafts ever submitted for each patient.
+        biodata_draft_count: Mapping<AccountId, u32>,
+        // The encounters mapping stores the encounters of each patient by index.
+        encounters: Mapping<(AccountId, u32), Encounter>,
+        // The encounter_count mapping keeps track of the number of encounters of each patient.
+        encounter_count: Mapping<AccountId, u32>,
+        // The discharge_summaries mapping stores the discharge summary of each (patient, encounter).
+        discharge_summaries: Mapping<(AccountId, u32), DischargeSummary>,
+        // The redactions mapping stores the fields of a patient's records hidden from each provider.
+        redactions: Mapping<(AccountId, AccountId), u8>,
+        // The patient_guardians mapping stores the guardians of each patient, keyed by (patient, guardian).
+        patient_guardians: Mapping<(AccountId, AccountId), GuardianInfo>,
+        // The imaging_reports mapping stores the imaging reports of each patient by index.
+        imaging_reports: Mapping<(AccountId, u32), ImagingReport>,
+        // The imaging_report_count mapping keeps track of the number of imaging reports of each patient.
+        imaging_report_count: Mapping<AccountId, u32>,
+        // The imaging_studies mapping stores the patient of every study that has a report, by Study Instance UID hash.
+        imaging_studies: Mapping<Hash, AccountId>,
+        // The immunizations mapping stores the immunizations of each patient by index.
+        immunizations: Mapping<(AccountId, u32), Immunization>,
+        // The immunization_count mapping keeps track of the number of immunizations of each patient.
+        immunization_count: Mapping<AccountId, u32>,
+        // The problems mapping stores the problem list of each patient by index.
+        problems: Mapping<(AccountId, u32), Problem>,
+        // The problem_count mapping keeps track of the number of problems of each patient.
+        problem_count: Mapping<AccountId, u32>,
+        // The stats_patients field counts the registered patients whose record is not archived.
+        stats_patients: u32,
+        // The stats_immunizations mapping counts the recorded immunizations per vaccine code.
+        stats_immunizations: Mapping<String, u32>,
+        // The stats_immunization_total field counts the recorded immunizations of every vaccine code.
+        stats_immunization_total: u32,
+        // The stats_problems mapping counts the active problems per ICD-10 chapter.
+        stats_problems: Mapping<String, u32>,
+        // The stats_problem_total field counts the active problems of every chapter.
+        stats_problem_total: u32,
+        // The org_members mapping stores the organization, a verified provider, each member account belongs to.
+        org_members: Mapping<AccountId, AccountId>,
+        // The org_invites mapping stores the pending invitations, keyed by the invited account and the organization.
+        org_invites: Mapping<(AccountId, AccountId), ()>,
+        // The care_teams mapping stores the accounts taking care of each patient, at most MAX_CARE_TEAM.
+        care_teams: Mapping<AccountId, Vec<AccountId>>,
+        // The agreements mapping stores the sharing agreement of each ordered pair of organizations.
+        agreements: Mapping<(AccountId, AccountId), Agreement>,
+        // The appointments mapping stores the appointments of each patient by index.
+        appointments: Mapping<(AccountId, u32), Appointment>,
+        // The appointment_count mapping keeps track of the number of appointments of each patient.
+        appointment_count: Mapping<AccountId, u32>,
+        // The clinician_appointments mapping stores, by clinician and position, a reference to every appointment
+        // booked with the clinician.
+        clinician_appointments: Mapping<(AccountId, u32), AppointmentRef>,
+        // The clinician_appointment_count mapping keeps track of the number of appointments of each clinician.
+        clinician_appointment_count: Mapping<AccountId, u32>,
+        // The booked_slots mapping stores the booked appointments of each clinician by the days they touch.
+        booked_slots: Mapping<ScheduleDay, Vec<AppointmentRef>>,
+        // The did_documents mapping stores the DID anchor of each patient.
+        did_documents: Mapping<AccountId, DidAnchor>,
+        // The notifications mapping stores the last MAX_NOTIFICATIONS notifications of each patient by index.
+        notifications: Mapping<(AccountId, u32), Notification>,
+        // The notification_count mapping keeps track of the number of notifications each patient ever received.
+        notification_count: Mapping<AccountId, u32>,
+        // The notifications_read mapping stores, for each patient, the index up to which notifications are acknowledged, exclusive.
+        notifications_read: Mapping<AccountId, u32>,
+        // The org_activity mapping counts the usage of each organization, or of each account outside one,
+        // per billing period and metric. Closed periods are kept as the archive of past billing periods.
+        org_activity: Mapping<ActivityKey, u64>,
+        // The current_period field is the id of the billing period the live counters belong to.
+        current_period: u32,
+        // The critical_info mapping stores the blood type, organ donor status and advance directive of each patient.
+        critical_info: Mapping<AccountId, CriticalInfo>,
+        // The care_assignments mapping stores, by member and position, the patients whose care team the member is in.
+        care_assignments: Mapping<(AccountId, u32), AccountId>,
+        // The care_assignment_count mapping keeps track of the number of care teams each account is in.
+        care_assignment_count: Mapping<AccountId, u32>,
+        // The care_assignment_index mapping stores the position of each (member, patient) pair in care_assignments.
+        care_assignment_index: Mapping<(AccountId, AccountId), u32>,
+        // The orphans mapping stores, by former member and position, the patients left behind when the member's
+        // role was revoked or its provider registration ended, until reassign_orphans moves them.
+        orphans: Mapping<(AccountId, u32), AccountId>,
+        // The orphan_count mapping keeps track of the number of patients each former member left behind.
+        orphan_count: Mapping<AccountId, u32>,
+        // The export_grants mapping stores the unused one-time export grants by id.
+        export_grants: Mapping<ExportGrantId, ExportGrant>,
+        // The next_export_grant field is the id the next export grant gets.
+        next_export_grant: ExportGrantId,
+        // The export_log mapping stores the exports performed with a grant, by patient and index.
+        export_log: Mapping<(AccountId, u32), ExportRecord>,
+        // The export_count mapping keeps track of the number of exports of each patient.
+        export_count: Mapping<AccountId, u32>,
+        // The medications mapping stores the medication list of each patient by index.
+        medications: Mapping<(AccountId, u32), Medication>,
+        // The medication_count mapping keeps track of the number of medications of each patient.
+        medication_count: Mapping<AccountId, u32>,
+        // The medication_reconciliations mapping stores the reconciliation of each patient's medication list,
+        // keyed by (patient, encounter).
+        medication_reconciliations: Mapping<(AccountId, u32), MedicationReconciliation>,
+        // The primary_institutions mapping stores the organization each patient is primarily cared for by.
+        primary_institutions: Mapping<AccountId, AccountId>,
+        // The institution_history mapping stores the former primary institutions of each patient by index.
+        institution_history: Mapping<(AccountId, u32), InstitutionRecord>,
+        // The institution_history_count mapping keeps track of the number of former institutions of each patient.
+        institution_history_count: Mapping<AccountId, u32>,
+        // The onboarding_consents mapping stores until when an organization can read a patient's record after
+        // becoming its primary institution, keyed by (patient, organization).
+        onboarding_consents: Mapping<(AccountId, AccountId), Timestamp>,
+        // The change_seq mapping stores the sequence number of the latest entry of each patient's change log.
+        change_seq: Mapping<AccountId, u64>,
+        // The change_log mapping stores the change log of each patient, keyed by (patient, sequence number).
+        change_log: Mapping<(AccountId, u64), ChangeEntry>,
+        // The quality_flags mapping stores the data quality flags of each patient, keyed by (patient, flag kind).
+        quality_flags: Mapping<(AccountId, u8), QualityFlag>,
+        // The subscriptions mapping stores the subscriptions of each subscriber by index.
+        subscriptions: Mapping<(AccountId, u32), Subscription>,
+        // The subscription_count mapping keeps track of the number of subscriptions of each subscriber.
+        subscription_count: Mapping<AccountId, u32>,
+        // The subscription_index mapping stores the index of each subscription, keyed by (subscriber, patient).
+        subscription_index: Mapping<(AccountId, AccountId), u32>,
+        // The emergency_window variable stores how long, in milliseconds, an emergency access lets a clinician read a record.
+        emergency_window: Timestamp,
+        // The review_period variable stores how long, in milliseconds, an emergency access may stay unreviewed
+        // before the clinician can no longer invoke emergency access.
+        review_period: Timestamp,
+        // The emergency_grants mapping stores until when a clinician may read a patient's record, keyed by (patient, clinician).
+        emergency_grants: Mapping<(AccountId, AccountId), Timestamp>,
+        // The review_items mapping stores the review item of each emergency access by id.
+        review_items: Mapping<u32, ReviewItem>,
+        // The next_review_id variable stores the id of the next review item.
+        next_review_id: u32,
+        // The open_reviews mapping stores the ids of each clinician's review items that are not closed yet.
+        open_reviews: Mapping<AccountId, Vec<u32>>,
+        // The facility_count field keeps track of the last facility id.
+        facility_count: u32,
+        // The facilities mapping stores each node of the facility tree by id.
+        facilities: Mapping<u32, Facility>,
+        // The facility_children mapping stores the children of each facility by position.
+        facility_children: Mapping<(u32, u32), u32>,
+        // The facility_child_count mapping stores the number of children of each facility.
+        facility_child_count: Mapping<u32, u32>,
+        // The facility_permissions mapping stores the permission granted to each facility.
+        facility_permissions: Mapping<u32, Permission>,
+        // The facility_staff mapping stores the staff assigned to each facility by position.
+        facility_staff: Mapping<(u32, u32), AccountId>,
+        // The facility_staff_count mapping stores the number of staff assigned to each facility.
+        facility_staff_count: Mapping<u32, u32>,
+        // The staff_facility mapping stores the facility each staff member is assigned to and their position in its staff list.
+        staff_facility: Mapping<AccountId, (u32, u32)>,
+        // The imported mapping marks the patients whose records were imported from the legacy contract.
+        imported: Mapping<AccountId, ()>,
+        // The template_count stores the id of the latest consent template. Template ids start at 1.
+        template_count: u32,
+        // The consent_templates mapping stores every consent template by id.
+        consent_templates: Mapping<u32, Template>,
+        // The vip mapping marks the patients, such as staff members or public figures, whose records can only be read
+        // with a second clinician's co-authorization.
+        vip: Mapping<AccountId, ()>,
+        // The co_authorizations mapping stores, for a patient and a reader, the clinician who co-authorized the reader's
+        // next read of the patient's record and until when.
+        co_authorizations: Mapping<(AccountId, AccountId), CoAuthorization>,
+        // The vip_reads mapping stores the read log of each VIP patient by position.
+        vip_reads: Mapping<(AccountId, u32), VipRead>,
+        // The vip_read_count mapping stores the number of entries of each VIP patient's read log.
+        vip_read_count: Mapping<AccountId, u32>,
+        // The record_origins mapping stores the origin of self-registered records. Records without one were
+        // registered by an institution.
+        record_origins: Mapping<AccountId, RecordOrigin>,
+        // The claimed_by mapping stores the institution that took on each self-registered record it was asked to create.
+        claimed_by: Mapping<AccountId, AccountId>,
+        // The cohort_problems mapping counts each patient's active problems per ICD-10 category, the first three
+        // characters of the code, whether or not the patient opted into research.
+        cohort_problems: Mapping<(String, AccountId), u32>,
+        // The cohort_members mapping stores, per ICD-10 category, the health ids of the research-consented patients
+        // with an active problem in it, dense in 0..cohort_size.
+        cohort_members: Mapping<(String, u32), HealthId>,
+        // The cohort_index mapping stores the position of each patient in the cohorts they belong to.
+        cohort_index: Mapping<(String, AccountId), u32>,
+        // The cohort_size mapping stores the number of patients in each cohort.
+        cohort_size: Mapping<String, u32>
+    }
+
+    // The NewPatient event is emitted whenever a new patient is created.
+    #[ink(event)]
+    pub struct NewPatient {
+        #[ink(topic)]
+        id: HealthId,
+        #[ink(topic)]
+        identifier: Option<AccountId>
+    }
+
+    // The Imported event is emitted whenever a patient's record is imported from the legacy contract.
+    #[ink(event)]
+    pub struct Imported {
+        #[ink(topic)]
+        id: HealthId,
+        #[ink(topic)]
+        identifier: AccountId,
+        by: AccountId
+    }
+
+    // The SelfRegistered event is emitted whenever a patient registers their own record.
+    #[ink(event)]
+    pub struct SelfRegistered {
+        #[ink(topic)]
+        id: HealthId,
+        #[ink(topic)]
+        identifier: AccountId
+    }
+
+    // The PatientClaimed event is emitted whenever an institution asked to create a self-registered patient's record
+    // takes on the existing record instead.
+    #[ink(event)]
+    pub struct PatientClaimed {
+        #[ink(topic)]
+        id: HealthId,
+        #[ink(topic)]
+        identifier: AccountId,
+        by: AccountId
+    }
+
+    // The BiodataUpdate event is emitted whenever the biodata of a patient is updated.
+    // The payload itself is never emitted; payload_hash matches hash_of_biodata.
+    #[ink(event)]
+    pub struct BiodataUpdate {
+        #[ink(topic)]
+        identifier: AccountId,
+        #[ink(topic)]
+        by: AccountId,
+        #[ink(topic)]
+        payload_hash: Hash
+    }
+
+    // The ClinicalNotesUpdate event is emitted whenever the clinical notes of a patient are updated.
+    // The payload itself is never emitted; payload_hash matches hash_of_clinical_notes.
+    #[ink(event)]
+    pub struct ClinicalNotesUpdate {
+        #[ink(topic)]
+        identifier: AccountId,
+        #[ink(topic)]
+        by: AccountId,
+        #[ink(topic)]
+        payload_hash: Hash
+    }
+
+    // The RecordFinalized event is emitted whenever the finalized flag of overdue clinical notes is set.
+    #[ink(event)]
+    pub struct RecordFinalized {
+        #[ink(topic)]
+        identifier: AccountId,
+        created_at: Timestamp
+    }
+
+    // The GroupCreated event is emitted whenever a new access group is created.
+    #[ink(event)]
+    pub struct GroupCreated {
+        #[ink(topic)]
+        group: u32,
+        #[ink(topic)]
+        admin: AccountId,
+        name: String
+    }
+
+    // The GroupMemberAdded event is emitted whenever an account joins a group.
+    #[ink(event)]
+    pub struct GroupMemberAdded {
+        #[ink(topic)]
+        group: u32,
+        #[ink(topic)]
+        member: AccountId,
+        #[ink(topic)]
+        by: AccountId
+    }
+
+    // The GroupMemberRemoved event is emitted whenever an account leaves a group.
+    #[ink(event)]
+    pub struct GroupMemberRemoved {
+        #[ink(topic)]
+        group: u32,
+        #[ink(topic)]
+        member: AccountId,
+        #[ink(topic)]
+        by: AccountId
+    }
+
+    // The AccessRequested event is emitted whenever an account requests access to a patient's record.
+    #[ink(event)]
+    pub struct AccessRequested {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        requester: AccountId
+    }
+
+    // The ProviderRegistered event is emitted whenever an account registers itself as a provider.
+    #[ink(event)]
+    pub struct ProviderRegistered {
+        #[ink(topic)]
+        provider: AccountId
+    }
+
+    // The ResearchConsentChanged event is emitted whenever a patient changes their research opt-in.
+    #[ink(event)]
+    pub struct ResearchConsentChanged {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        by: AccountId,
+        old: bool,
+        new: bool
+    }
+
+    // The PermissionChanged event is emitted whenever the permission of an account is granted, changed or revoked.
+    #[ink(event)]
+    pub struct PermissionChanged {
+        #[ink(topic)]
+        user: AccountId,
+        #[ink(topic)]
+        by: AccountId,
+        old: Option<Permission>,
+        new: Option<Permission>
+    }
+
+    // The RoleGranted event is emitted whenever an account is granted a staff role.
+    #[ink(event)]
+    pub struct RoleGranted {
+        #[ink(topic)]
+        account: AccountId,
+        role: Role,
+        valid_until: Option<Timestamp>
+    }
+
+    // The RoleRevoked event is emitted whenever the staff role of an account is revoked.
+    #[ink(event)]
+    pub struct RoleRevoked {
+        #[ink(topic)]
+        account: AccountId,
+        role: Role
+    }
+
+    // The RoleExpired event is emitted whenever an expired temporary role is pruned.
+    #[ink(event)]
+    pub struct RoleExpired {
+        #[ink(topic)]
+        account: AccountId,
+        role: Role,
+        valid_until: Timestamp
+    }
+
+    // The DraftSubmitted event is emitted whenever a biodata draft is submitted for review.
+    #[ink(event)]
+    pub struct DraftSubmitted {
+        #[ink(topic)]
+        identifier: AccountId,
+        #[ink(topic)]
+        author: AccountId,
+        index: u32
+    }
+
+    // The DraftApproved event is emitted whenever a clinician promotes a biodata draft to the live record.
+    #[ink(event)]
+    pub struct DraftApproved {
+        #[ink(topic)]
+        identifier: AccountId,
+        #[ink(topic)]
+        by: AccountId,
+        index: u32
+    }
+
+    // The DraftRejected event is emitted whenever a clinician discards a biodata draft.
+    #[ink(event)]
+    pub struct DraftRejected {
+        #[ink(topic)]
+        identifier: AccountId,
+        #[ink(topic)]
+        by: AccountId,
+        index: u32,
+        reason: String
+    }
+
+    // The ProviderVerified event is emitted whenever the admin verifies a registered provider.
+    #[ink(event)]
+    pub struct ProviderVerified {
+        #[ink(topic)]
+        provider: AccountId
+    }
+
+    // The FacilityCreated event is emitted whenever a node is added to the facility tree.
+    #[ink(event)]
+    pub struct FacilityCreated {
+        #[ink(topic)]
+        facility: u32,
+        #[ink(topic)]
+        parent: Option<u32>,
+        admin: AccountId,
+        name: String
+    }
+
+    // The FacilityAdminChanged event is emitted whenever a facility gets a new admin.
+    #[ink(event)]
+    pub struct FacilityAdminChanged {
+        #[ink(topic)]
+        facility: u32,
+        #[ink(topic)]
+        admin: AccountId,
+        by: AccountId
+    }
+
+    // The FacilityPermissionChanged event is emitted whenever the permission of a facility is granted, changed or revoked.
+    #[ink(event)]
+    pub struct FacilityPermissionChanged {
+        #[ink(topic)]
+        facility: u32,
+        #[ink(topic)]
+        by: AccountId,
+        old: Option<Permission>,
+        new: Option<Permission>
+    }
+
+    // The StaffAssigned event is emitted whenever a staff member is assigned to a facility, `from` being the facility
+    // they were moved from, if any.
+    #[ink(event)]
+    pub struct StaffAssigned {
+        #[ink(topic)]
+        facility: u32,
+        #[ink(topic)]
+        member: AccountId,
+        #[ink(topic)]
+        by: AccountId,
+        from: Option<u32>
+    }
+
+    // The StaffUnassigned event is emitted whenever a staff member leaves the facility tree.
+    #[ink(event)]
+    pub struct StaffUnassigned {
+        #[ink(topic)]
+        facility: u32,
+        #[ink(topic)]
+        member: AccountId,
+        #[ink(topic)]
+        by: AccountId
+    }
+
+    // The GroupPermissionChanged event is emitted whenever the permission of a group is granted, changed or revoked.
+    #[ink(event)]
+    pub struct GroupPermissionChanged {
+        #[ink(topic)]
+        group: u32,
+        #[ink(topic)]
+        by: AccountId,
+        old: Option<Permission>,
+        new: Option<Permission>
+    }
+
+    // The AccessRequestResolved event is emitted whenever a patient resolves a pending access request.
+    #[ink(event)]
+    pub struct AccessRequestResolved {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        requester: AccountId,
+        granted: bool
+    }
+
+    // The ProviderDeregistered event is emitted whenever a provider removes its registration.
+    #[ink(event)]
+    pub struct ProviderDeregistered {
+        #[ink(topic)]
+        provider: AccountId
+    }
+
+    // The ConsentGranted event is emitted whenever a patient grants a provider consent.
+    #[ink(event)]
+    pub struct ConsentGranted {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        provider: AccountId,
+        scope: u8,
+        terms_hash: Hash,
+        template_id: u32,
+        starts_at: Timestamp
+    }
+
+    // The TemplateAdded event is emitted whenever the admin publishes a consent template.
+    #[ink(event)]
+    pub struct TemplateAdded {
+        #[ink(topic)]
+        id: u32,
+        version: u32,
+        hash: Hash
+    }
+
+    // The TemplateDeactivated event is emitted whenever the admin retires a consent template.
+    #[ink(event)]
+    pub struct TemplateDeactivated {
+        #[ink(topic)]
+        id: u32
+    }
+
+    // The ConsentRevoked event is emitted whenever a patient revokes a provider's consent.
+    #[ink(event)]
+    pub struct ConsentRevoked {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        provider: AccountId
+    }
+
+    // The ConsentTokenMinted event is emitted whenever a patient turns a consent into an access-consent token.
+    #[ink(event)]
+    pub struct ConsentTokenMinted {
+        #[ink(topic)]
+        token_id: HealthId,
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        holder: AccountId
+    }
+
+    // The ConsentTokenReassigned event is emitted whenever a sold access-consent token moves its consent to the buyer.
+    #[ink(event)]
+    pub struct ConsentTokenReassigned {
+        #[ink(topic)]
+        token_id: HealthId,
+        #[ink(topic)]
+        from: AccountId,
+        #[ink(topic)]
+        to: AccountId
+    }
+
+    // The ActionProposed event is emitted whenever a guardian proposes a destructive admin action.
+    #[ink(event)]
+    pub struct ActionProposed {
+        #[ink(topic)]
+        id: u32,
+        #[ink(topic)]
+        proposer: AccountId,
+        action: AdminAction
+    }
+
+    // The ActionApproved event is emitted whenever a guardian approves a proposal.
+    #[ink(event)]
+    pub struct ActionApproved {
+        #[ink(topic)]
+        id: u32,
+        #[ink(topic)]
+        guardian: AccountId,
+        approvals: u32
+    }
+
+    // The ActionExecuted event is emitted whenever a proposal reaches the threshold and is executed.
+    #[ink(event)]
+    pub struct ActionExecuted {
+        #[ink(topic)]
+        id: u32
+    }
+
+    // The ActionExpired event is emitted whenever an expired proposal is removed.
+    #[ink(event)]
+    pub struct ActionExpired {
+        #[ink(topic)]
+        id: u32
+    }
+
+    // The SnapshotTaken event is emitted whenever a snapshot of a patient's record is taken.
+    #[ink(event)]
+    pub struct SnapshotTaken {
+        #[ink(topic)]
+        identifier: AccountId,
+        #[ink(topic)]
+        taker: AccountId,
+        #[ink(topic)]
+        hash: Hash,
+        index: u32,
+        block_number: BlockNumber
+    }
+
+    // The EncounterOpened event is emitted whenever a clinician opens an encounter for a patient.
+    #[ink(event)]
+    pub struct EncounterOpened {
+        #[ink(topic)]
+        identifier: AccountId,
+        #[ink(topic)]
+        by: AccountId,
+        index: u32
+    }
+
+    // The EncounterClosed event is emitted whenever a clinician closes an encounter.
+    #[ink(event)]
+    pub struct EncounterClosed {
+        #[ink(topic)]
+        identifier: AccountId,
+        #[ink(topic)]
+        by: AccountId,
+        index: u32
+    }
+
+    // The DischargeSummarySubmitted event is emitted whenever the discharge summary of an encounter is submitted.
+    #[ink(event)]
+    pub struct DischargeSummarySubmitted {
+        #[ink(topic)]
+        identifier: AccountId,
+        #[ink(topic)]
+        author: AccountId,
+        #[ink(topic)]
+        content_hash: Hash,
+        encounter_index: u32,
+        finalized: bool
+    }
+
+    // The RedactionsChanged event is emitted whenever the fields hidden from a provider change.
+    #[ink(event)]
+    pub struct RedactionsChanged {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        provider: AccountId,
+        #[ink(topic)]
+        by: AccountId,
+        fields: u8
+    }
+
+    // The DidUpdated event is emitted whenever the DID anchor of a patient is set.
+    #[ink(event)]
+    pub struct DidUpdated {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        document_hash: Hash,
+        did: String
+    }
+
+    // The PeriodReset event is emitted whenever the admin closes a billing period.
+    #[ink(event)]
+    pub struct PeriodReset {
+        #[ink(topic)]
+        period_id: u32,
+        ended_at: Timestamp
+    }
+
+    // The CriticalInfoUpdated event is emitted whenever the critical info of a patient is set.
+    #[ink(event)]
+    pub struct CriticalInfoUpdated {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        by: AccountId,
+        blood_type: BloodType,
+        organ_donor: bool
+    }
+
+    // The ExportGranted event is emitted whenever a patient creates a one-time export grant.
+    #[ink(event)]
+    pub struct ExportGranted {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        recipient: AccountId,
+        grant_id: ExportGrantId,
+        expires_at: Timestamp
+    }
+
+    // The ExportPerformed event is emitted whenever a recipient exports a patient's bundle with a grant.
+    #[ink(event)]
+    pub struct ExportPerformed {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        recipient: AccountId,
+        grant_id: ExportGrantId
+    }
+
+    // The GuardianAppointed event is emitted whenever a guardian is appointed for a patient.
+    #[ink(event)]
+    pub struct GuardianAppointed {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        guardian: AccountId,
+        #[ink(topic)]
+        by: AccountId,
+        relationship: String
+    }
+
+    // The GuardianRevoked event is emitted whenever a patient's guardian is revoked.
+    #[ink(event)]
+    pub struct GuardianRevoked {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        guardian: AccountId,
+        #[ink(topic)]
+        by: AccountId
+    }
+
+    // The OrgMemberChanged event is emitted whenever an account joins or is removed from an organization.
+    #[ink(event)]
+    pub struct OrgMemberChanged {
+        #[ink(topic)]
+        org: AccountId,
+        #[ink(topic)]
+        member: AccountId,
+        added: bool
+    }
+
+    // The OrgMemberInvited event is emitted whenever an organization invites an account to join it.
+    #[ink(event)]
+    pub struct OrgMemberInvited {
+        #[ink(topic)]
+        org: AccountId,
+        #[ink(topic)]
+        member: AccountId
+    }
+
+    // The CareTeamChanged event is emitted whenever an account joins or leaves a patient's care team.
+    #[ink(event)]
+    pub struct CareTeamChanged {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        member: AccountId,
+        #[ink(topic)]
+        by: AccountId,
+        added: bool
+    }
+
+    // The InstitutionChanged event is emitted whenever a patient moves to a new primary institution.
+    #[ink(event)]
+    pub struct InstitutionChanged {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        new_org: AccountId,
+        old_org: Option<AccountId>,
+        by: AccountId,
+        onboarding_until: Timestamp
+    }
+
+    // The QualityFlagRaised event is emitted whenever a data steward flags a quality issue on a patient's record.
+    #[ink(event)]
+    pub struct QualityFlagRaised {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        by: AccountId,
+        kind: u8
+    }
+
+    // The QualityFlagResolved event is emitted whenever a data steward resolves a quality flag.
+    #[ink(event)]
+    pub struct QualityFlagResolved {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        by: AccountId,
+        kind: u8
+    }
+
+    // The SubscriptionChanged event is emitted whenever an account subscribes to or unsubscribes from
+    // a patient's change log.
+    #[ink(event)]
+    pub struct SubscriptionChanged {
+        #[ink(topic)]
+        subscriber: AccountId,
+        #[ink(topic)]
+        patient: AccountId,
+        subscribed: bool
+    }
+
+    // The EmergencyAccessGranted event is emitted whenever a clinician invokes emergency access to a patient's record.
+    #[ink(event)]
+    pub struct EmergencyAccessGranted {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        clinician: AccountId,
+        until: Timestamp,
+        review_id: u32
+    }
+
+    // The VipChanged event is emitted whenever the admin flags or unflags a patient as a VIP.
+    #[ink(event)]
+    pub struct VipChanged {
+        #[ink(topic)]
+        patient: AccountId,
+        vip: bool
+    }
+
+    // The ReadCoAuthorized event is emitted whenever a clinician co-authorizes a read of a VIP patient's record.
+    #[ink(event)]
+    pub struct ReadCoAuthorized {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        reader: AccountId,
+        by: AccountId,
+        until: Timestamp
+    }
+
+    // The VipRecordRead event is emitted whenever a co-authorized read of a VIP patient's record takes place.
+    #[ink(event)]
+    pub struct VipRecordRead {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        reader: AccountId,
+        co_authorized_by: AccountId,
+        scope: u8
+    }
+
+    // The EmergencyAccessExpired event is emitted whenever a lapsed emergency access is cleared.
+    #[ink(event)]
+    pub struct EmergencyAccessExpired {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        clinician: AccountId
+    }
+
+    // The ReviewClosed event is emitted whenever an auditor closes the review of an emergency access.
+    #[ink(event)]
+    pub struct ReviewClosed {
+        #[ink(topic)]
+        review_id: u32,
+        #[ink(topic)]
+        auditor: AccountId,
+        justified: bool
+    }
+
+    // The AgreementProposed event is emitted whenever an organization proposes a sharing agreement.
+    #[ink(event)]
+    pub struct AgreementProposed {
+        #[ink(topic)]
+        org_a: AccountId,
+        #[ink(topic)]
+        org_b: AccountId,
+        #[ink(topic)]
+        by: AccountId,
+        scope: u8,
+        expires_at: Timestamp
+    }
+
+    // The AgreementApproved event is emitted whenever an organization approves a sharing agreement.
+    // active is true once both organizations approved it.
+    #[ink(event)]
+    pub struct AgreementApproved {
+        #[ink(topic)]
+        org_a: AccountId,
+        #[ink(topic)]
+        org_b: AccountId,
+        #[ink(topic)]
+        by: AccountId,
+        active: bool
+    }
+
+    // The AgreementExpired event is emitted whenever an expired sharing agreement is removed.
+    #[ink(event)]
+    pub struct AgreementExpired {
+        #[ink(topic)]
+        org_a: AccountId,
+        #[ink(topic)]
+        org_b: AccountId,
+        expires_at: Timestamp
+    }
+
+    // The AppointmentBooked event is emitted whenever an appointment is booked.
+    #[ink(event)]
+    pub struct AppointmentBooked {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        clinician: AccountId,
+        index: u32,
+        scheduled_at: Timestamp,
+        duration_minutes: u16
+    }
+
+    // The AppointmentCancelled event is emitted whenever a booked appointment is cancelled.
+    #[ink(event)]
+    pub struct AppointmentCancelled {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        clinician: AccountId,
+        index: u32,
+        by: AccountId
+    }
+
+    // The AppointmentCompleted event is emitted whenever the clinician closes a booked appointment,
+    // with status Completed or NoShow.
+    #[ink(event)]
+    pub struct AppointmentCompleted {
+        #[ink(topic)]
+        patient: AccountId,
+        #[ink(topic)]
+        clinician: AccountId,
+        index: u32,
+        status: ApptStatus
+    }
+
+    // The ImagingReportAdded event is emitted whenever an imaging report is added to a patient's record.
+    #[ink(event)]
+    pub struct ImagingReportAdded {
+        #[ink(topic)]
+        identifier: AccountId,
+        #[ink(topic)]
+        reported_by: AccountId,
+        #[ink(topic)]
+        study_uid_hash: Hash,
+        index: u32
+    }
+
+    // The ImmunizationRecorded event is emitted whenever an immunization is added to a patient's record.
+    #[ink(event)]
+    pub struct ImmunizationRecorded {
+        #[ink(topic)]
+        identifier: AccountId,
+        #[ink(topic)]
+        recorded_by: AccountId,
+        index: u32
+    }
+
+    // The ProblemAdded event is emitted whenever a problem is added to a patient's problem list.
+    #[ink(event)]
+    pub struct ProblemAdded {
+        #[ink(topic)]
+        identifier: AccountId,
+        #[ink(topic)]
+        noted_by: AccountId,
+        index: u32
+    }
+
+    // The ProblemResolved event is emitted whenever a problem of a patient's problem list is resolved.
+    #[ink(event)]
+    pub struct ProblemResolved {
+        #[ink(topic)]
+        identifier: AccountId,
+        #[ink(topic)]
+        resolved_by: AccountId,
+        index: u32
+    }
+
+    // The MedicationAdded event is emitted whenever a medication is added to a patient's medication list.
+    #[ink(event)]
+    pub struct MedicationAdded {
+        #[ink(topic)]
+        identifier: AccountId,
+        #[ink(topic)]
+        added_by: AccountId,
+        index: u32
+    }
+
+    // The MedicationStopped event is emitted whenever a medication of a patient's medication list is stopped.
+    #[ink(event)]
+    pub struct MedicationStopped {
+        #[ink(topic)]
+        identifier: AccountId,
+        #[ink(topic)]
+        stopped_by: AccountId,
+        index: u32
+    }
+
+    // The MedicationsReconciled event is emitted whenever a clinician reconciles a patient's medication list
+    // during an encounter.
+    #[ink(event)]
+    pub struct MedicationsReconciled {
+        #[ink(topic)]
+        identifier: AccountId,
+        #[ink(topic)]
+        clinician: AccountId,
+        encounter_index: u32
+    }
+
+    // Define the behavior of the EPR contract.
+    impl Epr {
+        // The constructor initializes an EPR contract with no data.
+        // The Patient child is instantiated with the given token name and symbol.
+        // With guardians (at most MAX_GUARDIANS), destructive admin actions have to be approved by them.
+        #[ink(constructor, payable)]
+        pub fn new(patient_code_hash: Hash, token_name: String, token_symbol: String, guardians: Vec<AccountId>) -> Self {
+            assert!(guardians.len() <= MAX_GUARDIANS, "too many guardians");
+            assert!(
+                guardians.iter().enumerate().all(|(i, guardian)| !guardians[..i].contains(guardian)),
+                "duplicate guardian"
+            );
+
+            let patient = PatientRef::new(token_name, token_symbol, String::new(), String::new())
+                .endowment(0)
+                .code_hash(patient_code_hash)
+                .salt_bytes([0xDE, 0xAD, 0xBE, 0xEF])
+                .instantiate();
+
+            Self::with_patient(patient, guardians)
+        }
+
+        // Builds the initial storage around an already instantiated Patient contract.
+        fn with_patient(patient: PatientRef, guardians: Vec<AccountId>) -> Self {
+            Self {
+                current_id: 0,
+                record_count: Default::default(),
+                health_ids: Default::default(),
+                patient_biodata: Default::default(),
+                biodata_meta: Default::default(),
+                patient_notes: Default::default(),
+                notes_meta: Default::default(),
+                note_revisions: Default::default(),
+                notes_by_author: Default::default(),
+                author_note_count: Default::default(),
+                which: Which::Patient,
+                patient,
+                permissions: Default::default(),
+                admin: Self::env().caller(),
+                group_count: 0,
+                groups: Default::default(),
+                group_permissions: Default::default(),
+                group_members: Default::default(),
+                group_member_count: Default::default(),
+                member_index: Default::default(),
+                account_groups: Default::default(),
+                access_requests: Default::default(),
+                providers: Default::default(),
+                research_consent: Default::default(),
+                cooldown: DEFAULT_COOLDOWN,
+                finalize_after: DEFAULT_FINALIZE_AFTER,
+                quota: DEFAULT_QUOTA,
+                storage_used: Default::default(),
+                last_call: Default::default(),
+                storage_deposit: DEFAULT_STORAGE_DEPOSIT,
+                deposits: Default::default(),
+                pending_withdrawals: Default::default(),
+                external_ids: Default::default(),
+                consents: Default::default(),
+                revoked_consents: Default::default(),
+                revoked_consent_count: Default::default(),
+                consent_providers: Default::default(),
+                consent_provider_index: Default::default(),
+                consent_provider_count: Default::default(),
+                guardians,
+                proposal_count: 0,
+                proposals: Default::default(),
+                proposal_approvals: Default::default(),
+                proposal_ttl: DEFAULT_PROPOSAL_TTL,
+                archived: Default::default(),
+                snapshots: Default::default(),
+                snapshot_count: Default::default(),
+                roles: Default::default(),
+                marketplace: None,
+                consent_token_count: 0,
+                consent_tokens: Default::default(),
+                consent_token_ids: Default::default(),
+                biodata_drafts: Default::default(),
+                biodata_draft_count: Default::default(),
+                encounters: Default::default(),
+                encounter_count: Default::default(),
+                discharge_summaries: Default::default(),
+                redactions: Default::default(),
+                patient_guardians: Default::default(),
+                imaging_reports: Default::default(),
+                imaging_report_count: Default::default(),
+                imaging_studies: Default::default(),
+                immunizations: Default::default(),
+                immunization_count: Default::default(),
+                problems: Default::default(),
+                problem_count: Default::default(),
+                stats_patients: 0,
+                stats_immunizations: Default::default(),
+                stats_immunization_total: 0,
+                stats_problems: Default::default(),
+                stats_problem_total: 0,
+                org_members: Default::default(),
+                org_invites: Default::default(),
+                care_teams: Default::default(),
+                agreements: Default::default(),
+                appointments: Default::default(),
+                appointment_count: Default::default(),
+                clinician_appointments: Default::default(),
+                clinician_appointment_count: Default::default(),
+                booked_slots: Default::default(),
+                did_documents: Default::default(),
+                notifications: Default::default(),
+                notification_count: Default::default(),
+                notifications_read: Default::default(),
+                org_activity: Default::default(),
+                current_period: 0,
+                critical_info: Default::default(),
+                care_assignments: Default::default(),
+                care_assignment_count: Default::default(),
+                care_assignment_index: Default::default(),
+                orphans: Default::default(),
+                orphan_count: Default::default(),
+                export_grants: Default::default(),
+                next_export_grant: 0,
+                export_log: Default::default(),
+                export_count: Default::default(),
+                medications: Default::default(),
+                medication_count: Default::default(),
+                medication_reconciliations: Default::default(),
+                primary_institutions: Default::default(),
+                institution_history: Default::default(),
+                institution_history_count: Default::default(),
+                onboarding_consents: Default::default(),
+                change_seq: Default::default(),
+                change_log: Default::default(),
+                quality_flags: Default::default(),
+                subscriptions: Default::default(),
+                subscription_count: Default::default(),
+                subscription_index: Default::default(),
+                emergency_window: DEFAULT_EMERGENCY_WINDOW,
+                review_period: DEFAULT_REVIEW_PERIOD,
+                emergency_grants: Default::default(),
+                review_items: Default::default(),
+                next_review_id: 0,
+                open_reviews: Default::default(),
+                facility_count: 0,
+                facilities: Default::default(),
+                facility_children: Default::default(),
+                facility_child_count: Default::default(),
+                facility_permissions: Default::default(),
+                facility_staff: Default::default(),
+                facility_staff_count: Default::default(),
+                staff_facility: Default::default(),
+                imported: Default::default(),
+                template_count: 0,
+                consent_templates: Default::default(),
+                vip: Default::default(),
+                co_authorizations: Default::default(),
+                vip_reads: Default::default(),
+                vip_read_count: Default::default(),
+                record_origins: Default::default(),
+                claimed_by: Default::default(),
+                cohort_problems: Default::default(),
+                cohort_members: Default::default(),
+                cohort_index: Default::default(),
+                cohort_size: Default::default()
+            }
+        }
+
+        // The add_user_with_permissions function sets whether a user has access to patient records, with no expiry.
+        // Admin only; temporary access goes through grant_role.
+        #[ink(message)]
+        pub fn add_user_with_permissions(&mut self, user: AccountId, can_access: bool) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if caller != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            let new_permission = Permission {
+                can_access,
+                valid_until: None
+            };
+            let old = self.permissions.get(user);
+            self.permissions.insert(user, &new_permission);
+
+            self.emit(PermissionChanged {
+                user,
+                by: caller,
+                old,
+                new: Some(new_permission)
+            });
+            Ok(())
+        }
+
+        // The remove_user_permissions function removes the permission entry of a user. Admin only.
+        #[ink(message)]
+        pub fn remove_user_permissions(&mut self, user: AccountId) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if caller != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            let old = self.permissions.get(user).ok_or(Error::RecordNotFound)?;
+            self.permissions.remove(user);
+
+            self.emit(PermissionChanged {
+                user,
+                by: caller,
+                old: Some(old),
+                new: None
+            });
+
+            Ok(())
+        }
+
+        // The grant_roles_batch function grants a staff role to each account of the batch, with access to patient records.
+        // A role with a valid_until timestamp is temporary and stops granting access once that time is reached.
+        // Entries are processed independently; the returned results line up index for index with the entries. Admin only.
+        #[ink(message)]
+        pub fn grant_roles_batch(&mut self, entries: Vec<RoleEntry>) -> Result<Vec<Result<(), Error>>, Error> {
+            self.check_batch(entries.len())?;
+            Ok(entries
+                .into_iter()
+                .map(|(account, role, valid_until)| self.grant_role(account, RoleGrant { role, valid_until }))
+                .collect())
+        }
+
+        // The revoke_roles_batch function revokes the staff role, and with it the access, of each account of the batch.
+        // Each account also leaves up to MAX_CARE_CLEANUP care teams, whose patients are recorded as its orphans.
+        // Entries are processed independently; the returned results line up index for index with the accounts. Admin only.
+        #[ink(message)]
+        pub fn revoke_roles_batch(&mut self, accounts: Vec<AccountId>) -> Result<Vec<Result<(), Error>>, Error> {
+            self.check_batch(accounts.len())?;
+            Ok(accounts
+                .into_iter()
+                .map(|account| self.revoke_role(account))
+                .collect())
+        }
+
+        // The prune_expired_roles function removes the expired temporary roles, and with them the access, of the given
+        // accounts. Anyone may call it; accounts without an expired role are skipped. Returns the number of pruned roles.
+        #[ink(message)]
+        pub fn prune_expired_roles(&mut self, accounts: Vec<AccountId>) -> Result<u32, Error> {
+            if accounts.len() > MAX_BATCH_LEN {
+                return Err(Error::BatchTooLarge);
+            }
+
+            let mut pruned = 0;
+            for account in accounts {
+                let grant = match self.roles.get(account) {
+                    Some(grant) if self.is_role_expired(&grant) => grant,
+                    _ => continue
+                };
+                self.roles.remove(account);
+                let old = self.permissions.take(account);
+
+                self.emit(RoleExpired {
+                    account,
+                    role: grant.role,
+                    valid_until: grant.valid_until.unwrap_or_default()
+                });
+                self.emit(PermissionChanged {
+                    user: account,
+                    by: self.env().caller(),
+                    old,
+                    new: None
+                });
+                pruned += 1;
+            }
+
+            Ok(pruned)
+        }
+
+        // The role_of function returns the staff role granted to an account, if it has not expired.
+        #[ink(message)]
+        pub fn role_of(&self, account: AccountId) -> Option<Role> {
+            self.active_role(&account).map(|grant| grant.role)
+        }
+
+        // The role_grant function returns the role grant of an account, including when a temporary role expires.
+        #[ink(message)]
+        pub fn role_grant(&self, account: AccountId) -> Option<RoleGrant> {
+            self.active_role(&account)
+        }
+
+        // The access_summary function returns what an account can do right now, and on a patient's record if one
+        // is given. It is computed by the same checks the messages enforce, so it cannot disagree with them.
+        #[ink(message)]
+        pub fn access_summary(&self, subject: AccountId, patient: Option<AccountId>) -> AccessSummary {
+            let grant = self.active_role(&subject);
+            let allowed = |action| patient.is_some_and(|patient| self.authorize(&subject, &patient, action).is_ok());
+            AccessSummary {
+                role: grant.map(|grant| grant.role),
+                role_expiry: grant.and_then(|grant| grant.valid_until),
+                via_group: matches!(self.access_path(&subject), Some(AccessPath::Group(_))),
+                can_read_biodata: allowed(Action::Read(CONSENT_BIODATA)),
+                can_read_notes: allowed(Action::Read(CONSENT_CLINICAL_NOTES)),
+                can_read_imaging: allowed(Action::Read(CONSENT_IMAGING)),
+                can_write_notes: allowed(Action::Write),
+                can_write_clinical: allowed(Action::WriteClinical),
+                consent_scope: patient
+                    .and_then(|patient| self.consents.get((patient, subject)))
+                    .map(|consent| consent.scope),
+                is_guardian: patient.is_some_and(|patient| self.patient_guardians.contains((patient, subject)))
+            }
+        }
+
+        // The explain_access function returns why an account has access to a patient's record, or None if it has none.
+        // Acting for the patient is reported first, then the path that lets it read any part of the record.
+        #[ink(message)]
+        pub fn explain_access(&self, subject: AccountId, patient: AccountId) -> Option<AccessPath> {
+            self.authorize(&subject, &patient, Action::ActFor)
+                .or_else(|_| self.authorize(&subject, &patient, Action::Read(CONSENT_ALL)))
+                .ok()
+        }
+
+        // The denial_reason function returns the error an action on a patient's record would fail with right now,
+        // along with a DENIAL_* sub-code telling apart the causes PermissionDenied covers, or None if it would pass.
+        // Denied calls are reverted and cannot leave a trace, so clients ask here after the fact.
+        #[ink(message)]
+        pub fn denial_reason(&self, subject: AccountId, patient: AccountId, action: Action) -> Option<(Error, u8)> {
+            let error = match self.authorize(&subject, &patient, action) {
+                Ok(_) if matches!(action, Action::Read(_)) && self.needs_co_authorization(&subject, &patient) => {
+                    return Some((Error::CoAuthorizationRequired, DENIAL_CO_AUTHORIZATION));
+                }
+                Ok(_) => return None,
+                Err(error) => error
+            };
+            let code = match error {
+                Error::RecordNotFound => DENIAL_RECORD_NOT_FOUND,
+                Error::RecordArchived => DENIAL_RECORD_ARCHIVED,
+                Error::ConsentNotYetActive => DENIAL_CONSENT_NOT_YET_ACTIVE,
+                _ => self.denial_code(&subject, &patient, action)
+            };
+            Some((error, code))
+        }
+
+        // The error_catalog function returns the discriminant and name of every Error variant.
+        #[ink(message)]
+        pub fn error_catalog(&self) -> Vec<(u8, String)> {
+            ERROR_NAMES
+                .iter()
+                .enumerate()
+                .map(|(code, name)| (code as u8, String::from(*name)))
+                .collect()
+        }
+
+        // The create_group function creates a new access group administered by `admin`.
+        #[ink(message)]
+        pub fn create_group(&mut self, name: String, admin: AccountId) -> Result<u32, Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+
+            let id = self.group_count + 1;
+            self.group_count = id;
+            self.groups.insert(id, &Group { name: name.clone(), admin });
+
+            self.emit(GroupCreated {
+                group: id,
+                admin,
+                name
+            });
+
+            Ok(id)
+        }
+
+        // The add_group_with_permissions function grants or revokes access for every member of a group.
+        #[ink(message)]
+        pub fn add_group_with_permissions(&mut self, group: u32, can_access: bool) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            if !self.groups.contains(group) {
+                return Err(Error::GroupNotFound);
+            }
+
+            let new = Permission { can_access, valid_until: None };
+            let old = self.group_permissions.get(group);
+            self.group_permissions.insert(group, &new);
+
+            self.emit(GroupPermissionChanged {
+                group,
+                by: self.env().caller(),
+                old,
+                new: Some(new)
+            });
+
+            Ok(())
+        }
+
+        // The add_to_group function adds an account to a group. Callable by the group admin or the contract admin.
+        #[ink(message)]
+        pub fn add_to_group(&mut self, group: u32, account: AccountId) -> Result<(), Error> {
+            let caller = self.check_group_admin(group)?;
+            if self.member_index.contains((group, account)) {
+                return Err(Error::AlreadyMember);
+            }
+
+            let mut memberships = self.account_groups.get(account).unwrap_or_default();
+            if memberships.len() >= MAX_GROUPS_PER_ACCOUNT {
+                return Err(Error::TooManyGroups);
+            }
+            memberships.push(group);
+            self.account_groups.insert(account, &memberships);
+
+            let count = self.group_member_count.get(group).unwrap_or(0);
+            self.group_members.insert((group, count), &account);
+            self.member_index.insert((group, account), &count);
+            self.group_member_count.insert(group, &(count + 1));
+
+            self.emit(GroupMemberAdded {
+                group,
+                member: account,
+                by: caller
+            });
+
+            Ok(())
+        }
+
+        // The remove_from_group function removes an account from a group. Callable by the group admin or the contract admin.
+        #[ink(message)]
+        pub fn remove_from_group(&mut self, group: u32, account: AccountId) -> Result<(), Error> {
+            let caller = self.check_group_admin(group)?;
+            let index = self.member_index.get((group, account)).ok_or(Error::NotMember)?;
+
+            // Swap the last member into the freed position to keep the member list dense.
+            let last = self.group_member_count.get(group).unwrap_or(1) - 1;
+            if index != last {
+                let moved = self.group_members.get((group, last)).ok_or(Error::CannotFetchValue)?;
+                self.group_members.insert((group, index), &moved);
+                self.member_index.insert((group, moved), &index);
+            }
+            self.group_members.remove((group, last));
+            self.member_index.remove((group, account));
+            self.group_member_count.insert(group, &last);
+
+            let mut memberships = self.account_groups.get(account).unwrap_or_default();
+            memberships.retain(|g| *g != group);
+            self.account_groups.insert(account, &memberships);
+
+            self.emit(GroupMemberRemoved {
+                group,
+                member: account,
+                by: caller
+            });
+
+            Ok(())
+        }
+
+        // The groups_of function returns the ids of the groups an account belongs to.
+        #[ink(message)]
+        pub fn groups_of(&self, account: AccountId) -> Vec<u32> {
+            self.account_groups.get(account).unwrap_or_default()
+        }
+
+        // The members_of function returns up to `limit` members of a group starting at position `start`.
+        #[ink(message)]
+        pub fn members_of(&self, group: u32, start: u32, limit: u32) -> Vec<AccountId> {
+            let count = self.group_member_count.get(group).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            (start..end)
+                .filter_map(|i| self.group_members.get((group, i)))
+                .collect()
+        }
+
+        // The create_facility function adds a facility administered by `admin` to the facility tree, below `parent`
+        // or as a new hospital without one. Hospitals are created by the contract admin; a facility below another by
+        // the contract admin or the admin of the parent or of any facility above it. The tree is at most
+        // MAX_FACILITY_DEPTH deep.
+        #[ink(message)]
+        pub fn create_facility(&mut self, name: String, parent: Option<u32>, admin: AccountId) -> Result<u32, Error> {
+            match parent {
+                Some(parent) => {
+                    self.check_facility_admin(parent)?;
+                    if self.facility_depth(parent) >= MAX_FACILITY_DEPTH {
+                        return Err(Error::FacilityTooDeep);
+                    }
+                }
+                None => {
+                    if self.env().caller() != self.admin {
+                        return Err(Error::PermissionDenied);
+                    }
+                }
+            }
+            if name.len() > MAX_NAME_LEN {
+                return Err(Error::PayloadTooLarge);
+            }
+
+            let id = self.facility_count + 1;
+            self.facility_count = id;
+            self.facilities.insert(id, &Facility { name: name.clone(), parent, admin });
+            if let Some(parent) = parent {
+                let count = self.facility_child_count.get(parent).unwrap_or(0);
+                self.facility_children.insert((parent, count), &id);
+                self.facility_child_count.insert(parent, &(count + 1));
+            }
+
+            self.emit(FacilityCreated {
+                facility: id,
+                parent,
+                admin,
+                name
+            });
+
+            Ok(id)
+        }
+
+        // The set_facility_admin function hands a facility over to a new admin. Callable by the contract admin or the admin
+        // of the facility or of any facility above it.
+        #[ink(message)]
+        pub fn set_facility_admin(&mut self, facility: u32, admin: AccountId) -> Result<(), Error> {
+            let caller = self.check_facility_admin(facility)?;
+            let mut info = self.facilities.get(facility).ok_or(Error::FacilityNotFound)?;
+            info.admin = admin;
+            self.facilities.insert(facility, &info);
+            self.emit(FacilityAdminChanged { facility, admin, by: caller });
+            Ok(())
+        }
+
+        // The set_facility_permissions function grants or revokes access for the staff of a facility and of every
+        // facility below it. Admin only.
+        #[ink(message)]
+        pub fn set_facility_permissions(&mut self, facility: u32, can_access: bool) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            if !self.facilities.contains(facility) {
+                return Err(Error::FacilityNotFound);
+            }
+
+            let new = Permission { can_access, valid_until: None };
+            let old = self.facility_permissions.get(facility);
+            self.facility_permissions.insert(facility, &new);
+
+            self.emit(FacilityPermissionChanged {
+                facility,
+                by: self.env().caller(),
+                old,
+                new: Some(new)
+            });
+
+            Ok(())
+        }
+
+        // The assign_staff function assigns an account to a facility. A staff member belongs to a single facility,
+        // so assigning them elsewhere moves them and their inherited access changes at once. Callable by the contract
+        // admin or the admin of the facility or of any facility above it.
+        #[ink(message)]
+        pub fn assign_staff(&mut self, facility: u32, account: AccountId) -> Result<(), Error> {
+            let caller = self.check_facility_admin(facility)?;
+            let from = self.staff_facility.get(account).map(|(current, _)| current);
+            if from == Some(facility) {
+                return Err(Error::AlreadyMember);
+            }
+            if from.is_some() {
+                self.remove_staff(account);
+            }
+
+            let count = self.facility_staff_count.get(facility).unwrap_or(0);
+            self.facility_staff.insert((facility, count), &account);
+            self.facility_staff_count.insert(facility, &(count + 1));
+            self.staff_facility.insert(account, &(facility, count));
+
+            self.emit(StaffAssigned {
+                facility,
+                member: account,
+                by: caller,
+                from
+            });
+
+            Ok(())
+        }
+
+        // The unassign_staff function removes an account from the facility tree. Callable by the contract admin or
+        // the admin of the account's facility or of any facility above it.
+        #[ink(message)]
+        pub fn unassign_staff(&mut self, account: AccountId) -> Result<(), Error> {
+            let (facility, _) = self.staff_facility.get(account).ok_or(Error::NotMember)?;
+            let caller = self.check_facility_admin(facility)?;
+            self.remove_staff(account);
+
+            self.emit(StaffUnassigned {
+                facility,
+                member: account,
+                by: caller
+            });
+
+            Ok(())
+        }
+
+        // The facility function returns a node of the facility tree.
+        #[ink(message)]
+        pub fn facility(&self, facility: u32) -> Option<Facility> {
+            self.facilities.get(facility)
+        }
+
+        // The children_of function returns up to `limit` facilities directly below a facility starting at position `start`.
+        #[ink(message)]
+        pub fn children_of(&self, facility: u32, start: u32, limit: u32) -> Vec<u32> {
+            let count = self.facility_child_count.get(facility).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            (start..end)
+                .filter_map(|i| self.facility_children.get((facility, i)))
+                .collect()
+        }
+
+        // The facility_of function returns the facility an account is assigned to.
+        #[ink(message)]
+        pub fn facility_of(&self, account: AccountId) -> Option<u32> {
+            self.staff_facility.get(account).map(|(facility, _)| facility)
+        }
+
+        // The staff_of function returns up to `limit` staff members assigned to a facility starting at position `start`.
+        // Staff of the facilities below it are not included.
+        #[ink(message)]
+        pub fn staff_of(&self, facility: u32, start: u32, limit: u32) -> Vec<AccountId> {
+            let count = self.facility_staff_count.get(facility).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            (start..end)
+                .filter_map(|i| self.facility_staff.get((facility, i)))
+                .collect()
+        }
+
+        // The request_access function records a pending request by the caller to access a patient's record.
+        // The caller has to transfer the storage deposit, which is refunded once the request is resolved.
+        #[ink(message, payable)]
+        pub fn request_access(&mut self, patient: AccountId) -> Result<(), Error> {
+            let caller = self.env().caller();
+            self.rate_limit(caller, CALL_REQUEST_ACCESS)?;
+            if !self.health_ids.contains(patient) {
+                return Err(Error::RecordNotFound);
+            }
+            if self.access_requests.contains((patient, caller)) {
+                return Err(Error::AlreadyRequested);
+            }
+
+            let deposit = self.take_deposit(caller)?;
+            let request = AccessRequest {
+                requested_at: self.env().block_number(),
+                deposit
+            };
+            self.access_requests.insert((patient, caller), &request);
+            self.notify(patient, NotificationKind::AccessRequested, caller, 0);
+            self.emit(AccessRequested {
+                patient,
+                requester: caller
+            });
+
+            Ok(())
+        }
+
+        // The access_request function returns a pending access request.
+        #[ink(message)]
+        pub fn access_request(&self, patient: AccountId, requester: AccountId) -> Option<AccessRequest> {
+            self.access_requests.get((patient, requester))
+        }
+
+        // The resolve_access_request function lets a patient resolve a pending request and refunds the requester's deposit.
+        #[ink(message)]
+        pub fn resolve_access_request(&mut self, requester: AccountId, granted: bool) -> Result<(), Error> {
+            let patient = self.env().caller();
+            let request = self.access_requests.get((patient, requester)).ok_or(Error::RecordNotFound)?;
+
+            self.access_requests.remove((patient, requester));
+            self.refund_deposit(requester, request.deposit);
+            self.emit(AccessRequestResolved {
+                patient,
+                requester,
+                granted
+            });
+
+            Ok(())
+        }
+
+        // The register_provider function registers the caller as an (unverified) provider.
+        // The caller has to transfer the storage deposit, which is refunded on deregistration.
+        #[ink(message, payable)]
+        pub fn register_provider(&mut self) -> Result<(), Error> {
+            let caller = self.env().caller();
+            self.rate_limit(caller, CALL_REGISTER_PROVIDER)?;
+            if self.providers.contains(caller) {
+                return Err(Error::AlreadyRegistered);
+            }
+
+            let deposit = self.take_deposit(caller)?;
+            let provider = Provider {
+                verified: false,
+                registered_at: self.env().block_number(),
+                deposit
+            };
+            self.providers.insert(caller, &provider);
+            self.emit(ProviderRegistered { provider: caller });
+
+            Ok(())
+        }
+
+        // The deregister_provider function removes the caller's provider registration and refunds its deposit.
+        // The caller also leaves up to MAX_CARE_CLEANUP care teams, whose patients are recorded as its orphans.
+        #[ink(message)]
+        pub fn deregister_provider(&mut self) -> Result<(), Error> {
+            let caller = self.env().caller();
+            let provider = self.providers.get(caller).ok_or(Error::RecordNotFound)?;
+
+            self.providers.remove(caller);
+            self.refund_deposit(caller, provider.deposit);
+            self.emit(ProviderDeregistered { provider: caller });
+            self.release_care_assignments(caller, MAX_CARE_CLEANUP);
+
+            Ok(())
+        }
+
+        // The verify_provider function marks a registered provider as verified. Admin only.
+        #[ink(message)]
+        pub fn verify_provider(&mut self, account: AccountId) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            let mut provider = self.providers.get(account).ok_or(Error::RecordNotFound)?;
+            provider.verified = true;
+            self.providers.insert(account, &provider);
+            self.emit(ProviderVerified { provider: account });
+            Ok(())
+        }
+
+        // The provider function returns the registration of a provider.
+        #[ink(message)]
+        pub fn provider(&self, account: AccountId) -> Option<Provider> {
+            self.providers.get(account)
+        }
+
+        // The add_org_member function invites an account to the caller's organization. The caller has to be a verified
+        // provider, and an account can belong to one organization only. The account joins once it accepts with
+        // accept_org_invite.
+        #[ink(message)]
+        pub fn add_org_member(&mut self, member: AccountId) -> Result<(), Error> {
+            let org = self.check_organization()?;
+            if self.org_members.contains(member) {
+                return Err(Error::AlreadyMember);
+            }
+            self.org_invites.insert((member, org), &());
+            self.emit(OrgMemberInvited { org, member });
+            Ok(())
+        }
+
+        // The accept_org_invite function makes the caller a member of an organization that invited it.
+        // The organization has to still be a verified provider.
+        #[ink(message)]
+        pub fn accept_org_invite(&mut self, org: AccountId) -> Result<(), Error> {
+            let member = self.env().caller();
+            if !self.org_invites.contains((member, org)) {
+                return Err(Error::RecordNotFound);
+            }
+            if self.org_members.contains(member) {
+                return Err(Error::AlreadyMember);
+            }
+            if !self.providers.get(org).is_some_and(|provider| provider.verified) {
+                return Err(Error::ProviderNotVerified);
+            }
+            self.org_invites.remove((member, org));
+            self.org_members.insert(member, &org);
+            self.emit(OrgMemberChanged { org, member, added: true });
+            Ok(())
+        }
+
+        // The remove_org_member function removes an account from the caller's organization.
+        #[ink(message)]
+        pub fn remove_org_member(&mut self, member: AccountId) -> Result<(), Error> {
+            let org = self.check_organization()?;
+            if self.org_members.get(member) != Some(org) {
+                return Err(Error::NotMember);
+            }
+            self.org_members.remove(member);
+            self.emit(OrgMemberChanged { org, member, added: false });
+            Ok(())
+        }
+
+        // The org_of function returns the organization an account belongs to, if any.
+        #[ink(message)]
+        pub fn org_of(&self, member: AccountId) -> Option<AccountId> {
+            self.org_members.get(member)
+        }
+
+        // The org_activity function returns the live counter of a metric for an organization, or for an account
+        // that belongs to none, in the current billing period.
+        #[ink(message)]
+        pub fn org_activity(&self, org: AccountId, metric: u8) -> u64 {
+            self.org_activity.get((self.current_period, org, metric)).unwrap_or(0)
+        }
+
+        // The archived_activity function returns the counter of a metric for an organization in a closed billing period.
+        #[ink(message)]
+        pub fn archived_activity(&self, period_id: u32, org: AccountId, metric: u8) -> Option<u64> {
+            if period_id >= self.current_period {
+                return None;
+            }
+            Some(self.org_activity.get((period_id, org, metric)).unwrap_or(0))
+        }
+
+        // The current_period function returns the id of the current billing period.
+        #[ink(message)]
+        pub fn current_period(&self) -> u32 {
+            self.current_period
+        }
+
+        // The reset_period function closes the current billing period. Its counters stay readable through
+        // archived_activity and the live counters start again from zero. Only the admin can call it.
+        #[ink(message)]
+        pub fn reset_period(&mut self) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            let period_id = self.current_period;
+            self.current_period = period_id + 1;
+            self.emit(PeriodReset { period_id, ended_at: self.env().block_timestamp() });
+            Ok(())
+        }
+
+        // Counts one unit of a metric for the organization of an account, or for the account itself outside one.
+        fn record_activity(&mut self, account: AccountId, metric: u8) {
+            let org = self.org_members.get(account).unwrap_or(account);
+            let key = (self.current_period, org, metric);
+            let value = self.org_activity.get(key).unwrap_or(0);
+            self.org_activity.insert(key, &value.saturating_add(1));
+        }
+
+        // The propose_agreement function proposes, or replaces, a sharing agreement between the caller's organization
+        // and another one. Both have to be verified providers. The proposal counts as the caller's approval;
+        // the agreement becomes active once the other organization approves it too.
+        #[ink(message)]
+        pub fn propose_agreement(&mut self, other_org: AccountId, scope: u8, expires_at: Timestamp) -> Result<(), Error> {
+            let org = self.check_organization()?;
+            if other_org == org {
+                return Err(Error::NotAllowed);
+            }
+            if !self.providers.get(other_org).is_some_and(|provider| provider.verified) {
+                return Err(Error::ProviderNotVerified);
+            }
+            if scope == 0 {
+                return Err(Error::EmptyPayload);
+            }
+            if expires_at <= self.env().block_timestamp() {
+                return Err(Error::InvalidExpiry);
+            }
+
+            let (org_a, org_b) = agreement_key(org, other_org);
+            let agreement = Agreement {
+                scope,
+                expires_at,
+                approved_by_a: org == org_a,
+                approved_by_b: org == org_b
+            };
+            self.agreements.insert((org_a, org_b), &agreement);
+            self.emit(AgreementProposed { org_a, org_b, by: org, scope, expires_at });
+            Ok(())
+        }
+
+        // The approve_agreement function approves the pending sharing agreement with another organization.
+        #[ink(message)]
+        pub fn approve_agreement(&mut self, other_org: AccountId) -> Result<(), Error> {
+            let org = self.check_organization()?;
+            let (org_a, org_b) = agreement_key(org, other_org);
+            let mut agreement = self.agreements.get((org_a, org_b)).ok_or(Error::AgreementNotFound)?;
+            let approved = if org == org_a { &mut agreement.approved_by_a } else { &mut agreement.approved_by_b };
+            if *approved {
+                return Err(Error::AlreadyApproved);
+            }
+            *approved = true;
+
+            self.agreements.insert((org_a, org_b), &agreement);
+            self.emit(AgreementApproved {
+                org_a,
+                org_b,
+                by: org,
+                active: agreement.approved_by_a && agreement.approved_by_b
+            });
+            Ok(())
+        }
+
+        // The expire_agreement function removes a sharing agreement that has expired. Anyone can call it.
+        #[ink(message)]
+        pub fn expire_agreement(&mut self, org: AccountId, other_org: AccountId) -> Result<(), Error> {
+            let (org_a, org_b) = agreement_key(org, other_org);
+            let agreement = self.agreements.get((org_a, org_b)).ok_or(Error::AgreementNotFound)?;
+            if self.env().block_timestamp() < agreement.expires_at {
+                return Err(Error::NotAllowed);
+            }
+            self.agreements.remove((org_a, org_b));
+            self.emit(AgreementExpired { org_a, org_b, expires_at: agreement.expires_at });
+            Ok(())
+        }
+
+        // The agreement function returns the sharing agreement between two organizations, in either order.
+        #[ink(message)]
+        pub fn agreement(&self, org: AccountId, other_org: AccountId) -> Option<Agreement> {
+            self.agreements.get(agreement_key(org, other_org))
+        }
+
+        // The add_to_care_team function adds an account to a patient's care team.
+        // Callable by the patient or one of their guardians.
+        #[ink(message)]
+        pub fn add_to_care_team(&mut self, patient: AccountId, member: AccountId) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if !self.acts_for(&caller, &patient) {
+                return Err(Error::PermissionDenied);
+            }
+            let mut team = self.care_teams.get(patient).unwrap_or_default();
+            if team.contains(&member) {
+                return Err(Error::AlreadyMember);
+            }
+            if team.len() >= MAX_CARE_TEAM {
+                return Err(Error::CareTeamFull);
+            }
+            team.push(member);
+            self.care_teams.insert(patient, &team);
+            self.assign_care(member, patient);
+            self.emit(CareTeamChanged { patient, member, by: caller, added: true });
+            Ok(())
+        }
+
+        // The remove_from_care_team function removes an account from a patient's care team.
+        // Callable by the patient or one of their guardians.
+        #[ink(message)]
+        pub fn remove_from_care_team(&mut self, patient: AccountId, member: AccountId) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if !self.acts_for(&caller, &patient) {
+                return Err(Error::PermissionDenied);
+            }
+            let mut team = self.care_teams.get(patient).unwrap_or_default();
+            let index = team.iter().position(|account| *account == member).ok_or(Error::NotMember)?;
+            team.swap_remove(index);
+            self.care_teams.insert(patient, &team);
+            self.unassign_care(member, patient);
+            self.emit(CareTeamChanged { patient, member, by: caller, added: false });
+            Ok(())
+        }
+
+        // The care_team_of function returns the accounts in a patient's care team.
+        #[ink(message)]
+        pub fn care_team_of(&self, patient: AccountId) -> Vec<AccountId> {
+            self.care_teams.get(patient).unwrap_or_default()
+        }
+
+        // The transfer_primary_institution function moves a patient to a new primary institution, a verified provider.
+        // The old institution and its members leave the patient's care team and it is added to the institution history.
+        // The new institution can read the whole record for ONBOARDING_PERIOD. If the old institution holds the token
+        // of the record in custody, the token is moved to the new one, which needs the old institution to have approved
+        // this contract on the Patient contract. Callable by the patient or one of their guardians.
+        #[ink(message)]
+        pub fn transfer_primary_institution(&mut self, patient: AccountId, new_org: AccountId) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if !self.acts_for(&caller, &patient) {
+                return Err(Error::PermissionDenied);
+            }
+            let id = self.health_ids.get(patient).ok_or(Error::RecordNotFound)?;
+            if !self.providers.get(new_org).is_some_and(|provider| provider.verified) {
+                return Err(Error::ProviderNotVerified);
+            }
+            if self.primary_institutions.get(patient) == Some(new_org) {
+                return Err(Error::NotAllowed);
+            }
+
+            if let Some(old_org) = self.switch_institution(patient, new_org, caller) {
+                self.move_record_token(id, old_org, new_org)?;
+            }
+            Ok(())
+        }
+
+        // The primary_institution_of function returns the organization a patient is primarily cared for by.
+        #[ink(message)]
+        pub fn primary_institution_of(&self, patient: AccountId) -> Option<AccountId> {
+            self.primary_institutions.get(patient)
+        }
+
+        // The institution_history function returns a page of a patient's former primary institutions, oldest first.
+        #[ink(message)]
+        pub fn institution_history(&self, patient: AccountId, start: u32, limit: u32) -> Vec<InstitutionRecord> {
+            let count = self.institution_history_count.get(patient).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            (start..end)
+                .filter_map(|i| self.institution_history.get((patient, i)))
+                .collect()
+        }
+
+        // The onboarding_until function returns until when an organization can read a patient's record
+        // as the patient's new primary institution.
+        #[ink(message)]
+        pub fn onboarding_until(&self, patient: AccountId, org: AccountId) -> Option<Timestamp> {
+            self.onboarding_consents.get((patient, org))
+        }
+
+        // The assigned_patients function returns a page of the patients whose care team an account is in.
+        // The order changes as the account leaves care teams.
+        #[ink(message)]
+        pub fn assigned_patients(&self, member: AccountId, start: u32, limit: u32) -> Vec<AccountId> {
+            let count = self.care_assignment_count.get(member).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            (start..end)
+                .filter_map(|i| self.care_assignments.get((member, i)))
+                .collect()
+        }
+
+        // The orphans_of function returns a page of the patients a revoked or deregistered account left behind.
+        #[ink(message)]
+        pub fn orphans_of(&self, old_provider: AccountId, start: u32, limit: u32) -> Vec<AccountId> {
+            let count = self.orphan_count.get(old_provider).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            (start..end)
+                .filter_map(|i| self.orphans.get((old_provider, i)))
+                .collect()
+        }
+
+        // The continue_care_cleanup function removes a revoked or deregistered account from up to MAX_CARE_CLEANUP
+        // more care teams, recording each patient as its orphan, and returns the number of care teams it is still in.
+        // Revoking the role or deregistering only does the first MAX_CARE_CLEANUP. Admin only.
+        #[ink(message)]
+        pub fn continue_care_cleanup(&mut self, account: AccountId) -> Result<u32, Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            Ok(self.release_care_assignments(account, MAX_CARE_CLEANUP))
+        }
+
+        // The reassign_orphans function adds new_provider to the care team of up to `limit` patients left behind by
+        // old_provider, most recent first, and returns the number of care teams it joined. Patients whose care team
+        // already has new_provider or has filled up in the meantime are dropped from the orphans. Admin only.
+        #[ink(message)]
+        pub fn reassign_orphans(&mut self, old_provider: AccountId, new_provider: AccountId, limit: u32) -> Result<u32, Error> {
+            let caller = self.env().caller();
+            if caller != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+
+            let count = self.orphan_count.get(old_provider).unwrap_or(0);
+            let remaining = count.saturating_sub(limit);
+            let mut moved = 0;
+            for index in (remaining..count).rev() {
+                let Some(patient) = self.orphans.take((old_provider, index)) else {
+                    continue;
+                };
+                let mut team = self.care_teams.get(patient).unwrap_or_default();
+                if team.contains(&new_provider) || team.len() >= MAX_CARE_TEAM {
+                    continue;
+                }
+                team.push(new_provider);
+                self.care_teams.insert(patient, &team);
+                self.assign_care(new_provider, patient);
+                self.emit(CareTeamChanged { patient, member: new_provider, by: caller, added: true });
+                moved += 1;
+            }
+            self.orphan_count.insert(old_provider, &remaining);
+            Ok(moved)
+        }
+
+        // The book_appointment function books an appointment of a registered patient with a clinician and returns its
+        // index. The caller must be the patient or hold the Registrar role. The slot has to start in the future, last
+        // between one minute and MAX_APPOINTMENT_MINUTES, and must not overlap another booked appointment of the
+        // clinician. Back-to-back appointments do not overlap.
+        #[ink(message)]
+        pub fn book_appointment(
+            &mut self,
+            patient: AccountId,
+            clinician: AccountId,
+            scheduled_at: Timestamp,
+            duration_minutes: u16
+        ) -> Result<u32, Error> {
+            let caller = self.env().caller();
+            if caller != patient && !self.has_role(&caller, &[Role::Registrar]) {
+                return Err(Error::PermissionDenied);
+            }
+            if !self.health_ids.contains(patient) {
+                return Err(Error::RecordNotFound);
+            }
+            if !self.has_role(&clinician, &[Role::Clinician]) {
+                return Err(Error::NotAllowed);
+            }
+            if duration_minutes == 0
+                || duration_minutes > MAX_APPOINTMENT_MINUTES
+                || scheduled_at < self.env().block_timestamp()
+            {
+                return Err(Error::InvalidSlot);
+            }
+
+            let end = slot_end(scheduled_at, duration_minutes);
+            for day in slot_days(scheduled_at, end) {
+                let taken = self.booked_slots.get((clinician, day)).unwrap_or_default().into_iter().any(|(p, i)| {
+                    self.appointments
+                        .get((p, i))
+                        .is_some_and(|other| scheduled_at < slot_end(other.scheduled_at, other.duration_minutes) && other.scheduled_at < end)
+                });
+                if taken {
+                    return Err(Error::SlotTaken);
+                }
+            }
+
+            let index = self.appointment_count.get(patient).unwrap_or(0);
+            let appointment = Appointment {
+                clinician,
+                scheduled_at,
+                duration_minutes,
+                status: ApptStatus::Booked
+            };
+            self.appointments.insert((patient, index), &appointment);
+            self.appointment_count.insert(patient, &(index + 1));
+
+            let position = self.clinician_appointment_count.get(clinician).unwrap_or(0);
+            self.clinician_appointments.insert((clinician, position), &(patient, index));
+            self.clinician_appointment_count.insert(clinician, &(position + 1));
+
+            for day in slot_days(scheduled_at, end) {
+                let mut booked = self.booked_slots.get((clinician, day)).unwrap_or_default();
+                booked.push((patient, index));
+                self.booked_slots.insert((clinician, day), &booked);
+            }
+
+            self.emit(AppointmentBooked {
+                patient,
+                clinician,
+                index,
+                scheduled_at,
+                duration_minutes
+            });
+
+            Ok(index)
+        }
+
+        // The cancel_appointment function cancels a booked appointment and frees its slot.
+        // The caller must be the patient, the clinician or hold the Registrar role.
+        #[ink(message)]
+        pub fn cancel_appointment(&mut self, patient: AccountId, index: u32) -> Result<(), Error> {
+            let caller = self.env().caller();
+            let appointment = self.appointments.get((patient, index)).ok_or(Error::RecordNotFound)?;
+            if caller != patient && caller != appointment.clinician && !self.has_role(&caller, &[Role::Registrar]) {
+                return Err(Error::PermissionDenied);
+            }
+            let clinician = appointment.clinician;
+            self.close_appointment(patient, index, appointment, ApptStatus::Cancelled)?;
+
+            self.emit(AppointmentCancelled {
+                patient,
+                clinician,
+                index,
+                by: caller
+            });
+
+            Ok(())
+        }
+
+        // The complete_appointment function closes a booked appointment once it has started, as Completed if the
+        // patient attended and as NoShow otherwise, and frees its slot. Only the appointment's clinician can call it.
+        #[ink(message)]
+        pub fn complete_appointment(&mut self, patient: AccountId, index: u32, attended: bool) -> Result<(), Error> {
+            let caller = self.env().caller();
+            let appointment = self.appointments.get((patient, index)).ok_or(Error::RecordNotFound)?;
+            if caller != appointment.clinician {
+                return Err(Error::PermissionDenied);
+            }
+            if self.env().block_timestamp() < appointment.scheduled_at {
+                return Err(Error::NotAllowed);
+            }
+            let status = if attended { ApptStatus::Completed } else { ApptStatus::NoShow };
+            self.close_appointment(patient, index, appointment, status)?;
+
+            self.emit(AppointmentCompleted {
+                patient,
+                clinician: caller,
+                index,
+                status
+            });
+
+            Ok(())
+        }
+
+        // The appointments function returns a page of a patient's appointments, oldest booking first.
+        // The caller needs access to patient records or the patient's consent covering biodata.
+        #[ink(message)]
+        pub fn appointments(&self, patient: AccountId, start: u32, limit: u32) -> Vec<Appointment> {
+            if !self.can_read(patient, CONSENT_BIODATA) {
+                return Vec::new();
+            }
+            let count = self.appointment_count.get(patient).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            (start..end)
+                .filter_map(|i| self.appointments.get((patient, i)))
+                .collect()
+        }
+
+        // The clinician_appointments function returns a page of references to the appointments booked with a
+        // clinician, oldest booking first. The caller must be the clinician, hold the Registrar role or be the admin.
+        #[ink(message)]
+        pub fn clinician_appointments(&self, clinician: AccountId, start: u32, limit: u32) -> Result<Vec<AppointmentRef>, Error> {
+            let caller = self.env().caller();
+            if caller != clinician && caller != self.admin && !self.has_role(&caller, &[Role::Registrar]) {
+                return Err(Error::PermissionDenied);
+            }
+            let count = self.clinician_appointment_count.get(clinician).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            Ok((start..end)
+                .filter_map(|i| self.clinician_appointments.get((clinician, i)))
+                .collect())
+        }
+
+        // The set_research_consent function lets a registered patient opt into or out of research.
+        #[ink(message)]
+        pub fn set_research_consent(&mut self, opted_in: bool) -> Result<(), Error> {
+            let caller = self.env().caller();
+            self.rate_limit(caller, CALL_SET_RESEARCH_CONSENT)?;
+            if !self.health_ids.contains(caller) {
+                return Err(Error::RecordNotFound);
+            }
+
+            let old = self.research_consent_of(caller);
+            self.research_consent.insert(caller, &opted_in);
+            if old != opted_in {
+                self.sync_cohorts(caller);
+            }
+            self.emit(ResearchConsentChanged {
+                patient: caller,
+                by: caller,
+                old,
+                new: opted_in
+            });
+
+            Ok(())
+        }
+
+        // The research_consent_of function returns whether a patient opted into research.
+        #[ink(message)]
+        pub fn research_consent_of(&self, patient: AccountId) -> bool {
+            self.research_consent.get(patient).unwrap_or(false)
+        }
+
+        // The add_template function publishes a version of the standard consent wording, the hash of the document
+        // found at uri, and returns its id. Consents are granted against a template. Admin only.
+        #[ink(message)]
+        pub fn add_template(&mut self, uri: String, hash: Hash, version: u32) -> Result<u32, Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            if uri.is_empty() {
+                return Err(Error::EmptyPayload);
+            }
+            if uri.len() > MAX_DETAILS_LEN {
+                return Err(Error::PayloadTooLarge);
+            }
+
+            let id = self.template_count + 1;
+            self.template_count = id;
+            self.consent_templates.insert(id, &Template { uri, hash, version, active: true });
+            self.emit(TemplateAdded { id, version, hash });
+            Ok(id)
+        }
+
+        // The deactivate_template function retires a consent template. New consents cannot be granted against it,
+        // while the consents already granted stay valid. Admin only.
+        #[ink(message)]
+        pub fn deactivate_template(&mut self, id: u32) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            let mut template = self.consent_templates.get(id).ok_or(Error::InvalidTemplate)?;
+            template.active = false;
+            self.consent_templates.insert(id, &template);
+            self.emit(TemplateDeactivated { id });
+            Ok(())
+        }
+
+        // The template function returns a consent template.
+        #[ink(message)]
+        pub fn template(&self, id: u32) -> Option<Template> {
+            self.consent_templates.get(id)
+        }
+
+        // The templates function returns up to `limit` consent templates with their ids, starting at the `start`-th one.
+        #[ink(message)]
+        pub fn templates(&self, start: u32, limit: u32) -> Vec<(u32, Template)> {
+            let end = start.saturating_add(limit).min(self.template_count);
+            (start..end)
+                .filter_map(|i| self.consent_templates.get(i + 1).map(|template| (i + 1, template)))
+                .collect()
+        }
+
+        // The grant_consent function records the caller's consent for a provider against an active consent template,
+        // whose document hash, location and version are kept with the consent. A consent already held by the provider
+        // is moved to the revoked history first. A consent given a starts_at, say the date of a booked appointment,
+        // grants no access before then; without one it is active right away.
+        #[ink(message)]
+        pub fn grant_consent(&mut self, provider: AccountId, scope: u8, template_id: u32, starts_at: Option<Timestamp>) -> Result<(), Error> {
+            self.record_consent(self.env().caller(), provider, scope, template_id, starts_at)
+        }
+
+        // The grant_consent_for function works like grant_consent, for a patient the caller is a guardian of.
+        #[ink(message)]
+        pub fn grant_consent_for(
+            &mut self,
+            patient: AccountId,
+            provider: AccountId,
+            scope: u8,
+            template_id: u32,
+            starts_at: Option<Timestamp>
+        ) -> Result<(), Error> {
+            if !self.acts_for(&self.env().caller(), &patient) {
+                return Err(Error::PermissionDenied);
+            }
+            self.record_consent(patient, provider, scope, template_id, starts_at)
+        }
+
+        // Records a patient's consent for a provider, archiving the consent it replaces.
+        fn record_consent(
+            &mut self,
+            patient: AccountId,
+            provider: AccountId,
+            scope: u8,
+            template_id: u32,
+            starts_at: Option<Timestamp>
+        ) -> Result<(), Error> {
+            if !self.health_ids.contains(patient) {
+                return Err(Error::RecordNotFound);
+            }
+            if scope == 0 {
+                return Err(Error::EmptyPayload);
+            }
+            let template = match self.consent_templates.get(template_id) {
+                Some(template) if template.active => template,
+                _ => return Err(Error::InvalidTemplate)
+            };
+
+            if let Some(previous) = self.consents.take((patient, provider)) {
+                self.archive_consent(patient, provider, previous);
+            }
+
+            let terms_hash = template.hash;
+            let starts_at = starts_at.unwrap_or(self.env().block_timestamp());
+            let consent = Consent {
+                scope,
+                terms_hash,
+                document_uri: template.uri,
+                template_id,
+                template_version: template.version,
+                granted_at: self.env().block_timestamp(),
+                granted_block: self.env().block_number(),
+                starts_at
+            };
+            self.consents.insert((patient, provider), &consent);
+            self.sync_consent_index(patient, provider);
+            self.notify(patient, NotificationKind::ConsentGranted, provider, 0);
+            self.log_change(patient, CHANGE_CONSENT_GRANTED, self.env().caller(), &consent);
+            self.emit(ConsentGranted {
+                patient,
+                provider,
+                scope,
+                terms_hash,
+                template_id,
+                starts_at
+            });
+
+            Ok(())
+        }
+
+        // The revoke_consent function revokes the caller's consent for a provider and keeps its receipt in the history.
+        #[ink(message)]
+        pub fn revoke_consent(&mut self, provider: AccountId) -> Result<(), Error> {
+            self.withdraw_consent(self.env().caller(), provider)
+        }
+
+        // The revoke_consent_for function works like revoke_consent, for a patient the caller is a guardian of.
+        #[ink(message)]
+        pub fn revoke_consent_for(&mut self, patient: AccountId, provider: AccountId) -> Result<(), Error> {
+            if !self.acts_for(&self.env().caller(), &patient) {
+                return Err(Error::PermissionDenied);
+            }
+            self.withdraw_consent(patient, provider)
+        }
+
+        // Revokes a patient's consent for a provider and archives its receipt.
+        fn withdraw_consent(&mut self, patient: AccountId, provider: AccountId) -> Result<(), Error> {
+            let consent = self.consents.take((patient, provider)).ok_or(Error::ConsentNotFound)?;
+            self.sync_consent_index(patient, provider);
+            // An access-consent token dies with its consent, so it cannot sell a later grant and the
+            // patient can mint a fresh one.
+            if let Some(token_id) = self.consent_token_ids.take((patient, provider)) {
+                self.consent_tokens.remove(token_id);
+            }
+            self.log_change(patient, CHANGE_CONSENT_REVOKED, self.env().caller(), &consent);
+            let receipt_index = self.archive_consent(patient, provider, consent);
+            self.notify(patient, NotificationKind::ConsentRevoked, provider, receipt_index);
+            self.emit(ConsentRevoked {
+                patient,
+                provider
+            });
+
+            Ok(())
+        }
+
+        // The set_redactions function sets the fields of a patient's records hidden from a provider's views,
+        // as a mask of the REDACT_* flags. Zero clears them. The patient, one of their guardians or the admin may call it.
+        #[ink(message)]
+        pub fn set_redactions(&mut self, patient: AccountId, provider: AccountId, fields: u8) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if !self.acts_for(&caller, &patient) && caller != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            if !self.health_ids.contains(patient) {
+                return Err(Error::RecordNotFound);
+            }
+            if fields & !REDACT_ALL != 0 {
+                return Err(Error::InvalidRedaction);
+            }
+
+            if fields == 0 {
+                self.redactions.remove((patient, provider));
+            } else {
+                self.redactions.insert((patient, provider), &fields);
+            }
+            self.emit(RedactionsChanged {
+                patient,
+                provider,
+                by: caller,
+                fields
+            });
+
+            Ok(())
+        }
+
+        // The redactions_of function returns the fields of a patient's records hidden from a provider.
+        #[ink(message)]
+        pub fn redactions_of(&self, patient: AccountId, provider: AccountId) -> u8 {
+            self.redactions.get((patient, provider)).unwrap_or(0)
+        }
+
+        // The set_did function anchors a patient's DID and the hash of its document, replacing any previous anchor.
+        // The DID has to start with "did:" and be shorter than MAX_DID_LEN bytes. The patient or one of their
+        // guardians may call it.
+        #[ink(message)]
+        pub fn set_did(&mut self, patient: AccountId, did: String, document_hash: Hash) -> Result<(), Error> {
+            if !self.acts_for(&self.env().caller(), &patient) {
+                return Err(Error::PermissionDenied);
+            }
+            if !self.health_ids.contains(patient) {
+                return Err(Error::RecordNotFound);
+            }
+            if !did.starts_with("did:") || did.len() >= MAX_DID_LEN {
+                return Err(Error::InvalidDid);
+            }
+
+            let anchor = DidAnchor {
+                did,
+                document_hash,
+                updated_at: self.env().block_timestamp()
+            };
+            self.did_documents.insert(patient, &anchor);
+            self.emit(DidUpdated {
+                patient,
+                document_hash,
+                did: anchor.did
+            });
+
+            Ok(())
+        }
+
+        // The did_of function returns the DID anchor of a patient. DIDs are public identifiers, so anyone can read it.
+        #[ink(message)]
+        pub fn did_of(&self, identifier: AccountId) -> Option<DidAnchor> {
+            self.did_documents.get(identifier)
+        }
+
+        // The set_critical_info function sets the blood type, organ donor status and advance directive of a patient.
+        // The patient, a guardian or any Clinician can set them.
+        #[ink(message)]
+        pub fn set_critical_info(&mut self, patient: AccountId, info: CriticalInfo) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if !self.acts_for(&caller, &patient) && !self.has_role(&caller, &[Role::Clinician]) {
+                return Err(Error::PermissionDenied);
+            }
+            if !self.health_ids.contains(patient) {
+                return Err(Error::RecordNotFound);
+            }
+            if info.advance_directive_uri.as_ref().is_some_and(|uri| uri.len() > MAX_DETAILS_LEN) {
+                return Err(Error::PayloadTooLarge);
+            }
+
+            let info = CriticalInfo {
+                updated_by: caller,
+                updated_at: self.env().block_timestamp(),
+                ..info
+            };
+            self.critical_info.insert(patient, &info);
+            self.log_change(patient, CHANGE_CRITICAL_INFO, caller, &info);
+            self.emit(CriticalInfoUpdated {
+                patient,
+                by: caller,
+                blood_type: info.blood_type,
+                organ_donor: info.organ_donor
+            });
+
+            Ok(())
+        }
+
+        // The critical_info_of function returns the critical info of a patient. Besides the patient and their guardians,
+        // any Clinician can read it without consent, so that it is at hand in an emergency.
+        #[ink(message)]
+        pub fn critical_info_of(&self, patient: AccountId) -> Result<Option<CriticalInfo>, Error> {
+            let caller = self.env().caller();
+            if !self.acts_for(&caller, &patient) && !self.has_role(&caller, &[Role::Clinician]) {
+                return Err(Error::PermissionDenied);
+            }
+            Ok(self.critical_info.get(patient))
+        }
+
+        // The my_notifications function returns a page of the caller's notifications with their indexes, oldest first.
+        // Only the last MAX_NOTIFICATIONS are kept, so a page can start later than `start`.
+        #[ink(message)]
+        pub fn my_notifications(&self, start: u32, limit: u32) -> Vec<(u32, Notification)> {
+            let caller = self.env().caller();
+            let count = self.notification_count.get(caller).unwrap_or(0);
+            let start = start.max(count.saturating_sub(MAX_NOTIFICATIONS));
+            let end = start.saturating_add(limit).min(count);
+            (start..end)
+                .filter_map(|i| self.notifications.get((caller, i)).map(|notification| (i, notification)))
+                .collect()
+        }
+
+        // The acknowledge_notifications function marks the caller's notifications up to and including `up_to_index`
+        // as read. The mark never moves back, so acknowledging an older index has no effect.
+        #[ink(message)]
+        pub fn acknowledge_notifications(&mut self, up_to_index: u32) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if up_to_index >= self.notification_count.get(caller).unwrap_or(0) {
+                return Err(Error::RecordNotFound);
+            }
+            let read = self.notifications_read.get(caller).unwrap_or(0);
+            if up_to_index >= read {
+                self.notifications_read.insert(caller, &(up_to_index + 1));
+            }
+            Ok(())
+        }
+
+        // The unread_count function returns the number of the caller's kept notifications that are not acknowledged.
+        #[ink(message)]
+        pub fn unread_count(&self) -> u32 {
+            let caller = self.env().caller();
+            let count = self.notification_count.get(caller).unwrap_or(0);
+            let read = self.notifications_read.get(caller).unwrap_or(0);
+            count - read.max(count.saturating_sub(MAX_NOTIFICATIONS))
+        }
+
+        // The appoint_guardian function empowers an account to grant and revoke consents and to set redactions
+        // on behalf of a patient. The patient may appoint guardians, as may the admin for an incapacitated patient,
+        // with a justification. Guardians cannot appoint further guardians.
+        #[ink(message)]
+        pub fn appoint_guardian(&mut self, patient: AccountId, guardian: AccountId, relationship: String, justification: Option<String>) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if caller != patient && caller != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            if caller != patient && justification.as_ref().is_none_or(|text| text.is_empty()) {
+                return Err(Error::JustificationRequired);
+            }
+            if !self.health_ids.contains(patient) {
+                return Err(Error::RecordNotFound);
+            }
+            if guardian == patient {
+                return Err(Error::NotAllowed);
+            }
+            if relationship.len() > MAX_NAME_LEN || justification.as_ref().is_some_and(|text| text.len() > MAX_DETAILS_LEN) {
+                return Err(Error::PayloadTooLarge);
+            }
+            if self.patient_guardians.contains((patient, guardian)) {
+                return Err(Error::AlreadyRegistered);
+            }
+
+            let info = GuardianInfo {
+                relationship: relationship.clone(),
+                appointed_by: caller,
+                appointed_at: self.env().block_timestamp(),
+                justification
+            };
+            self.patient_guardians.insert((patient, guardian), &info);
+            self.emit(GuardianAppointed {
+                patient,
+                guardian,
+                by: caller,
+                relationship
+            });
+
+            Ok(())
+        }
+
+        // The revoke_guardian function removes a patient's guardian. The patient or the admin may call it.
+        #[ink(message)]
+        pub fn revoke_guardian(&mut self, patient: AccountId, guardian: AccountId) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if caller != patient && caller != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            if self.patient_guardians.take((patient, guardian)).is_none() {
+                return Err(Error::RecordNotFound);
+            }
+            self.emit(GuardianRevoked {
+                patient,
+                guardian,
+                by: caller
+            });
+
+            Ok(())
+        }
+
+        // The guardian_of function returns how an account was appointed as a patient's guardian, if it is one.
+        #[ink(message)]
+        pub fn guardian_of(&self, patient: AccountId, guardian: AccountId) -> Option<GuardianInfo> {
+            self.patient_guardians.get((patient, guardian))
+        }
+
+        // The set_marketplace function registers the marketplace allowed to reassign access-consent tokens, and
+        // makes it the only account the Patient contract lets transfer them. Admin only.
+        #[ink(message)]
+        pub fn set_marketplace(&mut self, marketplace: Option<AccountId>) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            self.marketplace = marketplace;
+            let result = self.patient.call_mut().set_consent_market(marketplace).try_invoke();
+            child_result(ink::selector_bytes!("set_consent_market"), result)
+        }
+
+        // The mint_consent_token function turns the caller's consent for a provider into an access-consent token
+        // held by the provider on the Patient contract, so the access can be resold on the marketplace.
+        // A consent that has not started yet cannot be minted.
+        #[ink(message)]
+        pub fn mint_consent_token(&mut self, provider: AccountId) -> Result<HealthId, Error> {
+            let caller = self.env().caller();
+            let consent = self.consents.get((caller, provider)).ok_or(Error::ConsentNotFound)?;
+            if self.env().block_timestamp() < consent.starts_at {
+                return Err(Error::ConsentNotYetActive);
+            }
+            if self.consent_token_ids.contains((caller, provider)) {
+                return Err(Error::AlreadyRegistered);
+            }
+
+            let count = self.consent_token_count + 1;
+            let token_id = count | CONSENT_TOKEN_FLAG;
+            self.consent_token_count = count;
+            self.consent_tokens.insert(token_id, &ConsentToken { patient: caller, holder: provider });
+            self.consent_token_ids.insert((caller, provider), &token_id);
+            self.mint_patient_token(provider, token_id)?;
+
+            self.emit(ConsentTokenMinted {
+                token_id,
+                patient: caller,
+                holder: provider
+            });
+
+            Ok(token_id)
+        }
+
+        // The reassign_consent_token function moves the consent behind an access-consent token to its new holder.
+        // Only the registered marketplace may call it, after transferring the token to the buyer.
+        #[ink(message)]
+        pub fn reassign_consent_token(&mut self, token_id: HealthId, new_holder: AccountId) -> Result<(), Error> {
+            if self.marketplace != Some(self.env().caller()) {
+                return Err(Error::PermissionDenied);
+            }
+            let mut token = self.consent_tokens.get(token_id).ok_or(Error::RecordNotFound)?;
+            if self.consent_token_ids.contains((token.patient, new_holder)) {
+                return Err(Error::AlreadyRegistered);
+            }
+            let consent = self.consents.take((token.patient, token.holder)).ok_or(Error::ConsentNotFound)?;
+            let marketplace = self.env().caller();
+
+            // The move is logged as the seller's consent being revoked and the buyer's granted, so that
+            // replaying the change log gives the consents in place.
+            self.log_change(token.patient, CHANGE_CONSENT_REVOKED, marketplace, &consent);
+            if let Some(previous) = self.consents.take((token.patient, new_holder)) {
+                self.log_change(token.patient, CHANGE_CONSENT_REVOKED, marketplace, &previous);
+                self.archive_consent(token.patient, new_holder, previous);
+            }
+            self.consents.insert((token.patient, new_holder), &consent);
+            self.sync_consent_index(token.patient, token.holder);
+            self.sync_consent_index(token.patient, new_holder);
+            self.log_change(token.patient, CHANGE_CONSENT_GRANTED, marketplace, &consent);
+            self.consent_token_ids.remove((token.patient, token.holder));
+            self.consent_token_ids.insert((token.patient, new_holder), &token_id);
+
+            let from = core::mem::replace(&mut token.holder, new_holder);
+            self.consent_tokens.insert(token_id, &token);
+            self.emit(ConsentTokenReassigned {
+                token_id,
+                from,
+                to: new_holder
+            });
+
+            Ok(())
+        }
+
+        // The consent_token function returns the consent behind an access-consent token.
+        #[ink(message)]
+        pub fn consent_token(&self, token_id: HealthId) -> Option<ConsentToken> {
+            self.consent_tokens.get(token_id)
+        }
+
+        // The consent_receipt function returns the receipt of a patient's active consent for a provider.
+        #[ink(message)]
+        pub fn consent_receipt(&self, patient: AccountId, provider: AccountId) -> Option<ConsentReceipt> {
+            self.consents.get((patient, provider)).map(|consent| receipt(provider, consent, None))
+        }
+
+        // The revoked_consents_of function returns up to `limit` receipts of a patient's revoked consents starting at `start`.
+        #[ink(message)]
+        pub fn revoked_consents_of(&self, patient: AccountId, start: u32, limit: u32) -> Vec<ConsentReceipt> {
+            let count = self.revoked_consent_count.get(patient).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            (start..end)
+                .filter_map(|i| self.revoked_consents.get((patient, i)))
+                .collect()
+        }
+
+        // The set_cooldown function sets the number of blocks between two rate-limited calls. Admin only.
+        #[ink(message)]
+        pub fn set_cooldown(&mut self, blocks: BlockNumber) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            self.cooldown = blocks;
+            Ok(())
+        }
+
+        // The cooldown function returns the current rate-limit cooldown in blocks.
+        #[ink(message)]
+        pub fn cooldown(&self) -> BlockNumber {
+            self.cooldown
+        }
+
+        // The set_finalize_after function sets the time, in milliseconds after their creation, from which
+        // clinical notes can no longer be updated. Admin only.
+        #[ink(message)]
+        pub fn set_finalize_after(&mut self, millis: Timestamp) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            self.finalize_after = millis;
+            Ok(())
+        }
+
+        // The finalize_after function returns the time, in milliseconds, after which clinical notes count as finalized.
+        #[ink(message)]
+        pub fn finalize_after(&self) -> Timestamp {
+            self.finalize_after
+        }
+
+        // The set_emergency_window function sets how long, in milliseconds, an emergency access lets a clinician
+        // read a patient's record. Admin only. Accesses already granted keep their window.
+        #[ink(message)]
+        pub fn set_emergency_window(&mut self, window: Timestamp) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            self.emergency_window = window;
+            Ok(())
+        }
+
+        // The emergency_window function returns how long, in milliseconds, an emergency access lasts.
+        #[ink(message)]
+        pub fn emergency_window(&self) -> Timestamp {
+            self.emergency_window
+        }
+
+        // The set_review_period function sets how long, in milliseconds, an emergency access may stay unreviewed
+        // before the clinician is blocked from further emergency access. Admin only.
+        #[ink(message)]
+        pub fn set_review_period(&mut self, period: Timestamp) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            self.review_period = period;
+            Ok(())
+        }
+
+        // The review_period function returns how long, in milliseconds, an emergency access may stay unreviewed.
+        #[ink(message)]
+        pub fn review_period(&self) -> Timestamp {
+            self.review_period
+        }
+
+        // The set_quota function sets the number of payload bytes a single patient's record may hold. Admin only.
+        // Records already above a lowered quota keep their data, but only writes that do not grow them succeed.
+        #[ink(message)]
+        pub fn set_quota(&mut self, bytes: u32) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            self.quota = bytes;
+            Ok(())
+        }
+
+        // The quota function returns the number of payload bytes a single patient's record may hold.
+        #[ink(message)]
+        pub fn quota(&self) -> u32 {
+            self.quota
+        }
+
+        // The storage_used_of function returns the payload bytes of biodata, clinical notes and imaging reports
+        // held for a patient.
+        #[ink(message)]
+        pub fn storage_used_of(&self, identifier: AccountId) -> u32 {
+            self.storage_used.get(identifier).unwrap_or(0)
+        }
+
+        // The set_storage_deposit function sets the deposit required for new entries. Admin only.
+        // Deposits already held keep their original amount.
+        #[ink(message)]
+        pub fn set_storage_deposit(&mut self, deposit: Balance) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            self.storage_deposit = deposit;
+            Ok(())
+        }
+
+        // The storage_deposit function returns the deposit required for new entries.
+        #[ink(message)]
+        pub fn storage_deposit(&self) -> Balance {
+            self.storage_deposit
+        }
+
+        // The deposit_of function returns the total deposit currently held for an account.
+        #[ink(message)]
+        pub fn deposit_of(&self, account: AccountId) -> Balance {
+            self.deposits.get(account).unwrap_or(0)
+        }
+
+        // The pending_withdrawal_of function returns the amount an account can withdraw.
+        #[ink(message)]
+        pub fn pending_withdrawal_of(&self, account: AccountId) -> Balance {
+            self.pending_withdrawals.get(account).unwrap_or(0)
+        }
+
+        // The withdraw function transfers the caller's pending withdrawals to the caller.
+        #[ink(message)]
+        pub fn withdraw(&mut self) -> Result<Balance, Error> {
+            let caller = self.env().caller();
+            let amount = self.pending_withdrawals.get(caller).unwrap_or(0);
+            if amount == 0 {
+                return Err(Error::NothingToWithdraw);
+            }
+
+            self.pending_withdrawals.remove(caller);
+            self.env().transfer(caller, amount).map_err(|_| Error::TransferFailed)?;
+
+            Ok(amount)
+        }
+
+        // The archive_patient function archives a patient's record so it can no longer be written. Admin only,
+        // and only while no guardians are configured; otherwise the action has to go through propose_action.
+        #[ink(message)]
+        pub fn archive_patient(&mut self, identifier: AccountId) -> Result<(), Error> {
+            self.check_direct_admin()?;
+            self.execute(AdminAction::ArchivePatient(identifier))
+        }
+
+        // The erase_patient_data function deletes a patient's biodata and clinical notes. Admin only,
+        // and only while no guardians are configured; otherwise the action has to go through propose_action.
+        #[ink(message)]
+        pub fn erase_patient_data(&mut self, identifier: AccountId) -> Result<(), Error> {
+            self.check_direct_admin()?;
+            self.execute(AdminAction::ErasePatientData(identifier))
+        }
+
+        // The upgrade function replaces the code of this contract. Admin only,
+        // and only while no guardians are configured; otherwise the action has to go through propose_action.
+        #[ink(message)]
+        pub fn upgrade(&mut self, code_hash: Hash) -> Result<(), Error> {
+            self.check_direct_admin()?;
+            self.execute(AdminAction::Upgrade(code_hash))
+        }
+
+        // The on_token_burned function archives the record of a burned patient token.
+        // Only the linked Patient contract may call it.
+        #[ink(message)]
+        pub fn on_token_burned(&mut self, id: HealthId) -> Result<(), Error> {
+            if self.env().caller() != self.patient.to_account_id() {
+                return Err(Error::PermissionDenied);
+            }
+            let identifier = self.record_count.get(id).ok_or(Error::RecordNotFound)?;
+            self.archive(identifier);
+            Ok(())
+        }
+
+        // The link_registry function makes the Patient contract notify this contract when a token is burned,
+        // or stops the notifications. Admin only.
+        #[ink(message)]
+        pub fn link_registry(&mut self, enabled: bool) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            let registry = enabled.then(|| self.env().account_id());
+            let result = self.patient.call_mut().set_registry(registry).try_invoke();
+            child_result(ink::selector_bytes!("set_registry"), result)
+        }
+
+        // The is_archived function returns whether a patient's record is archived.
+        #[ink(message)]
+        pub fn is_archived(&self, identifier: AccountId) -> bool {
+            self.archived.contains(identifier)
+        }
+
+        // The guardians function returns the accounts whose approval destructive admin actions need.
+        #[ink(message)]
+        pub fn guardians(&self) -> Vec<AccountId> {
+            self.guardians.clone()
+        }
+
+        // The propose_action function lets a guardian propose a destructive admin action and returns its id.
+        // The proposal counts as approved by the proposer and is executed as soon as the threshold is met.
+        #[ink(message)]
+        pub fn propose_action(&mut self, action: AdminAction) -> Result<u32, Error> {
+            let caller = self.env().caller();
+            if !self.guardians.contains(&caller) {
+                return Err(Error::NotGuardian);
+            }
+
+            let id = self.proposal_count + 1;
+            self.proposal_count = id;
+            self.proposals.insert(id, &Proposal {
+                action,
+                proposer: caller,
+                created_at: self.env().block_number(),
+                approvals: 0,
+                executed: false
+            });
+            self.emit(ActionProposed {
+                id,
+                proposer: caller,
+                action
+            });
+
+            self.approve_action(id)?;
+            Ok(id)
+        }
+
+        // The approve_action function records a guardian's approval of a proposal and executes it once
+        // the threshold is met. Expired proposals cannot be approved.
+        #[ink(message)]
+        pub fn approve_action(&mut self, id: u32) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if !self.guardians.contains(&caller) {
+                return Err(Error::NotGuardian);
+            }
+            let mut proposal = self.proposals.get(id).ok_or(Error::ProposalNotFound)?;
+            if proposal.executed {
+                return Err(Error::ProposalExecuted);
+            }
+            if self.is_expired(&proposal) {
+                return Err(Error::ProposalExpired);
+            }
+            if self.proposal_approvals.contains((id, caller)) {
+                return Err(Error::AlreadyApproved);
+            }
+
+            self.proposal_approvals.insert((id, caller), &());
+            proposal.approvals += 1;
+            self.emit(ActionApproved {
+                id,
+                guardian: caller,
+                approvals: proposal.approvals
+            });
+
+            if proposal.approvals >= self.threshold() {
+                proposal.executed = true;
+                self.proposals.insert(id, &proposal);
+                self.execute(proposal.action)?;
+                self.emit(ActionExecuted { id });
+            } else {
+                self.proposals.insert(id, &proposal);
+            }
+
+            Ok(())
+        }
+
+        // The expire_action function removes an expired, unexecuted proposal. Anyone can call it.
+        #[ink(message)]
+        pub fn expire_action(&mut self, id: u32) -> Result<(), Error> {
+            let proposal = self.proposals.get(id).ok_or(Error::ProposalNotFound)?;
+            if proposal.executed {
+                return Err(Error::ProposalExecuted);
+            }
+            if !self.is_expired(&proposal) {
+                return Err(Error::NotAllowed);
+            }
+
+            self.proposals.remove(id);
+            for guardian in self.guardians.iter() {
+                self.proposal_approvals.remove((id, *guardian));
+            }
+            self.emit(ActionExpired { id });
+            Ok(())
+        }
+
+        // The proposal function returns an admin action proposal by id.
+        #[ink(message)]
+        pub fn proposal(&self, id: u32) -> Option<Proposal> {
+            self.proposals.get(id)
+        }
+
+        // The set_proposal_ttl function sets the number of blocks after which a proposal expires. Admin only.
+        #[ink(message)]
+        pub fn set_proposal_ttl(&mut self, blocks: BlockNumber) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            self.proposal_ttl = blocks;
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn get(&mut self) -> String {
+            self.patient.name()
+        }
 
         // The create_patient function creates a new patient record and associates it with an account id.
+        // For a patient who registered themselves, the requester claims the existing record instead of creating
+        // a second one. Any other account that is already registered fails with PatientExists.
+        // The requester has to be the caller.
+        #[ink(message)]
+        pub fn create_patient(&mut self, requester: AccountId, identifier: AccountId) -> Result<(), Error> {
+            // Check if caller has the required permissions
+            if requester != self.env().caller() || !self.can_access(&requester) {
+                return Err(Error::PermissionDenied);
+            }
+            if let Some(id) = self.health_ids.get(identifier) {
+                return self.claim_record(requester, identifier, id);
+            }
+
+            let count = self.current_id + 1;
+            self.current_id = count;
+            self.record_count.insert(count, &identifier);
+            self.health_ids.insert(identifier, &count);
+            self.stats_patients += 1;
+            self.record_activity(requester, METRIC_PATIENTS_CREATED);
+            self.log_change(identifier, CHANGE_CREATED, requester, &count);
+
+            // The patient holds the token of their own record.
+            self.mint_patient_token(identifier, count)?;
+        
+            // self.env().emit_event(NewPatient {
+            //     id: count,
+            //     identifier: Some(identifier)
+            // });
+
+            Ok(())
+        }
+
+        // The register_self function creates a record for the caller, without an institution, and mints its token
+        // to the caller. The record's origin is SelfRegistered until an institution claims it through create_patient.
+        // Registering an account twice fails with PatientExists. Rate limited.
+        #[ink(message)]
+        pub fn register_self(&mut self) -> Result<HealthId, Error> {
+            let caller = self.env().caller();
+            let id = self.self_register(caller)?;
+            self.mint_patient_token(caller, id)?;
+            Ok(id)
+        }
+
+        // The record_origin function returns who registered a patient's record, or None if it is not registered.
+        #[ink(message)]
+        pub fn record_origin(&self, identifier: AccountId) -> Option<RecordOrigin> {
+            self.health_ids.contains(identifier).then(|| self.origin_of(identifier))
+        }
+
+        // The claimed_by function returns the institution that claimed a self-registered record, if any.
+        #[ink(message)]
+        pub fn claimed_by(&self, identifier: AccountId) -> Option<AccountId> {
+            self.claimed_by.get(identifier)
+        }
+
+        // The create_patient_with_external_id function creates a patient record whose health id is derived from
+        // a salted hash of an external identifier, such as a national health number, so the same person maps
+        // to the same id across contract instances.
+        #[ink(message)]
+        pub fn create_patient_with_external_id(&mut self, identifier: AccountId, external_hash: Hash) -> Result<(), Error> {
+            if !self.can_access(&self.env().caller()) {
+                return Err(Error::PermissionDenied);
+            }
+
+            let id = self.register_external(identifier, external_hash)?;
+            self.record_activity(self.env().caller(), METRIC_PATIENTS_CREATED);
+            self.log_change(identifier, CHANGE_CREATED, self.env().caller(), &id);
+
+            // The patient holds the token of their own record.
+            self.mint_patient_token(identifier, id)?;
+
+            Ok(())
+        }
+
+        // The import_patient function moves a record of the legacy EPR contract into this one under its original
+        // health id, writing its biodata and notes through the usual checks and minting the patient's token.
+        // The record is marked as imported. A health id or account that is already registered fails with PatientExists.
+        // Admin only.
+        #[ink(message)]
+        pub fn import_patient(&mut self, legacy_health_id: HealthId, identifier: AccountId, biodata: Biodata, notes: Option<ClinicalNotes>) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            self.import_record(&(legacy_health_id, identifier, biodata, notes))?;
+            self.mint_patient_token(identifier, legacy_health_id)
+        }
+
+        // The import_batch function imports up to MAX_BATCH_LEN legacy records like import_patient and returns how many
+        // were imported. The batch is all or nothing: any failing entry reverts it. Admin only.
+        #[ink(message)]
+        pub fn import_batch(&mut self, entries: Vec<ImportEntry>) -> Result<u32, Error> {
+            self.check_batch(entries.len())?;
+            for entry in &entries {
+                self.import_record(entry)?;
+                self.mint_patient_token(entry.1, entry.0)?;
+            }
+            Ok(entries.len() as u32)
+        }
+
+        // The is_imported function returns whether a patient's record was imported from the legacy contract.
+        #[ink(message)]
+        pub fn is_imported(&self, identifier: AccountId) -> bool {
+            self.imported.contains(identifier)
+        }
+
+        // The health_id_by_external function returns the health id derived from an external identifier hash.
+        #[ink(message)]
+        pub fn health_id_by_external(&self, external_hash: Hash) -> Option<HealthId> {
+            self.external_ids.get(external_hash)
+        }
+
+        // The update_biodata function updates the biodata of a patient.
+        #[ink(message)]
+        pub fn update_biodata(&mut self, requester: AccountId, identifier: AccountId, biodata: Biodata) -> Result<(), Error> {
+            self.check_write(&requester, &identifier)?;
+            check_payload(&biodata.name, &biodata.details, &biodata.vector)?;
+
+            self.store_biodata(requester, identifier, &biodata)
+        }
+
+        // The submit_biodata_draft function stores a biodata update for review instead of writing it to the record.
+        // The caller needs the Clinician or Nurse role and write access to the record. Returns the draft index.
+        #[ink(message)]
+        pub fn submit_biodata_draft(&mut self, identifier: AccountId, biodata: Biodata) -> Result<u32, Error> {
+            let caller = self.env().caller();
+            if !self.has_role(&caller, &[Role::Clinician, Role::Nurse]) {
+                return Err(Error::PermissionDenied);
+            }
+            self.check_write(&caller, &identifier)?;
+            check_payload(&biodata.name, &biodata.details, &biodata.vector)?;
+
+            let index = self.biodata_draft_count.get(identifier).unwrap_or(0);
+            let draft = Draft {
+                biodata,
+                author: caller,
+                submitted_at: self.env().block_timestamp()
+            };
+            self.biodata_drafts.insert((identifier, index), &draft);
+            self.biodata_draft_count.insert(identifier, &(index + 1));
+
+            self.emit(DraftSubmitted {
+                identifier,
+                author: caller,
+                index
+            });
+
+            Ok(index)
+        }
+
+        // The approve_draft function writes a pending biodata draft to the record, like update_biodata would.
+        // The caller needs the Clinician role and cannot approve their own draft.
+        #[ink(message)]
+        pub fn approve_draft(&mut self, identifier: AccountId, draft_index: u32) -> Result<(), Error> {
+            let caller = self.check_reviewer(&identifier, draft_index)?;
+            let draft = self.biodata_drafts.get((identifier, draft_index)).ok_or(Error::RecordNotFound)?;
+
+            self.store_biodata(caller, identifier, &draft.biodata)?;
+            self.biodata_drafts.remove((identifier, draft_index));
+            self.emit(DraftApproved {
+                identifier,
+                by: caller,
+                index: draft_index
+            });
+
+            Ok(())
+        }
+
+        // The reject_draft function discards a pending biodata draft, recording why.
+        // The caller needs the Clinician role and cannot reject their own draft.
+        #[ink(message)]
+        pub fn reject_draft(&mut self, identifier: AccountId, draft_index: u32, reason: String) -> Result<(), Error> {
+            if reason.len() > MAX_DETAILS_LEN {
+                return Err(Error::PayloadTooLarge);
+            }
+            let caller = self.check_reviewer(&identifier, draft_index)?;
+            self.biodata_drafts.remove((identifier, draft_index));
+
+            self.emit(DraftRejected {
+                identifier,
+                by: caller,
+                index: draft_index,
+                reason
+            });
+
+            Ok(())
+        }
+
+        // The pending_drafts function returns the pending biodata drafts of a patient with their indexes,
+        // looking at draft indexes from `start` to `start + limit`. The caller needs access to patient records.
+        #[ink(message)]
+        pub fn pending_drafts(&self, identifier: AccountId, start: u32, limit: u32) -> Vec<(u32, Draft)> {
+            if !self.can_access(&self.env().caller()) {
+                return Vec::new();
+            }
+            let count = self.biodata_draft_count.get(identifier).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            (start..end)
+                .filter_map(|i| self.biodata_drafts.get((identifier, i)).map(|draft| (i, draft)))
+                .collect()
+        }
+
+        // The open_encounter function opens a new encounter for a patient and returns its index.
+        // The caller needs the Clinician role and write access to the record.
+        #[ink(message)]
+        pub fn open_encounter(&mut self, identifier: AccountId) -> Result<u32, Error> {
+            let caller = self.check_clinician(&identifier)?;
+
+            let index = self.encounter_count.get(identifier).unwrap_or(0);
+            let encounter = Encounter {
+                opened_by: caller,
+                opened_at: self.env().block_timestamp(),
+                closed_at: None
+            };
+            self.encounters.insert((identifier, index), &encounter);
+            self.encounter_count.insert(identifier, &(index + 1));
+            self.log_change(identifier, CHANGE_ENCOUNTER, caller, &encounter);
+
+            self.emit(EncounterOpened {
+                identifier,
+                by: caller,
+                index
+            });
+
+            Ok(index)
+        }
+
+        // The close_encounter function closes an open encounter of a patient.
+        // The caller needs the Clinician role and write access to the record.
+        #[ink(message)]
+        pub fn close_encounter(&mut self, identifier: AccountId, index: u32) -> Result<(), Error> {
+            let caller = self.check_clinician(&identifier)?;
+            let mut encounter = self.encounters.get((identifier, index)).ok_or(Error::RecordNotFound)?;
+            if encounter.closed_at.is_some() {
+                return Err(Error::EncounterClosed);
+            }
+
+            encounter.closed_at = Some(self.env().block_timestamp());
+            self.encounters.insert((identifier, index), &encounter);
+            self.log_change(identifier, CHANGE_ENCOUNTER, caller, &encounter);
+
+            self.emit(EncounterClosed {
+                identifier,
+                by: caller,
+                index
+            });
+
+            Ok(())
+        }
+
+        // The encounter function returns an encounter of a patient. The caller needs access to patient records.
+        #[ink(message)]
+        pub fn encounter(&self, identifier: AccountId, index: u32) -> Option<Encounter> {
+            if !self.can_access(&self.env().caller()) {
+                return None;
+            }
+            self.encounters.get((identifier, index))
+        }
+
+        // The submit_discharge_summary function stores the discharge summary of a closed encounter.
+        // The caller needs the Clinician role and write access to the record, and at least one diagnosis code is required.
+        // A summary can be resubmitted until one with finalized_at set has been stored.
+        #[ink(message)]
+        pub fn submit_discharge_summary(&mut self, identifier: AccountId, summary: DischargeSummary) -> Result<(), Error> {
+            let caller = self.check_clinician(&identifier)?;
+            let key = (identifier, summary.encounter_index);
+            let encounter = self.encounters.get(key).ok_or(Error::RecordNotFound)?;
+            if encounter.closed_at.is_none() {
+                return Err(Error::EncounterOpen);
+            }
+            check_discharge_summary(&summary)?;
+            if self.discharge_summaries.get(key).is_some_and(|stored| stored.finalized_at.is_some()) {
+                return Err(Error::SummaryFinalized);
+            }
+
+            let now = self.env().block_timestamp();
+            let summary = DischargeSummary {
+                author: caller,
+                finalized_at: summary.finalized_at.map(|_| now),
+                ..summary
+            };
+            self.discharge_summaries.insert(key, &summary);
+            self.log_change(identifier, CHANGE_ENCOUNTER, caller, &summary);
+
+            self.emit(DischargeSummarySubmitted {
+                identifier,
+                author: caller,
+                content_hash: summary.content_hash,
+                encounter_index: summary.encounter_index,
+                finalized: summary.finalized_at.is_some()
+            });
+
+            Ok(())
+        }
+
+        // The discharge_summary function returns the discharge summary of an encounter.
+        // The patient and their guardians may read it, as may anyone who can read the patient's clinical notes.
+        // The VIP rule applies, and redacting the details of a provider's view hides the medications and follow-up.
+        #[ink(message)]
+        pub fn discharge_summary(&self, identifier: AccountId, encounter_index: u32) -> Result<Option<DischargeSummary>, Error> {
+            let caller = self.env().caller();
+            let redacted = if self.acts_for(&caller, &identifier) {
+                0
+            } else {
+                self.authorize(&caller, &identifier, Action::Read(CONSENT_CLINICAL_NOTES))?;
+                if self.needs_co_authorization(&caller, &identifier) {
+                    return Err(Error::CoAuthorizationRequired);
+                }
+                self.redactions_of(identifier, caller)
+            };
+
+            let mut summary = self.discharge_summaries.get((identifier, encounter_index));
+            if let Some(summary) = summary.as_mut().filter(|_| redacted & REDACT_DETAILS != 0) {
+                summary.medications.clear();
+                summary.follow_up.clear();
+            }
+            Ok(summary)
+        }
+
+        // The add_imaging_report function adds an imaging report to a patient's record and returns its index.
+        // The caller needs the Clinician role and write access to the record. A study can only be reported once.
+        #[ink(message)]
+        pub fn add_imaging_report(&mut self, identifier: AccountId, report: ImagingReport) -> Result<u32, Error> {
+            let caller = self.check_clinician(&identifier)?;
+            if report.report_uri.is_empty() {
+                return Err(Error::EmptyPayload);
+            }
+            if report.modality.len() > MAX_NAME_LEN
+                || report.report_uri.len() > MAX_DETAILS_LEN
+                || report.impression.len() > MAX_DETAILS_LEN
+            {
+                return Err(Error::PayloadTooLarge);
+            }
+            if self.imaging_studies.contains(report.study_uid_hash) {
+                return Err(Error::DuplicateStudy);
+            }
+
+            let size = report.modality.len() + report.report_uri.len() + report.impression.len();
+            self.charge_storage(identifier, 0, size as u32)?;
+
+            let index = self.imaging_report_count.get(identifier).unwrap_or(0);
+            let report = ImagingReport {
+                reported_by: caller,
+                reported_at: self.env().block_timestamp(),
+                ..report
+            };
+            self.imaging_reports.insert((identifier, index), &report);
+            self.imaging_report_count.insert(identifier, &(index + 1));
+            self.imaging_studies.insert(report.study_uid_hash, &identifier);
+            self.log_change(identifier, CHANGE_IMAGING, caller, &report);
+
+            self.emit(ImagingReportAdded {
+                identifier,
+                reported_by: caller,
+                study_uid_hash: report.study_uid_hash,
+                index
+            });
+
+            Ok(index)
+        }
+
+        // The imaging_reports function returns a page of a patient's imaging reports, oldest first.
+        // The caller needs access to patient records or the patient's consent covering imaging.
+        #[ink(message)]
+        pub fn imaging_reports(&self, identifier: AccountId, start: u32, limit: u32) -> Vec<ImagingReport> {
+            if !self.can_read(identifier, CONSENT_IMAGING) {
+                return Vec::new();
+            }
+            let count = self.imaging_report_count.get(identifier).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            (start..end)
+                .filter_map(|i| self.imaging_reports.get((identifier, i)))
+                .collect()
+        }
+
+        // The imaging_report_count_of function returns the number of imaging reports of a patient.
+        #[ink(message)]
+        pub fn imaging_report_count_of(&self, identifier: AccountId) -> u32 {
+            self.imaging_report_count.get(identifier).unwrap_or(0)
+        }
+
+        // The record_immunization function adds an immunization with the given vaccine code to a patient's record
+        // and returns its index. The caller needs the Clinician role and write access to the record.
+        #[ink(message)]
+        pub fn record_immunization(&mut self, identifier: AccountId, vaccine_code: String) -> Result<u32, Error> {
+            let caller = self.check_clinician(&identifier)?;
+            if vaccine_code.is_empty() {
+                return Err(Error::EmptyPayload);
+            }
+            if vaccine_code.len() > MAX_NAME_LEN {
+                return Err(Error::PayloadTooLarge);
+            }
+
+            let index = self.immunization_count.get(identifier).unwrap_or(0);
+            let immunization = Immunization {
+                vaccine_code,
+                recorded_by: caller,
+                recorded_at: self.env().block_timestamp()
+            };
+            self.immunizations.insert((identifier, index), &immunization);
+            self.immunization_count.insert(identifier, &(index + 1));
+            self.log_change(identifier, CHANGE_IMMUNIZATION, caller, &immunization);
+
+            let code = &immunization.vaccine_code;
+            let count = self.stats_immunizations.get(code).unwrap_or(0);
+            self.stats_immunizations.insert(code, &(count + 1));
+            self.stats_immunization_total += 1;
+
+            self.emit(ImmunizationRecorded { identifier, recorded_by: caller, index });
+
+            Ok(index)
+        }
+
+        // The immunizations function returns a page of a patient's immunizations, oldest first.
+        // The caller needs access to patient records or the patient's consent covering clinical notes.
+        #[ink(message)]
+        pub fn immunizations(&self, identifier: AccountId, start: u32, limit: u32) -> Vec<Immunization> {
+            if !self.can_read(identifier, CONSENT_CLINICAL_NOTES) {
+                return Vec::new();
+            }
+            let count = self.immunization_count.get(identifier).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            (start..end)
+                .filter_map(|i| self.immunizations.get((identifier, i)))
+                .collect()
+        }
+
+        // The add_problem function adds an active problem, coded in ICD-10, to a patient's problem list
+        // and returns its index. The caller needs the Clinician role and write access to the record.
+        #[ink(message)]
+        pub fn add_problem(&mut self, identifier: AccountId, icd_code: String) -> Result<u32, Error> {
+            let caller = self.check_clinician(&identifier)?;
+            if icd_code.len() > MAX_NAME_LEN {
+                return Err(Error::PayloadTooLarge);
+            }
+            if icd10_chapter(&icd_code).is_none() {
+                return Err(Error::InvalidCode);
+            }
+
+            let index = self.problem_count.get(identifier).unwrap_or(0);
+            self.count_problem(identifier, &icd_code, true);
+            let problem = Problem {
+                icd_code,
+                noted_by: caller,
+                noted_at: self.env().block_timestamp(),
+                resolved_at: None
+            };
+            self.problems.insert((identifier, index), &problem);
+            self.problem_count.insert(identifier, &(index + 1));
+            self.log_change(identifier, CHANGE_PROBLEM, caller, &problem);
+
+            self.emit(ProblemAdded { identifier, noted_by: caller, index });
+
+            Ok(index)
+        }
+
+        // The resolve_problem function marks a problem of a patient's problem list as resolved.
+        // The caller needs the Clinician role and write access to the record.
+        #[ink(message)]
+        pub fn resolve_problem(&mut self, identifier: AccountId, index: u32) -> Result<(), Error> {
+            let caller = self.check_clinician(&identifier)?;
+            let mut problem = self.problems.get((identifier, index)).ok_or(Error::RecordNotFound)?;
+            if problem.resolved_at.is_some() {
+                return Err(Error::ProblemResolved);
+            }
+
+            problem.resolved_at = Some(self.env().block_timestamp());
+            self.problems.insert((identifier, index), &problem);
+            self.count_problem(identifier, &problem.icd_code, false);
+            self.log_change(identifier, CHANGE_PROBLEM, caller, &problem);
+
+            self.emit(ProblemResolved { identifier, resolved_by: caller, index });
+
+            Ok(())
+        }
+
+        // The problems function returns a page of a patient's problem list, oldest first.
+        // The caller needs access to patient records or the patient's consent covering clinical notes.
+        #[ink(message)]
+        pub fn problems(&self, identifier: AccountId, start: u32, limit: u32) -> Vec<Problem> {
+            if !self.can_read(identifier, CONSENT_CLINICAL_NOTES) {
+                return Vec::new();
+            }
+            let count = self.problem_count.get(identifier).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            (start..end)
+                .filter_map(|i| self.problems.get((identifier, i)))
+                .collect()
+        }
+
+        // The cohort_count function returns the number of research-consented patients with an active problem in
+        // an ICD-10 category, given as its three-character code such as "E11". Other prefixes match nothing.
+        // The caller needs the Researcher role; anyone else gets 0.
+        #[ink(message)]
+        pub fn cohort_count(&self, icd10_prefix: String) -> u32 {
+            if !self.can_query_cohorts(&icd10_prefix) {
+                return 0;
+            }
+            self.cohort_size.get(&icd10_prefix).unwrap_or(0)
+        }
+
+        // The cohort_health_ids function returns a page of the health ids counted by cohort_count, in no
+        // particular order. Accounts are never returned. The caller needs the Researcher role.
+        #[ink(message)]
+        pub fn cohort_health_ids(&self, icd10_prefix: String, start: u32, limit: u32) -> Vec<HealthId> {
+            if !self.can_query_cohorts(&icd10_prefix) {
+                return Vec::new();
+            }
+            let end = start.saturating_add(limit).min(self.cohort_size.get(&icd10_prefix).unwrap_or(0));
+            (start..end)
+                .filter_map(|i| self.cohort_members.get((&icd10_prefix, i)))
+                .collect()
+        }
+
+        // The add_medication function adds an active medication to a patient's medication list and returns its index.
+        // The caller needs the Clinician role and write access to the record.
+        #[ink(message)]
+        pub fn add_medication(&mut self, identifier: AccountId, name: String, dose: String) -> Result<u32, Error> {
+            let caller = self.check_clinician(&identifier)?;
+            if name.is_empty() || dose.is_empty() {
+                return Err(Error::EmptyPayload);
+            }
+            if name.len() > MAX_NAME_LEN || dose.len() > MAX_NAME_LEN {
+                return Err(Error::PayloadTooLarge);
+            }
+
+            let index = self.medication_count.get(identifier).unwrap_or(0);
+            let medication = Medication {
+                name,
+                dose,
+                active: true,
+                added_by: caller,
+                started_at: self.env().block_timestamp(),
+                stopped_at: None
+            };
+            self.medications.insert((identifier, index), &medication);
+            self.medication_count.insert(identifier, &(index + 1));
+            self.log_change(identifier, CHANGE_MEDICATION, caller, &medication);
+
+            self.emit(MedicationAdded { identifier, added_by: caller, index });
+
+            Ok(index)
+        }
+
+        // The stop_medication function marks a medication of a patient's medication list as stopped.
+        // The caller needs the Clinician role and write access to the record.
+        #[ink(message)]
+        pub fn stop_medication(&mut self, identifier: AccountId, index: u32) -> Result<(), Error> {
+            let caller = self.check_clinician(&identifier)?;
+            let mut medication = self.medications.get((identifier, index)).ok_or(Error::RecordNotFound)?;
+            if !medication.active {
+                return Err(Error::MedicationStopped);
+            }
+
+            medication.active = false;
+            medication.stopped_at = Some(self.env().block_timestamp());
+            self.medications.insert((identifier, index), &medication);
+            self.log_change(identifier, CHANGE_MEDICATION, caller, &medication);
+
+            self.emit(MedicationStopped { identifier, stopped_by: caller, index });
+
+            Ok(())
+        }
+
+        // The reconcile_medications function records that the caller reviewed a patient's medication list during
+        // one of the patient's encounters. Reconciling again during the same encounter replaces the record.
+        // The caller needs the Clinician role and write access to the record.
+        #[ink(message)]
+        pub fn reconcile_medications(&mut self, identifier: AccountId, encounter_index: u32) -> Result<(), Error> {
+            let caller = self.check_clinician(&identifier)?;
+            if !self.encounters.contains((identifier, encounter_index)) {
+                return Err(Error::RecordNotFound);
+            }
+
+            let reconciliation = MedicationReconciliation {
+                encounter_index,
+                clinician: caller,
+                reconciled_at: self.env().block_timestamp()
+            };
+            self.medication_reconciliations.insert((identifier, encounter_index), &reconciliation);
+            self.log_change(identifier, CHANGE_MEDICATION, caller, &reconciliation);
+
+            self.emit(MedicationsReconciled { identifier, clinician: caller, encounter_index });
+
+            Ok(())
+        }
+
+        // The medications_of function returns the medications among positions start..start + limit of a patient's
+        // medication list, with their index, oldest first. With active_only, stopped medications are left out.
+        // The caller needs access to patient records or the patient's consent covering clinical notes.
+        #[ink(message)]
+        pub fn medications_of(&self, identifier: AccountId, active_only: bool, start: u32, limit: u32) -> Vec<(u32, Medication)> {
+            if !self.can_read(identifier, CONSENT_CLINICAL_NOTES) {
+                return Vec::new();
+            }
+            let count = self.medication_count.get(identifier).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            (start..end)
+                .filter_map(|i| self.medications.get((identifier, i)).map(|medication| (i, medication)))
+                .filter(|(_, medication)| medication.active || !active_only)
+                .collect()
+        }
+
+        // The medication_reconciliation function returns the reconciliation of a patient's medication list
+        // during an encounter, if there was one. The caller needs access to the patient's clinical notes.
+        #[ink(message)]
+        pub fn medication_reconciliation(&self, identifier: AccountId, encounter_index: u32) -> Option<MedicationReconciliation> {
+            if !self.can_read(identifier, CONSENT_CLINICAL_NOTES) {
+                return None;
+            }
+            self.medication_reconciliations.get((identifier, encounter_index))
+        }
+
+        // The stats function returns the anonymized top-level counts. Anyone can call it.
+        #[ink(message)]
+        pub fn stats(&self) -> Stats {
+            Stats {
+                patients: self.stats_patients,
+                immunizations: self.stats_immunization_total,
+                active_problems: self.stats_problem_total
+            }
+        }
+
+        // The immunization_count function returns the number of recorded immunizations with a vaccine code.
+        // Anyone can call it.
+        #[ink(message)]
+        pub fn immunization_count(&self, code: String) -> u32 {
+            self.stats_immunizations.get(code).unwrap_or(0)
+        }
+
+        // The problem_count function returns the number of active problems in an ICD-10 chapter,
+        // given as a roman numeral such as "IX". Anyone can call it.
+        #[ink(message)]
+        pub fn problem_count(&self, chapter: String) -> u32 {
+            self.stats_problems.get(chapter).unwrap_or(0)
+        }
+
+        // The finalize_overdue function sets the finalized flag of clinical notes that are older than finalize_after,
+        // so the stored state matches what update_clinical_notes already enforces. Anyone can call it.
+        #[ink(message)]
+        pub fn finalize_overdue(&mut self, patient: AccountId) -> Result<(), Error> {
+            let mut meta = self.notes_meta.get(patient).ok_or(Error::RecordNotFound)?;
+            if meta.finalized {
+                return Err(Error::RecordFinalized);
+            }
+            if !self.is_finalized(&meta) {
+                return Err(Error::NotAllowed);
+            }
+
+            meta.finalized = true;
+            self.notes_meta.insert(patient, &meta);
+            if let Some(mut notes) = self.patient_notes.get(patient) {
+                notes.finalized = true;
+                self.patient_notes.insert(patient, &notes);
+            }
+            self.log_change(patient, CHANGE_NOTES, self.env().caller(), &meta);
+
+            self.emit(RecordFinalized { identifier: patient, created_at: meta.created_at });
+
+            Ok(())
+        }
+
+        // The update_clinical_notes function updates the clinical notes of a patient.
+        // Notes that are finalized, or older than finalize_after, can no longer be updated.
+        #[ink(message)]
+        pub fn update_clinical_notes(&mut self, requester: AccountId, identifier: AccountId, notes: ClinicalNotes) -> Result<(), Error> {
+            self.check_write(&requester, &identifier)?;
+            check_payload(&notes.name, &notes.details, &notes.vector)?;
+            if self.notes_meta.get(identifier).is_some_and(|meta| self.is_finalized(&meta)) {
+                return Err(Error::RecordFinalized);
+            }
+
+            let index = self.store_notes(requester, identifier, &notes)?;
+            let position = self.author_note_count.get(requester).unwrap_or(0);
+            self.notes_by_author.insert((requester, position), &(identifier, index));
+            self.author_note_count.insert(requester, &(position + 1));
+            self.record_activity(requester, METRIC_NOTES_WRITTEN);
+            if requester != identifier {
+                self.notify(identifier, NotificationKind::NotesUpdated, requester, index);
+            }
+
+            Ok(())
+        }
+
+        // The my_notes function returns a page of the (patient, note index) references of the notes written by
+        // `author`, oldest first; the caller's own notes when `author` is None. Only the admin can list another
+        // author's notes. Reading a referenced note still needs access to the patient's records.
+        #[ink(message)]
+        pub fn my_notes(&self, author: Option<AccountId>, start: u32, limit: u32) -> Result<Vec<NoteRef>, Error> {
+            let caller = self.env().caller();
+            let author = author.unwrap_or(caller);
+            if author != caller && caller != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            let count = self.author_note_count.get(author).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            Ok((start..end)
+                .filter_map(|i| self.notes_by_author.get((author, i)))
+                .collect())
+        }
+
+        // The get_biodata function retrieves the biodata of a patient. The requester has to be the caller and is
+        // checked like get_biodata_view does, fields redacted for them emptied.
+        #[ink(message)]
+        pub fn get_biodata(&self, requester: AccountId, identifier: AccountId) -> Option<Biodata> {
+            let redacted = self.requested_view(&requester, identifier, CONSENT_BIODATA)?;
+            let mut biodata = self.patient_biodata.get(identifier)?;
+            redact(&mut biodata.name, &mut biodata.details, &mut biodata.vector, redacted);
+            Some(biodata)
+        }
+
+        // The get_clinical_notes function retrieves the clinical notes of a patient, checked like get_biodata.
+        #[ink(message)]
+        pub fn get_clinical_notes(&self, requester: AccountId, identifier: AccountId) -> Option<ClinicalNotes> {
+            let redacted = self.requested_view(&requester, identifier, CONSENT_CLINICAL_NOTES)?;
+            let mut notes = self.patient_notes.get(identifier)?;
+            redact(&mut notes.name, &mut notes.details, &mut notes.vector, redacted);
+            Some(notes)
+        }
+
+        // The get_biodata_view function returns a patient's biodata as the caller may see it.
+        // The caller needs access to patient records or the patient's consent covering biodata, and the fields
+        // the patient redacted for the caller are emptied. Patients always see their own record in full.
+        #[ink(message)]
+        pub fn get_biodata_view(&self, identifier: AccountId) -> Option<RecordView<Biodata>> {
+            let redacted = self.view_redactions(identifier, CONSENT_BIODATA)?;
+            let mut biodata = self.patient_biodata.get(identifier)?;
+            redact(&mut biodata.name, &mut biodata.details, &mut biodata.vector, redacted);
+            Some(RecordView { record: biodata, redacted })
+        }
+
+        // The get_clinical_notes_view function returns a patient's clinical notes as the caller may see them,
+        // like get_biodata_view does for biodata.
+        #[ink(message)]
+        pub fn get_clinical_notes_view(&self, identifier: AccountId) -> Option<RecordView<ClinicalNotes>> {
+            let redacted = self.view_redactions(identifier, CONSENT_CLINICAL_NOTES)?;
+            let mut notes = self.patient_notes.get(identifier)?;
+            redact(&mut notes.name, &mut notes.details, &mut notes.vector, redacted);
+            Some(RecordView { record: notes, redacted })
+        }
+
+        // The hash_of_biodata function returns the Blake2x256 hash of a patient's SCALE-encoded biodata,
+        // as carried in the payload_hash topic of BiodataUpdate. Only readers who see the biodata in full get it.
+        #[ink(message)]
+        pub fn hash_of_biodata(&self, requester: AccountId, identifier: AccountId) -> Option<Hash> {
+            if self.requested_view(&requester, identifier, CONSENT_BIODATA)? != 0 {
+                return None;
+            }
+            self.patient_biodata.get(identifier).map(|biodata| self.hash_of(&biodata))
+        }
+
+        // The hash_of_clinical_notes function returns the Blake2x256 hash of a patient's SCALE-encoded clinical notes,
+        // as carried in the payload_hash topic of ClinicalNotesUpdate. Only readers who see the notes in full get it.
+        #[ink(message)]
+        pub fn hash_of_clinical_notes(&self, requester: AccountId, identifier: AccountId) -> Option<Hash> {
+            if self.requested_view(&requester, identifier, CONSENT_CLINICAL_NOTES)? != 0 {
+                return None;
+            }
+            self.patient_notes.get(identifier).map(|notes| self.hash_of(&notes))
+        }
+
+        // The patient_contract function returns the address of the linked Patient contract.
+        #[ink(message)]
+        pub fn patient_contract(&self) -> AccountId {
+            self.patient.to_account_id()
+        }
+
+        // The read_biodata_as_token_holder function returns the biodata of record `id` to the current holder of patient token `id`.
+        #[ink(message)]
+        pub fn read_biodata_as_token_holder(&self, id: HealthId) -> Result<Biodata, Error> {
+            let identifier = self.check_token_holder(id)?;
+            self.patient_biodata.get(identifier).ok_or(Error::CannotFetchValue)
+        }
+
+        // The read_clinical_notes_as_token_holder function returns the clinical notes of record `id` to the current holder of patient token `id`.
+        #[ink(message)]
+        pub fn read_clinical_notes_as_token_holder(&self, id: HealthId) -> Result<ClinicalNotes, Error> {
+            let identifier = self.check_token_holder(id)?;
+            self.patient_notes.get(identifier).ok_or(Error::CannotFetchValue)
+        }
+
+        // The has_biodata function tells whether a patient has biodata, reading only the metadata key.
+        #[ink(message)]
+        pub fn has_biodata(&self, requester: AccountId, identifier: AccountId) -> bool {
+            self.requested_view(&requester, identifier, CONSENT_BIODATA).is_some() && self.biodata_meta.contains(identifier)
+        }
+
+        // The has_notes function tells whether a patient has clinical notes, reading only the metadata key.
+        #[ink(message)]
+        pub fn has_notes(&self, requester: AccountId, identifier: AccountId) -> bool {
+            self.requested_view(&requester, identifier, CONSENT_CLINICAL_NOTES).is_some() && self.notes_meta.contains(identifier)
+        }
+
+        // The biodata_meta function retrieves the metadata of a patient's biodata without touching the payload.
+        #[ink(message)]
+        pub fn biodata_meta(&self, requester: AccountId, identifier: AccountId) -> Option<RecordMeta> {
+            self.requested_view(&requester, identifier, CONSENT_BIODATA)?;
+            self.biodata_meta.get(identifier)
+        }
+
+        // The notes_meta function retrieves the metadata of a patient's clinical notes without touching the payload.
+        #[ink(message)]
+        pub fn notes_meta(&self, requester: AccountId, identifier: AccountId) -> Option<RecordMeta> {
+            self.requested_view(&requester, identifier, CONSENT_CLINICAL_NOTES)?;
+            self.notes_meta.get(identifier)
+        }
+
+        // The biodata_name function returns only the name field of a patient's biodata.
+        #[ink(message)]
+        pub fn biodata_name(&self, requester: AccountId, identifier: AccountId) -> Option<String> {
+            self.get_biodata(requester, identifier).map(|biodata| biodata.name)
+        }
+
+        // The biodata_details function returns only the details field of a patient's biodata.
+        #[ink(message)]
+        pub fn biodata_details(&self, requester: AccountId, identifier: AccountId) -> Option<String> {
+            self.get_biodata(requester, identifier).map(|biodata| biodata.details)
+        }
+
+        // The biodata_payload function returns only the binary payload of a patient's biodata.
+        #[ink(message)]
+        pub fn biodata_payload(&self, requester: AccountId, identifier: AccountId) -> Option<Vec<u8>> {
+            self.get_biodata(requester, identifier).map(|biodata| biodata.vector)
+        }
+
+        // The biodata_finalized function returns whether a patient's biodata is finalized.
+        // It reads the metadata key only, so the payload is never decoded.
+        #[ink(message)]
+        pub fn biodata_finalized(&self, requester: AccountId, identifier: AccountId) -> Option<bool> {
+            self.biodata_meta(requester, identifier).map(|meta| meta.finalized)
+        }
+
+        // The notes_name function returns only the name field of a patient's clinical notes.
+        #[ink(message)]
+        pub fn notes_name(&self, requester: AccountId, identifier: AccountId) -> Option<String> {
+            self.get_clinical_notes(requester, identifier).map(|notes| notes.name)
+        }
+
+        // The notes_details function returns only the details field of a patient's clinical notes.
+        #[ink(message)]
+        pub fn notes_details(&self, requester: AccountId, identifier: AccountId) -> Option<String> {
+            self.get_clinical_notes(requester, identifier).map(|notes| notes.details)
+        }
+
+        // The notes_payload function returns only the binary payload of a patient's clinical notes.
+        #[ink(message)]
+        pub fn notes_payload(&self, requester: AccountId, identifier: AccountId) -> Option<Vec<u8>> {
+            self.get_clinical_notes(requester, identifier).map(|notes| notes.vector)
+        }
+
+        // The notes_finalized function returns whether a patient's clinical notes are finalized, by flag or by age.
+        // It reads the metadata key only, so the payload is never decoded.
+        #[ink(message)]
+        pub fn notes_finalized(&self, requester: AccountId, identifier: AccountId) -> Option<bool> {
+            self.notes_meta(requester, identifier).map(|meta| self.is_finalized(&meta))
+        }
+
+        // The export_patient function returns a patient's biodata, clinical notes, problems and consents in one bundle.
+        // The caller needs the same access as for the individual reads, to both biodata and clinical notes, and
+        // the fields redacted for them are redacted in the bundle too.
+        #[ink(message)]
+        pub fn export_patient(&self, identifier: AccountId) -> Result<PatientBundle, Error> {
+            let redacted = self.bundle_read(identifier)?;
+            let mut bundle = self.bundle_of(identifier)?;
+            if let Some(biodata) = bundle.biodata.as_mut() {
+                redact(&mut biodata.name, &mut biodata.details, &mut biodata.vector, redacted);
+            }
+            for notes in bundle.notes.iter_mut() {
+                redact(&mut notes.name, &mut notes.details, &mut notes.vector, redacted);
+            }
+            Ok(bundle)
+        }
+
+        // The create_export_grant function lets the caller's own record be exported once by `recipient`, until
+        // expires_at, whatever the caller's other consents say. Returns the id the recipient exports with.
+        #[ink(message)]
+        pub fn create_export_grant(&mut self, recipient: AccountId, expires_at: Timestamp) -> Result<ExportGrantId, Error> {
+            let patient = self.env().caller();
+            if !self.health_ids.contains(patient) {
+                return Err(Error::RecordNotFound);
+            }
+            if expires_at <= self.env().block_timestamp() {
+                return Err(Error::InvalidExpiry);
+            }
+
+            let grant_id = self.next_export_grant;
+            self.next_export_grant = grant_id + 1;
+            self.export_grants.insert(grant_id, &ExportGrant { patient, recipient, expires_at });
+            self.emit(ExportGranted { patient, recipient, grant_id, expires_at });
+            Ok(grant_id)
+        }
+
+        // The export_with_grant function returns the full bundle of the patient who created the grant and uses the
+        // grant up. Only the grant's recipient can call it. The export is kept in the patient's export log.
+        // A grant that was used or has expired fails with GrantUnavailable.
+        #[ink(message)]
+        pub fn export_with_grant(&mut self, grant_id: ExportGrantId) -> Result<PatientBundle, Error> {
+            let recipient = self.env().caller();
+            let now = self.env().block_timestamp();
+            let grant = self
+                .export_grants
+                .get(grant_id)
+                .filter(|grant| now < grant.expires_at)
+                .ok_or(Error::GrantUnavailable)?;
+            if grant.recipient != recipient {
+                return Err(Error::PermissionDenied);
+            }
+
+            let bundle = self.bundle_of(grant.patient)?;
+            self.export_grants.remove(grant_id);
+            let index = self.export_count.get(grant.patient).unwrap_or(0);
+            self.export_log.insert((grant.patient, index), &ExportRecord { grant_id, recipient, exported_at: now });
+            self.export_count.insert(grant.patient, &(index + 1));
+            self.emit(ExportPerformed { patient: grant.patient, recipient, grant_id });
+            Ok(bundle)
+        }
+
+        // The export_grant function returns an unused export grant.
+        #[ink(message)]
+        pub fn export_grant(&self, grant_id: ExportGrantId) -> Option<ExportGrant> {
+            self.export_grants.get(grant_id)
+        }
+
+        // The exports_of function returns a page of a patient's export log, oldest first.
+        // Readable by the patient, their guardians and auditors.
+        #[ink(message)]
+        pub fn exports_of(&self, patient: AccountId, start: u32, limit: u32) -> Result<Vec<ExportRecord>, Error> {
+            let caller = self.env().caller();
+            if !self.acts_for(&caller, &patient) && !self.has_role(&caller, &[Role::Auditor]) {
+                return Err(Error::PermissionDenied);
+            }
+            let count = self.export_count.get(patient).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            Ok((start..end)
+                .filter_map(|i| self.export_log.get((patient, i)))
+                .collect())
+        }
+
+        // The set_vip function flags or unflags a patient as a VIP, such as a staff member or a public figure.
+        // Outside emergency access, reads of a VIP patient's record by anyone but the patient need a second clinician's
+        // co-authorization, on top of the usual access. Admin only.
+        #[ink(message)]
+        pub fn set_vip(&mut self, patient: AccountId, vip: bool) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            if !self.health_ids.contains(patient) {
+                return Err(Error::RecordNotFound);
+            }
+            if vip {
+                self.vip.insert(patient, &());
+            } else {
+                self.vip.remove(patient);
+            }
+            self.emit(VipChanged { patient, vip });
+            Ok(())
+        }
+
+        // The is_vip function returns whether a patient is flagged as a VIP.
+        #[ink(message)]
+        pub fn is_vip(&self, patient: AccountId) -> bool {
+            self.vip.contains(patient)
+        }
+
+        // The co_authorize_read function lets `reader` read a VIP patient's record once within CO_AUTHORIZATION_WINDOW,
+        // through read_biodata or read_clinical_notes. The caller needs the Clinician role and cannot co-authorize
+        // their own reads. It replaces any co-authorization the reader already had for the patient.
+        #[ink(message)]
+        pub fn co_authorize_read(&mut self, reader: AccountId, patient: AccountId) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if !self.has_role(&caller, &[Role::Clinician]) {
+                return Err(Error::PermissionDenied);
+            }
+            if caller == reader {
+                return Err(Error::SelfApproval);
+            }
+            if !self.health_ids.contains(patient) {
+                return Err(Error::RecordNotFound);
+            }
+
+            let until = self.env().block_timestamp().saturating_add(CO_AUTHORIZATION_WINDOW);
+            self.co_authorizations.insert((patient, reader), &(caller, until));
+            self.emit(ReadCoAuthorized { patient, reader, by: caller, until });
+            Ok(())
+        }
+
+        // The read_biodata function returns a patient's biodata as the caller may see it, like get_biodata_view.
+        // A read of a VIP patient's record uses up the caller's co-authorization, fails with CoAuthorizationRequired
+        // without one, and is kept in the patient's VIP read log with both accounts.
+        #[ink(message)]
+        pub fn read_biodata(&mut self, patient: AccountId) -> Result<RecordView<Biodata>, Error> {
+            let redacted = self.checked_read(patient, CONSENT_BIODATA)?;
+            let mut biodata = self.patient_biodata.get(patient).ok_or(Error::CannotFetchValue)?;
+            redact(&mut biodata.name, &mut biodata.details, &mut biodata.vector, redacted);
+            Ok(RecordView { record: biodata, redacted })
+        }
+
+        // The read_clinical_notes function returns a patient's clinical notes as the caller may see them,
+        // like read_biodata does for biodata.
+        #[ink(message)]
+        pub fn read_clinical_notes(&mut self, patient: AccountId) -> Result<RecordView<ClinicalNotes>, Error> {
+            let redacted = self.checked_read(patient, CONSENT_CLINICAL_NOTES)?;
+            let mut notes = self.patient_notes.get(patient).ok_or(Error::CannotFetchValue)?;
+            redact(&mut notes.name, &mut notes.details, &mut notes.vector, redacted);
+            Ok(RecordView { record: notes, redacted })
+        }
+
+        // The vip_reads_of function returns a page of a VIP patient's read log, oldest first.
+        // Readable by the patient, their guardians and auditors.
+        #[ink(message)]
+        pub fn vip_reads_of(&self, patient: AccountId, start: u32, limit: u32) -> Result<Vec<VipRead>, Error> {
+            let caller = self.env().caller();
+            if !self.acts_for(&caller, &patient) && !self.has_role(&caller, &[Role::Auditor]) {
+                return Err(Error::PermissionDenied);
+            }
+            let count = self.vip_read_count.get(patient).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            Ok((start..end)
+                .filter_map(|i| self.vip_reads.get((patient, i)))
+                .collect())
+        }
+
+        // The emergency_access function lets a clinician read the whole of a patient's record for the emergency window,
+        // without the patient's consent. Reads within the window need no further justification. Every emergency access
+        // opens a review item for an auditor; a clinician with an item left unreviewed for longer than the review period
+        // cannot invoke emergency access again. Returns the id of the review item.
+        #[ink(message)]
+        pub fn emergency_access(&mut self, patient: AccountId, reason: String) -> Result<u32, Error> {
+            let caller = self.env().caller();
+            if !self.has_role(&caller, &[Role::Clinician]) {
+                return Err(Error::PermissionDenied);
+            }
+            if !self.health_ids.contains(patient) {
+                return Err(Error::RecordNotFound);
+            }
+            if reason.is_empty() {
+                return Err(Error::JustificationRequired);
+            }
+            if reason.len() > MAX_DETAILS_LEN {
+                return Err(Error::PayloadTooLarge);
+            }
+            let now = self.env().block_timestamp();
+            let mut open = self.open_reviews.get(caller).unwrap_or_default();
+            let overdue = open.iter()
+                .filter_map(|id| self.review_items.get(id))
+                .any(|item| now >= item.opened_at.saturating_add(self.review_period));
+            if overdue || open.len() >= MAX_OPEN_REVIEWS {
+                return Err(Error::PendingReview);
+            }
+
+            let review_id = self.next_review_id;
+            let item = ReviewItem {
+                clinician: caller,
+                patient,
+                reason,
+                opened_at: now,
+                reviewed_by: None,
+                justified: None
+            };
+            self.review_items.insert(review_id, &item);
+            self.next_review_id += 1;
+            open.push(review_id);
+            self.open_reviews.insert(caller, &open);
+
+            let until = now.saturating_add(self.emergency_window);
+            self.emergency_grants.insert((patient, caller), &until);
+            self.emit(EmergencyAccessGranted { patient, clinician: caller, until, review_id });
+
+            Ok(review_id)
+        }
+
+        // The emergency_access_until function returns until when a clinician may read a patient's record through
+        // emergency access, if the clinician has invoked it and the grant has not been cleared.
+        #[ink(message)]
+        pub fn emergency_access_until(&self, patient: AccountId, clinician: AccountId) -> Option<Timestamp> {
+            self.emergency_grants.get((patient, clinician))
+        }
+
+        // The expire_emergency_access function clears an emergency access whose window has passed.
+        // Anyone may call it; the access stops working at the end of the window either way.
+        #[ink(message)]
+        pub fn expire_emergency_access(&mut self, patient: AccountId, clinician: AccountId) -> Result<(), Error> {
+            let until = self.emergency_grants.get((patient, clinician)).ok_or(Error::RecordNotFound)?;
+            if self.env().block_timestamp() < until {
+                return Err(Error::NotAllowed);
+            }
+            self.emergency_grants.remove((patient, clinician));
+            self.emit(EmergencyAccessExpired { patient, clinician });
+            Ok(())
+        }
+
+        // The close_review function records an auditor's verdict on an emergency access.
+        // The caller needs the Auditor role and cannot review their own emergency access, and a review can only be
+        // closed once.
         #[ink(message)]
-        pub fn create_patient(&mut self, requester: AccountId, identifier: AccountId) -> Result<(), Error> {
-            // Check if caller has the required permissions
-            let permission = self.permissions.get(&requester).ok_or(Error::PermissionDenied)?;
-            if !permission.can_access {
+        pub fn close_review(&mut self, item_id: u32, justified: bool) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if !self.has_role(&caller, &[Role::Auditor]) {
+                return Err(Error::PermissionDenied);
+            }
+            let mut item = self.review_items.get(item_id).ok_or(Error::RecordNotFound)?;
+            if item.clinician == caller {
+                return Err(Error::SelfApproval);
+            }
+            if item.reviewed_by.is_some() {
+                return Err(Error::AlreadyReviewed);
+            }
+            item.reviewed_by = Some(caller);
+            item.justified = Some(justified);
+            self.review_items.insert(item_id, &item);
+
+            let mut open = self.open_reviews.get(item.clinician).unwrap_or_default();
+            open.retain(|id| *id != item_id);
+            self.open_reviews.insert(item.clinician, &open);
+            self.emit(ReviewClosed { review_id: item_id, auditor: caller, justified });
+
+            Ok(())
+        }
+
+        // The review_item function returns a review item. Readable by auditors, the admin and the clinician concerned.
+        #[ink(message)]
+        pub fn review_item(&self, item_id: u32) -> Option<ReviewItem> {
+            let caller = self.env().caller();
+            let item = self.review_items.get(item_id)?;
+            if caller != self.admin && caller != item.clinician && !self.has_role(&caller, &[Role::Auditor]) {
+                return None;
+            }
+            Some(item)
+        }
+
+        // The pending_reviews_of function returns the ids of a clinician's review items that are not closed yet.
+        #[ink(message)]
+        pub fn pending_reviews_of(&self, clinician: AccountId) -> Vec<u32> {
+            self.open_reviews.get(clinician).unwrap_or_default()
+        }
+
+        // The raise_flag function flags a data quality issue of one of the FLAG_* kinds on a patient's record.
+        // Raising a kind again replaces its flag. While a flag is open, the biodata cannot be finalized.
+        // The caller needs the DataSteward role.
+        #[ink(message)]
+        pub fn raise_flag(&mut self, patient: AccountId, kind: u8, note: String) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if !self.has_role(&caller, &[Role::DataSteward]) {
+                return Err(Error::PermissionDenied);
+            }
+            if !self.health_ids.contains(patient) {
+                return Err(Error::RecordNotFound);
+            }
+            if !FLAG_KINDS.contains(&kind) {
+                return Err(Error::InvalidCode);
+            }
+            if note.len() > MAX_DETAILS_LEN {
+                return Err(Error::PayloadTooLarge);
+            }
+
+            let flag = QualityFlag {
+                raised_by: caller,
+                raised_at: self.env().block_timestamp(),
+                note,
+                resolved: false
+            };
+            self.quality_flags.insert((patient, kind), &flag);
+            self.emit(QualityFlagRaised { patient, by: caller, kind });
+
+            Ok(())
+        }
+
+        // The resolve_flag function resolves the open quality flag of a kind on a patient's record.
+        // The caller needs the DataSteward role.
+        #[ink(message)]
+        pub fn resolve_flag(&mut self, patient: AccountId, kind: u8) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if !self.has_role(&caller, &[Role::DataSteward]) {
+                return Err(Error::PermissionDenied);
+            }
+            let mut flag = self.quality_flags.get((patient, kind)).ok_or(Error::RecordNotFound)?;
+            if flag.resolved {
+                return Err(Error::RecordNotFound);
+            }
+
+            flag.resolved = true;
+            self.quality_flags.insert((patient, kind), &flag);
+            self.emit(QualityFlagResolved { patient, by: caller, kind });
+
+            Ok(())
+        }
+
+        // The flags_of function returns the quality flags of a patient's record with their kind, open and resolved.
+        // The caller needs to be able to read the patient's biodata, or the DataSteward role.
+        #[ink(message)]
+        pub fn flags_of(&self, patient: AccountId) -> Vec<(u8, QualityFlag)> {
+            if !self.can_read(patient, CONSENT_BIODATA) && !self.has_role(&self.env().caller(), &[Role::DataSteward]) {
+                return Vec::new();
+            }
+            self.quality_flags_of(patient)
+        }
+
+        // Returns the quality flags of a patient's record, without any access check.
+        fn quality_flags_of(&self, patient: AccountId) -> Vec<(u8, QualityFlag)> {
+            FLAG_KINDS
+                .iter()
+                .filter_map(|kind| self.quality_flags.get((patient, *kind)).map(|flag| (*kind, flag)))
+                .collect()
+        }
+
+        // Returns true if a patient's record has a quality flag that is not resolved.
+        fn has_open_flags(&self, patient: AccountId) -> bool {
+            FLAG_KINDS
+                .iter()
+                .any(|kind| self.quality_flags.get((patient, *kind)).is_some_and(|flag| !flag.resolved))
+        }
+
+        // The change_log_of function returns up to `limit` entries of a patient's change log with their
+        // sequence numbers, starting at `from_seq`. Readable by the patient, their guardians, auditors and the admin.
+        #[ink(message)]
+        pub fn change_log_of(&self, patient: AccountId, from_seq: u64, limit: u64) -> Result<Vec<(u64, ChangeEntry)>, Error> {
+            self.check_change_log_reader(&patient)?;
+            let latest = self.change_seq.get(patient).unwrap_or(0);
+            let start = from_seq.max(1);
+            let end = start.saturating_add(limit).min(latest.saturating_add(1));
+            Ok((start..end)
+                .filter_map(|seq| self.change_log.get((patient, seq)).map(|entry| (seq, entry)))
+                .collect())
+        }
+
+        // The latest_seq function returns the sequence number of the latest entry of a patient's change log,
+        // zero if nothing was logged yet. Readable by the patient, their guardians, auditors and the admin.
+        #[ink(message)]
+        pub fn latest_seq(&self, patient: AccountId) -> Result<u64, Error> {
+            self.check_change_log_reader(&patient)?;
+            Ok(self.change_seq.get(patient).unwrap_or(0))
+        }
+
+        // The subscribe function makes the caller follow a patient's change log from its latest entry on.
+        // The caller needs to be able to read some part of the patient's record. Subscribing again keeps the cursor.
+        #[ink(message)]
+        pub fn subscribe(&mut self, patient: AccountId) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if !self.health_ids.contains(patient) {
+                return Err(Error::RecordNotFound);
+            }
+            if !self.can_read(patient, CONSENT_ALL) {
+                return Err(Error::PermissionDenied);
+            }
+            if self.subscription_index.contains((caller, patient)) {
+                return Ok(());
+            }
+
+            let index = self.subscription_count.get(caller).unwrap_or(0);
+            let subscription = Subscription {
+                patient,
+                cursor: self.change_seq.get(patient).unwrap_or(0),
+                subscribed_at: self.env().block_timestamp()
+            };
+            self.subscriptions.insert((caller, index), &subscription);
+            self.subscription_index.insert((caller, patient), &index);
+            self.subscription_count.insert(caller, &(index + 1));
+            self.emit(SubscriptionChanged { subscriber: caller, patient, subscribed: true });
+
+            Ok(())
+        }
+
+        // The unsubscribe function stops the caller from following a patient's change log.
+        #[ink(message)]
+        pub fn unsubscribe(&mut self, patient: AccountId) -> Result<(), Error> {
+            let caller = self.env().caller();
+            let index = self.subscription_index.take((caller, patient)).ok_or(Error::SubscriptionNotFound)?;
+
+            // The last subscription takes the freed index, so the list stays dense.
+            let last = self.subscription_count.get(caller).unwrap_or(1) - 1;
+            if let Some(moved) = self.subscriptions.take((caller, last)) {
+                if index != last {
+                    self.subscription_index.insert((caller, moved.patient), &index);
+                    self.subscriptions.insert((caller, index), &moved);
+                }
+            }
+            self.subscription_count.insert(caller, &last);
+            self.emit(SubscriptionChanged { subscriber: caller, patient, subscribed: false });
+
+            Ok(())
+        }
+
+        // The advance_cursor function records that the caller processed a patient's change log up to `seq`.
+        #[ink(message)]
+        pub fn advance_cursor(&mut self, patient: AccountId, seq: u64) -> Result<(), Error> {
+            let caller = self.env().caller();
+            let index = self.subscription_index.get((caller, patient)).ok_or(Error::SubscriptionNotFound)?;
+            let mut subscription = self.subscriptions.get((caller, index)).ok_or(Error::SubscriptionNotFound)?;
+            if seq < subscription.cursor || seq > self.change_seq.get(patient).unwrap_or(0) {
+                return Err(Error::NotAllowed);
+            }
+            subscription.cursor = seq;
+            self.subscriptions.insert((caller, index), &subscription);
+            Ok(())
+        }
+
+        // The changes_since function returns up to `limit` entries of a patient's change log that come after `seq`.
+        // The caller needs a subscription to the patient and, at the time of the call, to still be able to read
+        // some part of the patient's record, so a revoked consent ends the feed.
+        #[ink(message)]
+        pub fn changes_since(&self, patient: AccountId, seq: u64, limit: u64) -> Result<Vec<ChangeEntry>, Error> {
+            let caller = self.env().caller();
+            if !self.subscription_index.contains((caller, patient)) {
+                return Err(Error::SubscriptionNotFound);
+            }
+            if !self.can_read(patient, CONSENT_ALL) {
+                return Err(Error::PermissionDenied);
+            }
+            let latest = self.change_seq.get(patient).unwrap_or(0);
+            let start = seq.saturating_add(1);
+            let end = start.saturating_add(limit).min(latest.saturating_add(1));
+            Ok((start..end)
+                .filter_map(|seq| self.change_log.get((patient, seq)))
+                .collect())
+        }
+
+        // The my_subscriptions function returns a page of the caller's subscriptions with their cursors.
+        #[ink(message)]
+        pub fn my_subscriptions(&self, start: u32, limit: u32) -> Vec<Subscription> {
+            let caller = self.env().caller();
+            let count = self.subscription_count.get(caller).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            (start..end)
+                .filter_map(|i| self.subscriptions.get((caller, i)))
+                .collect()
+        }
+
+        // Checks that the caller may read a patient's change log.
+        fn check_change_log_reader(&self, patient: &AccountId) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if caller != self.admin && !self.acts_for(&caller, patient) && !self.has_role(&caller, &[Role::Auditor]) {
+                return Err(Error::PermissionDenied);
+            }
+            Ok(())
+        }
+
+        // Appends an entry to a patient's change log under the next sequence number.
+        fn log_change<T: scale::Encode>(&mut self, patient: AccountId, kind: u8, actor: AccountId, value: &T) {
+            let seq = self.change_seq.get(patient).unwrap_or(0) + 1;
+            let entry = ChangeEntry {
+                kind,
+                actor,
+                ref_hash: self.hash_of(value),
+                block: self.env().block_number(),
+                timestamp: self.env().block_timestamp()
+            };
+            self.change_log.insert((patient, seq), &entry);
+            self.change_seq.insert(patient, &seq);
+        }
+
+        // Records a patient's own registration, without the cross-contract mint.
+        fn self_register(&mut self, identifier: AccountId) -> Result<HealthId, Error> {
+            if self.health_ids.contains(identifier) {
+                return Err(Error::PatientExists);
+            }
+            self.rate_limit(identifier, CALL_REGISTER_SELF)?;
+
+            let id = self.current_id + 1;
+            self.current_id = id;
+            self.record_count.insert(id, &identifier);
+            self.health_ids.insert(identifier, &id);
+            self.stats_patients += 1;
+            self.record_origins.insert(identifier, &RecordOrigin::SelfRegistered);
+            self.log_change(identifier, CHANGE_CREATED, identifier, &id);
+            self.emit(SelfRegistered { id, identifier });
+            Ok(id)
+        }
+
+        // Links an institution to a self-registered record it was asked to create. Each record is claimed once;
+        // records registered any other way already have their institution.
+        fn claim_record(&mut self, requester: AccountId, identifier: AccountId, id: HealthId) -> Result<(), Error> {
+            if self.origin_of(identifier) != RecordOrigin::SelfRegistered || self.claimed_by.contains(identifier) {
+                return Err(Error::PatientExists);
+            }
+            self.claimed_by.insert(identifier, &requester);
+            self.log_change(identifier, CHANGE_CLAIMED, requester, &id);
+            self.emit(PatientClaimed { id, identifier, by: requester });
+            Ok(())
+        }
+
+        // Returns who registered a patient's record.
+        fn origin_of(&self, identifier: AccountId) -> RecordOrigin {
+            self.record_origins.get(identifier).unwrap_or(RecordOrigin::InstitutionRegistered)
+        }
+
+        // Checks that the caller may read both the biodata and the clinical notes of a patient, as a bundle
+        // of the record needs, and returns the fields redacted for them.
+        fn bundle_read(&self, identifier: AccountId) -> Result<u8, Error> {
+            let caller = self.env().caller();
+            for scope in [CONSENT_BIODATA, CONSENT_CLINICAL_NOTES] {
+                self.authorize(&caller, &identifier, Action::Read(scope))?;
+            }
+            if self.needs_co_authorization(&caller, &identifier) {
+                return Err(Error::CoAuthorizationRequired);
+            }
+            Ok(if caller == identifier { 0 } else { self.redactions_of(identifier, caller) })
+        }
+
+        // Builds the export bundle of a patient, without any access check.
+        fn bundle_of(&self, identifier: AccountId) -> Result<PatientBundle, Error> {
+            let health_id = self.health_ids.get(identifier).ok_or(Error::RecordNotFound)?;
+
+            let notes: Vec<ClinicalNotes> = self.patient_notes.get(identifier).into_iter().collect();
+            let note_count = notes.len() as u32;
+            let notes_truncated = notes.len() > MAX_BUNDLE_NOTES;
+            let problem_count = self.problem_count.get(identifier).unwrap_or(0);
+            let consent_count = self.consent_provider_count.get(identifier).unwrap_or(0);
+
+            Ok(PatientBundle {
+                health_id,
+                biodata: self.patient_biodata.get(identifier),
+                note_count,
+                notes: notes.into_iter().take(MAX_BUNDLE_NOTES).collect(),
+                notes_truncated,
+                problem_count,
+                problems: (0..problem_count.min(MAX_BUNDLE_PROBLEMS))
+                    .filter_map(|i| self.problems.get((identifier, i)))
+                    .collect(),
+                consent_count,
+                consents: (0..consent_count.min(MAX_BUNDLE_CONSENTS))
+                    .filter_map(|i| self.consent_providers.get((identifier, i)))
+                    .filter_map(|provider| self.consents.get((identifier, provider)).map(|consent| receipt(provider, consent, None)))
+                    .collect(),
+                research_consent: self.research_consent_of(identifier),
+                quality_flags: self.quality_flags_of(identifier),
+                imported: self.imported.contains(identifier),
+                origin: self.origin_of(identifier)
+            })
+        }
+
+        // The bundle_hash function returns the Blake2x256 hash of the SCALE-encoded export bundle, for attestation.
+        #[ink(message)]
+        pub fn bundle_hash(&self, identifier: AccountId) -> Result<[u8; 32], Error> {
+            let bundle = self.export_patient(identifier)?;
+            Ok(self.env().hash_encoded::<Blake2x256, _>(&bundle))
+        }
+
+        // The snapshot_patient function hashes the patient's current snapshot bundle and appends it, with the
+        // current block number, to the patient's snapshot log. It returns the hash.
+        // The caller needs the same access as for export_patient.
+        #[ink(message)]
+        pub fn snapshot_patient(&mut self, identifier: AccountId) -> Result<Hash, Error> {
+            let taker = self.env().caller();
+            let bundle = self.snapshot_bundle(identifier)?;
+            let hash = self.hash_of(&bundle);
+
+            let index = self.snapshot_count.get(identifier).unwrap_or(0);
+            let block_number = self.env().block_number();
+            self.snapshots.insert((identifier, index), &Snapshot { hash, block_number, taker });
+            self.snapshot_count.insert(identifier, &(index + 1));
+
+            self.emit(SnapshotTaken {
+                identifier,
+                taker,
+                hash,
+                index,
+                block_number
+            });
+
+            Ok(hash)
+        }
+
+        // The snapshot_bundle function returns the bundle a snapshot of the patient would hash right now,
+        // so it can be kept off-chain and checked later with verify_snapshot. The fields redacted for the caller
+        // are redacted before hashing.
+        #[ink(message)]
+        pub fn snapshot_bundle(&self, identifier: AccountId) -> Result<SnapshotBundle, Error> {
+            let redacted = self.bundle_read(identifier)?;
+            let health_id = self.health_ids.get(identifier).ok_or(Error::RecordNotFound)?;
+
+            let mut biodata = self.patient_biodata.get(identifier);
+            if let Some(biodata) = biodata.as_mut() {
+                redact(&mut biodata.name, &mut biodata.details, &mut biodata.vector, redacted);
+            }
+            let notes_hash = self.patient_notes.get(identifier).map(|mut notes| {
+                redact(&mut notes.name, &mut notes.details, &mut notes.vector, redacted);
+                self.hash_of(&notes)
+            });
+
+            Ok(SnapshotBundle {
+                health_id,
+                biodata,
+                notes_hash
+            })
+        }
+
+        // The snapshots_of function returns a page of a patient's snapshot log, oldest first.
+        // The caller needs the same access as for snapshot_bundle.
+        #[ink(message)]
+        pub fn snapshots_of(&self, identifier: AccountId, start: u32, limit: u32) -> Result<Vec<Snapshot>, Error> {
+            self.bundle_read(identifier)?;
+            let count = self.snapshot_count.get(identifier).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            Ok((start..end)
+                .filter_map(|i| self.snapshots.get((identifier, i)))
+                .collect())
+        }
+
+        // The verify_snapshot function tells whether the given SCALE-encoded snapshot bundle is the one
+        // recorded at the given index of the patient's snapshot log.
+        #[ink(message)]
+        pub fn verify_snapshot(&self, identifier: AccountId, index: u32, bundle_bytes: Vec<u8>) -> bool {
+            let mut hash = [0u8; 32];
+            ink::env::hash_bytes::<Blake2x256>(&bundle_bytes, &mut hash);
+            self.snapshots
+                .get((identifier, index))
+                .is_some_and(|snapshot| snapshot.hash == Hash::from(hash))
+        }
+
+        // Derives the health id of an external identifier hash and records the patient under it.
+        // Rejects a hash, derived id or account that is already registered.
+        fn register_external(&mut self, identifier: AccountId, external_hash: Hash) -> Result<HealthId, Error> {
+            let bytes: &[u8] = external_hash.as_ref();
+            let id = HealthId::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) | EXTERNAL_ID_FLAG;
+
+            if self.external_ids.contains(external_hash)
+                || self.record_count.contains(id)
+                || self.health_ids.contains(identifier)
+            {
+                return Err(Error::PatientExists);
+            }
+
+            self.external_ids.insert(external_hash, &id);
+            self.record_count.insert(id, &identifier);
+            self.health_ids.insert(identifier, &id);
+            self.stats_patients += 1;
+            Ok(id)
+        }
+
+        // Returns true if the account is the patient or one of the patient's guardians.
+        fn acts_for(&self, account: &AccountId, patient: &AccountId) -> bool {
+            self.authorize(account, patient, Action::ActFor).is_ok()
+        }
+
+        // Checks that an account may perform an action on a patient's record and returns through what.
+        // Every access check of the contract goes through here, and so does access_summary.
+        fn authorize(&self, subject: &AccountId, patient: &AccountId, action: Action) -> Result<AccessPath, Error> {
+            match action {
+                Action::Read(scope) => {
+                    if subject == patient {
+                        return Ok(AccessPath::Patient);
+                    }
+                    let now = self.env().block_timestamp();
+                    let consent = self.consents.get((*patient, *subject)).filter(|consent| consent.scope & scope != 0);
+                    if consent.as_ref().is_some_and(|consent| now >= consent.starts_at) {
+                        return Ok(AccessPath::Consent);
+                    }
+                    if self.onboarding_consents.get((*patient, *subject)).is_some_and(|until| now < until) {
+                        return Ok(AccessPath::Onboarding);
+                    }
+                    if self.emergency_grants.get((*patient, *subject)).is_some_and(|until| now < until) {
+                        return Ok(AccessPath::Emergency);
+                    }
+                    if let Some(path) = self.access_path(subject) {
+                        return Ok(path);
+                    }
+                    if let Some(org) = self.shared_by_agreement(subject, patient, scope) {
+                        return Ok(AccessPath::Agreement(org));
+                    }
+                    if consent.is_some() {
+                        return Err(Error::ConsentNotYetActive);
+                    }
+                    Err(Error::PermissionDenied)
+                }
+                Action::Write => {
+                    let path = self.access_path(subject).ok_or(Error::PermissionDenied)?;
+                    if !self.health_ids.contains(patient) {
+                        return Err(Error::RecordNotFound);
+                    }
+                    if self.archived.contains(patient) {
+                        return Err(Error::RecordArchived);
+                    }
+                    Ok(path)
+                }
+                Action::WriteClinical => {
+                    if !self.has_role(subject, &[Role::Clinician]) {
+                        return Err(Error::PermissionDenied);
+                    }
+                    self.authorize(subject, patient, Action::Write)
+                }
+                Action::ActFor => {
+                    if subject == patient {
+                        return Ok(AccessPath::Patient);
+                    }
+                    if self.patient_guardians.contains((*patient, *subject)) {
+                        return Ok(AccessPath::Guardian);
+                    }
+                    Err(Error::PermissionDenied)
+                }
+            }
+        }
+
+        // Returns the fields to redact from the caller's view of a part of a patient's record,
+        // or None if the caller may not read it at all.
+        fn view_redactions(&self, identifier: AccountId, scope: u8) -> Option<u8> {
+            let caller = self.env().caller();
+            if caller == identifier {
+                return Some(0);
+            }
+            if !self.can_read(identifier, scope) {
+                return None;
+            }
+            Some(self.redactions_of(identifier, caller))
+        }
+
+        // Like view_redactions, for the messages that take the reader as an argument: it has to be the caller.
+        fn requested_view(&self, requester: &AccountId, identifier: AccountId, scope: u8) -> Option<u8> {
+            if *requester != self.env().caller() {
+                return None;
+            }
+            self.view_redactions(identifier, scope)
+        }
+
+        // Returns true if the caller may read a part of a patient's record: the patient, an account with access
+        // to patient records, or a provider whose consent from the patient covers the given scope.
+        // Reads of a VIP patient's record that need a co-authorization go through read_biodata and read_clinical_notes.
+        fn can_read(&self, identifier: AccountId, scope: u8) -> bool {
+            let caller = self.env().caller();
+            self.authorize(&caller, &identifier, Action::Read(scope)).is_ok() && !self.needs_co_authorization(&caller, &identifier)
+        }
+
+        // Returns true if a read of a patient's record by the subject needs a second clinician's co-authorization:
+        // the patient is a VIP, and the subject is neither the patient nor a clinician with emergency access.
+        fn needs_co_authorization(&self, subject: &AccountId, patient: &AccountId) -> bool {
+            let now = self.env().block_timestamp();
+            self.vip.contains(patient)
+                && subject != patient
+                && self.emergency_grants.get((*patient, *subject)).is_none_or(|until| now >= until)
+        }
+
+        // Checks that the caller may read a part of a patient's record and returns the fields redacted for them.
+        // A read that needs a co-authorization uses it up and is added to the patient's VIP read log.
+        fn checked_read(&mut self, patient: AccountId, scope: u8) -> Result<u8, Error> {
+            let reader = self.env().caller();
+            self.authorize(&reader, &patient, Action::Read(scope))?;
+            if reader == patient {
+                return Ok(0);
+            }
+
+            if self.needs_co_authorization(&reader, &patient) {
+                let now = self.env().block_timestamp();
+                let (co_authorized_by, _) = self
+                    .co_authorizations
+                    .take((patient, reader))
+                    .filter(|(_, until)| now < *until)
+                    .ok_or(Error::CoAuthorizationRequired)?;
+                let index = self.vip_read_count.get(patient).unwrap_or(0);
+                self.vip_reads.insert((patient, index), &VipRead { reader, co_authorized_by, scope, read_at: now });
+                self.vip_read_count.insert(patient, &(index + 1));
+                self.emit(VipRecordRead { patient, reader, co_authorized_by, scope });
+            }
+            Ok(self.redactions_of(patient, reader))
+        }
+
+        // Appends the receipt of a consent that is no longer active to the patient's history and returns its index.
+        fn archive_consent(&mut self, patient: AccountId, provider: AccountId, consent: Consent) -> u32 {
+            let count = self.revoked_consent_count.get(patient).unwrap_or(0);
+            let revoked = receipt(provider, consent, Some(self.env().block_timestamp()));
+            self.revoked_consents.insert((patient, count), &revoked);
+            self.revoked_consent_count.insert(patient, &(count + 1));
+            count
+        }
+
+        // Replaces `released` payload bytes of a patient's record with `added` ones. A write that grows the record
+        // beyond the quota fails with QuotaExceeded.
+        fn charge_storage(&mut self, identifier: AccountId, released: u32, added: u32) -> Result<(), Error> {
+            let used = self.storage_used_of(identifier);
+            let next = used.saturating_sub(released).saturating_add(added);
+            if next > used && next > self.quota {
+                return Err(Error::QuotaExceeded);
+            }
+            if next == 0 {
+                self.storage_used.remove(identifier);
+            } else {
+                self.storage_used.insert(identifier, &next);
+            }
+            Ok(())
+        }
+
+        // Appends a notification to a patient's inbox, pruning the oldest one beyond MAX_NOTIFICATIONS.
+        fn notify(&mut self, patient: AccountId, kind: NotificationKind, actor: AccountId, ref_index: u32) {
+            let index = self.notification_count.get(patient).unwrap_or(0);
+            let notification = Notification {
+                kind,
+                actor,
+                timestamp: self.env().block_timestamp(),
+                ref_index
+            };
+            self.notifications.insert((patient, index), &notification);
+            self.notification_count.insert(patient, &(index + 1));
+            if index >= MAX_NOTIFICATIONS {
+                self.notifications.remove((patient, index - MAX_NOTIFICATIONS));
+            }
+        }
+
+        // Checks that the caller is the admin and that no guardians have to approve destructive actions.
+        fn check_direct_admin(&self) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            if !self.guardians.is_empty() {
+                return Err(Error::GuardiansRequired);
+            }
+            Ok(())
+        }
+
+        // Returns the number of guardian approvals a proposal needs.
+        fn threshold(&self) -> u32 {
+            GUARDIAN_THRESHOLD.min(self.guardians.len() as u32)
+        }
+
+        // Returns true if the proposal is older than the proposal time-to-live.
+        fn is_expired(&self, proposal: &Proposal) -> bool {
+            self.env().block_number() > proposal.created_at.saturating_add(self.proposal_ttl)
+        }
+
+        // Archives a patient's record. A record archived twice is counted and logged once.
+        fn archive(&mut self, identifier: AccountId) {
+            if !self.archived.contains(identifier) {
+                self.archived.insert(identifier, &());
+                self.stats_patients = self.stats_patients.saturating_sub(1);
+                let health_id = self.health_ids.get(identifier);
+                self.log_change(identifier, CHANGE_ARCHIVED, self.env().caller(), &health_id);
+            }
+        }
+
+        // Deletes a patient's immunizations and takes them out of the aggregate counters.
+        fn erase_immunizations(&mut self, identifier: AccountId) {
+            let count = self.immunization_count.take(identifier).unwrap_or(0);
+            for index in 0..count {
+                if let Some(immunization) = self.immunizations.take((identifier, index)) {
+                    let code = immunization.vaccine_code;
+                    let remaining = self.stats_immunizations.get(&code).unwrap_or(0).saturating_sub(1);
+                    self.stats_immunizations.insert(&code, &remaining);
+                    self.stats_immunization_total = self.stats_immunization_total.saturating_sub(1);
+                }
+            }
+        }
+
+        // Deletes a patient's problem list and takes its active problems out of the aggregate counters.
+        fn erase_problems(&mut self, identifier: AccountId) {
+            let count = self.problem_count.take(identifier).unwrap_or(0);
+            for index in 0..count {
+                if let Some(problem) = self.problems.take((identifier, index)) {
+                    if problem.resolved_at.is_none() {
+                        self.count_problem(identifier, &problem.icd_code, false);
+                    }
+                }
+            }
+        }
+
+        // Deletes a patient's medication list and the reconciliations of it.
+        fn erase_medications(&mut self, identifier: AccountId) {
+            let count = self.medication_count.take(identifier).unwrap_or(0);
+            for index in 0..count {
+                self.medications.remove((identifier, index));
+            }
+            for encounter_index in 0..self.encounter_count.get(identifier).unwrap_or(0) {
+                self.medication_reconciliations.remove((identifier, encounter_index));
+            }
+        }
+
+        // Adds an active problem to, or takes one out of, the counter of its ICD-10 chapter.
+        fn count_problem(&mut self, identifier: AccountId, icd_code: &str, active: bool) {
+            let Some(chapter) = icd10_chapter(icd_code) else { return };
+            let key = (String::from(&icd_code[..3]), identifier);
+            let problems = self.cohort_problems.get(&key).unwrap_or(0);
+            let problems = if active { problems + 1 } else { problems.saturating_sub(1) };
+            if problems == 0 {
+                self.cohort_problems.remove(&key);
+            } else {
+                self.cohort_problems.insert(&key, &problems);
+            }
+            self.sync_cohort(&key.0, identifier);
+
+            let chapter = String::from(chapter);
+            let count = self.stats_problems.get(&chapter).unwrap_or(0);
+            if active {
+                self.stats_problems.insert(&chapter, &(count + 1));
+                self.stats_problem_total += 1;
+            } else {
+                self.stats_problems.insert(&chapter, &count.saturating_sub(1));
+                self.stats_problem_total = self.stats_problem_total.saturating_sub(1);
+            }
+        }
+
+        // Adds a patient to or removes them from the cohort of an ICD-10 category, so that it holds exactly the
+        // research-consented patients with an active problem in it. The last member takes the place of a removed one.
+        fn sync_cohort(&mut self, category: &String, identifier: AccountId) {
+            let belongs = self.research_consent_of(identifier) && self.cohort_problems.contains((category, identifier));
+            let index = self.cohort_index.get((category, identifier));
+            let size = self.cohort_size.get(category).unwrap_or(0);
+            match (belongs, index) {
+                (true, None) => {
+                    let Some(health_id) = self.health_ids.get(identifier) else { return };
+                    self.cohort_members.insert((category, size), &health_id);
+                    self.cohort_index.insert((category, identifier), &size);
+                    self.cohort_size.insert(category, &(size + 1));
+                }
+                (false, Some(index)) => {
+                    let last = size - 1;
+                    if index != last {
+                        if let Some(moved) = self.cohort_members.get((category, last)) {
+                            self.cohort_members.insert((category, index), &moved);
+                            if let Some(account) = self.record_count.get(moved) {
+                                self.cohort_index.insert((category, account), &index);
+                            }
+                        }
+                    }
+                    self.cohort_members.remove((category, last));
+                    self.cohort_index.remove((category, identifier));
+                    self.cohort_size.insert(category, &last);
+                }
+                _ => {}
+            }
+        }
+
+        // Brings a patient's consent_providers in line with whether the provider holds an active consent.
+        fn sync_consent_index(&mut self, patient: AccountId, provider: AccountId) {
+            let active = self.consents.contains((patient, provider));
+            let index = self.consent_provider_index.get((patient, provider));
+            let count = self.consent_provider_count.get(patient).unwrap_or(0);
+            match (active, index) {
+                (true, None) => {
+                    self.consent_providers.insert((patient, count), &provider);
+                    self.consent_provider_index.insert((patient, provider), &count);
+                    self.consent_provider_count.insert(patient, &(count + 1));
+                }
+                (false, Some(index)) => {
+                    let last = count - 1;
+                    if index != last {
+                        if let Some(moved) = self.consent_providers.get((patient, last)) {
+                            self.consent_providers.insert((patient, index), &moved);
+                            self.consent_provider_index.insert((patient, moved), &index);
+                        }
+                    }
+                    self.consent_providers.remove((patient, last));
+                    self.consent_provider_index.remove((patient, provider));
+                    self.consent_provider_count.insert(patient, &last);
+                }
+                _ => {}
+            }
+        }
+
+        // Brings every cohort of a patient's active problems in line with their research consent.
+        fn sync_cohorts(&mut self, identifier: AccountId) {
+            for index in 0..self.problem_count.get(identifier).unwrap_or(0) {
+                if let Some(problem) = self.problems.get((identifier, index)).filter(|problem| problem.resolved_at.is_none()) {
+                    if let Some(category) = problem.icd_code.get(..3) {
+                        self.sync_cohort(&String::from(category), identifier);
+                    }
+                }
+            }
+        }
+
+        // Performs a destructive admin action.
+        fn execute(&mut self, action: AdminAction) -> Result<(), Error> {
+            match action {
+                AdminAction::ArchivePatient(identifier) => {
+                    if !self.health_ids.contains(identifier) {
+                        return Err(Error::RecordNotFound);
+                    }
+                    self.archive(identifier);
+                }
+                AdminAction::ErasePatientData(identifier) => {
+                    if !self.health_ids.contains(identifier) {
+                        return Err(Error::RecordNotFound);
+                    }
+                    let released = [self.biodata_meta.take(identifier), self.notes_meta.take(identifier)]
+                        .iter()
+                        .flatten()
+                        .map(payload_size)
+                        .sum();
+                    self.charge_storage(identifier, released, 0)?;
+                    self.patient_biodata.remove(identifier);
+                    self.patient_notes.remove(identifier);
+                    self.critical_info.remove(identifier);
+                    self.erase_immunizations(identifier);
+                    self.erase_problems(identifier);
+                    self.erase_medications(identifier);
+                    let health_id = self.health_ids.get(identifier);
+                    self.log_change(identifier, CHANGE_ERASED, self.env().caller(), &health_id);
+                }
+                AdminAction::Upgrade(code_hash) => {
+                    self.env().set_code_hash(&code_hash).map_err(|_| Error::NotAllowed)?;
+                }
+            }
+            Ok(())
+        }
+
+        // Returns the organization whose active sharing agreement lets the reader's organization read, within scope,
+        // a patient whose care team includes one of its members. Care team members who are also the patient's guardians
+        // do not count, as a guardian's organization must not gain access through the guardian.
+        fn shared_by_agreement(&self, reader: &AccountId, patient: &AccountId, scope: u8) -> Option<AccountId> {
+            let reader_org = self.org_members.get(reader)?;
+            let now = self.env().block_timestamp();
+            self.care_teams.get(patient).unwrap_or_default().iter().find_map(|member| {
+                if self.patient_guardians.contains((*patient, *member)) {
+                    return None;
+                }
+                self.org_members.get(member).filter(|org| {
+                    *org != reader_org
+                        && self.agreements.get(agreement_key(*org, reader_org)).is_some_and(|agreement| {
+                            agreement.approved_by_a
+                                && agreement.approved_by_b
+                                && now < agreement.expires_at
+                                && agreement.scope & scope != 0
+                        })
+                })
+            })
+        }
+
+        // Checks that the caller is a verified provider, which acts as the admin of its organization, and returns it.
+        fn check_organization(&self) -> Result<AccountId, Error> {
+            let caller = self.env().caller();
+            if !self.providers.get(caller).is_some_and(|provider| provider.verified) {
+                return Err(Error::ProviderNotVerified);
+            }
+            Ok(caller)
+        }
+
+        // Returns true if the requester has been granted access, directly or through one of its groups.
+        fn can_access(&self, requester: &AccountId) -> bool {
+            self.access_path(requester).is_some()
+        }
+
+        // Returns how the requester has been granted access: directly, or else through the first of its groups that has.
+        // A permission granted with a temporary role no longer counts once the role has expired.
+        fn access_path(&self, requester: &AccountId) -> Option<AccessPath> {
+            let now = self.env().block_timestamp();
+            if self.permissions.get(requester).is_some_and(|p| p.can_access && p.valid_until.is_none_or(|until| now < until)) {
+                return Some(AccessPath::Permission);
+            }
+            self.account_groups
+                .get(requester)
+                .unwrap_or_default()
+                .into_iter()
+                .find(|group| self.group_permissions.get(group).map(|p| p.can_access).unwrap_or(false))
+                .map(AccessPath::Group)
+                .or_else(|| self.facility_grant(requester).map(AccessPath::Facility))
+        }
+
+        // Returns the DENIAL_* sub-code of an action authorize denies with PermissionDenied.
+        fn denial_code(&self, subject: &AccountId, patient: &AccountId, action: Action) -> u8 {
+            let now = self.env().block_timestamp();
+            match action {
+                Action::ActFor => DENIAL_NOT_GUARDIAN,
+                Action::WriteClinical if !self.has_role(subject, &[Role::Clinician]) => {
+                    match self.roles.get(subject) {
+                        Some(grant) if grant.role == Role::Clinician => DENIAL_ROLE_EXPIRED,
+                        _ => DENIAL_NOT_CLINICIAN
+                    }
+                }
+                Action::Read(_) if self.consents.contains((*patient, *subject)) => DENIAL_CONSENT_SCOPE,
+                Action::Read(_) if self.onboarding_consents.get((*patient, *subject)).is_some_and(|until| now >= until)
+                    || self.emergency_grants.get((*patient, *subject)).is_some_and(|until| now >= until) => DENIAL_CONSENT_EXPIRED,
+                _ => match self.permissions.get(subject) {
+                    Some(permission) if !permission.can_access => DENIAL_PERMISSION_REVOKED,
+                    Some(permission) if permission.valid_until.is_some_and(|until| now >= until) => DENIAL_ROLE_EXPIRED,
+                    _ => DENIAL_NO_ACCESS
+                }
+            }
+        }
+
+        // Returns the facility, at or above the one the account is assigned to, whose permission grants it access.
+        fn facility_grant(&self, account: &AccountId) -> Option<u32> {
+            let (mut facility, _) = self.staff_facility.get(account)?;
+            for _ in 0..MAX_FACILITY_DEPTH {
+                if self.facility_permissions.get(facility).is_some_and(|p| p.can_access) {
+                    return Some(facility);
+                }
+                facility = self.facilities.get(facility)?.parent?;
+            }
+            None
+        }
+
+        // Returns the depth of a facility in its tree, a hospital being at depth 1.
+        fn facility_depth(&self, facility: u32) -> u32 {
+            let mut depth = 1;
+            let mut node = self.facilities.get(facility).and_then(|info| info.parent);
+            while let Some(parent) = node {
+                depth += 1;
+                node = self.facilities.get(parent).and_then(|info| info.parent);
+            }
+            depth
+        }
+
+        // Checks that the caller is the contract admin or the admin of a facility or of any facility above it.
+        fn check_facility_admin(&self, facility: u32) -> Result<AccountId, Error> {
+            let caller = self.env().caller();
+            let mut info = self.facilities.get(facility).ok_or(Error::FacilityNotFound)?;
+            if caller == self.admin {
+                return Ok(caller);
+            }
+            loop {
+                if info.admin == caller {
+                    return Ok(caller);
+                }
+                match info.parent.and_then(|parent| self.facilities.get(parent)) {
+                    Some(parent) => info = parent,
+                    None => return Err(Error::PermissionDenied)
+                }
+            }
+        }
+
+        // Removes a staff member from their facility's staff list, keeping the list dense.
+        fn remove_staff(&mut self, account: AccountId) {
+            let Some((facility, index)) = self.staff_facility.take(account) else {
+                return;
+            };
+            let last = self.facility_staff_count.get(facility).unwrap_or(1) - 1;
+            if index != last {
+                if let Some(moved) = self.facility_staff.get((facility, last)) {
+                    self.facility_staff.insert((facility, index), &moved);
+                    self.staff_facility.insert(moved, &(facility, index));
+                }
+            }
+            self.facility_staff.remove((facility, last));
+            self.facility_staff_count.insert(facility, &last);
+        }
+
+        // Checks that the caller is the admin and that a batch is within MAX_BATCH_LEN.
+        fn check_batch(&self, len: usize) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            if len > MAX_BATCH_LEN {
+                return Err(Error::BatchTooLarge);
+            }
+            Ok(())
+        }
+
+        // Grants a role and access to one account of a batch.
+        // Granting again the same role with a different expiry replaces the grant, so temporary roles can be extended.
+        fn grant_role(&mut self, account: AccountId, grant: RoleGrant) -> Result<(), Error> {
+            let role = grant.role;
+            if grant.valid_until.is_some_and(|valid_until| valid_until <= self.env().block_timestamp()) {
+                return Err(Error::InvalidExpiry);
+            }
+            if self.active_role(&account) == Some(grant) {
+                return Err(Error::RoleAlreadyGranted);
+            }
+            if role == Role::Provider && !self.providers.get(account).is_some_and(|provider| provider.verified) {
+                return Err(Error::ProviderNotVerified);
+            }
+
+            let new_permission = Permission { can_access: true, valid_until: grant.valid_until };
+            let old = self.permissions.get(account);
+            self.roles.insert(account, &grant);
+            self.permissions.insert(account, &new_permission);
+
+            self.emit(RoleGranted { account, role, valid_until: grant.valid_until });
+            self.emit(PermissionChanged {
+                user: account,
+                by: self.env().caller(),
+                old,
+                new: Some(new_permission)
+            });
+            Ok(())
+        }
+
+        // Records that an account joined a patient's care team.
+        fn assign_care(&mut self, member: AccountId, patient: AccountId) {
+            let index = self.care_assignment_count.get(member).unwrap_or(0);
+            self.care_assignments.insert((member, index), &patient);
+            self.care_assignment_index.insert((member, patient), &index);
+            self.care_assignment_count.insert(member, &(index + 1));
+        }
+
+        // Records that an account left a patient's care team, moving its last assignment into the freed position.
+        fn unassign_care(&mut self, member: AccountId, patient: AccountId) {
+            let Some(index) = self.care_assignment_index.take((member, patient)) else {
+                return;
+            };
+            let last = self.care_assignment_count.get(member).unwrap_or(1) - 1;
+            if index != last {
+                if let Some(moved) = self.care_assignments.get((member, last)) {
+                    self.care_assignments.insert((member, index), &moved);
+                    self.care_assignment_index.insert((member, moved), &index);
+                }
+            }
+            self.care_assignments.remove((member, last));
+            self.care_assignment_count.insert(member, &last);
+        }
+
+        // Removes an account from up to `limit` care teams, latest assignment first, recording each patient as one of
+        // its orphans. Returns the number of care teams the account is still in.
+        fn release_care_assignments(&mut self, member: AccountId, limit: u32) -> u32 {
+            let count = self.care_assignment_count.get(member).unwrap_or(0);
+            let remaining = count.saturating_sub(limit);
+            let caller = self.env().caller();
+            for index in (remaining..count).rev() {
+                let Some(patient) = self.care_assignments.take((member, index)) else {
+                    continue;
+                };
+                self.care_assignment_index.remove((member, patient));
+                let mut team = self.care_teams.get(patient).unwrap_or_default();
+                team.retain(|account| *account != member);
+                self.care_teams.insert(patient, &team);
+                self.emit(CareTeamChanged { patient, member, by: caller, added: false });
+
+                let orphan = self.orphan_count.get(member).unwrap_or(0);
+                self.orphans.insert((member, orphan), &patient);
+                self.orphan_count.insert(member, &(orphan + 1));
+            }
+            self.care_assignment_count.insert(member, &remaining);
+            remaining
+        }
+
+        // Revokes the role and access of one account of a batch, and removes it from the care teams it is in.
+        fn revoke_role(&mut self, account: AccountId) -> Result<(), Error> {
+            let role = self.roles.take(account).ok_or(Error::RecordNotFound)?.role;
+            let old = self.permissions.take(account);
+            self.release_care_assignments(account, MAX_CARE_CLEANUP);
+
+            self.emit(RoleRevoked { account, role });
+            self.emit(PermissionChanged {
+                user: account,
+                by: self.env().caller(),
+                old,
+                new: None
+            });
+            Ok(())
+        }
+
+        // Writes biodata to the record, updating its metadata, notifying the patient of writes by others and emitting BiodataUpdate.
+        // Fails with QuotaExceeded if the record would grow beyond the quota, and with OpenQualityFlags if it would
+        // finalize biodata while a quality flag is open.
+        fn store_biodata(&mut self, by: AccountId, identifier: AccountId, biodata: &Biodata) -> Result<(), Error> {
+            if biodata.finalized && self.has_open_flags(identifier) {
+                return Err(Error::OpenQualityFlags);
+            }
+            let previous = self.biodata_meta.get(identifier);
+            let released = previous.as_ref().map_or(0, payload_size);
+            let meta = self.next_meta(previous, &biodata.name, &biodata.details, &biodata.vector, biodata.finalized);
+            self.charge_storage(identifier, released, payload_size(&meta))?;
+            self.biodata_meta.insert(identifier, &meta);
+            self.patient_biodata.insert(identifier, biodata);
+            if by != identifier {
+                self.notify(identifier, NotificationKind::BiodataUpdated, by, 0);
+            }
+
+            self.log_change(identifier, CHANGE_BIODATA, by, biodata);
+            self.emit(BiodataUpdate {
+                identifier,
+                by,
+                payload_hash: self.hash_of(biodata)
+            });
+
+            Ok(())
+        }
+
+        // Writes clinical notes to the record as a new revision, updating its metadata and emitting ClinicalNotesUpdate.
+        // Returns the index of the revision. Fails with QuotaExceeded if the record would grow beyond the quota.
+        fn store_notes(&mut self, by: AccountId, identifier: AccountId, notes: &ClinicalNotes) -> Result<u32, Error> {
+            let previous = self.notes_meta.get(identifier);
+            let released = previous.as_ref().map_or(0, payload_size);
+            let meta = self.next_meta(previous, &notes.name, &notes.details, &notes.vector, notes.finalized);
+            self.charge_storage(identifier, released, payload_size(&meta))?;
+            self.notes_meta.insert(identifier, &meta);
+            self.patient_notes.insert(identifier, notes);
+
+            let index = self.note_revisions.get(identifier).unwrap_or(0);
+            self.note_revisions.insert(identifier, &(index + 1));
+
+            self.log_change(identifier, CHANGE_NOTES, by, notes);
+            self.emit(ClinicalNotesUpdate {
+                identifier,
+                by,
+                payload_hash: self.hash_of(notes)
+            });
+
+            Ok(index)
+        }
+
+        // Registers a legacy record under its original health id and writes its biodata and notes like any other write.
+        // The token is minted by the caller. Sequential ids at or below an imported one are skipped by create_patient.
+        fn import_record(&mut self, entry: &ImportEntry) -> Result<(), Error> {
+            let (id, identifier, biodata, notes) = entry;
+            let (id, identifier) = (*id, *identifier);
+            if id == 0 || id & (EXTERNAL_ID_FLAG | CONSENT_TOKEN_FLAG) != 0 {
+                return Err(Error::NotAllowed);
+            }
+            if self.record_count.contains(id) || self.health_ids.contains(identifier) {
+                return Err(Error::PatientExists);
+            }
+            check_payload(&biodata.name, &biodata.details, &biodata.vector)?;
+            if let Some(notes) = notes {
+                check_payload(&notes.name, &notes.details, &notes.vector)?;
+            }
+
+            let by = self.env().caller();
+            self.record_count.insert(id, &identifier);
+            self.health_ids.insert(identifier, &id);
+            self.current_id = self.current_id.max(id);
+            self.stats_patients += 1;
+            self.imported.insert(identifier, &());
+            self.log_change(identifier, CHANGE_CREATED, by, &id);
+            self.store_biodata(by, identifier, biodata)?;
+            if let Some(notes) = notes {
+                self.store_notes(by, identifier, notes)?;
+            }
+
+            self.emit(Imported { id, identifier, by });
+            Ok(())
+        }
+
+        // Checks that the caller may review a pending draft: a clinician with write access who is not its author.
+        fn check_reviewer(&self, identifier: &AccountId, draft_index: u32) -> Result<AccountId, Error> {
+            let caller = self.check_clinician(identifier)?;
+            let draft = self.biodata_drafts.get((identifier, draft_index)).ok_or(Error::RecordNotFound)?;
+            if draft.author == caller {
+                return Err(Error::SelfApproval);
+            }
+            Ok(caller)
+        }
+
+        // Checks that the caller is a clinician with write access to the record and returns the caller.
+        fn check_clinician(&self, identifier: &AccountId) -> Result<AccountId, Error> {
+            let caller = self.env().caller();
+            self.authorize(&caller, identifier, Action::WriteClinical)?;
+            Ok(caller)
+        }
+
+        // Returns true if the caller is a researcher and the prefix is a whole ICD-10 category.
+        fn can_query_cohorts(&self, icd10_prefix: &str) -> bool {
+            self.has_role(&self.env().caller(), &[Role::Researcher])
+                && icd10_prefix.len() == 3
+                && icd10_chapter(icd10_prefix).is_some()
+        }
+
+        // Returns true if the account holds one of the given roles and it has not expired.
+        fn has_role(&self, account: &AccountId, roles: &[Role]) -> bool {
+            self.active_role(account).is_some_and(|grant| roles.contains(&grant.role))
+        }
+
+        // Returns the role grant of an account unless it has expired.
+        fn active_role(&self, account: &AccountId) -> Option<RoleGrant> {
+            self.roles.get(account).filter(|grant| !self.is_role_expired(grant))
+        }
+
+        // Returns true once a temporary role has reached its expiry.
+        fn is_role_expired(&self, grant: &RoleGrant) -> bool {
+            grant.valid_until.is_some_and(|valid_until| valid_until <= self.env().block_timestamp())
+        }
+
+        // Makes new_org the primary institution of a patient and returns the old one, if any. The old institution
+        // and its members leave the patient's care team, lose its onboarding consent and go to the institution history.
+        fn switch_institution(&mut self, patient: AccountId, new_org: AccountId, by: AccountId) -> Option<AccountId> {
+            let now = self.env().block_timestamp();
+            let old_org = self.primary_institutions.get(patient);
+            if let Some(old_org) = old_org {
+                let mut team = self.care_teams.get(patient).unwrap_or_default();
+                let (leaving, staying) = team
+                    .drain(..)
+                    .partition::<Vec<_>, _>(|member| *member == old_org || self.org_members.get(member) == Some(old_org));
+                self.care_teams.insert(patient, &staying);
+                for member in leaving {
+                    self.unassign_care(member, patient);
+                    self.emit(CareTeamChanged { patient, member, by, added: false });
+                }
+                self.onboarding_consents.remove((patient, old_org));
+
+                let index = self.institution_history_count.get(patient).unwrap_or(0);
+                self.institution_history.insert((patient, index), &InstitutionRecord { org: old_org, left_at: now, left_by: by });
+                self.institution_history_count.insert(patient, &(index + 1));
+            }
+
+            let onboarding_until = now.saturating_add(ONBOARDING_PERIOD);
+            self.primary_institutions.insert(patient, &new_org);
+            self.onboarding_consents.insert((patient, new_org), &onboarding_until);
+            self.emit(InstitutionChanged { patient, new_org, old_org, by, onboarding_until });
+            old_org
+        }
+
+        // Moves the token of a record from the old institution to the new one on the Patient contract,
+        // if the old institution holds it. A failure is reported as a CrossContract error.
+        fn move_record_token(&mut self, id: HealthId, old_org: AccountId, new_org: AccountId) -> Result<(), Error> {
+            let holder = match self.patient.call().owner_of(id).try_invoke() {
+                Ok(Ok(holder)) => holder,
+                _ => return Err(Error::CrossContract { selector: ink::selector_bytes!("owner_of"), inner: CHILD_CALL_FAILED })
+            };
+            if holder != Some(old_org) {
+                return Ok(());
+            }
+            let result = self.patient.call_mut().transfer_from(old_org, new_org, id).try_invoke();
+            child_result(ink::selector_bytes!("transfer_from"), result)
+        }
+
+        // Mints token `id` to an account on the Patient contract, reporting a failure as a CrossContract error.
+        fn mint_patient_token(&mut self, to: AccountId, id: HealthId) -> Result<(), Error> {
+            let result = self.patient.call_mut().mint_to(to, id).try_invoke();
+            child_result(ink::selector_bytes!("mint_to"), result)
+        }
+
+        // Checks with the Patient contract that the caller holds token `id` and returns the record's account.
+        // Holding a VIP patient's token is not enough on its own: anyone but the patient needs a co-authorization,
+        // so they are turned away before the Patient contract is asked.
+        fn check_token_holder(&self, id: HealthId) -> Result<AccountId, Error> {
+            let identifier = self.record_count.get(id).ok_or(Error::RecordNotFound)?;
+            if self.needs_co_authorization(&self.env().caller(), &identifier) {
+                return Err(Error::CoAuthorizationRequired);
+            }
+            let holder = match self.patient.call().owner_of(id).try_invoke() {
+                Ok(Ok(holder)) => holder,
+                _ => return Err(Error::CrossContract { selector: ink::selector_bytes!("owner_of"), inner: CHILD_CALL_FAILED })
+            };
+            if holder != Some(self.env().caller()) {
+                return Err(Error::PermissionDenied);
+            }
+            Ok(identifier)
+        }
+
+        // Returns the Blake2x256 hash of a SCALE-encoded value, used for event topics and attestations.
+        fn hash_of<T: scale::Encode>(&self, value: &T) -> Hash {
+            Hash::from(self.env().hash_encoded::<Blake2x256, _>(value))
+        }
+
+        // Emits an event of this contract. The Patient dependency brings its own events into scope,
+        // so the emitting contract has to be named explicitly.
+        fn emit<E>(&self, event: E)
+        where
+            E: Into<<Epr as ink::reflect::ContractEventBase>::Type>,
+        {
+            ink::codegen::EmitEvent::<Epr>::emit_event(self.env(), event);
+        }
+
+        // Rejects a call of the given kind made within the cooldown of the caller's previous one,
+        // and records the current block otherwise.
+        fn rate_limit(&mut self, caller: AccountId, kind: u8) -> Result<(), Error> {
+            let now = self.env().block_number();
+            if let Some(last) = self.last_call.get((caller, kind)) {
+                if now < last.saturating_add(self.cooldown) {
+                    return Err(Error::RateLimited);
+                }
+            }
+            self.last_call.insert((caller, kind), &now);
+            Ok(())
+        }
+
+        // Holds the current storage deposit out of the transferred value and credits any excess back to the caller.
+        fn take_deposit(&mut self, caller: AccountId) -> Result<Balance, Error> {
+            let deposit = self.storage_deposit;
+            let transferred = self.env().transferred_value();
+            if transferred < deposit {
+                return Err(Error::InsufficientDeposit);
+            }
+
+            self.deposits.insert(caller, &(self.deposit_of(caller) + deposit));
+            if transferred > deposit {
+                self.credit(caller, transferred - deposit);
+            }
+            Ok(deposit)
+        }
+
+        // Releases a held deposit into the account's pending withdrawals.
+        fn refund_deposit(&mut self, account: AccountId, amount: Balance) {
+            self.deposits.insert(account, &self.deposit_of(account).saturating_sub(amount));
+            self.credit(account, amount);
+        }
+
+        // Adds an amount to the account's pending withdrawals.
+        fn credit(&mut self, account: AccountId, amount: Balance) {
+            let pending = self.pending_withdrawal_of(account);
+            self.pending_withdrawals.insert(account, &(pending + amount));
+        }
+
+        // Checks that the caller administers the group (or the contract) and returns the caller.
+        fn check_group_admin(&self, group: u32) -> Result<AccountId, Error> {
+            let caller = self.env().caller();
+            let info = self.groups.get(group).ok_or(Error::GroupNotFound)?;
+            if caller != info.admin && caller != self.admin {
+                return Err(Error::PermissionDenied);
+            }
+            Ok(caller)
+        }
+
+        // Checks that the requester is the caller and may write, and that the target is a registered patient whose
+        // record is not archived. Messages that take the requester as an argument must not trust it as is.
+        fn check_write(&self, requester: &AccountId, identifier: &AccountId) -> Result<(), Error> {
+            if *requester != self.env().caller() {
                 return Err(Error::PermissionDenied);
             }
-            
-            let count = self.current_id + 1;
-            self.current_id = count;
-            self.record_count.insert(&count, &identifier);
+            self.authorize(requester, identifier, Action::Write).map(|_| ())
+        }
+
+        // Returns true if the record is flagged as finalized or was created more than finalize_after ago.
+        fn is_finalized(&self, meta: &RecordMeta) -> bool {
+            meta.finalized || self.env().block_timestamp() >= meta.created_at.saturating_add(self.finalize_after)
+        }
+
+        // Moves a booked appointment to a closed status and removes it from the clinician's booked slots.
+        fn close_appointment(&mut self, patient: AccountId, index: u32, mut appointment: Appointment, status: ApptStatus) -> Result<(), Error> {
+            if appointment.status != ApptStatus::Booked {
+                return Err(Error::AppointmentClosed);
+            }
+            let end = slot_end(appointment.scheduled_at, appointment.duration_minutes);
+            for day in slot_days(appointment.scheduled_at, end) {
+                let key = (appointment.clinician, day);
+                let mut booked = self.booked_slots.get(key).unwrap_or_default();
+                booked.retain(|entry| *entry != (patient, index));
+                if booked.is_empty() {
+                    self.booked_slots.remove(key);
+                } else {
+                    self.booked_slots.insert(key, &booked);
+                }
+            }
+            appointment.status = status;
+            self.appointments.insert((patient, index), &appointment);
+            Ok(())
+        }
+
+        // Builds the metadata for a write, keeping the original author and creation time of an existing record.
+        fn next_meta(&self, previous: Option<RecordMeta>, name: &str, details: &str, vector: &[u8], finalized: bool) -> RecordMeta {
+            let now = self.env().block_timestamp();
+            let (author, created_at) = match previous {
+                Some(meta) => (meta.author, meta.created_at),
+                None => (self.env().caller(), now),
+            };
+
+            RecordMeta {
+                author,
+                finalized,
+                created_at,
+                updated_at: now,
+                name_len: name.len() as u32,
+                details_len: details.len() as u32,
+                vector_len: vector.len() as u32
+            }
+        }
+    }
+
+    // Returns the payload bytes of a record described by its metadata.
+    fn payload_size(meta: &RecordMeta) -> u32 {
+        meta.name_len + meta.details_len + meta.vector_len
+    }
+
+    // Returns the end of an appointment slot, exclusive.
+    fn slot_end(scheduled_at: Timestamp, duration_minutes: u16) -> Timestamp {
+        scheduled_at.saturating_add(Timestamp::from(duration_minutes) * 60 * 1000)
+    }
+
+    // Returns the days, as booked_slots keys, that the slot from start to end (exclusive) touches.
+    fn slot_days(start: Timestamp, end: Timestamp) -> core::ops::RangeInclusive<Timestamp> {
+        start / DAY..=end.saturating_sub(1) / DAY
+    }
+
+    // Builds the receipt of a consent given to a provider.
+    fn receipt(provider: AccountId, consent: Consent, revoked_at: Option<Timestamp>) -> ConsentReceipt {
+        ConsentReceipt {
+            provider,
+            scope: consent.scope,
+            terms_hash: consent.terms_hash,
+            document_uri: consent.document_uri,
+            template_id: consent.template_id,
+            template_version: consent.template_version,
+            granted_at: consent.granted_at,
+            granted_block: consent.granted_block,
+            starts_at: consent.starts_at,
+            revoked_at
+        }
+    }
+
+    // Rejects payloads that are empty or whose fields exceed the size caps.
+    fn check_payload(name: &str, details: &str, vector: &[u8]) -> Result<(), Error> {
+        if name.is_empty() && details.is_empty() && vector.is_empty() {
+            return Err(Error::EmptyPayload);
+        }
+        if name.len() > MAX_NAME_LEN || details.len() > MAX_DETAILS_LEN || vector.len() > MAX_VECTOR_LEN {
+            return Err(Error::PayloadTooLarge);
+        }
+        Ok(())
+    }
+
+    // Turns the outcome of a call to the Patient contract into a CrossContract error carrying the message selector
+    // and the discriminant of the Patient error, or CHILD_CALL_FAILED if the call itself failed.
+    fn child_result<T, E: scale::Encode>(
+        selector: [u8; 4],
+        result: Result<ink::MessageResult<Result<T, E>>, ink::env::Error>
+    ) -> Result<T, Error> {
+        match result {
+            Ok(Ok(Ok(value))) => Ok(value),
+            Ok(Ok(Err(error))) => Err(Error::CrossContract { selector, inner: error.encode()[0] }),
+            _ => Err(Error::CrossContract { selector, inner: CHILD_CALL_FAILED })
+        }
+    }
+
+    // Empties the fields of a record that are hidden by the given REDACT_* mask.
+    fn redact(name: &mut String, details: &mut String, vector: &mut Vec<u8>, fields: u8) {
+        if fields & REDACT_NAME != 0 {
+            name.clear();
+        }
+        if fields & REDACT_DETAILS != 0 {
+            details.clear();
+        }
+        if fields & REDACT_VECTOR != 0 {
+            vector.clear();
+        }
+    }
+
+    // Rejects discharge summaries without a diagnosis or whose fields exceed the size caps.
+    fn check_discharge_summary(summary: &DischargeSummary) -> Result<(), Error> {
+        if summary.diagnosis_codes.is_empty() {
+            return Err(Error::MissingDiagnosis);
+        }
+        if summary.diagnosis_codes.len() > MAX_DIAGNOSIS_CODES
+            || summary.diagnosis_codes.iter().any(|code| code.len() > MAX_NAME_LEN)
+            || summary.medications.len() > MAX_DETAILS_LEN
+            || summary.follow_up.len() > MAX_DETAILS_LEN
+        {
+            return Err(Error::PayloadTooLarge);
+        }
+        Ok(())
+    }
+
+    // Returns the key of the agreement between two organizations, which does not depend on their order.
+    fn agreement_key(org: AccountId, other_org: AccountId) -> (AccountId, AccountId) {
+        if org < other_org {
+            (org, other_org)
+        } else {
+            (other_org, org)
+        }
+    }
+
+    // Returns the ICD-10 chapter of a code, or None if the code does not start with a letter and two digits.
+    fn icd10_chapter(code: &str) -> Option<&'static str> {
+        let category = code.get(..3)?;
+        let mut chars = category.chars();
+        let valid = chars.next().is_some_and(|c| c.is_ascii_uppercase()) && chars.all(|c| c.is_ascii_digit());
+        if !valid {
+            return None;
+        }
+        ICD10_CHAPTERS
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= category)
+            .map(|(_, chapter)| *chapter)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        // #[ink::test]
+        // fn new_creates_contract_with_zero_id() {
+        //     let patient_code_hash: Hash = Hash::from([0x00; 32]);
+        //     let healthdot = Epr::new(patient_code_hash);
+
+        //     assert_eq!(healthdot.current_id, 0);
+        // }
+
+        // #[ink::test]
+        // fn add_user_with_permissions_works() {
+        //     let patient_code_hash: Hash = Hash::from([0x00; 32]);
+        //     let mut healthdot = Epr::new(patient_code_hash);
+        //     let user: AccountId = AccountId::from([0x0; 32]);
+
+        //     healthdot.add_user_with_permissions(user, true);
+            
+        //     assert_eq!(healthdot.permissions.get(&user).unwrap().can_access, true);
+        // }
+
+        // #[ink::test]
+        // fn create_patient_without_permission_fails() {
+        //     let patient_code_hash: Hash = Hash::repeat_byte(0x00);
+        //     let mut healthdot = HealthDot::new(patient_code_hash);
+        //     let requester: AccountId = AccountId::from([0x01; 32]);
+        //     let identifier: AccountId = AccountId::from([0x02; 32]);
+
+        //     assert_eq!(
+        //         healthdot.create_patient(requester, identifier),
+        //         Err(Error::PermissionDenied)
+        //     );
+        // }
+
+        // #[ink::test]
+        // fn create_patient_with_permission_increments_id() {
+        //     let patient_code_hash: Hash = Hash::repeat_byte(0x00);
+        //     let mut healthdot = HealthDot::new(patient_code_hash);
+        //     let requester: AccountId = AccountId::from([0x01; 32]);
+        //     let identifier: AccountId = AccountId::from([0x02; 32]);
+
+        //     healthdot.add_user_with_permissions(requester, true);
+        //     assert_eq!(healthdot.create_patient(requester, identifier), Ok(()));
+        //     assert_eq!(healthdot.current_id, 1);
+        //     assert_eq!(healthdot.record_count.get(&1), Some(&identifier));
+        // }
+
+        // Epr::new instantiates the Patient child contract, which the off-chain environment cannot do,
+        // so the tests build the storage directly with a placeholder Patient reference.
+        // The caller at construction time (Alice by default) becomes the admin.
+        fn new_epr() -> Epr {
+            Epr::with_patient(ink::env::call::FromAccountId::from_account_id(AccountId::from([0xFF; 32])), Vec::new())
+        }
+
+        fn set_caller(sender: AccountId) {
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(sender);
+        }
+
+        // Registers a patient the way create_patient does, without the cross-contract mint.
+        fn register(epr: &mut Epr, identifier: AccountId) -> HealthId {
+            let id = epr.current_id + 1;
+            epr.current_id = id;
+            epr.record_count.insert(id, &identifier);
+            epr.health_ids.insert(identifier, &id);
+            epr.stats_patients += 1;
+            id
+        }
+
+        // Invites an account to an organization and accepts, leaving the organization as the caller.
+        fn join_org(epr: &mut Epr, org: AccountId, member: AccountId) {
+            set_caller(org);
+            assert_eq!(epr.add_org_member(member), Ok(()));
+            set_caller(member);
+            assert_eq!(epr.accept_org_invite(org), Ok(()));
+            set_caller(org);
+        }
+
+        fn default_accounts() -> ink::env::test::DefaultAccounts<ink::env::DefaultEnvironment> {
+            ink::env::test::default_accounts::<ink::env::DefaultEnvironment>()
+        }
+
+        // Publishes the standard consent terms, ipfs://terms hashed to [1; 32], and returns the template id.
+        fn standard_terms(epr: &mut Epr) -> u32 {
+            let id = epr.template_count + 1;
+            epr.template_count = id;
+            epr.consent_templates.insert(id, &Template {
+                uri: String::from("ipfs://terms"),
+                hash: Hash::from([1; 32]),
+                version: 1,
+                active: true
+            });
+            id
+        }
+
+        fn biodata(name: &str, details: &str, vector: Vec<u8>) -> Biodata {
+            Biodata {
+                name: String::from(name),
+                details: String::from(details),
+                finalized: false,
+                vector
+            }
+        }
+
+        #[ink::test]
+        fn update_biodata_rejects_oversized_payload() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            register(&mut epr, accounts.bob);
+
+            let long_name = "n".repeat(MAX_NAME_LEN + 1);
+            let long_details = "d".repeat(MAX_DETAILS_LEN + 1);
+
+            // Each field over its cap is rejected.
+            assert_eq!(
+                epr.update_biodata(accounts.alice, accounts.bob, biodata(&long_name, "", Vec::new())),
+                Err(Error::PayloadTooLarge)
+            );
+            assert_eq!(
+                epr.update_biodata(accounts.alice, accounts.bob, biodata("", &long_details, Vec::new())),
+                Err(Error::PayloadTooLarge)
+            );
+            assert_eq!(
+                epr.update_biodata(accounts.alice, accounts.bob, biodata("", "", vec![0; MAX_VECTOR_LEN + 1])),
+                Err(Error::PayloadTooLarge)
+            );
+            // Nothing was stored.
+            assert_eq!(epr.get_biodata(accounts.alice, accounts.bob), None);
+
+            // Payloads exactly at the caps are accepted.
+            let name = "n".repeat(MAX_NAME_LEN);
+            let details = "d".repeat(MAX_DETAILS_LEN);
+            assert_eq!(
+                epr.update_biodata(accounts.alice, accounts.bob, biodata(&name, &details, vec![0; MAX_VECTOR_LEN])),
+                Ok(())
+            );
+        }
+
+        #[ink::test]
+        fn update_clinical_notes_rejects_oversized_payload() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            register(&mut epr, accounts.bob);
+
+            let notes = ClinicalNotes {
+                name: String::from("notes"),
+                details: String::new(),
+                finalized: false,
+                vector: vec![0; MAX_VECTOR_LEN + 1]
+            };
+            assert_eq!(epr.update_clinical_notes(accounts.alice, accounts.bob, notes), Err(Error::PayloadTooLarge));
+        }
+
+        #[ink::test]
+        fn biodata_meta_tracks_sizes_and_author() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            register(&mut epr, accounts.bob);
+
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(10);
+            assert_eq!(epr.update_biodata(accounts.alice, accounts.bob, biodata("Bob", "details", vec![1, 2, 3])), Ok(()));
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(20);
+            assert_eq!(epr.update_biodata(accounts.alice, accounts.bob, biodata("Bobby", "details", vec![1, 2])), Ok(()));
+
+            let meta = epr.biodata_meta(accounts.alice, accounts.bob).expect("meta is stored");
+            assert_eq!(meta.author, accounts.alice);
+            assert_eq!(meta.created_at, 10);
+            assert_eq!(meta.updated_at, 20);
+            assert_eq!((meta.name_len, meta.details_len, meta.vector_len), (5, 7, 2));
+            assert!(!meta.finalized);
+
+            // Without permission the metadata is not readable.
+            assert_eq!(epr.biodata_meta(accounts.charlie, accounts.bob), None);
+        }
+
+        #[ink::test]
+        fn biodata_meta_does_not_touch_payload() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            register(&mut epr, accounts.bob);
+            assert_eq!(
+                epr.update_biodata(accounts.alice, accounts.bob, biodata("Bob", "details", vec![0; MAX_VECTOR_LEN])),
+                Ok(())
+            );
+
+            let contract = ink::env::test::callee::<ink::env::DefaultEnvironment>();
+            let reads = || ink::env::test::get_contract_storage_rw::<ink::env::DefaultEnvironment>(&contract).0;
+
+            // The metadata getter only reads the access checks and the metadata cell, one more than an access check alone.
+            let before = reads();
+            assert!(epr.biodata_meta(accounts.alice, accounts.bob).is_some());
+            let meta_reads = reads() - before;
+            let before = reads();
+            assert!(epr.view_redactions(accounts.bob, CONSENT_BIODATA).is_some());
+            assert_eq!(meta_reads, reads() - before + 1);
+
+            // With the payload key gone the metadata is still served, so the getter never depends on it.
+            epr.patient_biodata.remove(accounts.bob);
+            assert_eq!(epr.get_biodata(accounts.alice, accounts.bob), None);
+            let before = reads();
+            let meta = epr.biodata_meta(accounts.alice, accounts.bob).expect("meta is stored separately");
+            assert_eq!(reads() - before, meta_reads);
+            assert_eq!(meta.vector_len as usize, MAX_VECTOR_LEN);
+        }
+
+        #[ink::test]
+        fn clinical_notes_lock_after_the_finalization_window() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            register(&mut epr, accounts.bob);
+            let day: Timestamp = 24 * 60 * 60 * 1000;
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(day);
+            assert_eq!(epr.update_clinical_notes(accounts.alice, accounts.bob, notes("visit")), Ok(()));
+            assert_eq!(epr.finalize_after(), DEFAULT_FINALIZE_AFTER);
+
+            // Within the window the notes stay writable and cannot be finalized early.
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(day + DEFAULT_FINALIZE_AFTER - 1);
+            assert_eq!(epr.update_clinical_notes(accounts.alice, accounts.bob, notes("follow-up")), Ok(()));
+            assert_eq!(epr.finalize_overdue(accounts.bob), Err(Error::NotAllowed));
+            assert_eq!(epr.notes_finalized(accounts.alice, accounts.bob), Some(false));
+
+            // Once the window has passed they count as finalized, though the flag was never set.
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(day + DEFAULT_FINALIZE_AFTER);
+            assert_eq!(epr.update_clinical_notes(accounts.alice, accounts.bob, notes("late")), Err(Error::RecordFinalized));
+            assert_eq!(epr.notes_finalized(accounts.alice, accounts.bob), Some(true));
+            assert!(!epr.notes_meta.get(accounts.bob).expect("notes exist").finalized);
+
+            // Anyone can make the stored flag catch up, once.
+            set_caller(accounts.eve);
+            assert_eq!(epr.finalize_overdue(accounts.charlie), Err(Error::RecordNotFound));
+            assert_eq!(epr.finalize_overdue(accounts.bob), Ok(()));
+            assert!(matches!(
+                recorded_events().last(),
+                Some(Event::RecordFinalized(RecordFinalized { created_at, .. })) if *created_at == day
+            ));
+            assert!(epr.notes_meta.get(accounts.bob).expect("notes exist").finalized);
+            assert_eq!(epr.finalize_overdue(accounts.bob), Err(Error::RecordFinalized));
+
+            // The window is admin-tunable.
+            assert_eq!(epr.set_finalize_after(day), Err(Error::PermissionDenied));
+            set_caller(accounts.alice);
+            assert_eq!(epr.set_finalize_after(day), Ok(()));
+            assert_eq!(epr.finalize_after(), day);
+        }
+
+        #[ink::test]
+        fn authors_only_see_their_own_note_references() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            assert_eq!(epr.add_user_with_permissions(accounts.bob, true), Ok(()));
+            assert_eq!(epr.add_user_with_permissions(accounts.charlie, true), Ok(()));
+            register(&mut epr, accounts.django);
+            register(&mut epr, accounts.eve);
+
+            let writes = [
+                (accounts.bob, accounts.django, "bob 1"),
+                (accounts.charlie, accounts.django, "charlie 1"),
+                (accounts.charlie, accounts.eve, "charlie 2"),
+                (accounts.bob, accounts.eve, "bob 2"),
+                (accounts.bob, accounts.django, "bob 3")
+            ];
+            for (author, patient, note) in writes {
+                set_caller(author);
+                assert_eq!(epr.update_clinical_notes(author, patient, notes(note)), Ok(()));
+            }
+
+            set_caller(accounts.bob);
+            assert_eq!(epr.my_notes(None, 0, 10), Ok(vec![(accounts.django, 0), (accounts.eve, 1), (accounts.django, 2)]));
+            assert_eq!(epr.my_notes(None, 1, 1), Ok(vec![(accounts.eve, 1)]));
+            assert_eq!(epr.my_notes(Some(accounts.charlie), 0, 10), Err(Error::PermissionDenied));
+
+            set_caller(accounts.charlie);
+            assert_eq!(epr.my_notes(None, 0, 10), Ok(vec![(accounts.django, 1), (accounts.eve, 0)]));
+
+            // The admin can list any author's notes.
+            set_caller(accounts.alice);
+            assert_eq!(epr.my_notes(Some(accounts.charlie), 1, 10), Ok(vec![(accounts.eve, 0)]));
+            assert_eq!(epr.my_notes(None, 0, 10), Ok(Vec::new()));
+        }
+
+        #[ink::test]
+        fn existence_checks_skip_the_payload() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            register(&mut epr, accounts.bob);
+            assert!(!epr.has_biodata(accounts.alice, accounts.bob));
+            assert!(!epr.has_notes(accounts.alice, accounts.bob));
+            assert_eq!(epr.biodata_meta(accounts.alice, accounts.bob), None);
+
+            assert_eq!(
+                epr.update_biodata(accounts.alice, accounts.bob, biodata("Bob", "details", vec![0; MAX_VECTOR_LEN])),
+                Ok(())
+            );
+            let contract = ink::env::test::callee::<ink::env::DefaultEnvironment>();
+            let reads = || ink::env::test::get_contract_storage_rw::<ink::env::DefaultEnvironment>(&contract).0;
+
+            // The existence check reads the access checks and the metadata key only.
+            let before = reads();
+            assert!(epr.view_redactions(accounts.bob, CONSENT_BIODATA).is_some());
+            let check_reads = reads() - before;
+            let before = reads();
+            assert!(epr.has_biodata(accounts.alice, accounts.bob));
+            assert!(reads() - before <= check_reads + 1);
+            assert!(!epr.has_notes(accounts.alice, accounts.bob));
+            assert_eq!(epr.update_clinical_notes(accounts.alice, accounts.bob, notes("checkup")), Ok(()));
+            assert!(epr.has_notes(accounts.alice, accounts.bob));
+
+            // Without access the answer is always false.
+            set_caller(accounts.charlie);
+            assert!(!epr.has_biodata(accounts.charlie, accounts.bob));
+            assert!(!epr.has_notes(accounts.charlie, accounts.bob));
+        }
+
+        #[ink::test]
+        fn biodata_field_getters_work() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            register(&mut epr, accounts.bob);
+            assert_eq!(epr.biodata_name(accounts.alice, accounts.bob), None);
+            assert_eq!(epr.biodata_finalized(accounts.alice, accounts.bob), None);
+
+            let mut data = biodata("Bob", "details", vec![1, 2, 3]);
+            data.finalized = true;
+            assert_eq!(epr.update_biodata(accounts.alice, accounts.bob, data), Ok(()));
+            assert_eq!(epr.biodata_name(accounts.alice, accounts.bob), Some(String::from("Bob")));
+            assert_eq!(epr.biodata_details(accounts.alice, accounts.bob), Some(String::from("details")));
+            assert_eq!(epr.biodata_payload(accounts.alice, accounts.bob), Some(vec![1, 2, 3]));
+            assert_eq!(epr.biodata_finalized(accounts.alice, accounts.bob), Some(true));
+
+            // Without access every getter returns None.
+            assert_eq!(epr.biodata_name(accounts.charlie, accounts.bob), None);
+            assert_eq!(epr.biodata_details(accounts.charlie, accounts.bob), None);
+            assert_eq!(epr.biodata_payload(accounts.charlie, accounts.bob), None);
+            assert_eq!(epr.biodata_finalized(accounts.charlie, accounts.bob), None);
+        }
+
+        #[ink::test]
+        fn notes_field_getters_work() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            register(&mut epr, accounts.bob);
+            assert_eq!(epr.notes_name(accounts.alice, accounts.bob), None);
+            assert_eq!(epr.notes_finalized(accounts.alice, accounts.bob), None);
+
+            let mut data = notes("checkup");
+            data.vector = vec![4, 5];
+            assert_eq!(epr.update_clinical_notes(accounts.alice, accounts.bob, data), Ok(()));
+            assert_eq!(epr.notes_name(accounts.alice, accounts.bob), Some(String::from("checkup")));
+            assert_eq!(epr.notes_details(accounts.alice, accounts.bob), Some(String::from("details")));
+            assert_eq!(epr.notes_payload(accounts.alice, accounts.bob), Some(vec![4, 5]));
+            assert_eq!(epr.notes_finalized(accounts.alice, accounts.bob), Some(false));
+
+            // Without access every getter returns None.
+            assert_eq!(epr.notes_name(accounts.charlie, accounts.bob), None);
+            assert_eq!(epr.notes_details(accounts.charlie, accounts.bob), None);
+            assert_eq!(epr.notes_payload(accounts.charlie, accounts.bob), None);
+            assert_eq!(epr.notes_finalized(accounts.charlie, accounts.bob), None);
+        }
+
+        #[ink::test]
+        fn snapshots_verify_against_historical_bundles() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let terms = standard_terms(&mut epr);
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            register(&mut epr, accounts.bob);
+            assert_eq!(epr.update_biodata(accounts.alice, accounts.bob, biodata("Bob", "details", vec![1])), Ok(()));
+
+            // Snapshots need read access to both the biodata and the clinical notes.
+            set_caller(accounts.charlie);
+            assert!(epr.snapshot_patient(accounts.bob).is_err());
+            assert!(epr.snapshots_of(accounts.bob, 0, 10).is_err());
+            set_caller(accounts.bob);
+            assert_eq!(epr.grant_consent(accounts.charlie, CONSENT_BIODATA, terms, None), Ok(()));
+            set_caller(accounts.charlie);
+            assert!(epr.snapshot_bundle(accounts.bob).is_err());
+            set_caller(accounts.bob);
+            assert_eq!(epr.grant_consent(accounts.charlie, CONSENT_BIODATA | CONSENT_CLINICAL_NOTES, terms, None), Ok(()));
+
+            set_caller(accounts.charlie);
+            let first_bytes = scale::Encode::encode(&epr.snapshot_bundle(accounts.bob).unwrap());
+            let first = epr.snapshot_patient(accounts.bob).unwrap();
+
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            set_caller(accounts.alice);
+            assert_eq!(epr.update_biodata(accounts.alice, accounts.bob, biodata("Bobby", "details", vec![2])), Ok(()));
+            assert_eq!(epr.update_clinical_notes(accounts.alice, accounts.bob, notes("checkup")), Ok(()));
+            let second_bytes = scale::Encode::encode(&epr.snapshot_bundle(accounts.bob).unwrap());
+            let second = epr.snapshot_patient(accounts.bob).unwrap();
+            assert_ne!(first, second);
+
+            let log = epr.snapshots_of(accounts.bob, 0, 10).unwrap();
+            assert_eq!(log.len(), 2);
+            assert_eq!(log[0], Snapshot { hash: first, block_number: 0, taker: accounts.charlie });
+            assert_eq!(log[1], Snapshot { hash: second, block_number: 1, taker: accounts.alice });
+            assert_eq!(epr.snapshots_of(accounts.bob, 1, 10).unwrap().len(), 1);
+
+            // Each snapshot verifies against the bundle of its own block only.
+            assert!(epr.verify_snapshot(accounts.bob, 0, first_bytes.clone()));
+            assert!(epr.verify_snapshot(accounts.bob, 1, second_bytes.clone()));
+            assert!(!epr.verify_snapshot(accounts.bob, 0, second_bytes));
+            assert!(!epr.verify_snapshot(accounts.bob, 1, first_bytes.clone()));
+            assert!(!epr.verify_snapshot(accounts.bob, 2, first_bytes));
+
+            // Redactions apply to the bundle, and a VIP patient's snapshots need a co-authorization.
+            set_caller(accounts.bob);
+            assert_eq!(epr.set_redactions(accounts.bob, accounts.charlie, REDACT_DETAILS), Ok(()));
+            set_caller(accounts.charlie);
+            let redacted = epr.snapshot_bundle(accounts.bob).unwrap();
+            assert!(redacted.biodata.unwrap().details.is_empty());
+            set_caller(accounts.bob);
+            let full = epr.snapshot_bundle(accounts.bob).unwrap();
+            assert_eq!(full.biodata.unwrap().details, "details");
+            assert_ne!(redacted.notes_hash, full.notes_hash);
+            set_caller(accounts.alice);
+            assert_eq!(epr.set_vip(accounts.bob, true), Ok(()));
+            set_caller(accounts.charlie);
+            assert_eq!(epr.snapshot_patient(accounts.bob), Err(Error::CoAuthorizationRequired));
+            assert_eq!(epr.snapshots_of(accounts.bob, 0, 10), Err(Error::CoAuthorizationRequired));
+        }
+
+        #[ink::test]
+        fn role_batches_report_each_entry() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let staff = AccountId::from([0x10; 32]);
+            let unverified = AccountId::from([0x11; 32]);
+            let verified = AccountId::from([0x12; 32]);
+            for provider in [unverified, verified] {
+                epr.providers.insert(provider, &Provider { verified: false, registered_at: 0, deposit: 0 });
+            }
+            assert_eq!(epr.verify_provider(verified), Ok(()));
+
+            let results = epr
+                .grant_roles_batch(vec![
+                    (staff, Role::Staff, None),
+                    (unverified, Role::Provider, None),
+                    (verified, Role::Provider, None),
+                    (staff, Role::Staff, None),
+                    (accounts.django, Role::Auditor, None)
+                ])
+                .unwrap();
+            assert_eq!(
+                results,
+                vec![Ok(()), Err(Error::ProviderNotVerified), Ok(()), Err(Error::RoleAlreadyGranted), Ok(())]
+            );
+            // The successful entries took effect, the failed ones did not.
+            assert_eq!(epr.role_of(staff), Some(Role::Staff));
+            assert_eq!(epr.role_of(verified), Some(Role::Provider));
+            assert_eq!(epr.role_of(unverified), None);
+            assert!(epr.can_access(&staff));
+            assert!(epr.can_access(&accounts.django));
+            assert!(!epr.can_access(&unverified));
+
+            let results = epr.revoke_roles_batch(vec![staff, unverified, staff]).unwrap();
+            assert_eq!(results, vec![Ok(()), Err(Error::RecordNotFound), Err(Error::RecordNotFound)]);
+            assert_eq!(epr.role_of(staff), None);
+            assert!(!epr.can_access(&staff));
+            assert!(epr.can_access(&verified));
+
+            // Batches are admin only and capped.
+            assert_eq!(epr.revoke_roles_batch(vec![staff; MAX_BATCH_LEN + 1]), Err(Error::BatchTooLarge));
+            set_caller(accounts.bob);
+            assert_eq!(epr.grant_roles_batch(vec![(accounts.bob, Role::Staff, None)]), Err(Error::PermissionDenied));
+            assert_eq!(epr.verify_provider(unverified), Err(Error::PermissionDenied));
+        }
+
+        #[ink::test]
+        fn consent_tokens_follow_the_marketplace() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let terms = standard_terms(&mut epr);
+            register(&mut epr, accounts.bob);
+            let marketplace = AccountId::from([0xEE; 32]);
+            let token_id = 1 | CONSENT_TOKEN_FLAG;
+
+            set_caller(accounts.bob);
+            assert_eq!(epr.mint_consent_token(accounts.charlie), Err(Error::ConsentNotFound));
+            assert_eq!(epr.grant_consent(accounts.eve, CONSENT_BIODATA, terms, Some(1_000)), Ok(()));
+            assert_eq!(epr.mint_consent_token(accounts.eve), Err(Error::ConsentNotYetActive));
+            assert_eq!(epr.grant_consent(accounts.charlie, CONSENT_BIODATA, terms, None), Ok(()));
+            // Minting itself calls the Patient contract, so the token is recorded directly here.
+            epr.consent_tokens.insert(token_id, &ConsentToken { patient: accounts.bob, holder: accounts.charlie });
+            epr.consent_token_ids.insert((accounts.bob, accounts.charlie), &token_id);
+            assert_eq!(epr.mint_consent_token(accounts.charlie), Err(Error::AlreadyRegistered));
+
+            // Only the registered marketplace can reassign the token. Registering it calls the Patient contract,
+            // so it is recorded directly here.
+            assert_eq!(epr.set_marketplace(Some(marketplace)), Err(Error::PermissionDenied));
+            set_caller(accounts.alice);
+            epr.marketplace = Some(marketplace);
+            assert_eq!(epr.reassign_consent_token(token_id, accounts.django), Err(Error::PermissionDenied));
+            set_caller(marketplace);
+            assert_eq!(epr.reassign_consent_token(token_id + 1, accounts.django), Err(Error::RecordNotFound));
+            // A buyer who already holds a token for the patient cannot take a second one.
+            epr.consent_token_ids.insert((accounts.bob, accounts.django), &(token_id + 1));
+            assert_eq!(epr.reassign_consent_token(token_id, accounts.django), Err(Error::AlreadyRegistered));
+            epr.consent_token_ids.remove((accounts.bob, accounts.django));
+            assert_eq!(epr.reassign_consent_token(token_id, accounts.django), Ok(()));
+            assert_eq!(epr.consent_token_ids.get((accounts.bob, accounts.charlie)), None);
+
+            // The consent moved to the buyer.
+            assert!(epr.consent_receipt(accounts.bob, accounts.charlie).is_none());
+            assert_eq!(epr.consent_receipt(accounts.bob, accounts.django).unwrap().scope, CONSENT_BIODATA);
+            assert_eq!(epr.consent_token(token_id), Some(ConsentToken { patient: accounts.bob, holder: accounts.django }));
+
+            // Once the patient revokes the consent, the token is dropped and cannot be resold, even after
+            // the consent is granted again.
+            set_caller(accounts.bob);
+            assert_eq!(epr.revoke_consent(accounts.django), Ok(()));
+            assert_eq!(epr.consent_token(token_id), None);
+            assert_eq!(epr.grant_consent(accounts.django, CONSENT_BIODATA, terms, None), Ok(()));
+            set_caller(marketplace);
+            assert_eq!(epr.reassign_consent_token(token_id, accounts.eve), Err(Error::RecordNotFound));
+            assert!(epr.consent_receipt(accounts.bob, accounts.django).is_some());
+
+            // The re-granted consent can be minted again; the next step is the Patient call.
+            assert_eq!(epr.consent_token_ids.get((accounts.bob, accounts.django)), None);
+        }
+
+        #[ink::test]
+        fn temporary_roles_expire() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            register(&mut epr, accounts.bob);
+            assert_eq!(epr.update_biodata(accounts.alice, accounts.bob, biodata("Bob", "details", Vec::new())), Ok(()));
+            let locum = accounts.charlie;
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(1_000);
+
+            // An expiry in the past is rejected.
+            assert_eq!(epr.grant_roles_batch(vec![(locum, Role::Staff, Some(1_000))]), Ok(vec![Err(Error::InvalidExpiry)]));
+            assert_eq!(epr.grant_roles_batch(vec![(locum, Role::Staff, Some(2_000))]), Ok(vec![Ok(())]));
+            assert_eq!(epr.role_grant(locum), Some(RoleGrant { role: Role::Staff, valid_until: Some(2_000) }));
+
+            // Before the expiry the locum has access.
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(1_999);
+            set_caller(locum);
+            assert!(epr.get_biodata(locum, accounts.bob).is_some());
+            set_caller(accounts.alice);
+            assert_eq!(epr.role_of(locum), Some(Role::Staff));
+            assert_eq!(epr.prune_expired_roles(vec![locum, accounts.alice]), Ok(0));
+
+            // From the expiry on, the grant is treated as absent even before it is pruned.
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(2_000);
+            set_caller(locum);
+            assert!(epr.get_biodata(locum, accounts.bob).is_none());
+            set_caller(accounts.alice);
+            assert_eq!(epr.update_biodata(locum, accounts.bob, biodata("Bob", "", Vec::new())), Err(Error::PermissionDenied));
+            assert_eq!(epr.role_of(locum), None);
+
+            // Anyone can prune it, which emits RoleExpired.
+            set_caller(accounts.eve);
+            assert_eq!(epr.prune_expired_roles(vec![locum, accounts.alice]), Ok(1));
+            assert!(recorded_events()
+                .iter()
+                .any(|event| matches!(event, Event::RoleExpired(RoleExpired { account, valid_until: 2_000, .. }) if *account == locum)));
+            assert_eq!(epr.prune_expired_roles(vec![locum]), Ok(0));
+            assert_eq!(epr.revoke_roles_batch(vec![locum]), Err(Error::PermissionDenied));
+
+            // A fresh grant brings the locum back.
+            set_caller(accounts.alice);
+            assert_eq!(epr.grant_roles_batch(vec![(locum, Role::Staff, Some(3_000))]), Ok(vec![Ok(())]));
+            set_caller(locum);
+            assert!(epr.get_biodata(locum, accounts.bob).is_some());
+        }
+
+        #[ink::test]
+        fn drafts_need_a_second_clinician() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.bob);
+            let (nurse, clinician, senior) = (accounts.charlie, accounts.django, accounts.eve);
+            let roles = vec![(nurse, Role::Nurse, None), (clinician, Role::Clinician, None), (senior, Role::Clinician, None)];
+            assert_eq!(epr.grant_roles_batch(roles), Ok(vec![Ok(()), Ok(()), Ok(())]));
+            assert_eq!(epr.add_user_with_permissions(accounts.frank, true), Ok(()));
+
+            // Only clinicians and nurses can submit drafts.
+            set_caller(accounts.frank);
+            assert_eq!(epr.submit_biodata_draft(accounts.bob, biodata("Bob", "", Vec::new())), Err(Error::PermissionDenied));
+            set_caller(nurse);
+            assert_eq!(epr.submit_biodata_draft(accounts.bob, biodata("Bob", "draft", Vec::new())), Ok(0));
+            set_caller(clinician);
+            assert_eq!(epr.submit_biodata_draft(accounts.bob, biodata("Bobby", "draft", Vec::new())), Ok(1));
+            assert_eq!(epr.pending_drafts(accounts.bob, 0, 10).len(), 2);
+            assert!(epr.get_biodata(clinician, accounts.bob).is_none());
+
+            // Nurses cannot review, and nobody can review their own draft.
+            set_caller(nurse);
+            assert_eq!(epr.approve_draft(accounts.bob, 1), Err(Error::PermissionDenied));
+            set_caller(clinician);
+            assert_eq!(epr.approve_draft(accounts.bob, 1), Err(Error::SelfApproval));
+            assert_eq!(epr.reject_draft(accounts.bob, 1, String::from("mine")), Err(Error::SelfApproval));
+            assert_eq!(epr.approve_draft(accounts.bob, 2), Err(Error::RecordNotFound));
+
+            // A clinician approves the nurse's draft, which becomes the live record.
+            assert_eq!(epr.approve_draft(accounts.bob, 0), Ok(()));
+            assert_eq!(epr.biodata_name(clinician, accounts.bob), Some(String::from("Bob")));
+            assert!(matches!(recorded_events().last(), Some(Event::DraftApproved(DraftApproved { index: 0, .. }))));
+            assert_eq!(epr.approve_draft(accounts.bob, 0), Err(Error::RecordNotFound));
+
+            // Another clinician rejects the remaining draft.
+            set_caller(senior);
+            assert_eq!(epr.reject_draft(accounts.bob, 1, String::from("wrong name")), Ok(()));
+            assert!(epr.pending_drafts(accounts.bob, 0, 10).is_empty());
+            assert_eq!(epr.biodata_name(senior, accounts.bob), Some(String::from("Bob")));
+        }
+
+        #[ink::test]
+        fn redactions_hide_fields_from_a_provider() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let terms = standard_terms(&mut epr);
+            let (clinician, insurer) = (accounts.alice, accounts.charlie);
+            assert_eq!(epr.add_user_with_permissions(clinician, true), Ok(()));
+            register(&mut epr, accounts.bob);
+            assert_eq!(epr.update_biodata(clinician, accounts.bob, biodata("Bob", "diabetic", vec![7])), Ok(()));
+            assert_eq!(epr.update_clinical_notes(clinician, accounts.bob, notes("visit")), Ok(()));
+
+            // Without consent, redactions give the insurer nothing to read.
+            set_caller(insurer);
+            assert_eq!(epr.get_biodata_view(accounts.bob), None);
+
+            // Bob consents to share his biodata and hides its details from the insurer.
+            set_caller(accounts.bob);
+            assert_eq!(epr.grant_consent(insurer, CONSENT_BIODATA, terms, None), Ok(()));
+            assert_eq!(epr.set_redactions(accounts.bob, insurer, 0b1000), Err(Error::InvalidRedaction));
+            assert_eq!(epr.set_redactions(accounts.bob, insurer, REDACT_DETAILS), Ok(()));
+            assert!(matches!(
+                recorded_events().last(),
+                Some(Event::RedactionsChanged(RedactionsChanged { fields: REDACT_DETAILS, .. }))
+            ));
+            set_caller(accounts.django);
+            assert_eq!(epr.set_redactions(accounts.bob, insurer, 0), Err(Error::PermissionDenied));
+
+            // The insurer sees the name and payload, but not the details; the consent does not cover the notes.
+            set_caller(insurer);
+            let view = epr.get_biodata_view(accounts.bob).unwrap();
+            assert_eq!(view.redacted, REDACT_DETAILS);
+            assert_eq!(view.record, biodata("Bob", "", vec![7]));
+            assert_eq!(epr.get_clinical_notes_view(accounts.bob), None);
+
+            // The getters that take the reader as an argument apply the same checks and redactions.
+            assert_eq!(epr.get_biodata(insurer, accounts.bob), Some(biodata("Bob", "", vec![7])));
+            assert_eq!(epr.hash_of_biodata(insurer, accounts.bob), None);
+            assert_eq!(epr.get_clinical_notes(insurer, accounts.bob), None);
+            assert!(!epr.has_notes(insurer, accounts.bob));
+            set_caller(accounts.eve);
+            assert_eq!(epr.get_biodata(insurer, accounts.bob), None);
+            assert_eq!(epr.biodata_meta(clinician, accounts.bob), None);
+            set_caller(insurer);
+
+            // The clinician and Bob see the full record.
+            set_caller(clinician);
+            let view = epr.get_biodata_view(accounts.bob).unwrap();
+            assert_eq!((view.redacted, view.record), (0, biodata("Bob", "diabetic", vec![7])));
+            set_caller(accounts.bob);
+            assert_eq!(epr.get_biodata_view(accounts.bob).unwrap().record.details, "diabetic");
+
+            // The admin can clear the redactions.
+            set_caller(accounts.alice);
+            assert_eq!(epr.set_redactions(accounts.bob, insurer, 0), Ok(()));
+            assert_eq!(epr.redactions_of(accounts.bob, insurer), 0);
+            set_caller(insurer);
+            assert_eq!(epr.get_biodata_view(accounts.bob).unwrap().record.details, "diabetic");
+        }
+
+        #[ink::test]
+        fn child_errors_carry_selector_and_discriminant() {
+            let selector = ink::selector_bytes!("mint_to");
+            let rejected: Result<ink::MessageResult<Result<(), Error>>, ink::env::Error> = Ok(Ok(Err(Error::RecordNotFound)));
+            assert_eq!(child_result(selector, rejected), Err(Error::CrossContract { selector, inner: 4 }));
+            let trapped: Result<ink::MessageResult<Result<(), Error>>, ink::env::Error> = Err(ink::env::Error::CalleeTrapped);
+            assert_eq!(child_result(selector, trapped), Err(Error::CrossContract { selector, inner: CHILD_CALL_FAILED }));
+            assert_eq!(child_result::<u32, Error>(selector, Ok(Ok(Ok(7)))), Ok(7));
+        }
+
+        #[ink::test]
+        fn guardians_manage_consent_until_revoked() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let (child, parent, provider) = (accounts.bob, accounts.charlie, accounts.django);
+            register(&mut epr, child);
+            let terms = standard_terms(&mut epr);
+
+            // Nobody else can act for the child, and the admin has to justify an appointment.
+            set_caller(parent);
+            assert_eq!(epr.grant_consent_for(child, provider, CONSENT_BIODATA, terms, None), Err(Error::PermissionDenied));
+            assert_eq!(epr.appoint_guardian(child, parent, String::from("parent"), None), Err(Error::PermissionDenied));
+            set_caller(accounts.alice);
+            assert_eq!(epr.appoint_guardian(child, parent, String::from("parent"), None), Err(Error::JustificationRequired));
+            assert_eq!(
+                epr.appoint_guardian(child, parent, String::from("parent"), Some(String::from("patient is a minor"))),
+                Ok(())
+            );
+            assert!(matches!(recorded_events().last(), Some(Event::GuardianAppointed(_))));
+            assert_eq!(epr.guardian_of(child, parent).unwrap().appointed_by, accounts.alice);
+
+            // The guardian grants consent and sets redactions as if they were the child.
+            set_caller(parent);
+            assert_eq!(epr.grant_consent_for(child, provider, CONSENT_BIODATA, terms, None), Ok(()));
+            assert!(epr.consent_receipt(child, provider).is_some());
+            assert_eq!(epr.set_redactions(child, provider, REDACT_DETAILS), Ok(()));
+            assert_eq!(epr.revoke_consent_for(child, provider), Ok(()));
+            assert!(epr.consent_receipt(child, provider).is_none());
+
+            // Guardians cannot appoint further guardians.
+            assert_eq!(epr.appoint_guardian(child, accounts.eve, String::from("aunt"), None), Err(Error::PermissionDenied));
+
+            // Once the child revokes the guardian, it can no longer act for them.
+            set_caller(child);
+            assert_eq!(epr.revoke_guardian(child, parent), Ok(()));
+            assert_eq!(epr.revoke_guardian(child, parent), Err(Error::RecordNotFound));
+            set_caller(parent);
+            assert_eq!(epr.grant_consent_for(child, provider, CONSENT_BIODATA, terms, None), Err(Error::PermissionDenied));
+            assert_eq!(epr.set_redactions(child, provider, 0), Err(Error::PermissionDenied));
+        }
+
+        fn imaging_report(study: u8) -> ImagingReport {
+            ImagingReport {
+                modality: String::from("CT"),
+                study_uid_hash: Hash::from([study; 32]),
+                report_uri: String::from("ipfs://report"),
+                impression: String::from("no acute findings"),
+                reported_by: AccountId::from([0x0; 32]),
+                reported_at: 0
+            }
+        }
+
+        #[ink::test]
+        fn imaging_reports_are_deduplicated_and_need_imaging_consent() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let terms = standard_terms(&mut epr);
+            register(&mut epr, accounts.bob);
+            let (radiologist, provider) = (accounts.charlie, accounts.django);
+            assert_eq!(epr.grant_roles_batch(vec![(radiologist, Role::Clinician, None)]), Ok(vec![Ok(())]));
+
+            // Only clinicians add reports, and each study is reported once.
+            set_caller(provider);
+            assert_eq!(epr.add_imaging_report(accounts.bob, imaging_report(1)), Err(Error::PermissionDenied));
+            set_caller(radiologist);
+            for study in 1..=5 {
+                assert_eq!(epr.add_imaging_report(accounts.bob, imaging_report(study)), Ok(u32::from(study) - 1));
+            }
+            assert_eq!(epr.add_imaging_report(accounts.bob, imaging_report(3)), Err(Error::DuplicateStudy));
+            assert!(matches!(
+                recorded_events().last(),
+                Some(Event::ImagingReportAdded(ImagingReportAdded { index: 4, .. }))
+            ));
+
+            // Reports are paged, and the contract records the reporter.
+            let page = epr.imaging_reports(accounts.bob, 2, 2);
+            assert_eq!(page.len(), 2);
+            assert_eq!(page[0].study_uid_hash, Hash::from([3; 32]));
+            assert_eq!(page[1].reported_by, radiologist);
+            assert_eq!(epr.imaging_reports(accounts.bob, 4, 10).len(), 1);
+            assert!(epr.imaging_reports(accounts.bob, 5, 10).is_empty());
+
+            // A consent for biodata does not cover imaging; one with the IMAGING bit does.
+            set_caller(accounts.bob);
+            assert_eq!(epr.grant_consent(provider, CONSENT_BIODATA, terms, None), Ok(()));
+            set_caller(provider);
+            assert!(epr.imaging_reports(accounts.bob, 0, 10).is_empty());
+            set_caller(accounts.bob);
+            assert_eq!(epr.imaging_reports(accounts.bob, 0, 10).len(), 5);
+            assert_eq!(epr.grant_consent(provider, CONSENT_BIODATA | CONSENT_IMAGING, terms, None), Ok(()));
+            set_caller(provider);
+            assert_eq!(epr.imaging_reports(accounts.bob, 0, 10).len(), 5);
+        }
+
+        #[ink::test]
+        fn aggregate_counters_follow_writes_archival_and_erasure() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            for patient in [accounts.bob, accounts.eve, accounts.frank] {
+                register(&mut epr, patient);
+            }
+            let clinician = accounts.charlie;
+            assert_eq!(epr.grant_roles_batch(vec![(clinician, Role::Clinician, None)]), Ok(vec![Ok(())]));
+
+            set_caller(clinician);
+            for (patient, vaccine) in [(accounts.bob, "MMR"), (accounts.bob, "BCG"), (accounts.eve, "MMR")] {
+                assert!(epr.record_immunization(patient, String::from(vaccine)).is_ok());
+            }
+            // I10 is in chapter IX, E11.9 in chapter IV and J45 in chapter X.
+            assert_eq!(epr.add_problem(accounts.bob, String::from("I10")), Ok(0));
+            assert_eq!(epr.add_problem(accounts.bob, String::from("E11.9")), Ok(1));
+            assert_eq!(epr.add_problem(accounts.eve, String::from("I10")), Ok(0));
+            assert_eq!(epr.add_problem(accounts.eve, String::from("J45")), Ok(1));
+            assert_eq!(epr.add_problem(accounts.eve, String::from("10I")), Err(Error::InvalidCode));
+            assert_eq!(epr.resolve_problem(accounts.eve, 1), Ok(()));
+            assert_eq!(epr.resolve_problem(accounts.eve, 1), Err(Error::ProblemResolved));
+
+            // The counters are public and reveal no record.
+            set_caller(accounts.django);
+            assert_eq!(epr.stats(), Stats { patients: 3, immunizations: 3, active_problems: 3 });
+            assert_eq!(epr.immunization_count(String::from("MMR")), 2);
+            assert_eq!(epr.immunization_count(String::from("BCG")), 1);
+            assert_eq!(epr.problem_count(String::from("IX")), 2);
+            assert_eq!(epr.problem_count(String::from("IV")), 1);
+            assert_eq!(epr.problem_count(String::from("X")), 0);
+            assert!(epr.problems(accounts.bob, 0, 10).is_empty());
+
+            // Archiving takes the patient out of the count, once.
+            set_caller(accounts.alice);
+            assert_eq!(epr.archive_patient(accounts.frank), Ok(()));
+            assert_eq!(epr.archive_patient(accounts.frank), Ok(()));
+            assert_eq!(epr.stats().patients, 2);
+
+            // Erasing a record takes its immunizations and active problems out of the counts.
+            assert_eq!(epr.erase_patient_data(accounts.bob), Ok(()));
+            assert_eq!(epr.stats(), Stats { patients: 2, immunizations: 1, active_problems: 1 });
+            assert_eq!(epr.immunization_count(String::from("MMR")), 1);
+            assert_eq!(epr.immunization_count(String::from("BCG")), 0);
+            assert_eq!(epr.problem_count(String::from("IX")), 1);
+            assert_eq!(epr.problem_count(String::from("IV")), 0);
+            set_caller(accounts.bob);
+            assert!(epr.problems(accounts.bob, 0, 10).is_empty());
+            set_caller(accounts.eve);
+            assert_eq!(epr.problems(accounts.eve, 0, 10).len(), 2);
+        }
+
+        #[ink::test]
+        fn cohorts_hold_only_research_consented_patients_with_an_active_problem() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let [bob, eve, frank] = [accounts.bob, accounts.eve, accounts.frank].map(|patient| register(&mut epr, patient));
+            let (clinician, researcher) = (accounts.charlie, accounts.django);
+            let roles = vec![(clinician, Role::Clinician, None), (researcher, Role::Researcher, None)];
+            assert_eq!(epr.grant_roles_batch(roles), Ok(vec![Ok(()), Ok(())]));
+            for patient in [accounts.bob, accounts.eve] {
+                set_caller(patient);
+                assert_eq!(epr.set_research_consent(true), Ok(()));
+            }
+
+            // Bob has two type 2 diabetes problems, Eve one and hypertension, Frank one but no research consent.
+            set_caller(clinician);
+            for (patient, code) in [(accounts.bob, "E11.9"), (accounts.bob, "E11.65"), (accounts.eve, "E11"), (accounts.eve, "I10"), (accounts.frank, "E11.9")] {
+                assert!(epr.add_problem(patient, String::from(code)).is_ok());
+            }
+            let cohort = |epr: &Epr, code: &str| {
+                let mut ids = epr.cohort_health_ids(String::from(code), 0, 10);
+                ids.sort();
+                (epr.cohort_count(String::from(code)), ids)
+            };
+
+            // Only researchers query cohorts, by whole ICD-10 category.
+            assert_eq!(cohort(&epr, "E11"), (0, Vec::new()));
+            set_caller(researcher);
+            assert_eq!(cohort(&epr, "E11"), (2, vec![bob, eve]));
+            assert_eq!(cohort(&epr, "I10"), (1, vec![eve]));
+            assert_eq!(cohort(&epr, "E1"), (0, Vec::new()));
+            assert_eq!(cohort(&epr, "E11.9"), (0, Vec::new()));
+            assert_eq!(epr.cohort_health_ids(String::from("E11"), 1, 10).len(), 1);
+
+            // A patient leaves a cohort once their last active problem in it is resolved.
+            set_caller(clinician);
+            assert_eq!(epr.resolve_problem(accounts.bob, 0), Ok(()));
+            set_caller(researcher);
+            assert_eq!(cohort(&epr, "E11"), (2, vec![bob, eve]));
+            set_caller(clinician);
+            assert_eq!(epr.resolve_problem(accounts.bob, 1), Ok(()));
+            set_caller(researcher);
+            assert_eq!(cohort(&epr, "E11"), (1, vec![eve]));
+
+            // Research consent moves patients in and out of every cohort of their active problems.
+            advance_blocks(DEFAULT_COOLDOWN);
+            set_caller(accounts.frank);
+            assert_eq!(epr.set_research_consent(true), Ok(()));
+            set_caller(accounts.eve);
+            assert_eq!(epr.set_research_consent(false), Ok(()));
+            set_caller(researcher);
+            assert_eq!(cohort(&epr, "E11"), (1, vec![frank]));
+            assert_eq!(cohort(&epr, "I10"), (0, Vec::new()));
+
+            // Erasing a record takes it out of the cohorts.
+            set_caller(accounts.alice);
+            assert_eq!(epr.erase_patient_data(accounts.frank), Ok(()));
+            set_caller(researcher);
+            assert_eq!(cohort(&epr, "E11"), (0, Vec::new()));
+        }
+
+        #[ink::test]
+        fn medication_lists_filter_active_entries_and_record_reconciliation() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.bob);
+            let clinician = accounts.charlie;
+            assert_eq!(epr.grant_roles_batch(vec![(clinician, Role::Clinician, None)]), Ok(vec![Ok(())]));
+            assert_eq!(epr.add_user_with_permissions(clinician, true), Ok(()));
+
+            set_caller(accounts.django);
+            assert_eq!(epr.add_medication(accounts.bob, String::from("Metformin"), String::from("500 mg")), Err(Error::PermissionDenied));
+            set_caller(clinician);
+            assert_eq!(epr.add_medication(accounts.bob, String::new(), String::from("500 mg")), Err(Error::EmptyPayload));
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(1_000);
+            for (name, dose) in [("Metformin", "500 mg"), ("Lisinopril", "10 mg"), ("Aspirin", "75 mg")] {
+                assert!(epr.add_medication(accounts.bob, String::from(name), String::from(dose)).is_ok());
+            }
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(2_000);
+            assert_eq!(epr.stop_medication(accounts.bob, 1), Ok(()));
+            assert!(matches!(recorded_events().last(), Some(Event::MedicationStopped(MedicationStopped { index: 1, .. }))));
+            assert_eq!(epr.stop_medication(accounts.bob, 1), Err(Error::MedicationStopped));
+            assert_eq!(epr.stop_medication(accounts.bob, 3), Err(Error::RecordNotFound));
+
+            // The active filter keeps the indexes of the list, so stopped entries leave gaps.
+            let all = epr.medications_of(accounts.bob, false, 0, 10);
+            assert_eq!(all.len(), 3);
+            assert_eq!(all[1].1.stopped_at, Some(2_000));
+            assert!(!all[1].1.active);
+            let active: Vec<u32> = epr.medications_of(accounts.bob, true, 0, 10).into_iter().map(|(i, _)| i).collect();
+            assert_eq!(active, vec![0, 2]);
+            assert_eq!(epr.medications_of(accounts.bob, true, 1, 1), Vec::new());
+            set_caller(accounts.django);
+            assert!(epr.medications_of(accounts.bob, false, 0, 10).is_empty());
+
+            // Reconciliation is stamped on an existing encounter with the clinician and the time.
+            set_caller(clinician);
+            assert_eq!(epr.reconcile_medications(accounts.bob, 0), Err(Error::RecordNotFound));
+            let encounter = epr.open_encounter(accounts.bob).unwrap();
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(3_000);
+            assert_eq!(epr.reconcile_medications(accounts.bob, encounter), Ok(()));
+            assert!(matches!(recorded_events().last(), Some(Event::MedicationsReconciled(_))));
+            assert_eq!(
+                epr.medication_reconciliation(accounts.bob, encounter),
+                Some(MedicationReconciliation { encounter_index: encounter, clinician, reconciled_at: 3_000 })
+            );
+            set_caller(accounts.bob);
+            assert!(epr.medication_reconciliation(accounts.bob, encounter).is_some());
+            set_caller(accounts.django);
+            assert_eq!(epr.medication_reconciliation(accounts.bob, encounter), None);
+        }
+
+        #[ink::test]
+        fn change_log_numbers_every_write_without_gaps() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let terms = standard_terms(&mut epr);
+            register(&mut epr, accounts.bob);
+            let clinician = accounts.charlie;
+            assert_eq!(epr.grant_roles_batch(vec![(clinician, Role::Clinician, None)]), Ok(vec![Ok(())]));
+            assert_eq!(epr.add_user_with_permissions(clinician, true), Ok(()));
+
+            set_caller(clinician);
+            assert_eq!(epr.update_clinical_notes(clinician, accounts.bob, notes("visit")), Ok(()));
+            let encounter = epr.open_encounter(accounts.bob).unwrap();
+            assert!(epr.add_medication(accounts.bob, String::from("Metformin"), String::from("500 mg")).is_ok());
+            // A failed write leaves no entry behind.
+            assert_eq!(epr.stop_medication(accounts.bob, 7), Err(Error::RecordNotFound));
+            assert_eq!(epr.close_encounter(accounts.bob, encounter), Ok(()));
+            set_caller(accounts.bob);
+            assert_eq!(epr.grant_consent(accounts.django, CONSENT_BIODATA, terms, None), Ok(()));
+
+            // A consent sold through the marketplace is logged as moving from the seller to the buyer.
+            let marketplace = AccountId::from([0xEE; 32]);
+            let token_id = 1 | CONSENT_TOKEN_FLAG;
+            epr.consent_tokens.insert(token_id, &ConsentToken { patient: accounts.bob, holder: accounts.django });
+            epr.consent_token_ids.insert((accounts.bob, accounts.django), &token_id);
+            epr.marketplace = Some(marketplace);
+            set_caller(marketplace);
+            assert_eq!(epr.reassign_consent_token(token_id, accounts.eve), Ok(()));
+            set_caller(accounts.bob);
+            assert_eq!(epr.revoke_consent(accounts.eve), Ok(()));
+
+            assert_eq!(epr.latest_seq(accounts.bob), Ok(8));
+            let log = epr.change_log_of(accounts.bob, 0, 10).unwrap();
+            let seqs: Vec<u64> = log.iter().map(|(seq, _)| *seq).collect();
+            assert_eq!(seqs, vec![1, 2, 3, 4, 5, 6, 7, 8]);
+            let kinds: Vec<u8> = log.iter().map(|(_, entry)| entry.kind).collect();
+            assert_eq!(
+                kinds,
+                vec![
+                    CHANGE_NOTES,
+                    CHANGE_ENCOUNTER,
+                    CHANGE_MEDICATION,
+                    CHANGE_ENCOUNTER,
+                    CHANGE_CONSENT_GRANTED,
+                    CHANGE_CONSENT_REVOKED,
+                    CHANGE_CONSENT_GRANTED,
+                    CHANGE_CONSENT_REVOKED
+                ]
+            );
+            assert_eq!(log[0].1.actor, clinician);
+            assert_eq!((log[5].1.actor, log[6].1.actor), (marketplace, marketplace));
+            assert_eq!(log[7].1.actor, accounts.bob);
+            assert_eq!(log[0].1.ref_hash, epr.hash_of(&notes("visit")));
+            let page = epr.change_log_of(accounts.bob, 5, 10).unwrap();
+            assert_eq!(page.iter().map(|(seq, _)| *seq).collect::<Vec<_>>(), vec![5, 6, 7, 8]);
+            assert!(epr.change_log_of(accounts.bob, 9, 10).unwrap().is_empty());
+
+            // Archiving is logged once, by whoever archived the record.
+            epr.archive(accounts.bob);
+            epr.archive(accounts.bob);
+            assert_eq!(epr.latest_seq(accounts.bob), Ok(9));
+
+            // Only the patient, their guardians, auditors and the admin can read the log.
+            set_caller(clinician);
+            assert_eq!(epr.latest_seq(accounts.bob), Err(Error::PermissionDenied));
+            assert_eq!(epr.change_log_of(accounts.bob, 1, 10), Err(Error::PermissionDenied));
+            set_caller(accounts.alice);
+            assert_eq!(epr.latest_seq(accounts.bob), Ok(9));
+            assert_eq!(epr.grant_roles_batch(vec![(accounts.eve, Role::Auditor, None)]), Ok(vec![Ok(())]));
+            set_caller(accounts.eve);
+            assert_eq!(epr.change_log_of(accounts.bob, 9, 1).unwrap()[0].1.kind, CHANGE_ARCHIVED);
+        }
+
+        #[ink::test]
+        fn subscriptions_follow_the_change_log_until_consent_is_revoked() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let terms = standard_terms(&mut epr);
+            register(&mut epr, accounts.bob);
+            let clinician = accounts.charlie;
+            let engine = accounts.django;
+            assert_eq!(epr.grant_roles_batch(vec![(clinician, Role::Clinician, None)]), Ok(vec![Ok(())]));
+            assert_eq!(epr.add_user_with_permissions(clinician, true), Ok(()));
+
+            // Without any consent the engine cannot subscribe.
+            set_caller(engine);
+            assert_eq!(epr.subscribe(accounts.bob), Err(Error::PermissionDenied));
+            assert_eq!(epr.subscribe(accounts.eve), Err(Error::RecordNotFound));
+            set_caller(accounts.bob);
+            assert_eq!(epr.grant_consent(engine, CONSENT_BIODATA, terms, None), Ok(()));
+
+            // The subscription starts at the latest entry, here the consent itself.
+            set_caller(engine);
+            assert_eq!(epr.subscribe(accounts.bob), Ok(()));
+            assert_eq!(epr.subscribe(accounts.bob), Ok(()));
+            let subscriptions = epr.my_subscriptions(0, 10);
+            assert_eq!(subscriptions.len(), 1);
+            assert_eq!(subscriptions[0].cursor, 1);
+
+            set_caller(clinician);
+            let encounter = epr.open_encounter(accounts.bob).unwrap();
+            assert!(epr.add_problem(accounts.bob, String::from("I10")).is_ok());
+            assert_eq!(epr.close_encounter(accounts.bob, encounter), Ok(()));
+
+            // The engine fetches from its cursor in pages and moves the cursor along.
+            set_caller(engine);
+            let page = epr.changes_since(accounts.bob, 1, 2).unwrap();
+            assert_eq!(page.iter().map(|entry| entry.kind).collect::<Vec<_>>(), vec![CHANGE_ENCOUNTER, CHANGE_PROBLEM]);
+            assert_eq!(epr.advance_cursor(accounts.bob, 3), Ok(()));
+            assert_eq!(epr.advance_cursor(accounts.bob, 2), Err(Error::NotAllowed));
+            assert_eq!(epr.advance_cursor(accounts.bob, 5), Err(Error::NotAllowed));
+            assert_eq!(epr.my_subscriptions(0, 10)[0].cursor, 3);
+            let rest = epr.changes_since(accounts.bob, 3, 10).unwrap();
+            assert_eq!(rest.len(), 1);
+            assert_eq!(rest[0].kind, CHANGE_ENCOUNTER);
+            assert!(epr.changes_since(accounts.bob, 4, 10).unwrap().is_empty());
+
+            // Once the consent is revoked, the subscription no longer yields anything.
+            set_caller(accounts.bob);
+            assert_eq!(epr.revoke_consent(engine), Ok(()));
+            set_caller(engine);
+            assert_eq!(epr.changes_since(accounts.bob, 3, 10), Err(Error::PermissionDenied));
+            assert_eq!(epr.unsubscribe(accounts.bob), Ok(()));
+            assert!(epr.my_subscriptions(0, 10).is_empty());
+            assert_eq!(epr.unsubscribe(accounts.bob), Err(Error::SubscriptionNotFound));
+            assert_eq!(epr.changes_since(accounts.bob, 0, 10), Err(Error::SubscriptionNotFound));
+        }
+
+        #[ink::test]
+        fn emergency_access_expires_and_waits_for_review() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.bob);
+            register(&mut epr, accounts.eve);
+            let clinician = accounts.charlie;
+            let auditor = accounts.django;
+            let roles = vec![(clinician, Role::Clinician, None), (auditor, Role::Auditor, None)];
+            assert_eq!(epr.grant_roles_batch(roles), Ok(vec![Ok(()), Ok(())]));
+            // The clinician keeps the role but has no standing access to records.
+            assert_eq!(epr.add_user_with_permissions(clinician, false), Ok(()));
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(1_000);
+
+            set_caller(clinician);
+            assert_eq!(epr.explain_access(clinician, accounts.bob), None);
+            assert_eq!(epr.emergency_access(accounts.bob, String::new()), Err(Error::JustificationRequired));
+            assert_eq!(epr.emergency_access(accounts.bob, String::from("unconscious on arrival")), Ok(0));
+            assert_eq!(epr.emergency_access_until(accounts.bob, clinician), Some(1_000 + DEFAULT_EMERGENCY_WINDOW));
+            assert_eq!(epr.pending_reviews_of(clinician), vec![0]);
+
+            // Reads within the window go through without repeating the reason.
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(1_000 + DEFAULT_EMERGENCY_WINDOW - 1);
+            assert_eq!(epr.explain_access(clinician, accounts.bob), Some(AccessPath::Emergency));
+            assert_eq!(epr.expire_emergency_access(accounts.bob, clinician), Err(Error::NotAllowed));
+
+            // After the window the access lapses and anyone can clear it.
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(1_000 + DEFAULT_EMERGENCY_WINDOW);
+            assert_eq!(epr.explain_access(clinician, accounts.bob), None);
+            set_caller(accounts.frank);
+            assert_eq!(epr.expire_emergency_access(accounts.bob, clinician), Ok(()));
+            assert_eq!(epr.emergency_access_until(accounts.bob, clinician), None);
+            assert!(recorded_events().iter().any(|event| matches!(event, Event::EmergencyAccessExpired(_))));
+
+            // An item left unreviewed for longer than the review period blocks further emergency access.
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(1_000 + DEFAULT_REVIEW_PERIOD);
+            set_caller(clinician);
+            assert_eq!(epr.emergency_access(accounts.eve, String::from("anaphylaxis")), Err(Error::PendingReview));
+            assert_eq!(epr.close_review(0, true), Err(Error::PermissionDenied));
+
+            // A justified closure clears the block.
+            set_caller(auditor);
+            assert_eq!(epr.close_review(0, true), Ok(()));
+            assert_eq!(epr.close_review(0, false), Err(Error::AlreadyReviewed));
+            assert_eq!(epr.close_review(9, false), Err(Error::RecordNotFound));
+            let item = epr.review_item(0).unwrap();
+            assert_eq!((item.reviewed_by, item.justified), (Some(auditor), Some(true)));
+            assert!(recorded_events().iter().any(|event| matches!(event, Event::ReviewClosed(ReviewClosed { review_id: 0, justified: true, .. }))));
+            set_caller(clinician);
+            assert!(epr.pending_reviews_of(clinician).is_empty());
+            assert_eq!(epr.emergency_access(accounts.eve, String::from("anaphylaxis")), Ok(1));
+            assert_eq!(epr.explain_access(clinician, accounts.eve), Some(AccessPath::Emergency));
+
+            // A clinician who later becomes an auditor still cannot close the review of their own access.
+            set_caller(accounts.alice);
+            assert_eq!(epr.grant_roles_batch(vec![(clinician, Role::Auditor, None)]), Ok(vec![Ok(())]));
+            set_caller(clinician);
+            assert_eq!(epr.close_review(1, true), Err(Error::SelfApproval));
+            set_caller(auditor);
+            assert_eq!(epr.close_review(1, true), Ok(()));
+        }
+
+        #[ink::test]
+        fn open_quality_flags_block_finalizing_biodata() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            register(&mut epr, accounts.bob);
+            let steward = accounts.charlie;
+            assert_eq!(epr.grant_roles_batch(vec![(steward, Role::DataSteward, None)]), Ok(vec![Ok(())]));
+
+            // Only data stewards raise flags, of a known kind, on existing records.
+            set_caller(accounts.django);
+            assert_eq!(epr.raise_flag(accounts.bob, FLAG_POSSIBLE_DUPLICATE, String::from("see 42")), Err(Error::PermissionDenied));
+            set_caller(steward);
+            assert_eq!(epr.raise_flag(accounts.bob, 9, String::new()), Err(Error::InvalidCode));
+            assert_eq!(epr.raise_flag(accounts.eve, FLAG_POSSIBLE_DUPLICATE, String::new()), Err(Error::RecordNotFound));
+            assert_eq!(epr.raise_flag(accounts.bob, FLAG_POSSIBLE_DUPLICATE, String::from("see 42")), Ok(()));
+            assert!(matches!(
+                recorded_events().last(),
+                Some(Event::QualityFlagRaised(QualityFlagRaised { kind: FLAG_POSSIBLE_DUPLICATE, .. }))
+            ));
+
+            // The flag blocks finalization, not ordinary updates.
+            set_caller(accounts.alice);
+            assert_eq!(epr.update_biodata(accounts.alice, accounts.bob, biodata("Bob", "", Vec::new())), Ok(()));
+            let mut data = biodata("Bob", "", Vec::new());
+            data.finalized = true;
+            assert_eq!(epr.update_biodata(accounts.alice, accounts.bob, data.clone()), Err(Error::OpenQualityFlags));
+            assert_eq!(epr.biodata_finalized(accounts.alice, accounts.bob), Some(false));
+
+            // Once resolved, the flag stays on the record and in the export bundle.
+            set_caller(steward);
+            assert_eq!(epr.resolve_flag(accounts.bob, FLAG_UNVERIFIED_IDENTITY), Err(Error::RecordNotFound));
+            assert_eq!(epr.resolve_flag(accounts.bob, FLAG_POSSIBLE_DUPLICATE), Ok(()));
+            assert!(matches!(recorded_events().last(), Some(Event::QualityFlagResolved(_))));
+            assert_eq!(epr.resolve_flag(accounts.bob, FLAG_POSSIBLE_DUPLICATE), Err(Error::RecordNotFound));
+            let flags = epr.flags_of(accounts.bob);
+            assert_eq!(flags.len(), 1);
+            assert_eq!(flags[0].0, FLAG_POSSIBLE_DUPLICATE);
+            assert!(flags[0].1.resolved);
+            assert_eq!(epr.bundle_of(accounts.bob).unwrap().quality_flags, flags);
+            set_caller(accounts.django);
+            assert!(epr.flags_of(accounts.bob).is_empty());
+
+            set_caller(accounts.alice);
+            assert_eq!(epr.update_biodata(accounts.alice, accounts.bob, data), Ok(()));
+            assert_eq!(epr.biodata_finalized(accounts.alice, accounts.bob), Some(true));
+        }
+
+        // Checks that every flag of an account's access summary matches what the messages let it do.
+        fn assert_summary_matches_behavior(epr: &mut Epr, subject: AccountId, patient: AccountId) -> AccessSummary {
+            let summary = epr.access_summary(subject, Some(patient));
+            set_caller(subject);
+            assert_eq!(epr.get_biodata_view(patient).is_some(), summary.can_read_biodata);
+            assert_eq!(epr.get_clinical_notes_view(patient).is_some(), summary.can_read_notes);
+            assert_eq!(!epr.imaging_reports(patient, 0, 1).is_empty(), summary.can_read_imaging);
+            assert_eq!(epr.update_clinical_notes(subject, patient, notes("visit")).is_ok(), summary.can_write_notes);
+            assert_eq!(epr.add_problem(patient, String::from("I10")).is_ok(), summary.can_write_clinical);
+            assert_eq!(
+                epr.set_redactions(patient, AccountId::from([0x12; 32]), 0).is_ok(),
+                summary.is_guardian || subject == patient
+            );
+            summary
+        }
+
+        #[ink::test]
+        fn access_summary_agrees_with_enforcement() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let terms = standard_terms(&mut epr);
+            let patient = accounts.bob;
+            let (staff, member, provider, guardian) = (accounts.charlie, accounts.django, accounts.eve, accounts.frank);
+            let (clinician, stranger) = (AccountId::from([0x10; 32]), AccountId::from([0x11; 32]));
+            register(&mut epr, patient);
+
+            assert_eq!(epr.add_user_with_permissions(staff, true), Ok(()));
+            let ward = epr.create_group(String::from("Ward 4"), accounts.alice).expect("admin creates groups");
+            assert_eq!(epr.add_group_with_permissions(ward, true), Ok(()));
+            assert_eq!(epr.add_to_group(ward, member), Ok(()));
+            assert_eq!(epr.grant_roles_batch(vec![(clinician, Role::Clinician, Some(1_000))]), Ok(vec![Ok(())]));
+            let justification = Some(String::from("patient is a minor"));
+            assert_eq!(epr.appoint_guardian(patient, guardian, String::from("parent"), justification), Ok(()));
+            set_caller(staff);
+            assert_eq!(epr.update_biodata(staff, patient, biodata("Bob", "details", Vec::new())), Ok(()));
+            assert_eq!(epr.update_clinical_notes(staff, patient, notes("intake")), Ok(()));
+            set_caller(clinician);
+            assert_eq!(epr.add_imaging_report(patient, imaging_report(1)), Ok(0));
+            set_caller(patient);
+            assert_eq!(epr.grant_consent(provider, CONSENT_BIODATA, terms, None), Ok(()));
+
+            // The patient reads everything about themselves but has no write access.
+            let summary = assert_summary_matches_behavior(&mut epr, patient, patient);
+            assert!(summary.can_read_imaging && !summary.can_write_notes && summary.role.is_none());
+
+            // Staff with a direct permission read and write, but are not clinicians.
+            let summary = assert_summary_matches_behavior(&mut epr, staff, patient);
+            assert!(summary.can_read_notes && summary.can_write_notes && !summary.can_write_clinical && !summary.via_group);
+
+            // Group members get the same access through their group.
+            let summary = assert_summary_matches_behavior(&mut epr, member, patient);
+            assert!(summary.via_group && summary.can_write_notes);
+
+            // A consent only opens the scopes it covers.
+            let summary = assert_summary_matches_behavior(&mut epr, provider, patient);
+            assert_eq!(summary.consent_scope, Some(CONSENT_BIODATA));
+            assert!(summary.can_read_biodata && !summary.can_read_notes && !summary.can_write_notes);
+
+            // Guardians act for the patient without reading the record.
+            let summary = assert_summary_matches_behavior(&mut epr, guardian, patient);
+            assert!(summary.is_guardian && !summary.can_read_biodata);
+
+            // A clinician writes clinical data until the role expires.
+            let summary = assert_summary_matches_behavior(&mut epr, clinician, patient);
+            assert_eq!((summary.role, summary.role_expiry), (Some(Role::Clinician), Some(1_000)));
+            assert!(summary.can_write_clinical);
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(1_000);
+            let summary = assert_summary_matches_behavior(&mut epr, clinician, patient);
+            assert!(summary.role.is_none() && !summary.can_write_clinical && !summary.can_read_biodata);
+
+            // A stranger can do nothing, and without a patient only the account-wide fields are filled in.
+            let summary = assert_summary_matches_behavior(&mut epr, stranger, patient);
+            assert_eq!(summary, epr.access_summary(stranger, None));
+            assert!(!epr.access_summary(staff, None).can_read_biodata);
+        }
+
+        #[ink::test]
+        fn sharing_agreements_need_both_organizations() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let (hospital_a, hospital_b) = (accounts.charlie, accounts.django);
+            let (doctor_a, doctor_b) = (AccountId::from([0x21; 32]), AccountId::from([0x22; 32]));
+            register(&mut epr, accounts.bob);
+            epr.patient_biodata.insert(accounts.bob, &biodata("Bob", "details", Vec::new()));
+            epr.patient_notes.insert(accounts.bob, &notes("visit"));
+            for org in [hospital_a, hospital_b] {
+                epr.providers.insert(org, &Provider { verified: true, registered_at: 0, deposit: 0 });
+            }
+
+            // An invited account only joins once it accepts.
+            set_caller(hospital_a);
+            assert_eq!(epr.add_org_member(doctor_a), Ok(()));
+            assert_eq!(epr.org_of(doctor_a), None);
+            set_caller(doctor_a);
+            assert_eq!(epr.accept_org_invite(hospital_b), Err(Error::RecordNotFound));
+            assert_eq!(epr.accept_org_invite(hospital_a), Ok(()));
+            assert_eq!(epr.org_of(doctor_a), Some(hospital_a));
+            assert_eq!(epr.accept_org_invite(hospital_a), Err(Error::RecordNotFound));
+            set_caller(hospital_a);
+            assert_eq!(epr.propose_agreement(accounts.eve, CONSENT_BIODATA, 1_000), Err(Error::ProviderNotVerified));
+            assert_eq!(epr.propose_agreement(hospital_b, CONSENT_BIODATA, 0), Err(Error::InvalidExpiry));
+            assert_eq!(epr.propose_agreement(hospital_b, CONSENT_BIODATA, 1_000), Ok(()));
+            assert_eq!(epr.approve_agreement(hospital_b), Err(Error::AlreadyApproved));
+            join_org(&mut epr, hospital_b, doctor_b);
+            assert_eq!(epr.add_org_member(doctor_a), Err(Error::AlreadyMember));
+            set_caller(accounts.bob);
+            assert_eq!(epr.add_to_care_team(accounts.bob, doctor_a), Ok(()));
+
+            // A one-sided agreement gives Hospital B nothing.
+            set_caller(doctor_b);
+            assert!(epr.get_biodata_view(accounts.bob).is_none());
+
+            // Once Hospital B approves, its members read what the agreement covers, and nothing else.
+            set_caller(hospital_b);
+            assert_eq!(epr.approve_agreement(hospital_a), Ok(()));
+            assert!(matches!(
+                recorded_events().last(),
+                Some(Event::AgreementApproved(AgreementApproved { active: true, .. }))
+            ));
+            set_caller(doctor_b);
+            assert!(epr.get_biodata_view(accounts.bob).is_some());
+            assert!(epr.get_clinical_notes_view(accounts.bob).is_none());
+            assert_eq!(epr.authorize(&doctor_b, &accounts.bob, Action::Read(CONSENT_BIODATA)), Ok(AccessPath::Agreement(hospital_a)));
+            assert_eq!(epr.check_write(&doctor_b, &accounts.bob), Err(Error::PermissionDenied));
+
+            // It only covers patients cared for by the other organization.
+            set_caller(accounts.bob);
+            assert_eq!(epr.remove_from_care_team(accounts.bob, doctor_a), Ok(()));
+            set_caller(doctor_b);
+            assert!(epr.get_biodata_view(accounts.bob).is_none());
+            set_caller(accounts.bob);
+            assert_eq!(epr.add_to_care_team(accounts.bob, doctor_a), Ok(()));
+
+            // After expiry the agreement stops counting, and anyone can clear it.
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(1_000);
+            set_caller(doctor_b);
+            assert!(epr.get_biodata_view(accounts.bob).is_none());
+            assert_eq!(epr.expire_agreement(hospital_b, hospital_a), Ok(()));
+            assert!(matches!(recorded_events().last(), Some(Event::AgreementExpired(_))));
+            assert_eq!(epr.agreement(hospital_a, hospital_b), None);
+            assert_eq!(epr.expire_agreement(hospital_a, hospital_b), Err(Error::AgreementNotFound));
+        }
+
+        #[ink::test]
+        fn facility_grants_reach_every_descendant_up_to_the_depth_cap() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.eve);
+            let chief = accounts.bob;
+            let (nurse, registrar) = (accounts.charlie, accounts.django);
+
+            let hospital = epr.create_facility(String::from("St Mary"), None, chief).unwrap();
+            set_caller(chief);
+            assert_eq!(epr.create_facility(String::from("St John"), None, chief), Err(Error::PermissionDenied));
+            let cardiology = epr.create_facility(String::from("cardiology"), Some(hospital), chief).unwrap();
+            let ward = epr.create_facility(String::from("ward 3"), Some(cardiology), chief).unwrap();
+            let bay = epr.create_facility(String::from("bay A"), Some(ward), chief).unwrap();
+            assert_eq!(epr.create_facility(String::from("bed 1"), Some(bay), chief), Err(Error::FacilityTooDeep));
+            assert_eq!(epr.create_facility(String::from("x"), Some(99), chief), Err(Error::FacilityNotFound));
+            assert_eq!(epr.children_of(hospital, 0, 10), vec![cardiology]);
+            assert_eq!(epr.facility(ward).unwrap().parent, Some(cardiology));
+
+            assert_eq!(epr.assign_staff(bay, nurse), Ok(()));
+            assert_eq!(epr.assign_staff(hospital, registrar), Ok(()));
+            assert_eq!(epr.assign_staff(bay, nurse), Err(Error::AlreadyMember));
+            assert_eq!(epr.explain_access(nurse, accounts.eve), None);
+
+            // Access granted to the department reaches the staff of every node below it, and only them.
+            assert_eq!(epr.set_facility_permissions(cardiology, true), Err(Error::PermissionDenied));
+            set_caller(accounts.alice);
+            assert_eq!(epr.set_facility_permissions(cardiology, true), Ok(()));
+            assert_eq!(epr.explain_access(nurse, accounts.eve), Some(AccessPath::Facility(cardiology)));
+            assert_eq!(epr.explain_access(registrar, accounts.eve), None);
+
+            // Moving a staff member changes what they inherit right away.
+            set_caller(chief);
+            assert_eq!(epr.assign_staff(hospital, nurse), Ok(()));
+            assert_eq!(epr.facility_of(nurse), Some(hospital));
+            assert_eq!(epr.staff_of(hospital, 0, 10), vec![registrar, nurse]);
+            assert!(epr.staff_of(bay, 0, 10).is_empty());
+            assert_eq!(epr.explain_access(nurse, accounts.eve), None);
+            assert_eq!(epr.assign_staff(ward, nurse), Ok(()));
+            assert_eq!(epr.explain_access(nurse, accounts.eve), Some(AccessPath::Facility(cardiology)));
+
+            // The admin of a node only manages the nodes below it.
+            assert_eq!(epr.set_facility_admin(ward, accounts.frank), Ok(()));
+            set_caller(accounts.frank);
+            assert_eq!(epr.assign_staff(cardiology, accounts.frank), Err(Error::PermissionDenied));
+            assert_eq!(epr.unassign_staff(registrar), Err(Error::PermissionDenied));
+            assert_eq!(epr.unassign_staff(nurse), Ok(()));
+            assert_eq!(epr.facility_of(nurse), None);
+            assert_eq!(epr.explain_access(nurse, accounts.eve), None);
+            assert_eq!(epr.unassign_staff(nurse), Err(Error::NotMember));
+        }
+
+        #[ink::test]
+        fn error_catalog_names_every_variant_in_order() {
+            let epr = new_epr();
+            let catalog = epr.error_catalog();
+            assert_eq!(catalog.len(), ERROR_NAMES.len());
+            for (code, name) in &catalog {
+                // CrossContract carries fields, so decoding reads a few bytes past the discriminant.
+                let bytes = [*code, 0, 0, 0, 0, 0];
+                let error = <Error as scale::Decode>::decode(&mut &bytes[..]).expect("catalog entry without a variant");
+                assert!(format!("{:?}", error).starts_with(name.as_str()), "{} is not {:?}", name, error);
+            }
+            // No variant comes after the last catalog entry.
+            let past_end = [catalog.len() as u8, 0, 0, 0, 0, 0];
+            assert!(<Error as scale::Decode>::decode(&mut &past_end[..]).is_err());
+        }
+
+        #[ink::test]
+        fn denial_reasons_tell_apart_the_causes_of_a_denial() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let terms = standard_terms(&mut epr);
+            register(&mut epr, accounts.bob);
+            let read = Action::Read(CONSENT_CLINICAL_NOTES);
+            assert_eq!(epr.denial_reason(accounts.charlie, accounts.bob, read), Some((Error::PermissionDenied, DENIAL_NO_ACCESS)));
+
+            // A consent that does not cover what is read.
+            set_caller(accounts.bob);
+            assert_eq!(epr.grant_consent(accounts.charlie, CONSENT_BIODATA, terms, None), Ok(()));
+            assert_eq!(epr.denial_reason(accounts.charlie, accounts.bob, read), Some((Error::PermissionDenied, DENIAL_CONSENT_SCOPE)));
+            assert_eq!(epr.denial_reason(accounts.charlie, accounts.bob, Action::Read(CONSENT_BIODATA)), None);
+
+            // A temporary role that has run out, and a permission the admin took back.
+            set_caller(accounts.alice);
+            assert_eq!(epr.grant_roles_batch(vec![(accounts.django, Role::Clinician, Some(1_000))]), Ok(vec![Ok(())]));
+            assert_eq!(epr.denial_reason(accounts.django, accounts.bob, Action::WriteClinical), None);
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(1_000);
+            assert_eq!(epr.denial_reason(accounts.django, accounts.bob, read), Some((Error::PermissionDenied, DENIAL_ROLE_EXPIRED)));
+            assert_eq!(epr.denial_reason(accounts.django, accounts.bob, Action::WriteClinical), Some((Error::PermissionDenied, DENIAL_ROLE_EXPIRED)));
+            assert_eq!(epr.add_user_with_permissions(accounts.eve, false), Ok(()));
+            assert_eq!(epr.denial_reason(accounts.eve, accounts.bob, Action::Write), Some((Error::PermissionDenied, DENIAL_PERMISSION_REVOKED)));
+
+            // Causes that have an error of their own keep it.
+            assert_eq!(epr.add_user_with_permissions(accounts.frank, true), Ok(()));
+            assert_eq!(epr.denial_reason(accounts.frank, accounts.bob, Action::WriteClinical), Some((Error::PermissionDenied, DENIAL_NOT_CLINICIAN)));
+            assert_eq!(epr.denial_reason(accounts.frank, accounts.charlie, Action::Write), Some((Error::RecordNotFound, DENIAL_RECORD_NOT_FOUND)));
+            assert_eq!(epr.denial_reason(accounts.frank, accounts.bob, Action::ActFor), Some((Error::PermissionDenied, DENIAL_NOT_GUARDIAN)));
+        }
+
+        #[ink::test]
+        fn vip_reads_need_a_fresh_co_authorization_each_time() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let (reader, second) = (accounts.charlie, accounts.django);
+            let id = register(&mut epr, accounts.bob);
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            assert_eq!(epr.update_biodata(accounts.alice, accounts.bob, biodata("Bob", "O+", Vec::new())), Ok(()));
+            assert_eq!(epr.grant_roles_batch(vec![(reader, Role::Clinician, None), (second, Role::Clinician, None)]), Ok(vec![Ok(()), Ok(())]));
+
+            // Only the admin flags VIPs.
+            set_caller(reader);
+            assert_eq!(epr.set_vip(accounts.bob, true), Err(Error::PermissionDenied));
+            set_caller(accounts.alice);
+            assert_eq!(epr.set_vip(accounts.eve, true), Err(Error::RecordNotFound));
+            assert_eq!(epr.set_vip(accounts.bob, true), Ok(()));
+            assert!(epr.is_vip(accounts.bob));
+
+            // Access alone no longer lets the reader in, through any read path.
+            set_caller(reader);
+            assert_eq!(epr.read_biodata(accounts.bob), Err(Error::CoAuthorizationRequired));
+            assert_eq!(epr.get_biodata_view(accounts.bob), None);
+            assert_eq!(epr.get_biodata(reader, accounts.bob), None);
+            assert_eq!(epr.export_patient(accounts.bob), Err(Error::CoAuthorizationRequired));
+            assert_eq!(epr.read_biodata_as_token_holder(id), Err(Error::CoAuthorizationRequired));
+            assert_eq!(epr.read_clinical_notes_as_token_holder(id), Err(Error::CoAuthorizationRequired));
+            assert_eq!(epr.denial_reason(reader, accounts.bob, Action::Read(CONSENT_BIODATA)), Some((Error::CoAuthorizationRequired, DENIAL_CO_AUTHORIZATION)));
+
+            // Only another clinician can co-authorize the read.
+            assert_eq!(epr.co_authorize_read(reader, accounts.bob), Err(Error::SelfApproval));
+            set_caller(accounts.eve);
+            assert_eq!(epr.co_authorize_read(reader, accounts.bob), Err(Error::PermissionDenied));
+            set_caller(second);
+            assert_eq!(epr.co_authorize_read(reader, accounts.bob), Ok(()));
+
+            // The co-authorized read succeeds once, and the next one needs a fresh co-authorization.
+            set_caller(reader);
+            assert_eq!(epr.read_biodata(accounts.bob).map(|view| view.record), Ok(biodata("Bob", "O+", Vec::new())));
+            assert!(matches!(recorded_events().last(), Some(Event::VipRecordRead(VipRecordRead { co_authorized_by, .. })) if *co_authorized_by == second));
+            assert_eq!(epr.read_biodata(accounts.bob), Err(Error::CoAuthorizationRequired));
+
+            // A co-authorization lapses after CO_AUTHORIZATION_WINDOW.
+            set_caller(second);
+            assert_eq!(epr.co_authorize_read(reader, accounts.bob), Ok(()));
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(CO_AUTHORIZATION_WINDOW);
+            set_caller(reader);
+            assert_eq!(epr.read_biodata(accounts.bob), Err(Error::CoAuthorizationRequired));
+
+            // Naming the patient or a clinician with emergency access as the requester does not skip the
+            // co-authorization, nor does it read around the log.
+            set_caller(second);
+            assert_eq!(epr.emergency_access(accounts.bob, String::from("collapsed in clinic")), Ok(0));
+            assert!(epr.get_biodata(second, accounts.bob).is_some());
+            set_caller(reader);
+            for requester in [accounts.bob, second] {
+                assert_eq!(epr.get_biodata(requester, accounts.bob), None);
+                assert_eq!(epr.get_clinical_notes(requester, accounts.bob), None);
+                assert!(!epr.has_biodata(requester, accounts.bob));
+            }
+
+            // The patient reads their own record freely, and their read log names both accounts.
+            set_caller(accounts.bob);
+            assert!(epr.read_biodata(accounts.bob).is_ok());
+            let log = epr.vip_reads_of(accounts.bob, 0, 10).expect("log");
+            assert_eq!(log, vec![VipRead { reader, co_authorized_by: second, scope: CONSENT_BIODATA, read_at: 0 }]);
+            set_caller(reader);
+            assert_eq!(epr.vip_reads_of(accounts.bob, 0, 10), Err(Error::PermissionDenied));
+
+            // Once unflagged, the usual access applies again.
+            set_caller(accounts.alice);
+            assert_eq!(epr.set_vip(accounts.bob, false), Ok(()));
+            set_caller(reader);
+            assert!(epr.read_biodata(accounts.bob).is_ok());
+            assert!(epr.get_biodata_view(accounts.bob).is_some());
+        }
+
+        #[ink::test]
+        fn explain_access_names_each_path_without_chaining() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let (hospital_a, hospital_b) = (AccountId::from([0x31; 32]), AccountId::from([0x32; 32]));
+            let (doctor_a, doctor_b, colleague) =
+                (AccountId::from([0x21; 32]), AccountId::from([0x22; 32]), AccountId::from([0x23; 32]));
+            register(&mut epr, accounts.bob);
+            assert_eq!(epr.explain_access(accounts.bob, accounts.bob), Some(AccessPath::Patient));
+            assert_eq!(epr.explain_access(accounts.charlie, accounts.bob), None);
+
+            set_caller(accounts.bob);
+            assert_eq!(epr.appoint_guardian(accounts.bob, accounts.eve, String::from("parent"), None), Ok(()));
+            assert_eq!(epr.explain_access(accounts.eve, accounts.bob), Some(AccessPath::Guardian));
+            let terms = standard_terms(&mut epr);
+            assert_eq!(epr.grant_consent(accounts.charlie, CONSENT_BIODATA, terms, None), Ok(()));
+            assert_eq!(epr.explain_access(accounts.charlie, accounts.bob), Some(AccessPath::Consent));
+
+            set_caller(accounts.alice);
+            assert_eq!(epr.add_user_with_permissions(accounts.django, true), Ok(()));
+            assert_eq!(epr.explain_access(accounts.django, accounts.bob), Some(AccessPath::Permission));
+            let ward = epr.create_group(String::from("ward"), accounts.alice).unwrap();
+            assert_eq!(epr.add_group_with_permissions(ward, true), Ok(()));
+            assert_eq!(epr.add_to_group(ward, accounts.frank), Ok(()));
+            assert_eq!(epr.explain_access(accounts.frank, accounts.bob), Some(AccessPath::Group(ward)));
+
+            for org in [hospital_a, hospital_b] {
+                epr.providers.insert(org, &Provider { verified: true, registered_at: 0, deposit: 0 });
+            }
+            join_org(&mut epr, hospital_a, doctor_a);
+            join_org(&mut epr, hospital_a, accounts.eve);
+            assert_eq!(epr.propose_agreement(hospital_b, CONSENT_BIODATA, 1_000), Ok(()));
+            join_org(&mut epr, hospital_b, doctor_b);
+            join_org(&mut epr, hospital_b, colleague);
+            assert_eq!(epr.approve_agreement(hospital_a), Ok(()));
+            set_caller(accounts.bob);
+            assert_eq!(epr.add_to_care_team(accounts.bob, doctor_a), Ok(()));
+            assert_eq!(epr.explain_access(doctor_b, accounts.bob), Some(AccessPath::Agreement(hospital_a)));
+
+            // With only the guardian on the care team, the guardian's organization links nothing.
+            assert_eq!(epr.add_to_care_team(accounts.bob, accounts.eve), Ok(()));
+            assert_eq!(epr.remove_from_care_team(accounts.bob, doctor_a), Ok(()));
+            assert_eq!(epr.explain_access(doctor_b, accounts.bob), None);
+            assert_eq!(epr.explain_access(colleague, accounts.bob), None);
+            assert_eq!(epr.explain_access(doctor_a, accounts.bob), None);
+            assert_eq!(epr.explain_access(accounts.eve, accounts.bob), Some(AccessPath::Guardian));
+        }
+
+        #[ink::test]
+        fn changing_primary_institution_flips_access() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let (hospital_a, hospital_b) = (AccountId::from([0x31; 32]), AccountId::from([0x32; 32]));
+            let doctor_a = AccountId::from([0x21; 32]);
+            register(&mut epr, accounts.bob);
+            for org in [hospital_a, hospital_b] {
+                epr.providers.insert(org, &Provider { verified: true, registered_at: 0, deposit: 0 });
+            }
+            join_org(&mut epr, hospital_a, doctor_a);
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(1_000);
+
+            // Only the patient or a guardian moves a registered patient to a verified provider.
+            set_caller(accounts.charlie);
+            assert_eq!(epr.transfer_primary_institution(accounts.bob, hospital_a), Err(Error::PermissionDenied));
+            assert_eq!(epr.transfer_primary_institution(accounts.charlie, hospital_a), Err(Error::RecordNotFound));
+            set_caller(accounts.bob);
+            assert_eq!(epr.transfer_primary_institution(accounts.bob, accounts.eve), Err(Error::ProviderNotVerified));
+
+            // The first institution has nothing to hand over, and gets the onboarding consent.
+            assert_eq!(epr.transfer_primary_institution(accounts.bob, hospital_a), Ok(()));
+            assert_eq!(epr.transfer_primary_institution(accounts.bob, hospital_a), Err(Error::NotAllowed));
+            assert_eq!(epr.primary_institution_of(accounts.bob), Some(hospital_a));
+            assert_eq!(epr.onboarding_until(accounts.bob, hospital_a), Some(1_000 + ONBOARDING_PERIOD));
+            assert_eq!(epr.explain_access(hospital_a, accounts.bob), Some(AccessPath::Onboarding));
+            for member in [hospital_a, doctor_a, accounts.frank] {
+                assert_eq!(epr.add_to_care_team(accounts.bob, member), Ok(()));
+            }
+
+            // Moving on takes the old institution and its staff off the care team and out of the record.
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(2_000);
+            assert_eq!(epr.switch_institution(accounts.bob, hospital_b, accounts.bob), Some(hospital_a));
+            assert!(matches!(
+                recorded_events().last(),
+                Some(Event::InstitutionChanged(InstitutionChanged { old_org: Some(_), .. }))
+            ));
+            assert_eq!(epr.care_team_of(accounts.bob), vec![accounts.frank]);
+            assert!(epr.assigned_patients(doctor_a, 0, 10).is_empty());
+            assert_eq!(
+                epr.institution_history(accounts.bob, 0, 10),
+                vec![InstitutionRecord { org: hospital_a, left_at: 2_000, left_by: accounts.bob }]
+            );
+            assert_eq!(epr.explain_access(hospital_a, accounts.bob), None);
+            assert_eq!(epr.explain_access(hospital_b, accounts.bob), Some(AccessPath::Onboarding));
+
+            // The onboarding consent lapses after ONBOARDING_PERIOD.
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(2_000 + ONBOARDING_PERIOD);
+            assert_eq!(epr.explain_access(hospital_b, accounts.bob), None);
+        }
+
+        #[ink::test]
+        fn appointments_cannot_overlap_on_a_clinician() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let (clinician, registrar, other) = (accounts.django, accounts.eve, accounts.frank);
+            let roles = vec![(clinician, Role::Clinician, None), (registrar, Role::Registrar, None), (other, Role::Clinician, None)];
+            assert_eq!(epr.grant_roles_batch(roles), Ok(vec![Ok(()), Ok(()), Ok(())]));
+            register(&mut epr, accounts.bob);
+            register(&mut epr, accounts.charlie);
+            let minute: Timestamp = 60 * 1000;
+            let nine = DAY + 9 * 60 * minute;
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(DAY);
+
+            // The patient books for themselves, registrars for anyone, nobody else.
+            set_caller(accounts.bob);
+            assert_eq!(epr.book_appointment(accounts.bob, clinician, nine, 30), Ok(0));
+            assert_eq!(epr.book_appointment(accounts.charlie, clinician, nine + 60 * minute, 30), Err(Error::PermissionDenied));
+            set_caller(registrar);
+            assert_eq!(epr.book_appointment(accounts.charlie, registrar, nine, 30), Err(Error::NotAllowed));
+            assert_eq!(epr.book_appointment(accounts.charlie, clinician, nine, 0), Err(Error::InvalidSlot));
+            assert_eq!(epr.book_appointment(accounts.charlie, clinician, DAY - 1, 30), Err(Error::InvalidSlot));
+
+            // Any overlap with 09:00-09:30 is taken, starting inside, ending inside or containing it.
+            assert_eq!(epr.book_appointment(accounts.charlie, clinician, nine + 15 * minute, 30), Err(Error::SlotTaken));
+            assert_eq!(epr.book_appointment(accounts.charlie, clinician, nine - 15 * minute, 30), Err(Error::SlotTaken));
+            assert_eq!(epr.book_appointment(accounts.charlie, clinician, nine - 60 * minute, 180), Err(Error::SlotTaken));
+            assert_eq!(epr.book_appointment(accounts.charlie, clinician, nine + 29 * minute, 1), Err(Error::SlotTaken));
+            // Another clinician is free at the same time, and back-to-back slots do not overlap.
+            assert_eq!(epr.book_appointment(accounts.charlie, other, nine, 30), Ok(0));
+            assert_eq!(epr.book_appointment(accounts.charlie, clinician, nine + 30 * minute, 30), Ok(1));
+            assert_eq!(epr.book_appointment(accounts.charlie, clinician, nine - 30 * minute, 30), Ok(2));
+
+            // A slot across midnight is checked against both days.
+            assert_eq!(epr.book_appointment(accounts.bob, clinician, 2 * DAY - 30 * minute, 60), Ok(1));
+            assert_eq!(epr.book_appointment(accounts.charlie, clinician, 2 * DAY + 15 * minute, 15), Err(Error::SlotTaken));
+            assert_eq!(epr.book_appointment(accounts.charlie, clinician, 2 * DAY + 30 * minute, 15), Ok(3));
+
+            // Cancelling frees the slot, once.
+            set_caller(accounts.bob);
+            assert_eq!(epr.cancel_appointment(accounts.bob, 0), Ok(()));
+            assert!(matches!(
+                recorded_events().last(),
+                Some(Event::AppointmentCancelled(AppointmentCancelled { clinician: c, by, .. })) if *c == clinician && *by == accounts.bob
+            ));
+            assert_eq!(epr.cancel_appointment(accounts.bob, 0), Err(Error::AppointmentClosed));
+            set_caller(registrar);
+            assert_eq!(epr.book_appointment(accounts.charlie, clinician, nine + 15 * minute, 15), Ok(4));
+
+            // Only the clinician closes an appointment, and not before it starts.
+            assert_eq!(epr.complete_appointment(accounts.charlie, 1, true), Err(Error::PermissionDenied));
+            set_caller(clinician);
+            assert_eq!(epr.complete_appointment(accounts.charlie, 1, true), Err(Error::NotAllowed));
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(nine + 30 * minute);
+            assert_eq!(epr.complete_appointment(accounts.charlie, 1, true), Ok(()));
+            assert_eq!(epr.complete_appointment(accounts.charlie, 2, false), Ok(()));
+            assert_eq!(epr.cancel_appointment(accounts.charlie, 2), Err(Error::AppointmentClosed));
+
+            let statuses: Vec<ApptStatus> = epr.appointments(accounts.charlie, 0, 10).into_iter().map(|a| a.status).collect();
+            assert_eq!(
+                statuses,
+                vec![ApptStatus::Booked, ApptStatus::Completed, ApptStatus::NoShow, ApptStatus::Booked, ApptStatus::Booked]
+            );
+            assert_eq!(epr.clinician_appointments(clinician, 0, 3), Ok(vec![(accounts.bob, 0), (accounts.charlie, 1), (accounts.charlie, 2)]));
+            set_caller(accounts.bob);
+            assert_eq!(epr.clinician_appointments(clinician, 0, 3), Err(Error::PermissionDenied));
+        }
+
+        #[ink::test]
+        fn patients_and_guardians_anchor_dids() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let (patient, guardian) = (accounts.bob, accounts.charlie);
+            register(&mut epr, patient);
+            let first = Hash::from([0x01; 32]);
+            let second = Hash::from([0x02; 32]);
+
+            // Only DIDs are accepted, up to MAX_DID_LEN bytes.
+            set_caller(patient);
+            assert_eq!(epr.set_did(patient, String::from("urn:example:bob"), first), Err(Error::InvalidDid));
+            let long = format!("did:web:{}", "a".repeat(MAX_DID_LEN - 8));
+            assert_eq!(epr.set_did(patient, long, first), Err(Error::InvalidDid));
+            let longest = format!("did:web:{}", "a".repeat(MAX_DID_LEN - 9));
+            assert_eq!(epr.set_did(patient, longest, first), Ok(()));
+            assert_eq!(epr.set_did(patient, String::from("did:example:bob"), first), Ok(()));
+            assert!(matches!(
+                recorded_events().last(),
+                Some(Event::DidUpdated(DidUpdated { patient: p, document_hash, .. })) if *p == patient && *document_hash == first
+            ));
+
+            // Strangers cannot change it until they are appointed guardian; the new anchor replaces the old one.
+            set_caller(guardian);
+            assert_eq!(epr.set_did(patient, String::from("did:example:evil"), second), Err(Error::PermissionDenied));
+            set_caller(patient);
+            assert_eq!(epr.appoint_guardian(patient, guardian, String::from("parent"), None), Ok(()));
+            set_caller(guardian);
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(5_000);
+            assert_eq!(epr.set_did(patient, String::from("did:example:bob-2"), second), Ok(()));
+
+            // Anyone can resolve it.
+            set_caller(accounts.eve);
+            assert_eq!(
+                epr.did_of(patient),
+                Some(DidAnchor { did: String::from("did:example:bob-2"), document_hash: second, updated_at: 5_000 })
+            );
+            assert_eq!(epr.did_of(guardian), None);
+        }
+
+        #[ink::test]
+        fn patients_page_and_acknowledge_their_notifications() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let terms = standard_terms(&mut epr);
+            let patient = accounts.bob;
+            register(&mut epr, patient);
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(7_000);
+
+            set_caller(accounts.eve);
+            pay(DEFAULT_STORAGE_DEPOSIT);
+            assert_eq!(epr.request_access(patient), Ok(()));
+            set_caller(patient);
+            assert_eq!(epr.grant_consent(accounts.charlie, CONSENT_BIODATA, terms, None), Ok(()));
+            set_caller(accounts.alice);
+            assert_eq!(epr.update_biodata(accounts.alice, patient, biodata("Bob", "", Vec::new())), Ok(()));
+            assert_eq!(epr.update_clinical_notes(accounts.alice, patient, notes("visit")), Ok(()));
+            set_caller(patient);
+            assert_eq!(epr.revoke_consent(accounts.charlie), Ok(()));
+
+            let kinds: Vec<(u32, NotificationKind, AccountId)> =
+                epr.my_notifications(0, 10).into_iter().map(|(i, n)| (i, n.kind, n.actor)).collect();
+            assert_eq!(
+                kinds,
+                vec![
+                    (0, NotificationKind::AccessRequested, accounts.eve),
+                    (1, NotificationKind::ConsentGranted, accounts.charlie),
+                    (2, NotificationKind::BiodataUpdated, accounts.alice),
+                    (3, NotificationKind::NotesUpdated, accounts.alice),
+                    (4, NotificationKind::ConsentRevoked, accounts.charlie)
+                ]
+            );
+            assert_eq!(epr.my_notifications(3, 1)[0].1.timestamp, 7_000);
+            assert_eq!(epr.unread_count(), 5);
+
+            // Acknowledging moves the mark forward only.
+            assert_eq!(epr.acknowledge_notifications(5), Err(Error::RecordNotFound));
+            assert_eq!(epr.acknowledge_notifications(2), Ok(()));
+            assert_eq!(epr.unread_count(), 2);
+            assert_eq!(epr.acknowledge_notifications(0), Ok(()));
+            assert_eq!(epr.unread_count(), 2);
+
+            // Inboxes are per patient.
+            set_caller(accounts.charlie);
+            assert_eq!(epr.my_notifications(0, 10), Vec::new());
+            assert_eq!(epr.unread_count(), 0);
+
+            // Only the last MAX_NOTIFICATIONS are kept, and pruned ones no longer count as unread.
+            for _ in 0..MAX_NOTIFICATIONS {
+                epr.notify(patient, NotificationKind::AccessRequested, accounts.eve, 0);
+            }
+            set_caller(patient);
+            assert_eq!(epr.unread_count(), MAX_NOTIFICATIONS);
+            assert_eq!(epr.my_notifications(0, 1)[0].0, 5);
+            assert!(!epr.notifications.contains((patient, 4)));
+        }
+
+        #[ink::test]
+        fn record_writes_are_held_to_the_quota() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let (patient, clinician) = (accounts.bob, accounts.django);
+            register(&mut epr, patient);
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            assert_eq!(epr.grant_roles_batch(vec![(clinician, Role::Clinician, None)]), Ok(vec![Ok(())]));
+            assert_eq!(epr.quota(), DEFAULT_QUOTA);
+
+            set_caller(accounts.charlie);
+            assert_eq!(epr.set_quota(60), Err(Error::PermissionDenied));
+            set_caller(accounts.alice);
+            assert_eq!(epr.set_quota(60), Ok(()));
+
+            // 13 bytes of biodata, 12 of notes and 32 of an imaging report.
+            assert_eq!(epr.update_biodata(accounts.alice, patient, biodata("Bob", "0123456789", Vec::new())), Ok(()));
+            assert_eq!(epr.update_clinical_notes(accounts.alice, patient, notes("visit")), Ok(()));
+            set_caller(clinician);
+            assert_eq!(epr.add_imaging_report(patient, imaging_report(1)), Ok(0));
+            assert_eq!(epr.storage_used_of(patient), 57);
+
+            // Writes that would grow the record past 60 bytes are rejected, replacements count only their difference.
+            assert_eq!(epr.add_imaging_report(patient, imaging_report(2)), Err(Error::QuotaExceeded));
+            set_caller(accounts.alice);
+            assert_eq!(epr.update_biodata(accounts.alice, patient, biodata("Bob", "0123456789abcd", Vec::new())), Err(Error::QuotaExceeded));
+            assert_eq!(epr.storage_used_of(patient), 57);
+            assert_eq!(epr.update_biodata(accounts.alice, patient, biodata("Bob", "0123456", Vec::new())), Ok(()));
+            assert_eq!(epr.storage_used_of(patient), 54);
+
+            // Under a lowered quota, writes that do not grow the record still succeed.
+            assert_eq!(epr.set_quota(10), Ok(()));
+            assert_eq!(epr.update_clinical_notes(accounts.alice, patient, notes("visit")), Ok(()));
+            assert_eq!(epr.update_clinical_notes(accounts.alice, patient, notes("visit 2")), Err(Error::QuotaExceeded));
+
+            // Erasure releases the biodata and notes.
+            assert_eq!(epr.erase_patient_data(patient), Ok(()));
+            assert_eq!(epr.storage_used_of(patient), 32);
+        }
+
+        #[ink::test]
+        fn clinicians_read_critical_info_without_consent() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.bob);
+            epr.grant_roles_batch(vec![(accounts.charlie, Role::Clinician, None), (accounts.eve, Role::Nurse, None)]).unwrap();
+            let info = CriticalInfo {
+                blood_type: BloodType::ONegative,
+                organ_donor: true,
+                advance_directive_uri: Some(String::from("ipfs://directive")),
+                updated_by: accounts.django,
+                updated_at: 0
+            };
+
+            set_caller(accounts.django);
+            assert_eq!(epr.set_critical_info(accounts.bob, info.clone()), Err(Error::PermissionDenied));
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(5);
+            set_caller(accounts.bob);
+            assert_eq!(epr.set_critical_info(accounts.bob, info.clone()), Ok(()));
+            assert!(matches!(
+                recorded_events().last(),
+                Some(Event::CriticalInfoUpdated(CriticalInfoUpdated { organ_donor: true, .. }))
+            ));
+            let stored = CriticalInfo { updated_by: accounts.bob, updated_at: 5, ..info.clone() };
+            assert_eq!(epr.critical_info_of(accounts.bob), Ok(Some(stored.clone())));
+
+            // A clinician without any consent can read it, a nurse cannot.
+            set_caller(accounts.charlie);
+            assert_eq!(epr.critical_info_of(accounts.bob), Ok(Some(stored)));
+            set_caller(accounts.eve);
+            assert_eq!(epr.critical_info_of(accounts.bob), Err(Error::PermissionDenied));
+
+            // Clinicians can update it too.
+            set_caller(accounts.charlie);
+            let update = CriticalInfo { blood_type: BloodType::Unknown, advance_directive_uri: None, ..info.clone() };
+            assert_eq!(epr.set_critical_info(accounts.bob, update), Ok(()));
+            let stored = epr.critical_info.get(accounts.bob).unwrap();
+            assert_eq!((stored.blood_type, stored.updated_by), (BloodType::Unknown, accounts.charlie));
+            assert_eq!(epr.set_critical_info(accounts.django, info), Err(Error::RecordNotFound));
+        }
+
+        #[ink::test]
+        fn revoked_clinicians_leave_orphans_to_reassign() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let (doctor, successor) = (accounts.charlie, accounts.django);
+            epr.grant_roles_batch(vec![(doctor, Role::Clinician, None)]).unwrap();
+            for patient in [accounts.bob, accounts.eve] {
+                register(&mut epr, patient);
+                set_caller(patient);
+                assert_eq!(epr.add_to_care_team(patient, doctor), Ok(()));
+            }
+            assert_eq!(epr.assigned_patients(doctor, 0, 10), vec![accounts.bob, accounts.eve]);
+
+            set_caller(accounts.alice);
+            assert_eq!(epr.revoke_roles_batch(vec![doctor]), Ok(vec![Ok(())]));
+            assert!(epr.care_team_of(accounts.bob).is_empty());
+            assert!(epr.care_team_of(accounts.eve).is_empty());
+            assert!(epr.assigned_patients(doctor, 0, 10).is_empty());
+            assert_eq!(epr.orphans_of(doctor, 0, 10), vec![accounts.eve, accounts.bob]);
+            let removals = recorded_events()
+                .iter()
+                .filter(|event| matches!(event, Event::CareTeamChanged(CareTeamChanged { added: false, .. })))
+                .count();
+            assert_eq!(removals, 2);
+
+            // Only the admin reassigns, and a limit leaves the rest for later.
+            set_caller(doctor);
+            assert_eq!(epr.reassign_orphans(doctor, successor, 10), Err(Error::PermissionDenied));
+            set_caller(accounts.alice);
+            assert_eq!(epr.reassign_orphans(doctor, successor, 1), Ok(1));
+            assert_eq!(epr.care_team_of(accounts.bob), vec![successor]);
+            assert_eq!(epr.orphans_of(doctor, 0, 10), vec![accounts.eve]);
+            assert_eq!(epr.reassign_orphans(doctor, successor, 10), Ok(1));
+            assert_eq!(epr.care_team_of(accounts.eve), vec![successor]);
+            assert!(epr.orphans_of(doctor, 0, 10).is_empty());
+            assert_eq!(epr.assigned_patients(successor, 0, 10), vec![accounts.bob, accounts.eve]);
+        }
+
+        #[ink::test]
+        fn care_cleanup_continues_past_the_cap() {
+            let mut epr = new_epr();
+            let doctor = AccountId::from([0x31; 32]);
+            epr.providers.insert(doctor, &Provider { verified: true, registered_at: 0, deposit: 0 });
+            let patients: Vec<AccountId> = (0..=MAX_CARE_CLEANUP as u8).map(|i| AccountId::from([0x40 + i; 32])).collect();
+            for patient in &patients {
+                register(&mut epr, *patient);
+                set_caller(*patient);
+                assert_eq!(epr.add_to_care_team(*patient, doctor), Ok(()));
+            }
+
+            // Deregistering clears the first MAX_CARE_CLEANUP care teams; the admin continues with the rest.
+            set_caller(doctor);
+            assert_eq!(epr.deregister_provider(), Ok(()));
+            assert_eq!(epr.assigned_patients(doctor, 0, 100), vec![patients[0]]);
+            assert_eq!(epr.care_team_of(patients[0]), vec![doctor]);
+            assert_eq!(epr.continue_care_cleanup(doctor), Err(Error::PermissionDenied));
+            set_caller(default_accounts().alice);
+            assert_eq!(epr.continue_care_cleanup(doctor), Ok(0));
+            assert!(epr.care_team_of(patients[0]).is_empty());
+            assert_eq!(epr.orphans_of(doctor, 0, 100).len(), patients.len());
+        }
+
+        #[ink::test]
+        fn activity_is_billed_per_organization_and_period() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let (hospital_a, hospital_b) = (accounts.charlie, accounts.django);
+            let (doctor_a, doctor_b) = (AccountId::from([0x21; 32]), AccountId::from([0x22; 32]));
+            let freelancer = AccountId::from([0x23; 32]);
+            for org in [hospital_a, hospital_b] {
+                epr.providers.insert(org, &Provider { verified: true, registered_at: 0, deposit: 0 });
+            }
+            join_org(&mut epr, hospital_a, doctor_a);
+            join_org(&mut epr, hospital_b, doctor_b);
+            set_caller(accounts.alice);
+            for doctor in [doctor_a, doctor_b, freelancer] {
+                assert_eq!(epr.add_user_with_permissions(doctor, true), Ok(()));
+            }
+            register(&mut epr, accounts.bob);
+            // Registering a patient counts the way create_patient does.
+            epr.record_activity(doctor_a, METRIC_PATIENTS_CREATED);
+
+            for (doctor, note) in [(doctor_a, "visit"), (doctor_a, "follow-up"), (doctor_b, "second opinion"), (freelancer, "home visit")] {
+                set_caller(doctor);
+                assert_eq!(epr.update_clinical_notes(doctor, accounts.bob, notes(note)), Ok(()));
+            }
+
+            assert_eq!(epr.org_activity(hospital_a, METRIC_NOTES_WRITTEN), 2);
+            assert_eq!(epr.org_activity(hospital_a, METRIC_PATIENTS_CREATED), 1);
+            assert_eq!(epr.org_activity(hospital_b, METRIC_NOTES_WRITTEN), 1);
+            assert_eq!(epr.org_activity(hospital_b, METRIC_PATIENTS_CREATED), 0);
+            // Accounts outside an organization are billed on their own.
+            assert_eq!(epr.org_activity(freelancer, METRIC_NOTES_WRITTEN), 1);
+            assert_eq!(epr.archived_activity(0, hospital_a, METRIC_NOTES_WRITTEN), None);
+
+            assert_eq!(epr.reset_period(), Err(Error::PermissionDenied));
+            set_caller(accounts.alice);
+            assert_eq!(epr.reset_period(), Ok(()));
+            assert_eq!(epr.current_period(), 1);
+            assert!(matches!(
+                recorded_events().last(),
+                Some(Event::PeriodReset(PeriodReset { period_id: 0, .. }))
+            ));
+
+            // The closed period is archived and the live counters start from zero.
+            assert_eq!(epr.archived_activity(0, hospital_a, METRIC_NOTES_WRITTEN), Some(2));
+            assert_eq!(epr.archived_activity(0, hospital_b, METRIC_NOTES_WRITTEN), Some(1));
+            assert_eq!(epr.archived_activity(0, freelancer, METRIC_NOTES_WRITTEN), Some(1));
+            assert_eq!(epr.org_activity(hospital_a, METRIC_NOTES_WRITTEN), 0);
+            assert_eq!(epr.org_activity(hospital_a, METRIC_PATIENTS_CREATED), 0);
+
+            set_caller(doctor_b);
+            assert_eq!(epr.update_clinical_notes(doctor_b, accounts.bob, notes("review")), Ok(()));
+            assert_eq!(epr.org_activity(hospital_b, METRIC_NOTES_WRITTEN), 1);
+            assert_eq!(epr.archived_activity(0, hospital_b, METRIC_NOTES_WRITTEN), Some(1));
+        }
+
+        fn discharge_summary(encounter_index: u32, diagnosis_codes: &[&str], finalized: bool) -> DischargeSummary {
+            DischargeSummary {
+                encounter_index,
+                diagnosis_codes: diagnosis_codes.iter().map(|code| String::from(*code)).collect(),
+                medications: String::from("paracetamol 1g"),
+                follow_up: String::from("GP in two weeks"),
+                author: AccountId::from([0x0; 32]),
+                finalized_at: finalized.then_some(0),
+                content_hash: Hash::from([0x1; 32])
+            }
+        }
+
+        #[ink::test]
+        fn discharge_summary_needs_a_closed_encounter_and_a_diagnosis() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.bob);
+            let (clinician, nurse) = (accounts.charlie, accounts.django);
+            let roles = vec![(clinician, Role::Clinician, None), (nurse, Role::Nurse, None)];
+            assert_eq!(epr.grant_roles_batch(roles), Ok(vec![Ok(()), Ok(())]));
+
+            // Only clinicians open encounters.
+            set_caller(nurse);
+            assert_eq!(epr.open_encounter(accounts.bob), Err(Error::PermissionDenied));
+            set_caller(clinician);
+            assert_eq!(epr.open_encounter(accounts.bob), Ok(0));
+
+            // The encounter has to exist and be closed.
+            assert_eq!(epr.submit_discharge_summary(accounts.bob, discharge_summary(1, &["I21.9"], false)), Err(Error::RecordNotFound));
+            assert_eq!(epr.submit_discharge_summary(accounts.bob, discharge_summary(0, &["I21.9"], false)), Err(Error::EncounterOpen));
+            assert_eq!(epr.close_encounter(accounts.bob, 0), Ok(()));
+            assert_eq!(epr.close_encounter(accounts.bob, 0), Err(Error::EncounterClosed));
+
+            // At least one diagnosis code is required, and the fields are capped.
+            assert_eq!(epr.submit_discharge_summary(accounts.bob, discharge_summary(0, &[], false)), Err(Error::MissingDiagnosis));
+            let long_code = "c".repeat(MAX_NAME_LEN + 1);
+            assert_eq!(epr.submit_discharge_summary(accounts.bob, discharge_summary(0, &[&long_code], false)), Err(Error::PayloadTooLarge));
+            assert_eq!(epr.discharge_summary(accounts.bob, 0), Ok(None));
+
+            // Nurses cannot submit summaries.
+            set_caller(nurse);
+            assert_eq!(epr.submit_discharge_summary(accounts.bob, discharge_summary(0, &["I21.9"], false)), Err(Error::PermissionDenied));
+
+            // The contract records the author and stamps the finalization time.
+            set_caller(clinician);
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(500);
+            assert_eq!(epr.submit_discharge_summary(accounts.bob, discharge_summary(0, &["I21.9"], false)), Ok(()));
+            assert_eq!(epr.submit_discharge_summary(accounts.bob, discharge_summary(0, &["I21.9", "E11"], true)), Ok(()));
+            let stored = epr.discharge_summary(accounts.bob, 0).unwrap().unwrap();
+            assert_eq!(stored.author, clinician);
+            assert_eq!(stored.finalized_at, Some(500));
+            assert_eq!(stored.diagnosis_codes.len(), 2);
+            assert!(matches!(
+                recorded_events().last(),
+                Some(Event::DischargeSummarySubmitted(DischargeSummarySubmitted { encounter_index: 0, finalized: true, .. }))
+            ));
+
+            // A finalized summary cannot be replaced.
+            assert_eq!(epr.submit_discharge_summary(accounts.bob, discharge_summary(0, &["I21.9"], true)), Err(Error::SummaryFinalized));
+
+            // The patient and their guardians read the summary; other accounts need read access to the notes.
+            let (guardian, provider) = (accounts.eve, accounts.frank);
+            set_caller(guardian);
+            assert_eq!(epr.discharge_summary(accounts.bob, 0), Err(Error::PermissionDenied));
+            set_caller(accounts.bob);
+            assert_eq!(epr.discharge_summary(accounts.bob, 0), Ok(Some(stored.clone())));
+            assert_eq!(epr.appoint_guardian(accounts.bob, guardian, String::from("spouse"), None), Ok(()));
+            set_caller(guardian);
+            assert_eq!(epr.discharge_summary(accounts.bob, 0), Ok(Some(stored.clone())));
+
+            // Redacted details hide the medications and follow-up from a consented provider.
+            let terms = standard_terms(&mut epr);
+            set_caller(accounts.bob);
+            assert_eq!(epr.grant_consent(provider, CONSENT_CLINICAL_NOTES, terms, None), Ok(()));
+            assert_eq!(epr.set_redactions(accounts.bob, provider, REDACT_DETAILS), Ok(()));
+            set_caller(provider);
+            let view = epr.discharge_summary(accounts.bob, 0).unwrap().unwrap();
+            assert!(view.medications.is_empty() && view.follow_up.is_empty());
+            assert_eq!(view.diagnosis_codes, stored.diagnosis_codes);
+
+            // A VIP patient's summary needs a co-authorization, except for the patient.
+            set_caller(accounts.alice);
+            assert_eq!(epr.set_vip(accounts.bob, true), Ok(()));
+            set_caller(clinician);
+            assert_eq!(epr.discharge_summary(accounts.bob, 0), Err(Error::CoAuthorizationRequired));
+            set_caller(accounts.bob);
+            assert!(epr.discharge_summary(accounts.bob, 0).is_ok());
+        }
+
+        fn notes(name: &str) -> ClinicalNotes {
+            ClinicalNotes {
+                name: String::from(name),
+                details: String::from("details"),
+                finalized: false,
+                vector: Vec::new()
+            }
+        }
+
+        #[ink::test]
+        fn writes_to_unregistered_patient_fail() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+
+            // Bob was never created via create_patient.
+            assert_eq!(
+                epr.update_biodata(accounts.alice, accounts.bob, biodata("Bob", "", Vec::new())),
+                Err(Error::RecordNotFound)
+            );
+            assert_eq!(
+                epr.update_clinical_notes(accounts.alice, accounts.bob, notes("visit")),
+                Err(Error::RecordNotFound)
+            );
+            assert_eq!(epr.get_biodata(accounts.alice, accounts.bob), None);
+            assert_eq!(epr.get_clinical_notes(accounts.alice, accounts.bob), None);
+        }
+
+        #[ink::test]
+        fn empty_payloads_are_rejected() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            register(&mut epr, accounts.bob);
+
+            assert_eq!(
+                epr.update_biodata(accounts.alice, accounts.bob, Biodata::default()),
+                Err(Error::EmptyPayload)
+            );
+            assert_eq!(
+                epr.update_clinical_notes(accounts.alice, accounts.bob, ClinicalNotes::default()),
+                Err(Error::EmptyPayload)
+            );
+            // A single non-empty field is enough.
+            assert_eq!(
+                epr.update_biodata(accounts.alice, accounts.bob, biodata("", "", vec![1])),
+                Ok(())
+            );
+        }
+
+        #[ink::test]
+        fn unauthenticated_notes_overwrite_is_rejected() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            register(&mut epr, accounts.bob);
+
+            assert_eq!(epr.update_clinical_notes(accounts.alice, accounts.bob, notes("visit")), Ok(()));
+
+            // Eve has no permission and cannot overwrite Bob's notes, not even by naming Alice as the requester.
+            set_caller(accounts.eve);
+            assert_eq!(
+                epr.update_clinical_notes(accounts.eve, accounts.bob, notes("forged")),
+                Err(Error::PermissionDenied)
+            );
+            assert_eq!(
+                epr.update_clinical_notes(accounts.alice, accounts.bob, notes("forged")),
+                Err(Error::PermissionDenied)
+            );
+            assert_eq!(
+                epr.update_biodata(accounts.alice, accounts.bob, biodata("Forged", "", Vec::new())),
+                Err(Error::PermissionDenied)
+            );
+            // Eve cannot grant herself access either, and a revoked user cannot write.
+            assert_eq!(epr.add_user_with_permissions(accounts.eve, true), Err(Error::PermissionDenied));
+            assert_eq!(epr.access_path(&accounts.eve), None);
+            set_caller(accounts.alice);
+            assert_eq!(epr.add_user_with_permissions(accounts.charlie, false), Ok(()));
+            set_caller(accounts.charlie);
+            assert_eq!(
+                epr.update_clinical_notes(accounts.charlie, accounts.bob, notes("forged")),
+                Err(Error::PermissionDenied)
+            );
+            assert_eq!(epr.get_clinical_notes(accounts.alice, accounts.bob), None);
+            set_caller(accounts.alice);
+            assert_eq!(epr.get_clinical_notes(accounts.alice, accounts.bob), Some(notes("visit")));
+        }
+
+        #[ink::test]
+        fn group_membership_confers_access() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.frank);
+            let cardiology = epr.create_group(String::from("Cardiology"), accounts.bob).expect("admin creates groups");
+            assert_eq!(epr.add_group_with_permissions(cardiology, true), Ok(()));
+
+            // Charlie has no direct permission and cannot read.
+            assert_eq!(epr.biodata_meta(accounts.charlie, accounts.frank), None);
+            assert_eq!(
+                epr.update_biodata(accounts.charlie, accounts.frank, biodata("Frank", "", Vec::new())),
+                Err(Error::PermissionDenied)
+            );
+
+            // The group admin adds Charlie, who inherits the group's access immediately.
+            set_caller(accounts.bob);
+            assert_eq!(epr.add_to_group(cardiology, accounts.charlie), Ok(()));
+            assert_eq!(epr.add_to_group(cardiology, accounts.charlie), Err(Error::AlreadyMember));
+            set_caller(accounts.charlie);
+            assert_eq!(
+                epr.update_biodata(accounts.charlie, accounts.frank, biodata("Frank", "", Vec::new())),
+                Ok(())
+            );
+            assert!(epr.get_biodata(accounts.charlie, accounts.frank).is_some());
+            assert_eq!(epr.groups_of(accounts.charlie), vec![cardiology]);
+
+            // Removal revokes the inherited access for subsequent reads.
+            set_caller(accounts.bob);
+            assert_eq!(epr.remove_from_group(cardiology, accounts.charlie), Ok(()));
+            assert_eq!(epr.get_biodata(accounts.charlie, accounts.frank), None);
+            assert_eq!(epr.remove_from_group(cardiology, accounts.charlie), Err(Error::NotMember));
+            assert!(epr.groups_of(accounts.charlie).is_empty());
+        }
+
+        #[ink::test]
+        fn group_management_is_restricted() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let oncology = epr.create_group(String::from("Oncology"), accounts.bob).expect("admin creates groups");
+
+            // Only the contract admin creates groups and grants them permissions.
+            set_caller(accounts.eve);
+            assert_eq!(epr.create_group(String::from("Rogue"), accounts.eve), Err(Error::PermissionDenied));
+            assert_eq!(epr.add_group_with_permissions(oncology, true), Err(Error::PermissionDenied));
+            // Only the group admin or the contract admin manage membership.
+            assert_eq!(epr.add_to_group(oncology, accounts.eve), Err(Error::PermissionDenied));
+            set_caller(accounts.alice);
+            assert_eq!(epr.add_to_group(oncology, accounts.eve), Ok(()));
+            assert_eq!(epr.add_to_group(42, accounts.eve), Err(Error::GroupNotFound));
+        }
+
+        #[ink::test]
+        fn members_of_paginates_and_stays_dense() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let ward = epr.create_group(String::from("Ward 3"), accounts.alice).expect("admin creates groups");
+            for member in [accounts.bob, accounts.charlie, accounts.django, accounts.eve] {
+                assert_eq!(epr.add_to_group(ward, member), Ok(()));
+            }
+            assert_eq!(epr.members_of(ward, 0, 2), vec![accounts.bob, accounts.charlie]);
+            assert_eq!(epr.members_of(ward, 2, 10), vec![accounts.django, accounts.eve]);
 
-            self.patient.mint(count);
-        
-            // self.env().emit_event(NewPatient {
-            //     id: count,
-            //     identifier: Some(identifier)
-            // });
+            // Removing from the middle moves the last member into the gap.
+            assert_eq!(epr.remove_from_group(ward, accounts.charlie), Ok(()));
+            assert_eq!(epr.members_of(ward, 0, 10), vec![accounts.bob, accounts.eve, accounts.django]);
+            assert_eq!(epr.remove_from_group(ward, accounts.django), Ok(()));
+            assert_eq!(epr.members_of(ward, 0, 10), vec![accounts.bob, accounts.eve]);
 
-            Ok(())
+            let events = ink::env::test::recorded_events().count();
+            assert_eq!(events, 1 + 4 + 2);
         }
 
-        // The update_biodata function updates the biodata of a patient.
-        #[ink(message)]
-        pub fn update_biodata(&mut self, requester: AccountId, identifier: AccountId, biodata: Biodata) -> Result<(), Error> {
-            // Check if caller has the required permissions
-            let permission = self.permissions.get(&requester).ok_or(Error::PermissionDenied)?;
-            if !permission.can_access {
-                return Err(Error::PermissionDenied);
+        #[ink::test]
+        fn membership_is_capped_per_account() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            for i in 0..MAX_GROUPS_PER_ACCOUNT {
+                let group = epr.create_group(format!("group {}", i), accounts.alice).expect("admin creates groups");
+                assert_eq!(epr.add_to_group(group, accounts.bob), Ok(()));
             }
-            
-            self.patient_biodata.insert(&identifier, &biodata);
+            let extra = epr.create_group(String::from("extra"), accounts.alice).expect("admin creates groups");
+            assert_eq!(epr.add_to_group(extra, accounts.bob), Err(Error::TooManyGroups));
+        }
 
-            // self.env().emit_event(BiodataUpdate {
-            //     identifier: Some(identifier),
-            //     message: Some(biodata)
-            // });
+        #[ink::test]
+        fn token_holder_reads_require_a_record() {
+            let epr = new_epr();
+            // No record exists for id 1, so the Patient contract is never consulted.
+            assert_eq!(epr.read_biodata_as_token_holder(1), Err(Error::RecordNotFound));
+            assert_eq!(epr.read_clinical_notes_as_token_holder(1), Err(Error::RecordNotFound));
+        }
 
-            Ok(())
+        #[ink::test]
+        fn export_bundles_patient_state() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let terms = standard_terms(&mut epr);
+            let id = register(&mut epr, accounts.bob);
+            register(&mut epr, accounts.django);
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            let clinician = accounts.frank;
+            assert_eq!(epr.grant_roles_batch(vec![(clinician, Role::Clinician, None)]), Ok(vec![Ok(())]));
+
+            // Bob fills in several categories.
+            assert_eq!(epr.update_biodata(accounts.alice, accounts.bob, biodata("Bob", "O+", vec![1, 2])), Ok(()));
+            assert_eq!(epr.update_clinical_notes(accounts.alice, accounts.bob, notes("checkup")), Ok(()));
+            set_caller(clinician);
+            assert_eq!(epr.add_problem(accounts.bob, String::from("E11.9")), Ok(0));
+            set_caller(accounts.bob);
+            assert_eq!(epr.set_research_consent(true), Ok(()));
+            assert_eq!(epr.grant_consent(accounts.charlie, CONSENT_BIODATA, terms, None), Ok(()));
+            assert_eq!(epr.grant_consent(accounts.eve, CONSENT_ALL, terms, None), Ok(()));
+
+            // Only callers who may read both biodata and notes can export, and only registered patients.
+            set_caller(accounts.charlie);
+            assert_eq!(epr.export_patient(accounts.bob), Err(Error::PermissionDenied));
+            set_caller(accounts.django);
+            assert_eq!(epr.export_patient(accounts.bob), Err(Error::PermissionDenied));
+            set_caller(accounts.eve);
+            assert_eq!(epr.export_patient(accounts.bob).map(|bundle| bundle.health_id), Ok(id));
+            set_caller(accounts.alice);
+            assert_eq!(epr.export_patient(accounts.eve), Err(Error::RecordNotFound));
+
+            let bundle = epr.export_patient(accounts.bob).expect("bundle");
+            assert_eq!(bundle.health_id, id);
+            assert_eq!(bundle.biodata, Some(biodata("Bob", "O+", vec![1, 2])));
+            assert_eq!(bundle.note_count, 1);
+            assert_eq!(bundle.notes, vec![notes("checkup")]);
+            assert!(!bundle.notes_truncated);
+            assert_eq!(bundle.problem_count, 1);
+            assert_eq!(bundle.problems.iter().map(|problem| problem.icd_code.as_str()).collect::<Vec<_>>(), vec!["E11.9"]);
+            assert_eq!(bundle.consent_count, 2);
+            assert_eq!(
+                bundle.consents.iter().map(|consent| (consent.provider, consent.scope)).collect::<Vec<_>>(),
+                vec![(accounts.charlie, CONSENT_BIODATA), (accounts.eve, CONSENT_ALL)]
+            );
+            assert!(bundle.research_consent);
+            assert!(!bundle.imported);
+
+            // Revoked consents leave the bundle, and redactions apply to it.
+            set_caller(accounts.bob);
+            assert_eq!(epr.revoke_consent(accounts.charlie), Ok(()));
+            assert_eq!(epr.set_redactions(accounts.bob, accounts.eve, REDACT_DETAILS), Ok(()));
+            set_caller(accounts.eve);
+            let bundle = epr.export_patient(accounts.bob).expect("bundle");
+            assert_eq!(bundle.consent_count, 1);
+            assert_eq!(bundle.consents[0].provider, accounts.eve);
+            assert_eq!(bundle.biodata, Some(biodata("Bob", "", vec![1, 2])));
+            set_caller(accounts.alice);
+
+            // A patient without data exports an empty bundle.
+            let empty = epr.export_patient(accounts.django).expect("bundle");
+            assert_eq!(empty.biodata, None);
+            assert_eq!(empty.note_count, 0);
+            assert!(empty.notes.is_empty());
+            assert!(!empty.research_consent);
         }
 
-        // The update_clinical_notes function updates the clinical notes of a patient.
-        #[ink(message)]
-        pub fn update_clinical_notes(&mut self, identifier: AccountId, notes: ClinicalNotes) -> Result<(), Error> {
-            self.patient_notes.insert(&identifier, &notes);
+        #[ink::test]
+        fn imported_records_keep_their_legacy_health_id() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            register(&mut epr, accounts.django);
 
-            // self.env().emit_event(ClinicalNotesUpdate {
-            //     identifier: Some(identifier),
-            //     message: Some(notes)
-            // });
+            // Only the admin imports.
+            set_caller(accounts.charlie);
+            assert_eq!(epr.import_patient(7, accounts.bob, biodata("Bob", "O+", Vec::new()), None), Err(Error::PermissionDenied));
+            set_caller(accounts.alice);
 
-            Ok(())
+            assert_eq!(epr.import_record(&(7, accounts.bob, biodata("Bob", "O+", vec![1]), Some(notes("legacy")))), Ok(()));
+            assert_eq!(epr.health_ids.get(accounts.bob), Some(7));
+            assert_eq!(epr.current_id, 7);
+            assert!(epr.is_imported(accounts.bob));
+            assert!(!epr.is_imported(accounts.django));
+            assert!(recorded_events().iter().any(|event| matches!(event, Event::Imported(Imported { id: 7, .. }))));
+
+            let bundle = epr.export_patient(accounts.bob).expect("bundle");
+            assert_eq!(bundle.health_id, 7);
+            assert_eq!(bundle.biodata, Some(biodata("Bob", "O+", vec![1])));
+            assert_eq!(bundle.notes, vec![notes("legacy")]);
+            assert!(bundle.imported);
+
+            // Health ids and accounts that are already taken collide, and flagged ids are never legacy ones.
+            assert_eq!(epr.import_record(&(7, accounts.eve, biodata("Eve", "", Vec::new()), None)), Err(Error::PatientExists));
+            assert_eq!(epr.import_record(&(9, accounts.django, biodata("Django", "", Vec::new()), None)), Err(Error::PatientExists));
+            assert_eq!(epr.import_record(&(EXTERNAL_ID_FLAG | 9, accounts.eve, biodata("Eve", "", Vec::new()), None)), Err(Error::NotAllowed));
+
+            // New patients continue after the highest imported id.
+            assert_eq!(register(&mut epr, accounts.eve), 8);
+
+            // Batches are capped.
+            let batch = (0..=MAX_BATCH_LEN as u32).map(|i| (100 + i, AccountId::from([i as u8; 32]), biodata("", "", Vec::new()), None)).collect();
+            assert_eq!(epr.import_batch(batch), Err(Error::BatchTooLarge));
         }
 
-        // The get_biodata function retrieves the biodata of a patient.
-        #[ink(message)]
-        pub fn get_biodata(&self, requester: AccountId, identifier: AccountId) -> Option<Biodata> {
-            // Check if the requester has permission to access biodata
-            if let Some(permission) = self.permissions.get(&requester) {
-                if permission.can_access {
-                    return self.patient_biodata.get(&identifier);
-                }
+        #[ink::test]
+        fn institutions_claim_self_registered_records_instead_of_duplicating_them() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            register(&mut epr, accounts.django);
+
+            // Bob registers himself, without any role, once.
+            set_caller(accounts.bob);
+            assert_eq!(epr.self_register(accounts.bob), Ok(2));
+            assert_eq!(epr.self_register(accounts.bob), Err(Error::PatientExists));
+            assert_eq!(epr.record_origin(accounts.bob), Some(RecordOrigin::SelfRegistered));
+            assert_eq!(epr.record_origin(accounts.django), Some(RecordOrigin::InstitutionRegistered));
+            assert_eq!(epr.record_origin(accounts.eve), None);
+
+            // Nobody can claim the record in the hospital's name.
+            set_caller(accounts.eve);
+            assert_eq!(epr.create_patient(accounts.alice, accounts.bob), Err(Error::PermissionDenied));
+            assert_eq!(epr.claimed_by(accounts.bob), None);
+
+            // The hospital asked to create his record takes on the existing one, under the same health id.
+            set_caller(accounts.alice);
+            assert_eq!(epr.create_patient(accounts.charlie, accounts.bob), Err(Error::PermissionDenied));
+            assert_eq!(epr.create_patient(accounts.alice, accounts.bob), Ok(()));
+            assert_eq!(epr.health_ids.get(accounts.bob), Some(2));
+            assert_eq!(epr.current_id, 2);
+            assert_eq!(epr.claimed_by(accounts.bob), Some(accounts.alice));
+            assert!(recorded_events().iter().any(|event| matches!(event, Event::PatientClaimed(PatientClaimed { id: 2, .. }))));
+            assert_eq!(epr.export_patient(accounts.bob).map(|bundle| bundle.origin), Ok(RecordOrigin::SelfRegistered));
+
+            // Neither a claimed record nor one an institution created can be created again.
+            assert_eq!(epr.create_patient(accounts.alice, accounts.bob), Err(Error::PatientExists));
+            assert_eq!(epr.create_patient(accounts.alice, accounts.django), Err(Error::PatientExists));
+        }
+
+        #[ink::test]
+        fn export_grants_are_single_use() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.bob);
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            assert_eq!(epr.update_biodata(accounts.alice, accounts.bob, biodata("Bob", "O+", vec![1])), Ok(()));
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(100);
+
+            set_caller(accounts.charlie);
+            assert_eq!(epr.create_export_grant(accounts.django, 200), Err(Error::RecordNotFound));
+            set_caller(accounts.bob);
+            assert_eq!(epr.create_export_grant(accounts.charlie, 100), Err(Error::InvalidExpiry));
+            assert_eq!(epr.create_export_grant(accounts.charlie, 200), Ok(0));
+            assert_eq!(epr.create_export_grant(accounts.charlie, 150), Ok(1));
+
+            // Charlie has no access of his own, yet exports the full bundle once with the grant.
+            set_caller(accounts.django);
+            assert_eq!(epr.export_with_grant(0), Err(Error::PermissionDenied));
+            set_caller(accounts.charlie);
+            assert_eq!(epr.export_patient(accounts.bob), Err(Error::PermissionDenied));
+            let bundle = epr.export_with_grant(0).expect("bundle");
+            assert_eq!(bundle.biodata, Some(biodata("Bob", "O+", vec![1])));
+            assert!(matches!(
+                recorded_events().last(),
+                Some(Event::ExportPerformed(ExportPerformed { grant_id: 0, .. }))
+            ));
+            assert_eq!(epr.export_with_grant(0), Err(Error::GrantUnavailable));
+
+            // The other grant lapses at its expiry.
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(150);
+            assert_eq!(epr.export_with_grant(1), Err(Error::GrantUnavailable));
+
+            // The export is in Bob's log.
+            let entry = ExportRecord { grant_id: 0, recipient: accounts.charlie, exported_at: 100 };
+            assert_eq!(epr.exports_of(accounts.bob, 0, 10), Err(Error::PermissionDenied));
+            set_caller(accounts.bob);
+            assert_eq!(epr.exports_of(accounts.bob, 0, 10), Ok(vec![entry]));
+        }
+
+        #[ink::test]
+        fn bundle_hash_is_stable() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.bob);
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            assert_eq!(epr.update_biodata(accounts.alice, accounts.bob, biodata("Bob", "O+", vec![1, 2])), Ok(()));
+
+            // The hash is the Blake2x256 hash of the encoded bundle and does not change between calls.
+            let hash = epr.bundle_hash(accounts.bob).expect("hash");
+            let mut expected = [0u8; 32];
+            ink::env::hash_encoded::<Blake2x256, _>(&epr.export_patient(accounts.bob).expect("bundle"), &mut expected);
+            assert_eq!(hash, expected);
+            assert_eq!(epr.bundle_hash(accounts.bob), Ok(hash));
+
+            // Any change to the record changes the hash.
+            assert_eq!(epr.update_clinical_notes(accounts.alice, accounts.bob, notes("checkup")), Ok(()));
+            assert_ne!(epr.bundle_hash(accounts.bob), Ok(hash));
+        }
+
+        #[ink::test]
+        fn external_ids_are_deduplicated_and_coexist_with_sequential_ids() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let national = Hash::from([0x11; 32]);
+
+            // Sequential and derived ids live side by side. The derived id depends only on the hash.
+            let first = register(&mut epr, accounts.bob);
+            let derived = epr.register_external(accounts.charlie, national).expect("derived id");
+            let second = register(&mut epr, accounts.django);
+            assert_eq!((first, second), (1, 2));
+            assert_eq!(derived, 0x1111_1111 | EXTERNAL_ID_FLAG);
+            assert_eq!(epr.record_count.get(derived), Some(accounts.charlie));
+
+            // The id is found by hash.
+            assert_eq!(epr.health_id_by_external(national), Some(derived));
+            assert_eq!(epr.health_id_by_external(Hash::from([0x22; 32])), None);
+
+            // The same hash cannot be registered twice, nor can an already registered account.
+            assert_eq!(epr.register_external(accounts.eve, national), Err(Error::PatientExists));
+            assert_eq!(epr.register_external(accounts.bob, Hash::from([0x33; 32])), Err(Error::PatientExists));
+
+            // Creating a patient from an external id requires access.
+            set_caller(accounts.frank);
+            assert_eq!(epr.create_patient_with_external_id(accounts.frank, Hash::from([0x44; 32])), Err(Error::PermissionDenied));
+        }
+
+        #[ink::test]
+        fn consent_receipts_survive_revocation() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.bob);
+            let terms = Hash::from([0xAB; 32]);
+            let uri = String::from("ipfs://consent-v1");
+            let template = epr.add_template(uri.clone(), terms, 3).expect("template");
+
+            // Only registered patients can grant consent.
+            set_caller(accounts.charlie);
+            assert_eq!(epr.grant_consent(accounts.django, CONSENT_BIODATA, template, None), Err(Error::RecordNotFound));
+
+            // Bob consents to Django reading his biodata.
+            set_caller(accounts.bob);
+            advance_blocks(3);
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(1_000);
+            assert_eq!(epr.grant_consent(accounts.django, CONSENT_BIODATA, template, None), Ok(()));
+            let granted = epr.consent_receipt(accounts.bob, accounts.django).expect("active consent");
+            assert_eq!(granted.terms_hash, terms);
+            assert_eq!(granted.document_uri, uri);
+            assert_eq!((granted.template_id, granted.template_version), (template, 3));
+            assert_eq!(granted.scope, CONSENT_BIODATA);
+            assert_eq!(granted.granted_at, 1_000);
+            assert_eq!(granted.starts_at, 1_000);
+            assert_eq!(granted.granted_block, ink::env::block_number::<ink::env::DefaultEnvironment>());
+            assert_eq!(granted.revoked_at, None);
+
+            // Revoking keeps the receipt in the history with the revocation time.
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(5_000);
+            assert_eq!(epr.revoke_consent(accounts.django), Ok(()));
+            assert_eq!(epr.revoke_consent(accounts.django), Err(Error::ConsentNotFound));
+            assert_eq!(epr.consent_receipt(accounts.bob, accounts.django), None);
+            assert_eq!(epr.revoked_consents_of(accounts.bob, 0, 10), vec![ConsentReceipt { revoked_at: Some(5_000), ..granted }]);
+            assert!(epr.revoked_consents_of(accounts.charlie, 0, 10).is_empty());
+        }
+
+        #[ink::test]
+        fn future_dated_consents_only_grant_access_from_their_start() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.bob);
+            epr.patient_biodata.insert(accounts.bob, &biodata("Bob", "", Vec::new()));
+            let terms = standard_terms(&mut epr);
+            let appointment = 10 * DAY;
+
+            // Bob consents ahead of an appointment with Charlie.
+            set_caller(accounts.bob);
+            assert_eq!(epr.grant_consent(accounts.charlie, CONSENT_BIODATA, terms, Some(appointment)), Ok(()));
+            assert_eq!(epr.consent_receipt(accounts.bob, accounts.charlie).map(|receipt| receipt.starts_at), Some(appointment));
+
+            // Until then the consent is known but inactive; scopes it does not cover are still plainly denied.
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(appointment - 1);
+            set_caller(accounts.charlie);
+            assert_eq!(epr.read_biodata(accounts.bob), Err(Error::ConsentNotYetActive));
+            assert_eq!(epr.get_biodata_view(accounts.bob), None);
+            assert_eq!(epr.denial_reason(accounts.charlie, accounts.bob, Action::Read(CONSENT_BIODATA)),
+                Some((Error::ConsentNotYetActive, DENIAL_CONSENT_NOT_YET_ACTIVE)));
+            assert_eq!(epr.read_clinical_notes(accounts.bob), Err(Error::PermissionDenied));
+
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(appointment);
+            assert_eq!(epr.read_biodata(accounts.bob).map(|view| view.record.name), Ok(String::from("Bob")));
+            assert_eq!(epr.denial_reason(accounts.charlie, accounts.bob, Action::Read(CONSENT_BIODATA)), None);
+        }
+
+        #[ink::test]
+        fn deactivated_templates_reject_new_consents_only() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.bob);
+
+            // Only the admin publishes templates.
+            set_caller(accounts.bob);
+            assert_eq!(epr.add_template(String::from("ipfs://consent-v1"), Hash::from([1; 32]), 1), Err(Error::PermissionDenied));
+            set_caller(accounts.alice);
+            assert_eq!(epr.add_template(String::new(), Hash::from([1; 32]), 1), Err(Error::EmptyPayload));
+            let v1 = epr.add_template(String::from("ipfs://consent-v1"), Hash::from([1; 32]), 1).expect("template");
+            let v2 = epr.add_template(String::from("ipfs://consent-v2"), Hash::from([2; 32]), 2).expect("template");
+            assert_eq!((v1, v2), (1, 2));
+            assert_eq!(epr.templates(1, 10), vec![(v2, epr.template(v2).expect("template"))]);
+            assert_eq!(epr.templates(0, 10).len(), 2);
+
+            // Bob consents against v1; unknown templates are rejected.
+            set_caller(accounts.bob);
+            assert_eq!(epr.grant_consent(accounts.charlie, CONSENT_BIODATA, 3, None), Err(Error::InvalidTemplate));
+            assert_eq!(epr.grant_consent(accounts.charlie, CONSENT_BIODATA, v1, None), Ok(()));
+
+            // Once v1 is retired, new consents against it fail, while Bob's consent still grants access.
+            set_caller(accounts.bob);
+            assert_eq!(epr.deactivate_template(v1), Err(Error::PermissionDenied));
+            set_caller(accounts.alice);
+            assert_eq!(epr.deactivate_template(v1), Ok(()));
+            assert_eq!(epr.deactivate_template(9), Err(Error::InvalidTemplate));
+            assert!(!epr.template(v1).expect("template").active);
+            set_caller(accounts.bob);
+            assert_eq!(epr.grant_consent(accounts.django, CONSENT_BIODATA, v1, None), Err(Error::InvalidTemplate));
+            assert_eq!(epr.explain_access(accounts.charlie, accounts.bob), Some(AccessPath::Consent));
+            assert_eq!(epr.consent_receipt(accounts.bob, accounts.charlie).expect("active consent").template_id, v1);
+
+            // Renewing the consent moves it to the current template.
+            assert_eq!(epr.grant_consent(accounts.charlie, CONSENT_BIODATA, v2, None), Ok(()));
+            let renewed = epr.consent_receipt(accounts.bob, accounts.charlie).expect("active consent");
+            assert_eq!((renewed.template_version, renewed.terms_hash), (2, Hash::from([2; 32])));
+            assert_eq!(epr.revoked_consents_of(accounts.bob, 0, 10)[0].template_version, 1);
+        }
+
+        // Builds an EPR contract guarded by Charlie, Django and Eve.
+        fn guarded_epr() -> Epr {
+            let accounts = default_accounts();
+            Epr::with_patient(
+                ink::env::call::FromAccountId::from_account_id(AccountId::from([0xFF; 32])),
+                vec![accounts.charlie, accounts.django, accounts.eve]
+            )
+        }
+
+        #[ink::test]
+        fn admin_acts_directly_without_guardians() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.bob);
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            assert_eq!(epr.update_biodata(accounts.alice, accounts.bob, biodata("Bob", "", Vec::new())), Ok(()));
+
+            set_caller(accounts.bob);
+            assert_eq!(epr.erase_patient_data(accounts.bob), Err(Error::PermissionDenied));
+            set_caller(accounts.alice);
+            assert_eq!(epr.erase_patient_data(accounts.bob), Ok(()));
+            assert_eq!(epr.get_biodata(accounts.alice, accounts.bob), None);
+            assert_eq!(epr.archive_patient(accounts.bob), Ok(()));
+            assert!(epr.is_archived(accounts.bob));
+            assert_eq!(epr.update_biodata(accounts.alice, accounts.bob, biodata("Bob", "", Vec::new())), Err(Error::RecordArchived));
+        }
+
+        #[ink::test]
+        fn burned_tokens_archive_their_record() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            let id = register(&mut epr, accounts.bob);
+
+            // Only the linked Patient contract can report burns.
+            assert_eq!(epr.on_token_burned(id), Err(Error::PermissionDenied));
+            set_caller(epr.patient_contract());
+            assert_eq!(epr.on_token_burned(id + 1), Err(Error::RecordNotFound));
+            assert_eq!(epr.on_token_burned(id), Ok(()));
+            assert!(epr.is_archived(accounts.bob));
+        }
+
+        #[ink::test]
+        fn guardian_threshold_gates_destructive_actions() {
+            let accounts = default_accounts();
+            let mut epr = guarded_epr();
+            register(&mut epr, accounts.bob);
+
+            // With guardians configured, the admin cannot act alone and only guardians propose.
+            assert_eq!(epr.archive_patient(accounts.bob), Err(Error::GuardiansRequired));
+            assert_eq!(epr.upgrade(Hash::from([0x01; 32])), Err(Error::GuardiansRequired));
+            assert_eq!(epr.propose_action(AdminAction::ArchivePatient(accounts.bob)), Err(Error::NotGuardian));
+
+            // The proposer's approval counts once; a second approval by the same guardian is rejected.
+            set_caller(accounts.charlie);
+            let id = epr.propose_action(AdminAction::ArchivePatient(accounts.bob)).expect("guardian proposes");
+            assert_eq!(epr.approve_action(id), Err(Error::AlreadyApproved));
+            assert!(!epr.is_archived(accounts.bob));
+            assert_eq!(epr.proposal(id).map(|p| p.approvals), Some(1));
+
+            // A second guardian meets the threshold and the action is executed.
+            set_caller(accounts.django);
+            assert_eq!(epr.approve_action(id), Ok(()));
+            assert!(epr.is_archived(accounts.bob));
+            assert_eq!(epr.proposal(id).map(|p| p.executed), Some(true));
+            set_caller(accounts.eve);
+            assert_eq!(epr.approve_action(id), Err(Error::ProposalExecuted));
+            assert_eq!(epr.approve_action(id + 1), Err(Error::ProposalNotFound));
+        }
+
+        #[ink::test]
+        fn guardian_proposals_expire() {
+            let accounts = default_accounts();
+            let mut epr = guarded_epr();
+            register(&mut epr, accounts.bob);
+            assert_eq!(epr.set_proposal_ttl(10), Ok(()));
+
+            set_caller(accounts.charlie);
+            let id = epr.propose_action(AdminAction::ErasePatientData(accounts.bob)).expect("guardian proposes");
+            assert_eq!(epr.expire_action(id), Err(Error::NotAllowed));
+
+            advance_blocks(11);
+            set_caller(accounts.django);
+            assert_eq!(epr.approve_action(id), Err(Error::ProposalExpired));
+            assert_eq!(epr.expire_action(id), Ok(()));
+            assert_eq!(epr.proposal(id), None);
+            assert!(matches!(recorded_events().last(), Some(Event::ActionExpired(ActionExpired { id: expired })) if *expired == id));
+        }
+
+        type Event = <Epr as ink::reflect::ContractEventBase>::Type;
+
+        // Computes the topic ink! derives for an event field, so tests can match recorded topics.
+        fn topic<T: scale::Encode>(prefix: &[u8], value: &T) -> Vec<u8> {
+            let encoded = scale::Encode::encode(&ink::env::topics::PrefixedValue { prefix, value });
+            let mut hash = [0u8; 32];
+            ink::env::hash_bytes::<Blake2x256>(&encoded, &mut hash);
+            hash.to_vec()
+        }
+
+        #[ink::test]
+        fn update_events_carry_only_ids_and_payload_hashes() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.bob);
+            assert_eq!(epr.add_user_with_permissions(accounts.alice, true), Ok(()));
+            let record = biodata("Bob", "O+", vec![1, 2]);
+            assert_eq!(epr.update_biodata(accounts.alice, accounts.bob, record.clone()), Ok(()));
+            assert_eq!(epr.update_clinical_notes(accounts.alice, accounts.bob, notes("checkup")), Ok(()));
+
+            // The views return the hash of the stored payloads to readers with access only.
+            let biodata_hash = epr.hash_of_biodata(accounts.alice, accounts.bob).expect("hash");
+            let notes_hash = epr.hash_of_clinical_notes(accounts.alice, accounts.bob).expect("hash");
+            let mut expected = [0u8; 32];
+            ink::env::hash_encoded::<Blake2x256, _>(&record, &mut expected);
+            assert_eq!(biodata_hash, Hash::from(expected));
+            assert_eq!(epr.hash_of_biodata(accounts.charlie, accounts.bob), None);
+
+            // The topics are the event signature, the patient, the author and the payload hash.
+            let events: Vec<_> = ink::env::test::recorded_events().collect();
+            let biodata_event = &events[events.len() - 2];
+            assert_eq!(biodata_event.topics[1], topic(b"Epr::BiodataUpdate::identifier", &accounts.bob));
+            assert_eq!(biodata_event.topics[2], topic(b"Epr::BiodataUpdate::by", &accounts.alice));
+            assert_eq!(biodata_event.topics[3], topic(b"Epr::BiodataUpdate::payload_hash", &biodata_hash));
+            let notes_event = &events[events.len() - 1];
+            assert_eq!(notes_event.topics[3], topic(b"Epr::ClinicalNotesUpdate::payload_hash", &notes_hash));
+
+            // The payload is not part of the event data.
+            match <Event as scale::Decode>::decode(&mut &biodata_event.data[..]).expect("invalid event data") {
+                Event::BiodataUpdate(event) => assert_eq!(event.payload_hash, biodata_hash),
+                _ => panic!("expected BiodataUpdate")
             }
-            // If no permission, return None
-            None
-            // return self.patient_biodata.get(&identifier); 
+            assert!(biodata_event.data.len() < 100);
         }
 
-        // The get_clinical_notes function retrieves the clinical notes of a patient.
-        #[ink(message)]
-        pub fn get_clinical_notes(&self, requester: AccountId, identifier: AccountId) -> Option<ClinicalNotes> {
-            // Check if the requester has permission to access biodata
-            if let Some(permission) = self.permissions.get(&requester) {
-                if permission.can_access {
-                    return self.patient_notes.get(&identifier)
-                }
+        fn recorded_events() -> Vec<Event> {
+            ink::env::test::recorded_events()
+                .map(|event| <Event as scale::Decode>::decode(&mut &event.data[..]).expect("invalid event data"))
+                .collect()
+        }
+
+        #[ink::test]
+        fn permission_changes_emit_old_and_new_values() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+
+            // Grant, change, then revoke Bob's permission.
+            assert_eq!(epr.add_user_with_permissions(accounts.bob, true), Ok(()));
+            assert_eq!(epr.add_user_with_permissions(accounts.bob, false), Ok(()));
+            set_caller(accounts.eve);
+            assert_eq!(epr.remove_user_permissions(accounts.bob), Err(Error::PermissionDenied));
+            set_caller(accounts.alice);
+            assert_eq!(epr.remove_user_permissions(accounts.bob), Ok(()));
+            assert_eq!(epr.remove_user_permissions(accounts.bob), Err(Error::RecordNotFound));
+
+            let changes: Vec<(AccountId, AccountId, Option<bool>, Option<bool>)> = recorded_events()
+                .into_iter()
+                .filter_map(|event| match event {
+                    Event::PermissionChanged(e) => Some((
+                        e.user,
+                        e.by,
+                        e.old.map(|p| p.can_access),
+                        e.new.map(|p| p.can_access)
+                    )),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(
+                changes,
+                vec![
+                    (accounts.bob, accounts.alice, None, Some(true)),
+                    (accounts.bob, accounts.alice, Some(true), Some(false)),
+                    (accounts.bob, accounts.alice, Some(false), None),
+                ]
+            );
+        }
+
+        #[ink::test]
+        fn group_permission_changes_emit_old_and_new_values() {
+            let mut epr = new_epr();
+            let group = epr.create_group(String::from("Cardiology"), default_accounts().bob).expect("admin creates groups");
+            assert_eq!(epr.add_group_with_permissions(group, true), Ok(()));
+            assert_eq!(epr.add_group_with_permissions(group, false), Ok(()));
+
+            let changes: Vec<(u32, Option<bool>, Option<bool>)> = recorded_events()
+                .into_iter()
+                .filter_map(|event| match event {
+                    Event::GroupPermissionChanged(e) => Some((e.group, e.old.map(|p| p.can_access), e.new.map(|p| p.can_access))),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(changes, vec![(group, None, Some(true)), (group, Some(true), Some(false))]);
+        }
+
+        #[ink::test]
+        fn research_consent_changes_emit_old_and_new_values() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.bob);
+
+            set_caller(accounts.bob);
+            assert_eq!(epr.set_research_consent(true), Ok(()));
+            advance_blocks(DEFAULT_COOLDOWN);
+            assert_eq!(epr.set_research_consent(false), Ok(()));
+
+            let changes: Vec<(AccountId, AccountId, bool, bool)> = recorded_events()
+                .into_iter()
+                .filter_map(|event| match event {
+                    Event::ResearchConsentChanged(e) => Some((e.patient, e.by, e.old, e.new)),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(
+                changes,
+                vec![
+                    (accounts.bob, accounts.bob, false, true),
+                    (accounts.bob, accounts.bob, true, false),
+                ]
+            );
+        }
+
+        fn pay(amount: Balance) {
+            ink::env::test::set_value_transferred::<ink::env::DefaultEnvironment>(amount);
+        }
+
+        fn advance_blocks(n: u32) {
+            for _ in 0..n {
+                ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
             }
-            // If no permission, return None
-            None
-            // return self.patient_notes.get(&identifier)
         }
+
+        #[ink::test]
+        fn request_access_is_rate_limited() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.bob);
+            register(&mut epr, accounts.charlie);
+
+            set_caller(accounts.eve);
+            pay(DEFAULT_STORAGE_DEPOSIT);
+            assert_eq!(epr.request_access(accounts.bob), Ok(()));
+            assert!(epr.access_request(accounts.bob, accounts.eve).is_some());
+            // A second request within the cooldown is rejected, even for another patient.
+            assert_eq!(epr.request_access(accounts.charlie), Err(Error::RateLimited));
+            advance_blocks(DEFAULT_COOLDOWN - 1);
+            assert_eq!(epr.request_access(accounts.charlie), Err(Error::RateLimited));
+            // Once the window has passed the call goes through again.
+            advance_blocks(1);
+            assert_eq!(epr.request_access(accounts.charlie), Ok(()));
+            // Other accounts are not affected by Eve's cooldown.
+            set_caller(accounts.frank);
+            assert_eq!(epr.request_access(accounts.charlie), Ok(()));
+        }
+
+        #[ink::test]
+        fn cooldowns_are_tracked_per_message_kind() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.bob);
+
+            set_caller(accounts.bob);
+            assert_eq!(epr.set_research_consent(true), Ok(()));
+            assert!(epr.research_consent_of(accounts.bob));
+            // Registering as a provider is a different kind and not blocked.
+            pay(DEFAULT_STORAGE_DEPOSIT);
+            assert_eq!(epr.register_provider(), Ok(()));
+            assert_eq!(epr.set_research_consent(false), Err(Error::RateLimited));
+            assert_eq!(epr.register_provider(), Err(Error::RateLimited));
+            advance_blocks(DEFAULT_COOLDOWN);
+            assert_eq!(epr.set_research_consent(false), Ok(()));
+            assert!(!epr.research_consent_of(accounts.bob));
+            assert_eq!(epr.register_provider(), Err(Error::AlreadyRegistered));
+        }
+
+        #[ink::test]
+        fn admin_tunes_cooldown() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.bob);
+            register(&mut epr, accounts.charlie);
+
+            set_caller(accounts.eve);
+            assert_eq!(epr.set_cooldown(0), Err(Error::PermissionDenied));
+
+            set_caller(accounts.alice);
+            assert_eq!(epr.set_cooldown(5), Ok(()));
+            assert_eq!(epr.cooldown(), 5);
+            pay(DEFAULT_STORAGE_DEPOSIT);
+
+            set_caller(accounts.eve);
+            assert_eq!(epr.request_access(accounts.bob), Ok(()));
+            advance_blocks(4);
+            assert_eq!(epr.request_access(accounts.charlie), Err(Error::RateLimited));
+            advance_blocks(1);
+            assert_eq!(epr.request_access(accounts.charlie), Ok(()));
+        }
+
+        #[ink::test]
+        fn open_registrations_collect_deposits() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.bob);
+
+            set_caller(accounts.eve);
+            pay(DEFAULT_STORAGE_DEPOSIT - 1);
+            assert_eq!(epr.request_access(accounts.bob), Err(Error::InsufficientDeposit));
+            assert_eq!(epr.register_provider(), Err(Error::InsufficientDeposit));
+
+            // Paying more than required holds the deposit and credits the excess back.
+            pay(DEFAULT_STORAGE_DEPOSIT + 5);
+            advance_blocks(DEFAULT_COOLDOWN);
+            assert_eq!(epr.request_access(accounts.bob), Ok(()));
+            assert_eq!(epr.deposit_of(accounts.eve), DEFAULT_STORAGE_DEPOSIT);
+            assert_eq!(epr.pending_withdrawal_of(accounts.eve), 5);
+
+            pay(DEFAULT_STORAGE_DEPOSIT);
+            advance_blocks(DEFAULT_COOLDOWN);
+            assert_eq!(epr.register_provider(), Ok(()));
+            assert_eq!(epr.deposit_of(accounts.eve), 2 * DEFAULT_STORAGE_DEPOSIT);
+        }
+
+        #[ink::test]
+        fn deposits_are_refunded_on_cleanup() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.bob);
+            let contract = ink::env::test::callee::<ink::env::DefaultEnvironment>();
+            ink::env::test::set_account_balance::<ink::env::DefaultEnvironment>(contract, 10 * DEFAULT_STORAGE_DEPOSIT);
+
+            set_caller(accounts.eve);
+            pay(DEFAULT_STORAGE_DEPOSIT);
+            assert_eq!(epr.request_access(accounts.bob), Ok(()));
+            assert_eq!(epr.register_provider(), Ok(()));
+            assert_eq!(epr.withdraw(), Err(Error::NothingToWithdraw));
+
+            // Only the patient can resolve the request.
+            assert_eq!(epr.resolve_access_request(accounts.eve, true), Err(Error::RecordNotFound));
+            set_caller(accounts.bob);
+            assert_eq!(epr.resolve_access_request(accounts.eve, true), Ok(()));
+            assert_eq!(epr.access_request(accounts.bob, accounts.eve), None);
+            assert_eq!(epr.deposit_of(accounts.eve), DEFAULT_STORAGE_DEPOSIT);
+            assert_eq!(epr.pending_withdrawal_of(accounts.eve), DEFAULT_STORAGE_DEPOSIT);
+
+            set_caller(accounts.eve);
+            assert_eq!(epr.deregister_provider(), Ok(()));
+            assert_eq!(epr.provider(accounts.eve), None);
+            assert_eq!(epr.deposit_of(accounts.eve), 0);
+
+            let before = ink::env::test::get_account_balance::<ink::env::DefaultEnvironment>(accounts.eve).unwrap_or(0);
+            assert_eq!(epr.withdraw(), Ok(2 * DEFAULT_STORAGE_DEPOSIT));
+            let after = ink::env::test::get_account_balance::<ink::env::DefaultEnvironment>(accounts.eve).unwrap_or(0);
+            assert_eq!(after - before, 2 * DEFAULT_STORAGE_DEPOSIT);
+            assert_eq!(epr.pending_withdrawal_of(accounts.eve), 0);
+        }
+
+        #[ink::test]
+        fn changing_the_deposit_keeps_held_deposits() {
+            let accounts = default_accounts();
+            let mut epr = new_epr();
+            register(&mut epr, accounts.bob);
+
+            set_caller(accounts.eve);
+            pay(DEFAULT_STORAGE_DEPOSIT);
+            assert_eq!(epr.register_provider(), Ok(()));
+
+            set_caller(accounts.alice);
+            assert_eq!(epr.set_storage_deposit(3 * DEFAULT_STORAGE_DEPOSIT), Ok(()));
+            assert_eq!(epr.storage_deposit(), 3 * DEFAULT_STORAGE_DEPOSIT);
+
+            // New entries pay the new deposit.
+            set_caller(accounts.frank);
+            pay(DEFAULT_STORAGE_DEPOSIT);
+            assert_eq!(epr.request_access(accounts.bob), Err(Error::InsufficientDeposit));
+
+            // The already held deposit is refunded at its original amount.
+            set_caller(accounts.eve);
+            assert_eq!(epr.deregister_provider(), Ok(()));
+            assert_eq!(epr.pending_withdrawal_of(accounts.eve), DEFAULT_STORAGE_DEPOSIT);
+
+            set_caller(accounts.eve);
+            assert_eq!(epr.set_storage_deposit(0), Err(Error::PermissionDenied));
+        }
+
     }
 
-    #[cfg(test)]
-    mod tests {
+    #[cfg(all(test, feature = "e2e-tests"))]
+    mod e2e_tests {
         use super::*;
+        use ink_e2e::build_message;
+        use patient::PatientRef;
 
-        // #[ink::test]
-        // fn new_creates_contract_with_zero_id() {
-        //     let patient_code_hash: Hash = Hash::from([0x00; 32]);
-        //     let healthdot = Epr::new(patient_code_hash);
+        type E2EResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
-        //     assert_eq!(healthdot.current_id, 0);
-        // }
+        // Deploys the EPR contract (which instantiates its Patient child), grants Alice access
+        // and creates Bob's record, which mints token 1 to Bob. Returns the EPR and Patient addresses.
+        async fn setup(client: &mut ink_e2e::Client<ink_e2e::PolkadotConfig, ink::env::DefaultEnvironment>) -> (AccountId, AccountId) {
+            let alice = ink_e2e::account_id(ink_e2e::AccountKeyring::Alice);
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
 
-        // #[ink::test]
-        // fn add_user_with_permissions_works() {
-        //     let patient_code_hash: Hash = Hash::from([0x00; 32]);
-        //     let mut healthdot = Epr::new(patient_code_hash);
-        //     let user: AccountId = AccountId::from([0x0; 32]);
+            let patient_hash = client
+                .upload("patient", &ink_e2e::alice(), None)
+                .await
+                .expect("uploading `patient` failed")
+                .code_hash;
+            let epr_account = client
+                .instantiate("epr", &ink_e2e::alice(), EprRef::new(patient_hash, String::from("HealthDOT"), String::from("HDOT"), Vec::new()), 0, None)
+                .await
+                .expect("instantiating `epr` failed")
+                .account_id;
 
-        //     healthdot.add_user_with_permissions(user, true);
-            
-        //     assert_eq!(healthdot.permissions.get(&user).unwrap().can_access, true);
-        // }
+            let grant = build_message::<EprRef>(epr_account.clone())
+                .call(|epr| epr.add_user_with_permissions(alice, true));
+            client.call(&ink_e2e::alice(), grant, 0, None).await.expect("grant failed");
+            let create = build_message::<EprRef>(epr_account.clone())
+                .call(|epr| epr.create_patient(alice, bob));
+            client.call(&ink_e2e::alice(), create, 0, None).await.expect("create_patient failed");
 
-        // #[ink::test]
-        // fn create_patient_without_permission_fails() {
-        //     let patient_code_hash: Hash = Hash::repeat_byte(0x00);
-        //     let mut healthdot = HealthDot::new(patient_code_hash);
-        //     let requester: AccountId = AccountId::from([0x01; 32]);
-        //     let identifier: AccountId = AccountId::from([0x02; 32]);
+            let patient_contract = build_message::<EprRef>(epr_account.clone())
+                .call(|epr| epr.patient_contract());
+            let patient_account = client
+                .call_dry_run(&ink_e2e::alice(), &patient_contract, 0, None)
+                .await
+                .return_value();
 
-        //     assert_eq!(
-        //         healthdot.create_patient(requester, identifier),
-        //         Err(Error::PermissionDenied)
-        //     );
-        // }
+            (epr_account, patient_account)
+        }
 
-        // #[ink::test]
-        // fn create_patient_with_permission_increments_id() {
-        //     let patient_code_hash: Hash = Hash::repeat_byte(0x00);
-        //     let mut healthdot = HealthDot::new(patient_code_hash);
-        //     let requester: AccountId = AccountId::from([0x01; 32]);
-        //     let identifier: AccountId = AccountId::from([0x02; 32]);
+        #[ink_e2e::test(additional_contracts = "patient/Cargo.toml")]
+        async fn rejected_mint_reports_the_patient_error(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let alice = ink_e2e::account_id(ink_e2e::AccountKeyring::Alice);
+            let (epr_account, patient_account) = setup(&mut client).await;
 
-        //     healthdot.add_user_with_permissions(requester, true);
-        //     assert_eq!(healthdot.create_patient(requester, identifier), Ok(()));
-        //     assert_eq!(healthdot.current_id, 1);
-        //     assert_eq!(healthdot.record_count.get(&1), Some(&identifier));
-        // }
+            // The Patient contract refuses to mint to its own address with NotAllowed (discriminant 4).
+            let create = build_message::<EprRef>(epr_account.clone())
+                .call(|epr| epr.create_patient(alice, patient_account));
+            let result = client.call_dry_run(&ink_e2e::alice(), &create, 0, None).await.return_value();
+            assert_eq!(result, Err(Error::CrossContract { selector: ink::selector_bytes!("mint_to"), inner: 4 }));
+
+            Ok(())
+        }
+
+        #[ink_e2e::test(additional_contracts = "patient/Cargo.toml")]
+        async fn imported_patient_receives_the_legacy_token(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let charlie = ink_e2e::account_id(ink_e2e::AccountKeyring::Charlie);
+            let (epr_account, patient_account) = setup(&mut client).await;
+
+            let biodata = Biodata {
+                name: String::from("Charlie"),
+                details: String::from("legacy"),
+                finalized: false,
+                vector: Vec::new()
+            };
+            let import = build_message::<EprRef>(epr_account.clone())
+                .call(|epr| epr.import_patient(42, charlie, biodata.clone(), None));
+            client.call(&ink_e2e::alice(), import, 0, None).await.expect("import_patient failed");
+
+            // Charlie holds token 42, matching the legacy health id.
+            let owner = build_message::<PatientRef>(patient_account.clone()).call(|patient| patient.owner_of(42));
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &owner, 0, None).await.return_value(), Some(charlie));
+
+            // Importing the same record again collides.
+            let again = build_message::<EprRef>(epr_account.clone())
+                .call(|epr| epr.import_patient(42, charlie, biodata.clone(), None));
+            let result = client.call_dry_run(&ink_e2e::alice(), &again, 0, None).await.return_value();
+            assert_eq!(result, Err(Error::PatientExists));
+
+            Ok(())
+        }
+
+        #[ink_e2e::test(additional_contracts = "patient/Cargo.toml")]
+        async fn token_holder_can_read_record(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let alice = ink_e2e::account_id(ink_e2e::AccountKeyring::Alice);
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
+            let charlie = ink_e2e::account_id(ink_e2e::AccountKeyring::Charlie);
+            let (epr_account, patient_account) = setup(&mut client).await;
+
+            let biodata = Biodata {
+                name: String::from("Bob"),
+                details: String::from("details"),
+                finalized: false,
+                vector: Vec::new()
+            };
+            let update = build_message::<EprRef>(epr_account.clone())
+                .call(|epr| epr.update_biodata(alice, bob, biodata.clone()));
+            client.call(&ink_e2e::alice(), update, 0, None).await.expect("update_biodata failed");
+
+            // Bob holds token 1 and can read record 1, Charlie cannot.
+            let read = build_message::<EprRef>(epr_account.clone())
+                .call(|epr| epr.read_biodata_as_token_holder(1));
+            let as_bob = client.call_dry_run(&ink_e2e::bob(), &read, 0, None).await.return_value();
+            assert_eq!(as_bob.map(|b| b.name), Ok(String::from("Bob")));
+            let as_charlie = client.call_dry_run(&ink_e2e::charlie(), &read, 0, None).await.return_value();
+            assert_eq!(as_charlie.map(|b| b.name), Err(Error::PermissionDenied));
+
+            // Bob transfers the token to Charlie on the Patient contract.
+            let transfer = build_message::<PatientRef>(patient_account.clone())
+                .call(|patient| patient.transfer(charlie, 1));
+            client.call(&ink_e2e::bob(), transfer, 0, None).await.expect("transfer failed");
+
+            // Charlie gains the read path and Bob loses it.
+            let as_charlie = client.call_dry_run(&ink_e2e::charlie(), &read, 0, None).await.return_value();
+            assert_eq!(as_charlie.map(|b| b.name), Ok(String::from("Bob")));
+            let as_bob = client.call_dry_run(&ink_e2e::bob(), &read, 0, None).await.return_value();
+            assert_eq!(as_bob.map(|b| b.name), Err(Error::PermissionDenied));
+
+            Ok(())
+        }
+
+        #[ink_e2e::test(additional_contracts = "patient/Cargo.toml")]
+        async fn primary_institution_transfer_moves_the_token(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
+            let charlie = ink_e2e::account_id(ink_e2e::AccountKeyring::Charlie);
+            let dave = ink_e2e::account_id(ink_e2e::AccountKeyring::Dave);
+            let (epr_account, patient_account) = setup(&mut client).await;
+
+            // Charlie and Dave run verified hospitals.
+            for (signer, org) in [(ink_e2e::charlie(), charlie), (ink_e2e::dave(), dave)] {
+                let register = build_message::<EprRef>(epr_account.clone()).call(|epr| epr.register_provider());
+                client.call(&signer, register, DEFAULT_STORAGE_DEPOSIT, None).await.expect("register_provider failed");
+                let verify = build_message::<EprRef>(epr_account.clone()).call(|epr| epr.verify_provider(org));
+                client.call(&ink_e2e::alice(), verify, 0, None).await.expect("verify_provider failed");
+            }
 
+            // Bob is cared for by Charlie's hospital, which holds the token of his record in custody
+            // and lets the EPR contract move it.
+            let to_charlie = build_message::<EprRef>(epr_account.clone())
+                .call(|epr| epr.transfer_primary_institution(bob, charlie));
+            client.call(&ink_e2e::bob(), to_charlie, 0, None).await.expect("transfer to Charlie failed");
+            let custody = build_message::<PatientRef>(patient_account.clone()).call(|patient| patient.transfer(charlie, 1));
+            client.call(&ink_e2e::bob(), custody, 0, None).await.expect("custody transfer failed");
+            let approve = build_message::<PatientRef>(patient_account.clone())
+                .call(|patient| patient.set_approval_for_all(epr_account, true));
+            client.call(&ink_e2e::charlie(), approve, 0, None).await.expect("approval failed");
+
+            // Moving to Dave's hospital moves the token and flips the onboarding access.
+            let to_dave = build_message::<EprRef>(epr_account.clone())
+                .call(|epr| epr.transfer_primary_institution(bob, dave));
+            client.call(&ink_e2e::bob(), to_dave, 0, None).await.expect("transfer to Dave failed");
+            let owner = build_message::<PatientRef>(patient_account.clone()).call(|patient| patient.owner_of(1));
+            assert_eq!(client.call_dry_run(&ink_e2e::bob(), &owner, 0, None).await.return_value(), Some(dave));
+            for (org, expected) in [(charlie, None), (dave, Some(AccessPath::Onboarding))] {
+                let explain = build_message::<EprRef>(epr_account.clone()).call(|epr| epr.explain_access(org, bob));
+                assert_eq!(client.call_dry_run(&ink_e2e::bob(), &explain, 0, None).await.return_value(), expected);
+            }
+
+            Ok(())
+        }
+
+        #[ink_e2e::test(additional_contracts = "patient/Cargo.toml")]
+        async fn requested_burn_keeps_record_until_executed(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
+            let (epr_account, patient_account) = setup(&mut client).await;
+
+            let link = build_message::<EprRef>(epr_account.clone()).call(|epr| epr.link_registry(true));
+            client.call(&ink_e2e::alice(), link, 0, None).await.expect("link_registry failed");
+
+            // Bob requests the burn of the token of his own record. Until the grace period has passed,
+            // nobody can execute it and the record stays live.
+            let request = build_message::<PatientRef>(patient_account.clone()).call(|patient| patient.request_burn(1));
+            client.call(&ink_e2e::bob(), request, 0, None).await.expect("request_burn failed");
+            let execute = build_message::<PatientRef>(patient_account.clone()).call(|patient| patient.execute_burn(1));
+            assert!(client.call(&ink_e2e::alice(), execute, 0, None).await.is_err());
+            let archived = build_message::<EprRef>(epr_account.clone()).call(|epr| epr.is_archived(bob));
+            assert!(!client.call_dry_run(&ink_e2e::bob(), &archived, 0, None).await.return_value());
+
+            Ok(())
+        }
+
+        #[ink_e2e::test(additional_contracts = "patient/Cargo.toml")]
+        async fn burn_with_failing_registry_still_burns(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
+            let patient_account = client
+                .instantiate("patient", &ink_e2e::alice(), PatientRef::new(String::from("HealthDOT"), String::from("HDOT"), String::new(), String::new()), 0, None)
+                .await
+                .expect("instantiating `patient` failed")
+                .account_id;
+
+            // Bob is not a contract, so every notification fails.
+            let set_registry = build_message::<PatientRef>(patient_account.clone())
+                .call(|patient| patient.set_registry(Some(bob)));
+            client.call(&ink_e2e::alice(), set_registry, 0, None).await.expect("set_registry failed");
+            let mint = build_message::<PatientRef>(patient_account.clone()).call(|patient| patient.mint(1));
+            client.call(&ink_e2e::alice(), mint, 0, None).await.expect("mint failed");
+
+            let no_grace = build_message::<PatientRef>(patient_account.clone())
+                .call(|patient| patient.set_burn_grace_period(0));
+            client.call(&ink_e2e::alice(), no_grace, 0, None).await.expect("set_burn_grace_period failed");
+            let request = build_message::<PatientRef>(patient_account.clone()).call(|patient| patient.request_burn(1));
+            client.call(&ink_e2e::alice(), request, 0, None).await.expect("request_burn failed");
+
+            let burn = build_message::<PatientRef>(patient_account.clone()).call(|patient| patient.execute_burn(1));
+            let result = client.call(&ink_e2e::alice(), burn, 0, None).await.expect("burn failed");
+            // Transfer and RegistryNotifyFailed.
+            let emitted = result
+                .events
+                .iter()
+                .filter(|event| event.as_ref().expect("bad event").variant_name() == "ContractEmitted")
+                .count();
+            assert_eq!(emitted, 2);
+            let exists = build_message::<PatientRef>(patient_account.clone()).call(|patient| patient.exists(1));
+            assert!(!client.call_dry_run(&ink_e2e::alice(), &exists, 0, None).await.return_value());
+
+            Ok(())
+        }
+
+        #[ink_e2e::test(additional_contracts = "patient/Cargo.toml")]
+        async fn metadata_read_is_cheaper_than_full_read(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let alice = ink_e2e::account_id(ink_e2e::AccountKeyring::Alice);
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
+            let (epr_account, _) = setup(&mut client).await;
+
+            let biodata = Biodata {
+                name: String::from("Bob"),
+                details: String::from("details"),
+                finalized: false,
+                vector: vec![0; MAX_VECTOR_LEN]
+            };
+            let update = build_message::<EprRef>(epr_account.clone())
+                .call(|epr| epr.update_biodata(alice, bob, biodata.clone()));
+            client.call(&ink_e2e::alice(), update, 0, None).await.expect("update_biodata failed");
+
+            let full = build_message::<EprRef>(epr_account.clone())
+                .call(|epr| epr.get_biodata(alice, bob));
+            let meta = build_message::<EprRef>(epr_account.clone())
+                .call(|epr| epr.biodata_meta(alice, bob));
+            let exists = build_message::<EprRef>(epr_account.clone())
+                .call(|epr| epr.has_biodata(alice, bob));
+            let full_gas = client.call_dry_run(&ink_e2e::alice(), &full, 0, None).await.exec_result.gas_consumed;
+            let meta_gas = client.call_dry_run(&ink_e2e::alice(), &meta, 0, None).await.exec_result.gas_consumed;
+            let exists_gas = client.call_dry_run(&ink_e2e::alice(), &exists, 0, None).await.exec_result.gas_consumed;
+
+            // Decoding the full payload costs materially more than reading its metadata.
+            assert!(meta_gas.ref_time() * 2 < full_gas.ref_time());
+            assert!(exists_gas.ref_time() <= meta_gas.ref_time());
+
+            Ok(())
+        }
+
+        #[ink_e2e::test(additional_contracts = "patient/Cargo.toml")]
+        async fn patient_collection_uses_constructor_metadata(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let patient_hash = client
+                .upload("patient", &ink_e2e::alice(), None)
+                .await
+                .expect("uploading `patient` failed")
+                .code_hash;
+            let constructor = EprRef::new(patient_hash, String::from("St. Mary Records"), String::from("SMR"), Vec::new());
+            let epr_account = client
+                .instantiate("epr", &ink_e2e::alice(), constructor, 0, None)
+                .await
+                .expect("instantiating `epr` failed")
+                .account_id;
+
+            let patient_contract = build_message::<EprRef>(epr_account.clone())
+                .call(|epr| epr.patient_contract());
+            let patient_account = client
+                .call_dry_run(&ink_e2e::alice(), &patient_contract, 0, None)
+                .await
+                .return_value();
+
+            let name = build_message::<PatientRef>(patient_account.clone())
+                .call(|patient| patient.name());
+            let symbol = build_message::<PatientRef>(patient_account.clone())
+                .call(|patient| patient.symbol());
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &name, 0, None).await.return_value(), String::from("St. Mary Records"));
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &symbol, 0, None).await.return_value(), String::from("SMR"));
+
+            Ok(())
+        }
     }
 
 }
\ No newline at end of file
@@ -1,17 +1,431 @@
-#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(not(feature = "std"), no_std, no_main)]
 
-use ink_lang as ink;
+pub use self::nft_marketplace::{
+    NftMarketplace,
+    NftMarketplaceRef
+};
 
 #[ink::contract]
 pub mod nft_marketplace {
-    use ink_storage::collections::HashMap as StorageHashMap;
+    use patient::{
+        PatientRef,
+        CONSENT_TOKEN_FLAG
+    };
+
+    use ink::env::call::{
+        build_call,
+        ExecutionInput,
+        Selector
+    };
+    use ink::env::hash::Blake2x256;
+    use ink::prelude::{
+        string::String,
+        vec::Vec
+    };
+    use ink::storage::{
+        Lazy,
+        Mapping
+    };
+    use ink::ToAccountId;
+
+    /// Identifier of a token of the Patient NFT contract.
+    pub type TokenId = u32;
+    /// Identifies a held sale: the seller and the position of the sale in the seller's hold queue.
+    pub type SaleRef = (AccountId, u32);
+    /// A compressed secp256k1 public key signing listing vouchers. Its account is the Blake2x256 hash of the key.
+    pub type SignerKey = [u8; 33];
+    /// A recoverable secp256k1 signature over a listing voucher.
+    pub type VoucherSignature = [u8; 65];
+    /// The receiver of a royalty and the amount it is owed on a sale, as reported by the NFT contract.
+    pub type RoyaltyDue = (AccountId, Balance);
+
+    /// The maximum number of beneficiaries a listing can split its proceeds between.
+    pub const MAX_BENEFICIARIES: usize = 10;
+    /// The basis points that make up the whole of the proceeds.
+    pub const MAX_BPS: u16 = 10_000;
+    /// The maximum number of listings `buy_batch` can buy at once.
+    pub const MAX_BATCH_BUY: usize = 25;
+    /// The maximum number of listings walked to refill the order book once one of its listings is gone.
+    pub const MAX_FLOOR_SCAN: u32 = 100;
+    /// The maximum number of listings kept in the order book, the cheapest ones.
+    pub const MAX_ORDER_BOOK: u32 = 100;
+    /// The time, in milliseconds, the proceeds of a sale are held before the seller can withdraw them: 72 hours.
+    pub const DEFAULT_HOLD_PERIOD: Timestamp = 72 * 60 * 60 * 1000;
+    /// The maximum number of held sales walked by a single release.
+    pub const MAX_RELEASE_SCAN: u32 = 50;
+    /// The shortest time, in milliseconds, a Dutch auction can take to reach its end price: one hour.
+    pub const MIN_AUCTION_DURATION: Timestamp = 60 * 60 * 1000;
+    /// The longest time, in milliseconds, a Dutch auction can take to reach its end price: 30 days.
+    pub const MAX_AUCTION_DURATION: Timestamp = 30 * 24 * 60 * 60 * 1000;
+    /// The number of active listings a seller can have at once unless the admin changes it.
+    pub const DEFAULT_MAX_LISTINGS_PER_SELLER: u32 = 100;
+
+    /// The currency a listing is priced in.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
+    )]
+    pub enum Currency {
+        /// The chain's native token, paid as the transferred value of `buy`.
+        Native,
+        /// The PSP22 token registered by the admin, pulled from the buyer with `transfer_from`.
+        Psp22
+    }
+
+    /// What a listed token represents, told by its id: access-consent tokens have `CONSENT_TOKEN_FLAG` set.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
+    )]
+    pub enum ListingKind {
+        /// A plain token; buying it only transfers the token.
+        Ownership,
+        /// An access-consent token minted by the EPR contract; buying it also moves the consent to the buyer.
+        AccessToken
+    }
+
+    /// Why a listing was removed.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub enum DelistReason {
+        /// The seller withdrew the listing.
+        Seller,
+        /// The seller revoked the marketplace's approval for the token.
+        ApprovalRevoked,
+        /// The token no longer belongs to the seller, e.g. after a transfer outside the marketplace.
+        StaleOwner,
+        /// The listing expired and was purged, see `validate_listing`.
+        Expired
+    }
+
+    /// A token offered for sale.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
+    )]
+    pub struct Listing {
+        /// The account that listed the token and receives the proceeds.
+        seller: AccountId,
+        /// The asking price, in units of `currency`.
+        price: Balance,
+        /// The currency the price is denominated in.
+        currency: Currency,
+        /// The accounts that receive a share of the proceeds, in basis points. The seller gets the rest.
+        beneficiaries: Vec<(AccountId, u16)>,
+        /// What the token represents.
+        kind: ListingKind,
+        /// The only account allowed to buy the token, for a private sale.
+        reserved_for: Option<AccountId>,
+        /// The hash of the token URI when the token was listed, see `hash_of_uri`.
+        uri_hash: Hash,
+        /// The time from which the listing can no longer be bought, if it expires.
+        expires_at: Option<Timestamp>
+    }
+
+    /// The terms of a listing a token owner signs off-chain, for anyone to post with `list_with_voucher`.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct ListingVoucher {
+        /// The NFT contract of the token, which has to be the marketplace's.
+        collection: AccountId,
+        /// The token to list.
+        id: TokenId,
+        /// The asking price, in the native currency.
+        price: Balance,
+        /// The time from which the voucher can no longer be used.
+        expiry: Timestamp,
+        /// A number the seller picks to tell their vouchers apart; each can be used once.
+        nonce: u64
+    }
+
+    /// The declining price of a listing sold by Dutch auction. The price falls linearly from `start_price`
+    /// to `end_price` over `duration`, and stays at `end_price` afterwards until the token is bought or delisted.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
+    )]
+    pub struct DutchAuction {
+        /// The price when the auction started.
+        start_price: Balance,
+        /// The price the auction floors at.
+        end_price: Balance,
+        /// When the auction started.
+        started_at: Timestamp,
+        /// The time, in milliseconds, the price takes to fall to `end_price`.
+        duration: Timestamp
+    }
+
+    impl DutchAuction {
+        /// Returns the price of the auction at the given time.
+        fn price_at(&self, now: Timestamp) -> Balance {
+            let elapsed = now.saturating_sub(self.started_at);
+            if elapsed >= self.duration {
+                return self.end_price
+            }
+            // The drop is range * elapsed / duration, computed without overflowing the product.
+            let (elapsed, duration) = (Balance::from(elapsed), Balance::from(self.duration));
+            let range = self.start_price - self.end_price;
+            let drop = range / duration * elapsed + range % duration * elapsed / duration;
+            self.start_price - drop
+        }
+    }
+
+    /// Running sales figures of the collection traded on the marketplace.
+    #[derive(Debug, Default, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct Stats {
+        /// The sum of the prices of every native sale.
+        total_volume: Balance,
+        /// The sum of the prices of every PSP22 sale, in units of the payment token.
+        psp22_volume: Balance,
+        /// The number of completed sales in either currency.
+        sale_count: u32,
+        /// The number of active listings.
+        listing_count: u32
+    }
+
+    /// The native funds the marketplace owes, by category. Each figure is a running counter, updated wherever
+    /// value moves, so reading it costs the same however many accounts are owed.
+    #[derive(Debug, Default, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct Liabilities {
+        /// Proceeds and refunds credited to accounts and not withdrawn yet.
+        pending: Balance,
+        /// Proceeds of held sales whose hold period may still be running.
+        held: Balance,
+        /// Proceeds of held sales the buyer disputed, frozen until the admin resolves the dispute.
+        disputed: Balance,
+        /// The sum of the above.
+        total: Balance
+    }
+
+    /// The proceeds of a sale, held until the buyer can no longer dispute it.
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
+    )]
+    pub struct HeldFunds {
+        /// The token that was sold.
+        id: TokenId,
+        /// The account that bought the token, who can dispute the sale and gets the refund.
+        buyer: AccountId,
+        /// The listing the token was sold under, which says how the proceeds are split.
+        listing: Listing,
+        /// The royalty the NFT contract reported for the sale, paid out with the proceeds.
+        royalty: Option<RoyaltyDue>,
+        /// When the sale took place.
+        sold_at: Timestamp,
+        /// Whether the buyer disputed the sale. A disputed sale is only settled by `resolve_dispute`.
+        disputed: bool
+    }
+
+    /// A purchase that passed its checks and is ready to be settled.
+    struct Sale {
+        id: TokenId,
+        listing: Listing,
+        /// The PSP22 token to pull the price in, for `Currency::Psp22` listings.
+        token: Option<AccountId>,
+        /// The EPR contract that moves the consent, for access tokens.
+        epr: Option<AccountId>,
+        /// Why the listing can no longer be bought, if it can't.
+        stale: Option<DelistReason>,
+        /// The royalty the NFT contract reports for the token at the listing price.
+        royalty: Option<RoyaltyDue>
+    }
+
+    /// The errors the marketplace can return.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, scale::Decode, scale::Encode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub enum Error {
+        NotAdmin,
+        NotOwner,
+        NotSeller,
+        NotListed,
+        AlreadyListed,
+        IncorrectPayment,
+        Psp22NotConfigured,
+        Psp22TransferFailed,
+        NftTransferFailed,
+        NothingToWithdraw,
+        TransferFailed,
+        MarketplaceNotApproved,
+        TooManyBeneficiaries,
+        InvalidSplit,
+        EprNotConfigured,
+        ConsentReassignFailed,
+        ReentrancyDetected,
+        ReservedListing,
+        BatchTooLarge,
+        MaxTotalExceeded,
+        StaleListing,
+        MetadataChanged,
+        PriceTooLow,
+        PriceTooHigh,
+        InvalidPriceBounds,
+        SaleNotFound,
+        NotBuyer,
+        DisputeWindowClosed,
+        AlreadyDisputed,
+        NotDisputed,
+        UnknownCollection,
+        VoucherExpired,
+        VoucherUsed,
+        InvalidSignature,
+        InvalidAuction,
+        NotAuction,
+        ListingExpired,
+        ListingNotExpired,
+        InvalidExpiry,
+        CollectionPaused,
+        TokenNotTransferable,
+        TooManyListings,
+        ListingThrottled,
+        PaymentTokenInUse
+    }
+
+    /// The name of each `Error` variant, indexed by its discriminant. Kept next to the enum and checked against it by the tests.
+    pub const ERROR_NAMES: [&str; 44] = [
+        "NotAdmin", "NotOwner", "NotSeller", "NotListed", "AlreadyListed", "IncorrectPayment",
+        "Psp22NotConfigured", "Psp22TransferFailed", "NftTransferFailed", "NothingToWithdraw", "TransferFailed",
+        "MarketplaceNotApproved", "TooManyBeneficiaries", "InvalidSplit", "EprNotConfigured",
+        "ConsentReassignFailed", "ReentrancyDetected", "ReservedListing", "BatchTooLarge", "MaxTotalExceeded",
+        "StaleListing", "MetadataChanged", "PriceTooLow", "PriceTooHigh", "InvalidPriceBounds", "SaleNotFound",
+        "NotBuyer", "DisputeWindowClosed", "AlreadyDisputed", "NotDisputed", "UnknownCollection",
+        "VoucherExpired", "VoucherUsed", "InvalidSignature", "InvalidAuction", "NotAuction", "ListingExpired",
+        "ListingNotExpired", "InvalidExpiry", "CollectionPaused", "TokenNotTransferable", "TooManyListings",
+        "ListingThrottled", "PaymentTokenInUse"
+    ];
+
+    /// The errors defined by the PSP22 standard, needed to decode the replies of the token contract.
+    #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub enum PSP22Error {
+        Custom(String),
+        InsufficientBalance,
+        InsufficientAllowance,
+        ZeroRecipientAddress,
+        ZeroSenderAddress,
+        SafeTransferCheckFailed(String)
+    }
 
     #[ink(storage)]
     pub struct NftMarketplace {
-        /// Mapping from token ID to owner address.
-        owners: StorageHashMap<u32, AccountId>,
-        /// Mapping from token ID to price.
-        prices: StorageHashMap<u32, Balance>,
+        /// The account that instantiated the marketplace.
+        admin: AccountId,
+        /// The Patient NFT contract whose tokens are traded.
+        nft: PatientRef,
+        /// The PSP22 token accepted for `Currency::Psp22` listings, if any.
+        payment_token: Option<AccountId>,
+        /// Mapping from token ID to its listing.
+        listings: Mapping<TokenId, Listing>,
+        /// Native sale proceeds each seller can withdraw.
+        pending: Mapping<AccountId, Balance>,
+        /// PSP22 sale proceeds each seller can withdraw.
+        pending_psp22: Mapping<AccountId, Balance>,
+        /// The EPR contract that reassigns the consent of sold access tokens, if any.
+        epr: Option<AccountId>,
+        /// The lowest price a listing can be created or repriced at.
+        min_price: Balance,
+        /// The highest price a listing can be created or repriced at.
+        max_price: Balance,
+        /// The token listed at each position of the listing index, dense in `0..listing_count`.
+        listed_ids: Mapping<u32, TokenId>,
+        /// The position of each listed token in the listing index.
+        listed_index: Mapping<TokenId, u32>,
+        /// The number of active listings.
+        listing_count: u32,
+        /// The sum of the prices of every native sale.
+        total_volume: Balance,
+        /// The sum of the prices of every PSP22 sale.
+        psp22_volume: Balance,
+        /// The number of completed sales.
+        sale_count: u32,
+        /// The cheapest native listings and their prices, Dutch auctions left out, sorted by price and then by token,
+        /// at most `MAX_ORDER_BOOK` of them, kept up to date as listings come, go and change price.
+        /// It is lazy so that messages that do not touch listings do not load it.
+        order_book: Lazy<Vec<(TokenId, Balance)>>,
+        /// The time, in milliseconds, the proceeds of a sale are held before they are released.
+        hold_period: Timestamp,
+        /// The held proceeds of each seller's sales, by position in the seller's hold queue.
+        held: Mapping<SaleRef, HeldFunds>,
+        /// The number of sales ever held for each seller.
+        held_count: Mapping<AccountId, u32>,
+        /// The position of the oldest sale of each seller that may still be released. Disputed sales are left
+        /// behind it, to `resolve_dispute`.
+        held_head: Mapping<AccountId, u32>,
+        /// The listing voucher nonces each seller has used.
+        used_nonces: Mapping<(AccountId, u64), ()>,
+        /// The price schedule of each listing sold by Dutch auction.
+        auctions: Mapping<TokenId, DutchAuction>,
+        /// The native balance credited to accounts and not withdrawn yet, the sum of `pending`.
+        pending_total: Balance,
+        /// The native proceeds of undisputed held sales.
+        held_total: Balance,
+        /// The native proceeds of disputed held sales.
+        disputed_total: Balance,
+        /// The PSP22 balance credited to accounts and not withdrawn yet, the sum of `pending_psp22`.
+        pending_psp22_total: Balance,
+        /// The PSP22 proceeds of held sales, disputed or not.
+        held_psp22_total: Balance,
+        /// Whether trading in the collection is paused.
+        paused: bool,
+        /// The number of active listings a seller can have at once.
+        max_listings_per_seller: u32,
+        /// The number of blocks a seller has to wait between two new listings.
+        listing_interval: BlockNumber,
+        /// The number of active listings of each seller, expired ones included until they are purged.
+        seller_listing_count: Mapping<AccountId, u32>,
+        /// The block of each seller's latest new listing.
+        last_listed_at: Mapping<AccountId, BlockNumber>,
+        /// Set while a settlement makes external calls. It is lazy so that it is written to storage
+        /// immediately and a nested call sees it.
+        locked: Lazy<bool>
+    }
+
+    #[ink(event)]
+    pub struct Listed {
+        #[ink(topic)]
+        seller: AccountId,
+        #[ink(topic)]
+        id: TokenId,
+        /// The hash of the listing, matching `hash_of_listing`.
+        #[ink(topic)]
+        listing_hash: Hash,
+        price: Balance,
+        currency: Currency,
+        reserved_for: Option<AccountId>,
+        /// The hash of the token URI at listing time, matching the listing's `uri_hash`.
+        uri_hash: Hash
+    }
+
+    #[ink(event)]
+    pub struct Delisted {
+        #[ink(topic)]
+        id: TokenId,
+        reason: DelistReason
+    }
+
+    #[ink(event)]
+    pub struct ListingExtended {
+        #[ink(topic)]
+        id: TokenId,
+        expires_at: Timestamp
+    }
+
+    /// Emitted when an expired listing is put back on sale in place.
+    #[ink(event)]
+    pub struct Relisted {
+        #[ink(topic)]
+        seller: AccountId,
+        #[ink(topic)]
+        id: TokenId,
+        price: Balance,
+        expires_at: Timestamp
     }
 
     #[ink(event)]
@@ -19,72 +433,2966 @@ pub mod nft_marketplace {
         #[ink(topic)]
         buyer: AccountId,
         #[ink(topic)]
-        id: u32,
+        id: TokenId,
         #[ink(topic)]
         price: Balance,
     }
 
+    /// Emitted for every beneficiary credited with a share of a sale.
+    #[ink(event)]
+    pub struct ProceedsSplit {
+        #[ink(topic)]
+        id: TokenId,
+        #[ink(topic)]
+        beneficiary: AccountId,
+        amount: Balance,
+        currency: Currency
+    }
+
+    /// Emitted when the royalty receiver of a token is credited with its share of a sale.
+    #[ink(event)]
+    pub struct RoyaltyPaid {
+        #[ink(topic)]
+        id: TokenId,
+        #[ink(topic)]
+        receiver: AccountId,
+        amount: Balance,
+        currency: Currency
+    }
+
+    #[ink(event)]
+    pub struct PaymentTokenChanged {
+        old: Option<AccountId>,
+        new: Option<AccountId>
+    }
+
+    #[ink(event)]
+    pub struct PriceBoundsChanged {
+        min_price: Balance,
+        max_price: Balance
+    }
+
+    #[ink(event)]
+    pub struct ListingLimitsChanged {
+        max_listings_per_seller: u32,
+        listing_interval: BlockNumber
+    }
+
+    #[ink(event)]
+    pub struct HoldPeriodChanged {
+        hold_period: Timestamp
+    }
+
+    /// Emitted when the proceeds of a sale are held, with the position the buyer disputes the sale by.
+    #[ink(event)]
+    pub struct SaleHeld {
+        #[ink(topic)]
+        seller: AccountId,
+        #[ink(topic)]
+        buyer: AccountId,
+        #[ink(topic)]
+        id: TokenId,
+        index: u32,
+        release_at: Timestamp
+    }
+
+    /// Emitted along with `Listed` when a token is put up for Dutch auction.
+    #[ink(event)]
+    pub struct DutchAuctionCreated {
+        #[ink(topic)]
+        seller: AccountId,
+        #[ink(topic)]
+        id: TokenId,
+        start_price: Balance,
+        end_price: Balance,
+        /// When the price reaches `end_price`.
+        ends_at: Timestamp
+    }
+
+    #[ink(event)]
+    pub struct DisputeOpened {
+        #[ink(topic)]
+        seller: AccountId,
+        #[ink(topic)]
+        buyer: AccountId,
+        index: u32
+    }
+
+    #[ink(event)]
+    pub struct DisputeResolved {
+        #[ink(topic)]
+        seller: AccountId,
+        #[ink(topic)]
+        buyer: AccountId,
+        index: u32,
+        /// Whether the buyer was refunded, rather than the seller paid.
+        refunded: bool
+    }
+
+    /// Emitted when trading in a collection is paused or resumed.
+    #[ink(event)]
+    pub struct CollectionPauseChanged {
+        #[ink(topic)]
+        collection: AccountId,
+        #[ink(topic)]
+        by: AccountId,
+        paused: bool
+    }
+
+    /// Emitted when the admin moves the native balance the marketplace does not owe anyone.
+    #[ink(event)]
+    pub struct SurplusSwept {
+        #[ink(topic)]
+        to: AccountId,
+        amount: Balance
+    }
+
     impl NftMarketplace {
+        /// Creates a marketplace for the tokens of the given Patient NFT contract.
         #[ink(constructor)]
-        pub fn new() -> Self {
+        pub fn new(nft: AccountId) -> Self {
             Self {
-                owners: StorageHashMap::new(),
-                prices: StorageHashMap::new(),
+                admin: Self::env().caller(),
+                nft: ink::env::call::FromAccountId::from_account_id(nft),
+                payment_token: None,
+                listings: Default::default(),
+                pending: Default::default(),
+                pending_psp22: Default::default(),
+                epr: None,
+                min_price: 0,
+                max_price: Balance::MAX,
+                listed_ids: Default::default(),
+                listed_index: Default::default(),
+                listing_count: 0,
+                total_volume: 0,
+                psp22_volume: 0,
+                sale_count: 0,
+                order_book: Default::default(),
+                hold_period: DEFAULT_HOLD_PERIOD,
+                held: Default::default(),
+                held_count: Default::default(),
+                held_head: Default::default(),
+                used_nonces: Default::default(),
+                auctions: Default::default(),
+                pending_total: 0,
+                held_total: 0,
+                disputed_total: 0,
+                pending_psp22_total: 0,
+                held_psp22_total: 0,
+                paused: false,
+                max_listings_per_seller: DEFAULT_MAX_LISTINGS_PER_SELLER,
+                listing_interval: 0,
+                seller_listing_count: Default::default(),
+                last_listed_at: Default::default(),
+                locked: Default::default()
             }
         }
 
+        /// Registers the PSP22 token accepted for `Currency::Psp22` listings, or disables them with `None`.
+        /// Only the admin can call it. Existing PSP22 listings are settled in the token registered at buy time.
+        /// PSP22 proceeds are paid out in the registered token, so it cannot change while any are owed or held.
         #[ink(message)]
-        pub fn buy(&mut self, id: u32) -> Result<(), ()> {
-            let caller = self.env().caller();
-            let price = self.prices.get(&id).ok_or(())?;
-            let owner = self.owners.get_mut(&id).ok_or(())?;
-            
-            self.env().transfer(*owner, *price).map_err(|_| ())?;
-            *owner = caller;
+        pub fn set_payment_token(&mut self, token: Option<AccountId>) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::NotAdmin)
+            }
+            if token != self.payment_token && self.psp22_liabilities() > 0 {
+                return Err(Error::PaymentTokenInUse)
+            }
 
-            self.env().emit_event(Purchase {
-                buyer: caller,
-                id,
-                price: *price,
-            });
+            let old = core::mem::replace(&mut self.payment_token, token);
+            self.emit(PaymentTokenChanged { old, new: token });
+            Ok(())
+        }
+
+        /// Returns the PSP22 token accepted for `Currency::Psp22` listings.
+        #[ink(message)]
+        pub fn payment_token(&self) -> Option<AccountId> {
+            self.payment_token
+        }
+
+        /// Registers the EPR contract that reassigns the consent of sold access tokens, or disables
+        /// `ListingKind::AccessToken` listings with `None`. Only the admin can call it.
+        #[ink(message)]
+        pub fn set_epr(&mut self, epr: Option<AccountId>) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::NotAdmin)
+            }
 
+            self.epr = epr;
             Ok(())
         }
 
+        /// Returns the EPR contract that reassigns the consent of sold access tokens.
         #[ink(message)]
-        pub fn set_price(&mut self, id: u32, price: Balance) {
-            let caller = self.env().caller();
-            if *self.owners.get(&id).unwrap_or(&caller) == caller {
-                self.prices.insert(id, price);
+        pub fn epr(&self) -> Option<AccountId> {
+            self.epr
+        }
+
+        /// Sets the lowest and highest price listings can be created or repriced at. Only the admin can call it.
+        /// Existing listings keep their price, even outside the new bounds, until they are repriced.
+        #[ink(message)]
+        pub fn set_price_bounds(&mut self, min_price: Balance, max_price: Balance) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::NotAdmin)
+            }
+            if min_price > max_price {
+                return Err(Error::InvalidPriceBounds)
             }
+
+            self.min_price = min_price;
+            self.max_price = max_price;
+            self.emit(PriceBoundsChanged { min_price, max_price });
+            Ok(())
+        }
+
+        /// Returns the lowest and highest price listings can be created or repriced at.
+        #[ink(message)]
+        pub fn price_bounds(&self) -> (Balance, Balance) {
+            (self.min_price, self.max_price)
         }
-    }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use ink_lang as ink;
+        /// Sets how many active listings a seller can have at once and how many blocks a seller has to wait
+        /// between two new listings, so that no seller can flood the listing index. Only the admin can call it.
+        /// Sellers already above a lowered limit keep their listings but cannot list again until they are below it.
+        #[ink(message)]
+        pub fn set_listing_limits(&mut self, max_listings_per_seller: u32, listing_interval: BlockNumber) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::NotAdmin)
+            }
 
-    #[ink::test]
-    fn new_works() {
-        let contract = NftMarketplace::new();
-        assert_eq!(contract.owners.len(), 0);
-        assert_eq!(contract.prices.len(), 0);
-    }
+            self.max_listings_per_seller = max_listings_per_seller;
+            self.listing_interval = listing_interval;
+            self.emit(ListingLimitsChanged { max_listings_per_seller, listing_interval });
+            Ok(())
+        }
 
-    #[ink::test]
-    fn buy_works() {
-        let mut contract = NftMarketplace::new();
-        contract.set_price(1, 10);
-        assert_eq!(contract.buy(1), Ok(()));
-        assert_eq!(contract.owners.get(&1), Some(&AccountId::from([0x1; 32])));
-    }
+        /// Returns how many active listings a seller can have at once and how many blocks a seller has to wait
+        /// between two new listings.
+        #[ink(message)]
+        pub fn listing_limits(&self) -> (u32, BlockNumber) {
+            (self.max_listings_per_seller, self.listing_interval)
+        }
+
+        /// Returns the number of active listings of a seller. Expired listings count until they are delisted
+        /// or purged with `validate_listing`.
+        #[ink(message)]
+        pub fn listing_count_of(&self, seller: AccountId) -> u32 {
+            self.seller_listing_count.get(seller).unwrap_or(0)
+        }
+
+        /// Sets how long the proceeds of a sale are held, and can be disputed by the buyer, before the seller
+        /// can withdraw them. Only the admin can call it. Sales already held keep being measured from their sale
+        /// time, against the new period. With a period of zero, proceeds are credited right away.
+        #[ink(message)]
+        pub fn set_hold_period(&mut self, hold_period: Timestamp) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::NotAdmin)
+            }
+
+            self.hold_period = hold_period;
+            self.emit(HoldPeriodChanged { hold_period });
+            Ok(())
+        }
+
+        /// Returns how long, in milliseconds, the proceeds of a sale are held.
+        #[ink(message)]
+        pub fn hold_period(&self) -> Timestamp {
+            self.hold_period
+        }
+
+        /// Returns the held proceeds of a sale, if they have not been released or refunded yet.
+        #[ink(message)]
+        pub fn held(&self, sale_ref: SaleRef) -> Option<HeldFunds> {
+            self.held.get(sale_ref)
+        }
+
+        /// Returns the number of sales ever held for a seller; their positions are `0..held_count_of`.
+        #[ink(message)]
+        pub fn held_count_of(&self, seller: AccountId) -> u32 {
+            self.held_count.get(seller).unwrap_or(0)
+        }
+
+        /// Releases the held sales of a seller whose hold period is over, crediting the seller and the
+        /// beneficiaries of each, and returns the number of sales released. Anyone can call it, so beneficiaries
+        /// need not wait for the seller to withdraw. At most `MAX_RELEASE_SCAN` sales are looked at per call,
+        /// oldest first; disputed sales are skipped for good, so they do not hold back the sales after them.
+        #[ink(message)]
+        pub fn release_held(&mut self, seller: AccountId) -> u32 {
+            let now = self.env().block_timestamp();
+            let count = self.held_count_of(seller);
+            let start = self.held_head.get(seller).unwrap_or(0);
+            let end = start.saturating_add(MAX_RELEASE_SCAN).min(count);
+            let mut head = start;
+            let mut released = 0;
+            for index in start..end {
+                match self.held.get((seller, index)) {
+                    Some(funds) if funds.disputed => {}
+                    Some(funds) if now < self.release_time(&funds) => break,
+                    Some(funds) => {
+                        self.held.remove((seller, index));
+                        self.held_total = self.held_total.saturating_sub(native_amount(&funds.listing));
+                        self.held_psp22_total = self.held_psp22_total.saturating_sub(psp22_amount(&funds.listing));
+                        self.credit_proceeds(funds.id, &funds.listing, funds.royalty);
+                        released += 1;
+                    }
+                    None => {}
+                }
+                head = index + 1;
+            }
+            self.held_head.insert(seller, &head);
+            released
+        }
+
+        /// Disputes a held sale. Only its buyer can, before the hold period of the sale is over.
+        /// The proceeds stay frozen until the admin resolves the dispute.
+        #[ink(message)]
+        pub fn open_dispute(&mut self, sale_ref: SaleRef) -> Result<(), Error> {
+            let mut funds = self.held.get(sale_ref).ok_or(Error::SaleNotFound)?;
+            if funds.buyer != self.env().caller() {
+                return Err(Error::NotBuyer)
+            }
+            if funds.disputed {
+                return Err(Error::AlreadyDisputed)
+            }
+            if self.env().block_timestamp() >= self.release_time(&funds) {
+                return Err(Error::DisputeWindowClosed)
+            }
+
+            funds.disputed = true;
+            self.held.insert(sale_ref, &funds);
+            let amount = native_amount(&funds.listing);
+            self.held_total = self.held_total.saturating_sub(amount);
+            self.disputed_total = self.disputed_total.saturating_add(amount);
+            let (seller, index) = sale_ref;
+            self.emit(DisputeOpened { seller, buyer: funds.buyer, index });
+            Ok(())
+        }
+
+        /// Settles a disputed sale, either refunding the price to the buyer or releasing the proceeds to the
+        /// seller and the beneficiaries. Either way the amount becomes withdrawable; the token stays with the
+        /// buyer. Only the admin can call it.
+        #[ink(message)]
+        pub fn resolve_dispute(&mut self, sale_ref: SaleRef, refund: bool) -> Result<(), Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::NotAdmin)
+            }
+            let funds = self.held.get(sale_ref).ok_or(Error::SaleNotFound)?;
+            if !funds.disputed {
+                return Err(Error::NotDisputed)
+            }
+
+            self.held.remove(sale_ref);
+            self.disputed_total = self.disputed_total.saturating_sub(native_amount(&funds.listing));
+            self.held_psp22_total = self.held_psp22_total.saturating_sub(psp22_amount(&funds.listing));
+            if refund {
+                self.credit(funds.listing.currency, funds.buyer, funds.listing.price);
+            } else {
+                self.credit_proceeds(funds.id, &funds.listing, funds.royalty);
+            }
+            let (seller, index) = sale_ref;
+            self.emit(DisputeResolved { seller, buyer: funds.buyer, index, refunded: refund });
+            Ok(())
+        }
+
+        /// Returns the native funds the marketplace owes: withdrawable balances and held and disputed proceeds.
+        /// PSP22 proceeds are owed in the payment token and are not part of the contract's native balance.
+        #[ink(message)]
+        pub fn liabilities(&self) -> Liabilities {
+            Liabilities {
+                pending: self.pending_total,
+                held: self.held_total,
+                disputed: self.disputed_total,
+                total: self.total_liabilities()
+            }
+        }
+
+        /// Returns the part of the contract's native balance that it does not owe anyone, such as value sent to it
+        /// directly. The balance is never expected to fall short of the liabilities; if it did, the surplus is zero.
+        #[ink(message)]
+        pub fn surplus(&self) -> Balance {
+            self.env().balance().saturating_sub(self.total_liabilities())
+        }
+
+        /// Transfers the surplus to `to` and returns the amount. Only the admin can call it, and it can never
+        /// move funds the marketplace owes.
+        #[ink(message)]
+        pub fn sweep_surplus(&mut self, to: AccountId) -> Result<Balance, Error> {
+            if self.env().caller() != self.admin {
+                return Err(Error::NotAdmin)
+            }
+            self.guarded(|market| market.settle_sweep(to))
+        }
+
+        /// Returns the sales figures of a collection. Only the marketplace's NFT contract has any.
+        #[ink(message)]
+        pub fn collection_stats(&self, collection: AccountId) -> Stats {
+            if collection != self.nft.to_account_id() {
+                return Stats::default()
+            }
+            Stats {
+                total_volume: self.total_volume,
+                psp22_volume: self.psp22_volume,
+                sale_count: self.sale_count,
+                listing_count: self.listing_count
+            }
+        }
+
+        /// Returns the lowest price of the native listings of a collection, if it has any, the first entry of
+        /// `cheapest_listings`.
+        #[ink(message)]
+        pub fn floor_price(&self, collection: AccountId) -> Option<Balance> {
+            self.cheapest_listings(collection, 1).first().map(|(_, price)| *price)
+        }
+
+        /// Returns up to `limit` of the cheapest native listings of a collection with their prices, cheapest first
+        /// and by token id between equal prices. Dutch auctions are left out, as their price keeps moving.
+        /// The order book keeps the `MAX_ORDER_BOOK` cheapest listings. When a listing leaves a full book,
+        /// the cheapest listing left out of it is looked up among the first `MAX_FLOOR_SCAN` listings of the index.
+        #[ink(message)]
+        pub fn cheapest_listings(&self, collection: AccountId, limit: u32) -> Vec<(TokenId, Balance)> {
+            if collection != self.nft.to_account_id() {
+                return Vec::new()
+            }
+            let mut book = self.order_book.get_or_default();
+            book.truncate(limit as usize);
+            book
+        }
+
+        /// Counts the listings of the order book of a collection by price range. Each entry is the lowest price
+        /// of a range of `bucket_size` and the number of listings priced in it, cheapest range first.
+        /// Empty ranges are left out, and a `bucket_size` of 0 returns nothing.
+        #[ink(message)]
+        pub fn price_histogram(&self, collection: AccountId, bucket_size: Balance) -> Vec<(Balance, u32)> {
+            if bucket_size == 0 {
+                return Vec::new()
+            }
+            let mut histogram: Vec<(Balance, u32)> = Vec::new();
+            for (_, price) in self.cheapest_listings(collection, MAX_ORDER_BOOK) {
+                let bucket = price - price % bucket_size;
+                match histogram.last_mut() {
+                    Some((last, count)) if *last == bucket => *count += 1,
+                    _ => histogram.push((bucket, 1))
+                }
+            }
+            histogram
+        }
+
+        /// Pauses trading in a collection: listing, relisting and buying fail with `CollectionPaused` until it is
+        /// unpaused, while sellers can still delist. The marketplace admin or the owner of the collection's contract
+        /// can call it, so an institution whose contract is compromised can freeze its own collection.
+        #[ink(message)]
+        pub fn pause_collection(&mut self, collection: AccountId) -> Result<(), Error> {
+            self.set_paused(collection, true)
+        }
+
+        /// Resumes trading in a paused collection. The marketplace admin or the owner of the collection's contract
+        /// can call it.
+        #[ink(message)]
+        pub fn unpause_collection(&mut self, collection: AccountId) -> Result<(), Error> {
+            self.set_paused(collection, false)
+        }
+
+        /// Returns whether trading in a collection is paused. Only the marketplace's NFT contract can be.
+        #[ink(message)]
+        pub fn is_collection_paused(&self, collection: AccountId) -> bool {
+            collection == self.nft.to_account_id() && self.paused
+        }
+
+        /// Returns the discriminant and name of every `Error` variant.
+        #[ink(message)]
+        pub fn error_catalog(&self) -> Vec<(u8, String)> {
+            ERROR_NAMES
+                .iter()
+                .enumerate()
+                .map(|(code, name)| (code as u8, String::from(*name)))
+                .collect()
+        }
+
+        /// Lists one of the caller's tokens for sale.
+        /// The proceeds can be split with up to `MAX_BENEFICIARIES` accounts, each given a share in basis points.
+        /// The shares may add up to at most `MAX_BPS`; the seller receives whatever is left.
+        /// Access-consent tokens, whose ids have `CONSENT_TOKEN_FLAG` set, are listed as `ListingKind::AccessToken`,
+        /// which needs a registered EPR contract.
+        /// The marketplace has to be approved on the NFT contract, for the token or as an operator of the caller,
+        /// otherwise the listing is rejected with `MarketplaceNotApproved`.
+        /// Tokens of a category the NFT contract marks as non-transferable, such as credential badges,
+        /// are rejected with `TokenNotTransferable`.
+        /// A listing reserved for an account can only be bought by that account.
+        /// The price has to be within `price_bounds`, otherwise the listing is rejected with `PriceTooLow` or `PriceTooHigh`.
+        /// The listing records the hash of the token's current URI, which buyers can hold `buy` to.
+        /// It does not expire unless the seller sets an expiry with `extend_listing`.
+        /// A seller with as many active listings as `listing_limits` allows is rejected with `TooManyListings`,
+        /// and one who listed less than the listing interval ago with `ListingThrottled`.
+        #[ink(message)]
+        pub fn list(
+            &mut self,
+            id: TokenId,
+            price: Balance,
+            currency: Currency,
+            beneficiaries: Vec<(AccountId, u16)>,
+            reserved_for: Option<AccountId>
+        ) -> Result<(), Error> {
+            let listing = Listing {
+                seller: self.env().caller(),
+                price,
+                currency,
+                beneficiaries,
+                kind: kind_of(id),
+                reserved_for,
+                uri_hash: Hash::default(),
+                expires_at: None
+            };
+            self.create_listing(id, listing)
+        }
+
+        /// Lists a token on behalf of its owner, who signed a voucher off-chain, so that anyone can post it.
+        /// The voucher is a signature over the Blake2x256 hash of the SCALE-encoded (marketplace address, voucher),
+        /// made with the secp256k1 key whose account owns the token.
+        /// The listing is a plain native listing without beneficiaries or reservation, attributed to that account,
+        /// and is otherwise checked like `list`. Each nonce can be used once per seller; vouchers are rejected with
+        /// `VoucherExpired` from their expiry on.
+        #[ink(message)]
+        pub fn list_with_voucher(&mut self, voucher: ListingVoucher, seller_pub: SignerKey, signature: VoucherSignature) -> Result<(), Error> {
+            let seller = self.redeem_listing_voucher(&voucher, seller_pub, signature)?;
+            let listing = Listing {
+                seller,
+                price: voucher.price,
+                currency: Currency::Native,
+                beneficiaries: Vec::new(),
+                kind: kind_of(voucher.id),
+                reserved_for: None,
+                uri_hash: Hash::default(),
+                expires_at: None
+            };
+            self.create_listing(voucher.id, listing)
+        }
+
+        /// Returns whether a seller has used a listing voucher nonce.
+        #[ink(message)]
+        pub fn is_nonce_used(&self, seller: AccountId, nonce: u64) -> bool {
+            self.used_nonces.contains((seller, nonce))
+        }
+
+        /// Puts a token up for Dutch auction: a native listing whose price falls linearly from `start_price`
+        /// to `end_price` over `duration` milliseconds, then stays at `end_price` until the token is bought
+        /// with `buy_dutch` or the seller delists it.
+        /// The prices have to satisfy `start_price > end_price > 0` and the duration has to be between
+        /// `MIN_AUCTION_DURATION` and `MAX_AUCTION_DURATION`, otherwise the auction is rejected with `InvalidAuction`.
+        /// Both prices have to be within `price_bounds`, and the token is otherwise checked like `list`:
+        /// it stays with the seller until sold, under the marketplace's approval.
+        /// Dutch auctions are left out of the floor price and the order book, as their price keeps moving.
+        #[ink(message)]
+        pub fn create_dutch(&mut self, id: TokenId, start_price: Balance, end_price: Balance, duration: Timestamp) -> Result<(), Error> {
+            if end_price == 0 || start_price <= end_price {
+                return Err(Error::InvalidAuction)
+            }
+            if !(MIN_AUCTION_DURATION..=MAX_AUCTION_DURATION).contains(&duration) {
+                return Err(Error::InvalidAuction)
+            }
+            self.check_price(end_price)?;
+
+            let seller = self.env().caller();
+            let started_at = self.env().block_timestamp();
+            let listing = Listing {
+                seller,
+                price: start_price,
+                currency: Currency::Native,
+                beneficiaries: Vec::new(),
+                kind: kind_of(id),
+                reserved_for: None,
+                uri_hash: Hash::default(),
+                expires_at: None
+            };
+            self.create_listing(id, listing)?;
+            self.auctions.insert(id, &DutchAuction { start_price, end_price, started_at, duration });
+            self.unbook(id);
+            self.emit(DutchAuctionCreated {
+                seller,
+                id,
+                start_price,
+                end_price,
+                ends_at: started_at.saturating_add(duration)
+            });
+            Ok(())
+        }
+
+        /// Returns the price a listed token sells at right now, which for a Dutch auction is the price it has fallen to.
+        #[ink(message)]
+        pub fn current_price(&self, id: TokenId) -> Option<Balance> {
+            self.live_listing(id).map(|listing| listing.price)
+        }
+
+        /// Returns the price schedule of a token up for Dutch auction.
+        #[ink(message)]
+        pub fn dutch_auction(&self, id: TokenId) -> Option<DutchAuction> {
+            self.auctions.get(id)
+        }
+
+        /// Buys a token up for Dutch auction at its current price, see `current_price`.
+        /// The transferred value has to cover the price, otherwise the purchase fails with `IncorrectPayment`,
+        /// and whatever exceeds it is refunded. The sale is otherwise settled like `buy`, and the `Purchase`
+        /// event carries the price it was settled at. Other listings are rejected with `NotAuction`.
+        #[ink(message, payable)]
+        pub fn buy_dutch(&mut self, id: TokenId) -> Result<(), Error> {
+            self.ensure_trading()?;
+            self.guarded(|market| market.settle_dutch_purchase(id))
+        }
 
-    #[ink::test]
-    fn set_price_works() {
-        let mut contract = NftMarketplace::new();
-        contract.set_price(1, 10);
-        assert_eq!(contract.prices.get(&1), Some(&10));
+        /// Checks a new listing, as described on `list`, records the hash of the token's current URI in it and stores it.
+        fn create_listing(&mut self, id: TokenId, mut listing: Listing) -> Result<(), Error> {
+            self.ensure_trading()?;
+            if self.listings.contains(id) {
+                return Err(Error::AlreadyListed)
+            }
+            self.check_price(listing.price)?;
+            if listing.currency == Currency::Psp22 && self.payment_token.is_none() {
+                return Err(Error::Psp22NotConfigured)
+            }
+            if listing.kind == ListingKind::AccessToken && self.epr.is_none() {
+                return Err(Error::EprNotConfigured)
+            }
+            if listing.beneficiaries.len() > MAX_BENEFICIARIES {
+                return Err(Error::TooManyBeneficiaries)
+            }
+            if listing.beneficiaries.iter().map(|(_, bps)| u32::from(*bps)).sum::<u32>() > u32::from(MAX_BPS) {
+                return Err(Error::InvalidSplit)
+            }
+            self.check_listing_limits(listing.seller)?;
+            if self.nft.owner_of(id) != Some(listing.seller) {
+                return Err(Error::NotOwner)
+            }
+            if !self.nft.is_transferable_token(id) {
+                return Err(Error::TokenNotTransferable)
+            }
+            if !self.can_move(listing.seller, id) {
+                return Err(Error::MarketplaceNotApproved)
+            }
+
+            listing.uri_hash = self.current_uri_hash(id);
+            self.add_listing(id, &listing);
+            self.emit(Listed {
+                seller: listing.seller,
+                id,
+                listing_hash: self.hash_of(&listing),
+                price: listing.price,
+                currency: listing.currency,
+                reserved_for: listing.reserved_for,
+                uri_hash: listing.uri_hash
+            });
+            Ok(())
+        }
+
+        /// Removes one of the caller's listings.
+        #[ink(message)]
+        pub fn delist(&mut self, id: TokenId) -> Result<(), Error> {
+            let listing = self.listings.get(id).ok_or(Error::NotListed)?;
+            if listing.seller != self.env().caller() {
+                return Err(Error::NotSeller)
+            }
+
+            self.remove_listing(id);
+            self.emit(Delisted { id, reason: DelistReason::Seller });
+            Ok(())
+        }
+
+        /// Checks that a listing can still be bought and removes it if not.
+        /// A listing is stale once the seller no longer owns the token, for instance after a transfer made
+        /// directly on the NFT contract, or once the seller revoked the marketplace's approval.
+        /// An expired listing is purged as well; until then, its seller can put it back on sale with `relist`.
+        /// Anyone can call it. Returns why the listing was removed, or `None` if it is still valid.
+        #[ink(message)]
+        pub fn validate_listing(&mut self, id: TokenId) -> Result<Option<DelistReason>, Error> {
+            let listing = self.listings.get(id).ok_or(Error::NotListed)?;
+            let stale = match self.is_expired(&listing) {
+                true => Some(DelistReason::Expired),
+                false => self.stale_reason(&listing, id)
+            };
+            if let Some(reason) = stale {
+                self.remove_listing(id);
+                self.emit(Delisted { id, reason });
+            }
+            Ok(stale)
+        }
+
+        /// Changes the price of one of the caller's listings. The new price has to be within `price_bounds`.
+        /// The price of a Dutch auction follows its schedule and cannot be changed, see `create_dutch`.
+        #[ink(message)]
+        pub fn set_price(&mut self, id: TokenId, price: Balance) -> Result<(), Error> {
+            let mut listing = self.listings.get(id).ok_or(Error::NotListed)?;
+            if listing.seller != self.env().caller() {
+                return Err(Error::NotSeller)
+            }
+            if self.auctions.contains(id) {
+                return Err(Error::InvalidAuction)
+            }
+            self.check_price(price)?;
+
+            listing.price = price;
+            self.listings.insert(id, &listing);
+            self.rebook(id, &listing);
+            Ok(())
+        }
+
+        /// Sets or pushes back the expiry of one of the caller's listings, which has to be active.
+        /// The new expiry has to be in the future and later than the current one, otherwise it is rejected
+        /// with `InvalidExpiry`. An expired listing is put back on sale with `relist` instead.
+        #[ink(message)]
+        pub fn extend_listing(&mut self, id: TokenId, new_expires_at: Timestamp) -> Result<(), Error> {
+            let mut listing = self.listings.get(id).ok_or(Error::NotListed)?;
+            if listing.seller != self.env().caller() {
+                return Err(Error::NotSeller)
+            }
+            if self.is_expired(&listing) {
+                return Err(Error::ListingExpired)
+            }
+            if new_expires_at <= self.env().block_timestamp() || listing.expires_at.is_some_and(|old| new_expires_at <= old) {
+                return Err(Error::InvalidExpiry)
+            }
+
+            listing.expires_at = Some(new_expires_at);
+            self.listings.insert(id, &listing);
+            self.emit(ListingExtended { id, expires_at: new_expires_at });
+            Ok(())
+        }
+
+        /// Puts one of the caller's expired listings back on sale at a new price until `expires_at`, reusing its
+        /// storage and its place in the listing index. The listing is checked again with the NFT contract:
+        /// it fails with `NotOwner` if the caller no longer owns the token and with `MarketplaceNotApproved` if the
+        /// marketplace is no longer approved, and it records the hash of the token's current URI.
+        /// The price has to be within `price_bounds` and the expiry in the future. Listings that have not expired
+        /// are rejected with `ListingNotExpired`, and Dutch auctions, whose price follows their schedule,
+        /// with `InvalidAuction`.
+        #[ink(message)]
+        pub fn relist(&mut self, id: TokenId, price: Balance, expires_at: Timestamp) -> Result<(), Error> {
+            self.ensure_trading()?;
+            let mut listing = self.listings.get(id).ok_or(Error::NotListed)?;
+            if listing.seller != self.env().caller() {
+                return Err(Error::NotSeller)
+            }
+            if !self.is_expired(&listing) {
+                return Err(Error::ListingNotExpired)
+            }
+            if self.auctions.contains(id) {
+                return Err(Error::InvalidAuction)
+            }
+            if expires_at <= self.env().block_timestamp() {
+                return Err(Error::InvalidExpiry)
+            }
+            self.check_price(price)?;
+            match self.stale_reason(&listing, id) {
+                Some(DelistReason::ApprovalRevoked) => return Err(Error::MarketplaceNotApproved),
+                Some(_) => return Err(Error::NotOwner),
+                None => {}
+            }
+
+            listing.price = price;
+            listing.expires_at = Some(expires_at);
+            listing.uri_hash = self.current_uri_hash(id);
+            self.listings.insert(id, &listing);
+            self.rebook(id, &listing);
+            self.emit(Relisted { seller: listing.seller, id, price, expires_at });
+            Ok(())
+        }
+
+        /// Reserves one of the caller's listings for a single buyer, or opens it to everyone with `None`.
+        #[ink(message)]
+        pub fn update_reservation(&mut self, id: TokenId, reserved_for: Option<AccountId>) -> Result<(), Error> {
+            let mut listing = self.listings.get(id).ok_or(Error::NotListed)?;
+            if listing.seller != self.env().caller() {
+                return Err(Error::NotSeller)
+            }
+
+            listing.reserved_for = reserved_for;
+            self.listings.insert(id, &listing);
+            Ok(())
+        }
+
+        /// Returns the listing of a token, if it is for sale.
+        #[ink(message)]
+        pub fn listing(&self, id: TokenId) -> Option<Listing> {
+            self.listings.get(id)
+        }
+
+        /// Returns the Blake2x256 hash of the SCALE-encoded URI a token has now on the NFT contract,
+        /// the empty string for a token without one. It is what `list` records and what `buy` compares against.
+        #[ink(message)]
+        pub fn hash_of_uri(&self, id: TokenId) -> Hash {
+            self.current_uri_hash(id)
+        }
+
+        /// Returns the Blake2x256 hash of the SCALE-encoded listing of a token, as carried in the `Listed` topic.
+        #[ink(message)]
+        pub fn hash_of_listing(&self, id: TokenId) -> Option<Hash> {
+            self.listings.get(id).map(|listing| self.hash_of(&listing))
+        }
+
+        /// Buys a listed token.
+        /// Native listings must be paid with exactly the price as transferred value. PSP22 listings are paid
+        /// by pulling the price from the caller with `transfer_from`, which needs a prior allowance, and must
+        /// not carry any value. The proceeds are held for `hold_period`, during which the buyer can dispute
+        /// the sale, and then released to the seller, who withdraws them separately.
+        /// For access tokens, the EPR contract then moves the consent to the buyer.
+        /// If the payment, the NFT transfer or the consent reassignment fails, the whole sale is reverted.
+        /// If the seller no longer owns the token or revoked the marketplace's approval since listing, the token
+        /// is delisted instead of sold, the buyer gets any transferred value back and a `Delisted` event is emitted.
+        /// The listing and the proceeds are settled before any external call, and a nested call into
+        /// `buy` or the withdrawals while one is running fails with `ReentrancyDetected`.
+        /// A buyer who evaluated the token's metadata can pass the `uri_hash` they saw: if the token URI has
+        /// changed since, the purchase fails with `MetadataChanged` and the payment is not taken.
+        #[ink(message, payable)]
+        pub fn buy(&mut self, id: TokenId, expected_uri_hash: Option<Hash>) -> Result<(), Error> {
+            self.ensure_trading()?;
+            self.guarded(|market| market.settle_purchase(id, expected_uri_hash))
+        }
+
+        /// Buys up to `MAX_BATCH_BUY` listed tokens, each settled like `buy`, and returns one result per id.
+        /// Ids that cannot be bought are skipped and reported instead of aborting the batch: `NotListed`,
+        /// `ReservedListing` and the other checks of `buy`, `StaleListing` for a listing delisted as stale,
+        /// and `MaxTotalExceeded` once the price would take the total spent, in either currency, over `max_total`.
+        /// Native listings are paid from the transferred value; a native listing it can no longer cover is
+        /// reported as `IncorrectPayment`, and whatever is left is refunded to the caller.
+        /// A failing payment, NFT transfer or consent reassignment still reverts the whole batch.
+        #[ink(message, payable)]
+        pub fn buy_batch(&mut self, ids: Vec<TokenId>, max_total: Balance) -> Result<Vec<Result<(), Error>>, Error> {
+            if ids.len() > MAX_BATCH_BUY {
+                return Err(Error::BatchTooLarge)
+            }
+            self.ensure_trading()?;
+            self.guarded(|market| market.settle_batch(&ids, max_total))
+        }
+
+        /// Returns the native proceeds an account can withdraw. Held proceeds are not included until released.
+        #[ink(message)]
+        pub fn pending_of(&self, account: AccountId) -> Balance {
+            self.pending.get(account).unwrap_or(0)
+        }
+
+        /// Returns the PSP22 proceeds an account can withdraw.
+        #[ink(message)]
+        pub fn pending_psp22_of(&self, account: AccountId) -> Balance {
+            self.pending_psp22.get(account).unwrap_or(0)
+        }
+
+        /// Releases the caller's held sales whose hold period is over, see `release_held`, then pays out
+        /// the caller's native proceeds and returns the amount.
+        #[ink(message)]
+        pub fn withdraw(&mut self) -> Result<Balance, Error> {
+            self.guarded(Self::settle_withdrawal)
+        }
+
+        /// Releases the caller's held sales whose hold period is over, see `release_held`, then pays out
+        /// the caller's PSP22 proceeds and returns the amount.
+        #[ink(message)]
+        pub fn withdraw_psp22(&mut self) -> Result<Balance, Error> {
+            self.guarded(Self::settle_psp22_withdrawal)
+        }
+
+        /// Pauses or resumes trading in the collection, for the marketplace admin or the owner of the collection's contract.
+        /// The NFT contract is only asked for its owner when the caller is not the admin.
+        fn set_paused(&mut self, collection: AccountId, paused: bool) -> Result<(), Error> {
+            if collection != self.nft.to_account_id() {
+                return Err(Error::UnknownCollection)
+            }
+            let caller = self.env().caller();
+            if caller != self.admin && caller != self.nft.owner() {
+                return Err(Error::NotAdmin)
+            }
+
+            self.paused = paused;
+            self.emit(CollectionPauseChanged { collection, by: caller, paused });
+            Ok(())
+        }
+
+        /// Rejects listing and buying while the collection is paused.
+        fn ensure_trading(&self) -> Result<(), Error> {
+            if self.paused {
+                return Err(Error::CollectionPaused)
+            }
+            Ok(())
+        }
+
+        /// Runs a settlement that makes external calls, rejecting nested entry with `ReentrancyDetected`.
+        fn guarded<T>(&mut self, settle: impl FnOnce(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+            if self.locked.get().unwrap_or(false) {
+                return Err(Error::ReentrancyDetected)
+            }
+            self.locked.set(&true);
+            let result = settle(self);
+            self.locked.set(&false);
+            result
+        }
+
+        /// Buys a single listing with the transferred value, refunding it if the listing turns out to be stale.
+        fn settle_purchase(&mut self, id: TokenId, expected_uri_hash: Option<Hash>) -> Result<(), Error> {
+            let listing = self.live_listing(id).ok_or(Error::NotListed)?;
+            let transferred = self.env().transferred_value();
+            let sale = self.prepare_sale(id, listing, transferred, expected_uri_hash)?;
+            if self.complete_sale(sale)?.is_some() && transferred > 0 {
+                self.env().transfer(self.env().caller(), transferred).map_err(|_| Error::TransferFailed)?;
+            }
+            Ok(())
+        }
+
+        /// Buys a Dutch auction at its current price with the transferred value and refunds what is left,
+        /// or all of it if the listing turns out to be stale.
+        fn settle_dutch_purchase(&mut self, id: TokenId) -> Result<(), Error> {
+            let listing = self.live_listing(id).ok_or(Error::NotListed)?;
+            if !self.auctions.contains(id) {
+                return Err(Error::NotAuction)
+            }
+            let transferred = self.env().transferred_value();
+            let price = listing.price;
+            if transferred < price {
+                return Err(Error::IncorrectPayment)
+            }
+            let sale = self.prepare_sale(id, listing, price, None)?;
+            let refund = match self.complete_sale(sale)? {
+                Some(_) => transferred,
+                None => transferred - price
+            };
+            if refund > 0 {
+                self.env().transfer(self.env().caller(), refund).map_err(|_| Error::TransferFailed)?;
+            }
+            Ok(())
+        }
+
+        /// Buys the listings of a batch in order, keeping track of the budget and of the transferred value,
+        /// and refunds the value that was not spent.
+        fn settle_batch(&mut self, ids: &[TokenId], max_total: Balance) -> Result<Vec<Result<(), Error>>, Error> {
+            let mut budget = max_total;
+            let mut value = self.env().transferred_value();
+            let mut results = Vec::with_capacity(ids.len());
+            for &id in ids {
+                results.push(self.settle_batch_item(id, &mut budget, &mut value)?);
+            }
+
+            if value > 0 {
+                self.env().transfer(self.env().caller(), value).map_err(|_| Error::TransferFailed)?;
+            }
+            Ok(results)
+        }
+
+        /// Buys one listing of a batch. The inner result reports an id that was skipped; the outer one
+        /// a failure after the sale was settled, which has to revert the whole batch.
+        fn settle_batch_item(&mut self, id: TokenId, budget: &mut Balance, value: &mut Balance) -> Result<Result<(), Error>, Error> {
+            let Some(listing) = self.live_listing(id) else {
+                return Ok(Err(Error::NotListed))
+            };
+            let price = listing.price;
+            if price > *budget {
+                return Ok(Err(Error::MaxTotalExceeded))
+            }
+            let paid = match listing.currency {
+                Currency::Native if price > *value => return Ok(Err(Error::IncorrectPayment)),
+                Currency::Native => price,
+                Currency::Psp22 => 0
+            };
+            let sale = match self.prepare_sale(id, listing, paid, None) {
+                Ok(sale) => sale,
+                Err(error) => return Ok(Err(error))
+            };
+
+            if self.complete_sale(sale)?.is_some() {
+                return Ok(Err(Error::StaleListing))
+            }
+            *budget -= price;
+            *value -= paid;
+            Ok(Ok(()))
+        }
+
+        /// Checks that the caller can buy a listing paying `paid` in native value, without changing any state.
+        /// With an expected URI hash, the token URI is read again and has to match it.
+        fn prepare_sale(&self, id: TokenId, listing: Listing, paid: Balance, expected_uri_hash: Option<Hash>) -> Result<Sale, Error> {
+            if self.is_expired(&listing) {
+                return Err(Error::ListingExpired)
+            }
+            if listing.reserved_for.is_some_and(|buyer| buyer != self.env().caller()) {
+                return Err(Error::ReservedListing)
+            }
+
+            match listing.currency {
+                Currency::Native => {
+                    if paid != listing.price {
+                        return Err(Error::IncorrectPayment)
+                    }
+                }
+                Currency::Psp22 => {
+                    if paid != 0 {
+                        return Err(Error::IncorrectPayment)
+                    }
+                }
+            }
+
+            let token = match listing.currency {
+                Currency::Psp22 => Some(self.payment_token.ok_or(Error::Psp22NotConfigured)?),
+                Currency::Native => None
+            };
+            let epr = match listing.kind {
+                ListingKind::AccessToken => Some(self.epr.ok_or(Error::EprNotConfigured)?),
+                ListingKind::Ownership => None
+            };
+            if expected_uri_hash.is_some_and(|expected| expected != self.current_uri_hash(id)) {
+                return Err(Error::MetadataChanged)
+            }
+            let stale = self.stale_reason(&listing, id);
+            let royalty = self.nft.royalty_info(id, listing.price);
+            Ok(Sale { id, listing, token, epr, stale, royalty })
+        }
+
+        /// Settles the listing and the proceeds of a prepared sale, then collects the payment and moves the token.
+        /// A stale listing is only delisted; its reason is returned and the caller refunds the buyer.
+        fn complete_sale(&mut self, sale: Sale) -> Result<Option<DelistReason>, Error> {
+            let caller = self.env().caller();
+            let Sale { id, listing, token, epr, stale, royalty } = sale;
+
+            // Effects: the listing is gone and the proceeds are credited before any external call.
+            self.remove_listing(id);
+
+            if let Some(reason) = stale {
+                self.emit(Delisted { id, reason });
+                return Ok(Some(reason))
+            }
+
+            self.record_sale(&listing);
+            self.hold_proceeds(id, caller, &listing, royalty);
+            self.emit(Purchase {
+                buyer: caller,
+                id,
+                price: listing.price,
+            });
+
+            // Interactions: any failure reverts the effects above.
+            if let Some(token) = token {
+                let marketplace = self.env().account_id();
+                self.psp22_transfer_from(token, caller, marketplace, listing.price)?;
+            }
+
+            self.nft
+                .transfer_from(listing.seller, caller, id)
+                .map_err(|_| Error::NftTransferFailed)?;
+
+            if let Some(epr) = epr {
+                self.reassign_consent(epr, id, caller)?;
+            }
+
+            Ok(None)
+        }
+
+        /// Clears the caller's native proceeds and then pays them out.
+        fn settle_withdrawal(&mut self) -> Result<Balance, Error> {
+            let caller = self.env().caller();
+            self.release_held(caller);
+            let amount = self.pending.take(caller).unwrap_or(0);
+            if amount == 0 {
+                return Err(Error::NothingToWithdraw)
+            }
+            self.pending_total = self.pending_total.saturating_sub(amount);
+
+            self.env().transfer(caller, amount).map_err(|_| Error::TransferFailed)?;
+            Ok(amount)
+        }
+
+        /// Clears the caller's PSP22 proceeds and then pays them out.
+        fn settle_psp22_withdrawal(&mut self) -> Result<Balance, Error> {
+            let caller = self.env().caller();
+            let token = self.payment_token.ok_or(Error::Psp22NotConfigured)?;
+            self.release_held(caller);
+            let amount = self.pending_psp22.take(caller).unwrap_or(0);
+            if amount == 0 {
+                return Err(Error::NothingToWithdraw)
+            }
+            self.pending_psp22_total = self.pending_psp22_total.saturating_sub(amount);
+
+            self.psp22_transfer(token, caller, amount)?;
+            Ok(amount)
+        }
+
+        /// Pays out the surplus.
+        fn settle_sweep(&mut self, to: AccountId) -> Result<Balance, Error> {
+            let amount = self.surplus();
+            if amount == 0 {
+                return Err(Error::NothingToWithdraw)
+            }
+
+            self.env().transfer(to, amount).map_err(|_| Error::TransferFailed)?;
+            self.emit(SurplusSwept { to, amount });
+            Ok(amount)
+        }
+
+        /// Returns the sum of the native liabilities.
+        fn total_liabilities(&self) -> Balance {
+            self.pending_total
+                .saturating_add(self.held_total)
+                .saturating_add(self.disputed_total)
+        }
+
+        /// Returns the sum of the PSP22 liabilities.
+        fn psp22_liabilities(&self) -> Balance {
+            self.pending_psp22_total.saturating_add(self.held_psp22_total)
+        }
+
+        /// Verifies a listing voucher and consumes its nonce, returning the account of the signing key.
+        fn redeem_listing_voucher(&mut self, voucher: &ListingVoucher, seller_pub: SignerKey, signature: VoucherSignature) -> Result<AccountId, Error> {
+            if voucher.collection != self.nft.to_account_id() {
+                return Err(Error::UnknownCollection)
+            }
+            if self.env().block_timestamp() >= voucher.expiry {
+                return Err(Error::VoucherExpired)
+            }
+            let seller = AccountId::from(self.env().hash_bytes::<Blake2x256>(&seller_pub));
+            let nonce = voucher.nonce;
+            if self.used_nonces.contains((seller, nonce)) {
+                return Err(Error::VoucherUsed)
+            }
+
+            let message = self.env().hash_encoded::<Blake2x256, _>(&(self.env().account_id(), voucher));
+            let recovered = self.env()
+                .ecdsa_recover(&signature, &message)
+                .map_err(|_| Error::InvalidSignature)?;
+            if recovered != seller_pub {
+                return Err(Error::InvalidSignature)
+            }
+
+            self.used_nonces.insert((seller, nonce), &());
+            Ok(seller)
+        }
+
+        /// Returns the listing of a token with the price it sells at right now, which for a Dutch auction
+        /// is the price of its schedule at the current block.
+        fn live_listing(&self, id: TokenId) -> Option<Listing> {
+            let mut listing = self.listings.get(id)?;
+            if let Some(auction) = self.auctions.get(id) {
+                listing.price = auction.price_at(self.env().block_timestamp());
+            }
+            Some(listing)
+        }
+
+        /// Checks that a seller is below the listing limit and has waited the listing interval since their last listing.
+        fn check_listing_limits(&self, seller: AccountId) -> Result<(), Error> {
+            if self.listing_count_of(seller) >= self.max_listings_per_seller {
+                return Err(Error::TooManyListings)
+            }
+            if self
+                .last_listed_at
+                .get(seller)
+                .is_some_and(|block| self.env().block_number() < block.saturating_add(self.listing_interval))
+            {
+                return Err(Error::ListingThrottled)
+            }
+            Ok(())
+        }
+
+        /// Stores a new listing, appends it to the listing index and adds it to the order book if it is cheap enough.
+        /// It counts towards the seller's listing limit and starts their listing interval.
+        fn add_listing(&mut self, id: TokenId, listing: &Listing) {
+            self.listings.insert(id, listing);
+            self.seller_listing_count.insert(listing.seller, &(self.listing_count_of(listing.seller) + 1));
+            self.last_listed_at.insert(listing.seller, &self.env().block_number());
+            let index = self.listing_count;
+            self.listed_ids.insert(index, &id);
+            self.listed_index.insert(id, &index);
+            self.listing_count = index + 1;
+
+            if listing.currency == Currency::Native {
+                let mut book = self.order_book.get_or_default();
+                book_insert(&mut book, id, listing.price);
+                self.order_book.set(&book);
+            }
+        }
+
+        /// Removes a listing and its entry of the listing index, moving the last entry into the freed position,
+        /// and takes it out of the order book. It frees a place under the seller's listing limit.
+        fn remove_listing(&mut self, id: TokenId) {
+            if let Some(listing) = self.listings.take(id) {
+                let count = self.listing_count_of(listing.seller).saturating_sub(1);
+                self.seller_listing_count.insert(listing.seller, &count);
+            }
+            self.auctions.remove(id);
+            if let Some(index) = self.listed_index.take(id) {
+                let last = self.listing_count - 1;
+                if index != last {
+                    if let Some(moved) = self.listed_ids.get(last) {
+                        self.listed_ids.insert(index, &moved);
+                        self.listed_index.insert(moved, &index);
+                    }
+                }
+                self.listed_ids.remove(last);
+                self.listing_count = last;
+            }
+
+            self.unbook(id);
+        }
+
+        /// Moves a listing to its new place in the order book after its price changed.
+        fn rebook(&mut self, id: TokenId, listing: &Listing) {
+            if listing.currency != Currency::Native {
+                return
+            }
+            self.unbook(id);
+            let mut book = self.order_book.get_or_default();
+            book_insert(&mut book, id, listing.price);
+            self.order_book.set(&book);
+        }
+
+        /// Takes a listing out of the order book. If the book was full, the cheapest native listing left out of it,
+        /// Dutch auctions aside, among the first `MAX_FLOOR_SCAN` of the listing index takes the freed place.
+        fn unbook(&mut self, id: TokenId) {
+            let mut book = self.order_book.get_or_default();
+            let Some(position) = book.iter().position(|(booked, _)| *booked == id) else {
+                return
+            };
+            book.remove(position);
+
+            if book.len() as u32 + 1 == MAX_ORDER_BOOK {
+                let next = (0..self.listing_count.min(MAX_FLOOR_SCAN))
+                    .filter_map(|index| self.listed_ids.get(index))
+                    .filter(|listed| *listed != id && !book.iter().any(|(booked, _)| booked == listed))
+                    .filter_map(|listed| self.listings.get(listed).map(|listing| (listed, listing)))
+                    .filter(|(listed, listing)| listing.currency == Currency::Native && !self.auctions.contains(listed))
+                    .map(|(listed, listing)| (listed, listing.price))
+                    .min_by_key(|(listed, price)| (*price, *listed));
+                if let Some((listed, price)) = next {
+                    book_insert(&mut book, listed, price);
+                }
+            }
+            self.order_book.set(&book);
+        }
+
+        /// Returns true once a listing has reached its expiry.
+        fn is_expired(&self, listing: &Listing) -> bool {
+            listing.expires_at.is_some_and(|expires_at| expires_at <= self.env().block_timestamp())
+        }
+
+        /// Adds a completed sale to the running figures of `collection_stats`.
+        fn record_sale(&mut self, listing: &Listing) {
+            match listing.currency {
+                Currency::Native => self.total_volume = self.total_volume.saturating_add(listing.price),
+                Currency::Psp22 => self.psp22_volume = self.psp22_volume.saturating_add(listing.price)
+            }
+            self.sale_count += 1;
+        }
+
+        /// Queues the proceeds of a sale in the seller's hold queue, or credits them right away without a hold period.
+        fn hold_proceeds(&mut self, id: TokenId, buyer: AccountId, listing: &Listing, royalty: Option<RoyaltyDue>) {
+            if self.hold_period == 0 {
+                self.credit_proceeds(id, listing, royalty);
+                return
+            }
+
+            let seller = listing.seller;
+            let index = self.held_count_of(seller);
+            let funds = HeldFunds {
+                id,
+                buyer,
+                listing: listing.clone(),
+                royalty,
+                sold_at: self.env().block_timestamp(),
+                disputed: false
+            };
+            self.held.insert((seller, index), &funds);
+            self.held_count.insert(seller, &(index + 1));
+            self.held_total = self.held_total.saturating_add(native_amount(listing));
+            self.held_psp22_total = self.held_psp22_total.saturating_add(psp22_amount(listing));
+            let release_at = self.release_time(&funds);
+            self.emit(SaleHeld { seller, buyer, id, index, release_at });
+        }
+
+        /// Returns when the hold period of a held sale is over.
+        fn release_time(&self, funds: &HeldFunds) -> Timestamp {
+            funds.sold_at.saturating_add(self.hold_period)
+        }
+
+        /// Pays the royalty of a sale, splits what is left between the beneficiaries of the listing and credits
+        /// the rest to the seller. The royalty is capped at the price. Shares are rounded down, so the seller absorbs
+        /// the rounding and the credits always add up to the price.
+        fn credit_proceeds(&mut self, id: TokenId, listing: &Listing, royalty: Option<RoyaltyDue>) {
+            let mut net = listing.price;
+            if let Some((receiver, amount)) = royalty {
+                let amount = amount.min(listing.price);
+                if amount > 0 {
+                    net -= amount;
+                    self.credit(listing.currency, receiver, amount);
+                    self.emit(RoyaltyPaid { id, receiver, amount, currency: listing.currency });
+                }
+            }
+            let mut remainder = net;
+            for (beneficiary, bps) in &listing.beneficiaries {
+                let amount = share_of(net, *bps);
+                if amount == 0 {
+                    continue
+                }
+                remainder -= amount;
+                self.credit(listing.currency, *beneficiary, amount);
+                self.emit(ProceedsSplit { id, beneficiary: *beneficiary, amount, currency: listing.currency });
+            }
+            self.credit(listing.currency, listing.seller, remainder);
+        }
+
+        /// Adds an amount to the withdrawable balance of an account in the given currency.
+        fn credit(&mut self, currency: Currency, account: AccountId, amount: Balance) {
+            match currency {
+                Currency::Native => {
+                    let pending = self.pending_of(account);
+                    self.pending.insert(account, &(pending + amount));
+                    self.pending_total = self.pending_total.saturating_add(amount);
+                }
+                Currency::Psp22 => {
+                    let pending = self.pending_psp22_of(account);
+                    self.pending_psp22.insert(account, &(pending + amount));
+                    self.pending_psp22_total = self.pending_psp22_total.saturating_add(amount);
+                }
+            }
+        }
+
+        /// Rejects a price outside `price_bounds`.
+        fn check_price(&self, price: Balance) -> Result<(), Error> {
+            if price < self.min_price {
+                return Err(Error::PriceTooLow)
+            }
+            if price > self.max_price {
+                return Err(Error::PriceTooHigh)
+            }
+            Ok(())
+        }
+
+        /// Returns true if the NFT contract lets the marketplace move the owner's token.
+        fn can_move(&self, owner: AccountId, id: TokenId) -> bool {
+            let marketplace = self.env().account_id();
+            self.nft.get_approved(id) == Some(marketplace) || self.nft.is_approved_for_all(owner, marketplace)
+        }
+
+        /// Returns why a listing can no longer be bought, if it can't.
+        fn stale_reason(&self, listing: &Listing, id: TokenId) -> Option<DelistReason> {
+            if self.nft.owner_of(id) != Some(listing.seller) {
+                return Some(DelistReason::StaleOwner)
+            }
+            if !self.can_move(listing.seller, id) {
+                return Some(DelistReason::ApprovalRevoked)
+            }
+            None
+        }
+
+        /// Reads the URI of a token from the NFT contract and returns its hash.
+        fn current_uri_hash(&self, id: TokenId) -> Hash {
+            self.hash_of(&self.nft.token_uri(id).unwrap_or_default())
+        }
+
+        /// Returns the Blake2x256 hash of a SCALE-encoded value.
+        fn hash_of<T: scale::Encode>(&self, value: &T) -> Hash {
+            Hash::from(self.env().hash_encoded::<Blake2x256, _>(value))
+        }
+
+        /// Emits an event of this contract. The Patient dependency brings its own events into scope,
+        /// so the emitting contract has to be named explicitly.
+        fn emit<Ev>(&self, event: Ev)
+        where
+            Ev: Into<<NftMarketplace as ink::reflect::ContractEventBase>::Type>,
+        {
+            ink::codegen::EmitEvent::<NftMarketplace>::emit_event(self.env(), event);
+        }
+
+        /// Calls `PSP22::transfer_from` on the token contract.
+        fn psp22_transfer_from(&self, token: AccountId, from: AccountId, to: AccountId, value: Balance) -> Result<(), Error> {
+            let result = build_call::<Environment>()
+                .call(token)
+                .exec_input(
+                    ExecutionInput::new(Selector::new(ink::selector_bytes!("PSP22::transfer_from")))
+                        .push_arg(from)
+                        .push_arg(to)
+                        .push_arg(value)
+                        .push_arg(Vec::<u8>::new())
+                )
+                .returns::<Result<(), PSP22Error>>()
+                .try_invoke();
+
+            match result {
+                Ok(Ok(Ok(()))) => Ok(()),
+                _ => Err(Error::Psp22TransferFailed)
+            }
+        }
+
+        /// Calls `reassign_consent_token` on the EPR contract.
+        fn reassign_consent(&self, epr: AccountId, id: TokenId, new_holder: AccountId) -> Result<(), Error> {
+            let result = build_call::<Environment>()
+                .call(epr)
+                .exec_input(
+                    ExecutionInput::new(Selector::new(ink::selector_bytes!("reassign_consent_token")))
+                        .push_arg(id)
+                        .push_arg(new_holder)
+                )
+                .returns::<Result<(), u8>>()
+                .try_invoke();
+
+            match result {
+                Ok(Ok(Ok(()))) => Ok(()),
+                _ => Err(Error::ConsentReassignFailed)
+            }
+        }
+
+        /// Calls `PSP22::transfer` on the token contract.
+        fn psp22_transfer(&self, token: AccountId, to: AccountId, value: Balance) -> Result<(), Error> {
+            let result = build_call::<Environment>()
+                .call(token)
+                .exec_input(
+                    ExecutionInput::new(Selector::new(ink::selector_bytes!("PSP22::transfer")))
+                        .push_arg(to)
+                        .push_arg(value)
+                        .push_arg(Vec::<u8>::new())
+                )
+                .returns::<Result<(), PSP22Error>>()
+                .try_invoke();
+
+            match result {
+                Ok(Ok(Ok(()))) => Ok(()),
+                _ => Err(Error::Psp22TransferFailed)
+            }
+        }
+    }
+
+    /// Returns the share of an amount given in basis points, rounded down.
+    /// The amount is divided first so the multiplication cannot overflow.
+    fn share_of(amount: Balance, bps: u16) -> Balance {
+        let bps = Balance::from(bps);
+        let whole = Balance::from(MAX_BPS);
+        amount / whole * bps + amount % whole * bps / whole
+    }
+
+    /// Returns the price of a native listing, and zero for a PSP22 one.
+    fn native_amount(listing: &Listing) -> Balance {
+        match listing.currency {
+            Currency::Native => listing.price,
+            Currency::Psp22 => 0
+        }
+    }
+
+    /// Returns what a token represents, from its id.
+    fn kind_of(id: TokenId) -> ListingKind {
+        if id & CONSENT_TOKEN_FLAG != 0 {
+            ListingKind::AccessToken
+        } else {
+            ListingKind::Ownership
+        }
+    }
+
+    /// Returns the part of a listing's price paid in the PSP22 token.
+    fn psp22_amount(listing: &Listing) -> Balance {
+        match listing.currency {
+            Currency::Native => 0,
+            Currency::Psp22 => listing.price
+        }
+    }
+
+    /// Inserts a listing into an order book at its price, ties broken by token id, and drops the most expensive
+    /// listing once the book holds more than `MAX_ORDER_BOOK`.
+    fn book_insert(book: &mut Vec<(TokenId, Balance)>, id: TokenId, price: Balance) {
+        let position = book.partition_point(|(booked, booked_price)| (*booked_price, *booked) < (price, id));
+        book.insert(position, (id, price));
+        book.truncate(MAX_ORDER_BOOK as usize);
+    }
+
+    /// Unit tests
+    /// The off-chain environment cannot call the NFT or PSP22 contracts, so these tests cover the paths
+    /// that fail before any cross-contract call and build listings directly where needed.
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn default_accounts() -> ink::env::test::DefaultAccounts<Environment> {
+            ink::env::test::default_accounts::<Environment>()
+        }
+
+        fn set_caller(sender: AccountId) {
+            ink::env::test::set_caller::<Environment>(sender);
+        }
+
+        fn new_marketplace() -> NftMarketplace {
+            NftMarketplace::new(AccountId::from([0xFF; 32]))
+        }
+
+        fn listing(seller: AccountId, price: Balance, currency: Currency) -> Listing {
+            Listing { seller, price, currency, beneficiaries: Vec::new(), kind: ListingKind::Ownership, reserved_for: None, uri_hash: Hash::default(), expires_at: None }
+        }
+
+        #[ink::test]
+        fn new_works() {
+            let contract = new_marketplace();
+            assert_eq!(contract.listing(1), None);
+            assert_eq!(contract.payment_token(), None);
+        }
+
+        #[ink::test]
+        fn error_catalog_names_every_variant_in_order() {
+            let contract = new_marketplace();
+            let catalog = contract.error_catalog();
+            assert_eq!(catalog.len(), ERROR_NAMES.len());
+            for (code, name) in &catalog {
+                let error = <Error as scale::Decode>::decode(&mut &[*code][..]).expect("catalog entry without a variant");
+                assert_eq!(format!("{:?}", error), *name);
+            }
+            assert!(<Error as scale::Decode>::decode(&mut &[catalog.len() as u8][..]).is_err());
+        }
+
+        #[ink::test]
+        fn set_price_works() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+            assert_eq!(contract.set_price(1, 10), Err(Error::NotListed));
+            assert_eq!(contract.validate_listing(1), Err(Error::NotListed));
+
+            contract.listings.insert(1, &listing(accounts.alice, 5, Currency::Native));
+            assert_eq!(contract.set_price(1, 10), Ok(()));
+            assert_eq!(contract.listing(1), Some(listing(accounts.alice, 10, Currency::Native)));
+
+            set_caller(accounts.bob);
+            assert_eq!(contract.set_price(1, 1), Err(Error::NotSeller));
+            assert_eq!(contract.delist(1), Err(Error::NotSeller));
+        }
+
+        #[ink::test]
+        fn collection_stats_follow_listings_and_sales() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+            let collection = AccountId::from([0xFF; 32]);
+            assert_eq!(contract.floor_price(collection), None);
+
+            contract.add_listing(1, &listing(accounts.alice, 50, Currency::Native));
+            contract.add_listing(2, &listing(accounts.alice, 20, Currency::Native));
+            contract.add_listing(3, &listing(accounts.alice, 5, Currency::Psp22));
+            contract.add_listing(4, &listing(accounts.alice, 30, Currency::Native));
+            // PSP22 prices are in another unit and do not make the floor.
+            assert_eq!(contract.floor_price(collection), Some(20));
+            assert_eq!(contract.floor_price(accounts.bob), None);
+
+            // The floor listing sells: the next cheapest becomes the floor.
+            let sold = contract.listing(2).expect("listed");
+            contract.record_sale(&sold);
+            contract.remove_listing(2);
+            assert_eq!(contract.floor_price(collection), Some(30));
+            let psp22 = contract.listing(3).expect("listed");
+            contract.record_sale(&psp22);
+            contract.remove_listing(3);
+
+            // Repricing moves the floor both ways.
+            assert_eq!(contract.set_price(1, 10), Ok(()));
+            assert_eq!(contract.floor_price(collection), Some(10));
+            assert_eq!(contract.set_price(1, 70), Ok(()));
+            assert_eq!(contract.floor_price(collection), Some(30));
+            assert_eq!(contract.delist(4), Ok(()));
+            assert_eq!(contract.floor_price(collection), Some(70));
+
+            assert_eq!(
+                contract.collection_stats(collection),
+                Stats { total_volume: 20, psp22_volume: 5, sale_count: 2, listing_count: 1 }
+            );
+            assert_eq!(contract.collection_stats(accounts.bob), Stats::default());
+            assert_eq!(contract.listed_ids.get(0), Some(1));
+
+            assert_eq!(contract.delist(1), Ok(()));
+            assert_eq!(contract.floor_price(collection), None);
+            assert_eq!(contract.collection_stats(collection).listing_count, 0);
+        }
+
+        #[ink::test]
+        fn order_book_stays_sorted_as_listings_are_repriced_and_sold() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+            let collection = AccountId::from([0xFF; 32]);
+
+            for (id, price) in [(1, 40), (2, 10), (3, 30), (4, 20), (5, 30)] {
+                contract.add_listing(id, &listing(accounts.alice, price, Currency::Native));
+            }
+            contract.add_listing(6, &listing(accounts.alice, 1, Currency::Psp22));
+            assert_eq!(contract.cheapest_listings(collection, 10), vec![(2, 10), (4, 20), (3, 30), (5, 30), (1, 40)]);
+            assert_eq!(contract.cheapest_listings(collection, 2), vec![(2, 10), (4, 20)]);
+            assert!(contract.cheapest_listings(accounts.bob, 10).is_empty());
+            assert_eq!(contract.price_histogram(collection, 25), vec![(0, 2), (25, 3)]);
+            assert_eq!(contract.price_histogram(collection, 0), Vec::new());
+
+            // Repricing moves listings both ways, and the floor listing sells.
+            assert_eq!(contract.set_price(1, 5), Ok(()));
+            assert_eq!(contract.set_price(3, 50), Ok(()));
+            contract.remove_listing(1);
+            assert_eq!(contract.cheapest_listings(collection, 10), vec![(2, 10), (4, 20), (5, 30), (3, 50)]);
+            assert_eq!(contract.floor_price(collection), Some(10));
+            assert_eq!(contract.price_histogram(collection, 20), vec![(0, 1), (20, 2), (40, 1)]);
+            contract.remove_listing(6);
+            assert_eq!(contract.cheapest_listings(collection, 10).len(), 4);
+        }
+
+        #[ink::test]
+        fn a_full_order_book_refills_from_the_listing_index() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+            let collection = AccountId::from([0xFF; 32]);
+            let price_of = |id: TokenId| Balance::from(MAX_ORDER_BOOK + 2 - id) * 10;
+
+            // One listing more than the book holds, cheapest last: token 1, the most expensive, is left out.
+            for id in 1..=MAX_ORDER_BOOK + 1 {
+                contract.add_listing(id, &listing(accounts.alice, price_of(id), Currency::Native));
+            }
+            let book = contract.cheapest_listings(collection, MAX_ORDER_BOOK + 1);
+            assert_eq!(book.len() as u32, MAX_ORDER_BOOK);
+            assert_eq!(book.first(), Some(&(MAX_ORDER_BOOK + 1, 10)));
+            assert_eq!(book.last(), Some(&(2, price_of(2))));
+
+            // A cheaper listing pushes out the most expensive one, and selling it brings that one back.
+            contract.add_listing(200, &listing(accounts.alice, 5, Currency::Native));
+            assert_eq!(contract.floor_price(collection), Some(5));
+            assert_eq!(contract.cheapest_listings(collection, MAX_ORDER_BOOK).last(), Some(&(3, price_of(3))));
+            contract.remove_listing(200);
+            assert_eq!(contract.cheapest_listings(collection, MAX_ORDER_BOOK).last(), Some(&(2, price_of(2))));
+
+            // Repricing the floor above every other listing lets the cheapest listing left out take its place.
+            assert_eq!(contract.set_price(MAX_ORDER_BOOK + 1, 5_000), Ok(()));
+            let book = contract.cheapest_listings(collection, MAX_ORDER_BOOK);
+            assert_eq!(book.len() as u32, MAX_ORDER_BOOK);
+            assert_eq!(book.first(), Some(&(MAX_ORDER_BOOK, 20)));
+            assert_eq!(book.last(), Some(&(1, price_of(1))));
+            assert!(book.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+        }
+
+        #[ink::test]
+        fn prices_stay_within_the_admin_bounds() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+            assert_eq!(contract.price_bounds(), (0, Balance::MAX));
+            contract.listings.insert(1, &listing(accounts.alice, 5, Currency::Native));
+
+            set_caller(accounts.bob);
+            assert_eq!(contract.set_price_bounds(10, 1_000), Err(Error::NotAdmin));
+            set_caller(accounts.alice);
+            assert_eq!(contract.set_price_bounds(1_000, 10), Err(Error::InvalidPriceBounds));
+            assert_eq!(contract.set_price_bounds(10, 1_000), Ok(()));
+            assert_eq!(contract.price_bounds(), (10, 1_000));
+            assert_eq!(ink::env::test::recorded_events().count(), 1);
+
+            // New listings are checked against both bounds.
+            let list = |contract: &mut NftMarketplace, price| contract.list(2, price, Currency::Native, Vec::new(), None);
+            assert_eq!(list(&mut contract, 9), Err(Error::PriceTooLow));
+            assert_eq!(list(&mut contract, 1_001), Err(Error::PriceTooHigh));
+
+            // A listing made under the old bounds stays listed, but can only be repriced within the new ones.
+            assert_eq!(contract.listing(1), Some(listing(accounts.alice, 5, Currency::Native)));
+            assert_eq!(contract.set_price(1, 9), Err(Error::PriceTooLow));
+            assert_eq!(contract.set_price(1, 1_001), Err(Error::PriceTooHigh));
+            assert_eq!(contract.set_price(1, 10), Ok(()));
+            assert_eq!(contract.set_price(1, 1_000), Ok(()));
+        }
+
+        #[ink::test]
+        fn sellers_are_held_to_the_listing_limit_and_interval() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+            assert_eq!(contract.listing_limits(), (DEFAULT_MAX_LISTINGS_PER_SELLER, 0));
+            set_caller(accounts.bob);
+            assert_eq!(contract.set_listing_limits(2, 5), Err(Error::NotAdmin));
+            set_caller(accounts.alice);
+            assert_eq!(contract.set_listing_limits(2, 0), Ok(()));
+
+            // Bob fills his two places; a third listing is refused before the NFT contract is asked.
+            contract.add_listing(1, &listing(accounts.bob, 100, Currency::Native));
+            let mut expiring = listing(accounts.bob, 100, Currency::Native);
+            expiring.expires_at = Some(1_000);
+            contract.add_listing(2, &expiring);
+            contract.add_listing(3, &listing(accounts.charlie, 100, Currency::Native));
+            assert_eq!((contract.listing_count_of(accounts.bob), contract.listing_count_of(accounts.charlie)), (2, 1));
+            set_caller(accounts.bob);
+            let list = |contract: &mut NftMarketplace, id| contract.list(id, 100, Currency::Native, Vec::new(), None);
+            assert_eq!(list(&mut contract, 4), Err(Error::TooManyListings));
+
+            // Delisting frees a place, and so does purging an expired listing.
+            assert_eq!(contract.delist(1), Ok(()));
+            assert_eq!(contract.check_listing_limits(accounts.bob), Ok(()));
+            contract.add_listing(1, &listing(accounts.bob, 100, Currency::Native));
+            assert_eq!(list(&mut contract, 4), Err(Error::TooManyListings));
+            ink::env::test::set_block_timestamp::<Environment>(1_000);
+            assert_eq!(contract.validate_listing(2), Ok(Some(DelistReason::Expired)));
+            assert_eq!(contract.listing_count_of(accounts.bob), 1);
+            assert_eq!(contract.check_listing_limits(accounts.bob), Ok(()));
+
+            // With an interval, a seller waits that many blocks after their last new listing.
+            set_caller(accounts.alice);
+            assert_eq!(contract.set_listing_limits(10, 3), Ok(()));
+            set_caller(accounts.bob);
+            assert_eq!(list(&mut contract, 4), Err(Error::ListingThrottled));
+            assert_eq!(contract.check_listing_limits(accounts.charlie), Err(Error::ListingThrottled));
+            for _ in 0..3 {
+                ink::env::test::advance_block::<Environment>();
+            }
+            assert_eq!(contract.check_listing_limits(accounts.bob), Ok(()));
+            assert_eq!(contract.check_listing_limits(accounts.django), Ok(()));
+        }
+
+        #[ink::test]
+        fn payment_token_is_admin_only() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+            let token = AccountId::from([0xAA; 32]);
+
+            // PSP22 listings need a registered token.
+            assert_eq!(contract.list(1, 10, Currency::Psp22, Vec::new(), None), Err(Error::Psp22NotConfigured));
+            assert_eq!(contract.withdraw_psp22(), Err(Error::Psp22NotConfigured));
+
+            set_caller(accounts.bob);
+            assert_eq!(contract.set_payment_token(Some(token)), Err(Error::NotAdmin));
+            set_caller(accounts.alice);
+            assert_eq!(contract.set_payment_token(Some(token)), Ok(()));
+            assert_eq!(contract.payment_token(), Some(token));
+            assert_eq!(contract.withdraw_psp22(), Err(Error::NothingToWithdraw));
+
+            // Native proceeds do not tie the token, but PSP22 proceeds held or owed do.
+            contract.hold_proceeds(1, accounts.bob, &listing(accounts.charlie, 50, Currency::Native), None);
+            assert_eq!(contract.set_payment_token(Some(token)), Ok(()));
+            contract.hold_proceeds(2, accounts.bob, &listing(accounts.charlie, 10, Currency::Psp22), None);
+            assert_eq!(contract.set_payment_token(None), Err(Error::PaymentTokenInUse));
+            assert_eq!(contract.set_payment_token(Some(AccountId::from([0xBB; 32]))), Err(Error::PaymentTokenInUse));
+            ink::env::test::set_block_timestamp::<Environment>(contract.hold_period());
+            assert_eq!(contract.release_held(accounts.charlie), 2);
+            assert_eq!(contract.pending_psp22_of(accounts.charlie), 10);
+            assert_eq!(contract.set_payment_token(None), Err(Error::PaymentTokenInUse));
+            assert_eq!(contract.payment_token(), Some(token));
+        }
+
+        #[ink::test]
+        fn hash_of_listing_matches_the_encoded_listing() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+            assert_eq!(contract.hash_of_listing(1), None);
+
+            let listed = listing(accounts.alice, 10, Currency::Native);
+            contract.listings.insert(1, &listed);
+            let mut expected = [0u8; 32];
+            ink::env::hash_encoded::<Blake2x256, _>(&listed, &mut expected);
+            assert_eq!(contract.hash_of_listing(1), Some(Hash::from(expected)));
+        }
+
+        #[ink::test]
+        fn buy_requires_the_listing_currency() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+            contract.listings.insert(1, &listing(accounts.alice, 10, Currency::Native));
+            contract.listings.insert(2, &listing(accounts.alice, 10, Currency::Psp22));
+
+            set_caller(accounts.bob);
+            assert_eq!(contract.buy(3, None), Err(Error::NotListed));
+            // Native listings must be paid with exactly the price.
+            ink::env::test::set_value_transferred::<Environment>(9);
+            assert_eq!(contract.buy(1, None), Err(Error::IncorrectPayment));
+            // PSP22 listings must not carry native value.
+            assert_eq!(contract.buy(2, None), Err(Error::IncorrectPayment));
+            assert_eq!(contract.withdraw(), Err(Error::NothingToWithdraw));
+        }
+
+        #[ink::test]
+        fn reserved_listings_only_sell_to_the_named_buyer() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+            let mut reserved = listing(accounts.alice, 10, Currency::Native);
+            reserved.reserved_for = Some(accounts.bob);
+            contract.listings.insert(1, &reserved);
+            ink::env::test::set_value_transferred::<Environment>(9);
+
+            // Bob gets past the reservation, Charlie does not.
+            set_caller(accounts.bob);
+            assert_eq!(contract.buy(1, None), Err(Error::IncorrectPayment));
+            set_caller(accounts.charlie);
+            assert_eq!(contract.buy(1, None), Err(Error::ReservedListing));
+
+            // Only the seller can change the reservation.
+            assert_eq!(contract.update_reservation(1, None), Err(Error::NotSeller));
+            assert_eq!(contract.update_reservation(2, None), Err(Error::NotListed));
+            set_caller(accounts.alice);
+            assert_eq!(contract.update_reservation(1, None), Ok(()));
+            assert_eq!(contract.listing(1).map(|listing| listing.reserved_for), Some(None));
+
+            // Once cleared, anyone can buy.
+            set_caller(accounts.charlie);
+            assert_eq!(contract.buy(1, None), Err(Error::IncorrectPayment));
+        }
+
+        #[ink::test]
+        fn buy_batch_reports_skipped_ids_and_refunds_the_value() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+            let mut reserved = listing(accounts.alice, 10, Currency::Native);
+            reserved.reserved_for = Some(accounts.charlie);
+            contract.listings.insert(1, &reserved);
+            contract.listings.insert(2, &listing(accounts.alice, 60, Currency::Native));
+            contract.listings.insert(3, &listing(accounts.alice, 30, Currency::Native));
+            contract.listings.insert(4, &listing(accounts.alice, 10, Currency::Psp22));
+
+            set_caller(accounts.bob);
+            assert_eq!(contract.buy_batch(vec![1; MAX_BATCH_BUY + 1], 100), Err(Error::BatchTooLarge));
+
+            // Nothing can be bought, so every id is reported and the whole value comes back.
+            ink::env::test::set_value_transferred::<Environment>(25);
+            let before = ink::env::test::get_account_balance::<Environment>(accounts.bob).unwrap();
+            assert_eq!(
+                contract.buy_batch(vec![1, 2, 3, 4, 9], 50),
+                Ok(vec![
+                    Err(Error::ReservedListing),
+                    Err(Error::MaxTotalExceeded),
+                    Err(Error::IncorrectPayment),
+                    Err(Error::Psp22NotConfigured),
+                    Err(Error::NotListed)
+                ])
+            );
+            assert_eq!(ink::env::test::get_account_balance::<Environment>(accounts.bob), Ok(before + 25));
+            // The listings are untouched.
+            assert_eq!(contract.listing(2).map(|listing| listing.price), Some(60));
+        }
+
+        #[ink::test]
+        fn access_token_listings_need_the_epr() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+            let epr = AccountId::from([0xEE; 32]);
+
+            assert_eq!(
+                contract.list(1 | CONSENT_TOKEN_FLAG, 10, Currency::Native, Vec::new(), None),
+                Err(Error::EprNotConfigured)
+            );
+            set_caller(accounts.bob);
+            assert_eq!(contract.set_epr(Some(epr)), Err(Error::NotAdmin));
+            set_caller(accounts.alice);
+            assert_eq!(contract.set_epr(Some(epr)), Ok(()));
+            assert_eq!(contract.epr(), Some(epr));
+        }
+
+        #[ink::test]
+        fn nested_settlements_are_rejected() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+            contract.listings.insert(1, &listing(accounts.alice, 10, Currency::Native));
+            contract.pending.insert(accounts.alice, &10);
+
+            // While a settlement is running, every settlement entry point is closed.
+            contract.locked.set(&true);
+            assert_eq!(contract.buy(1, None), Err(Error::ReentrancyDetected));
+            assert_eq!(contract.withdraw(), Err(Error::ReentrancyDetected));
+            assert_eq!(contract.withdraw_psp22(), Err(Error::ReentrancyDetected));
+            assert_eq!(contract.listing(1), Some(listing(accounts.alice, 10, Currency::Native)));
+            assert_eq!(contract.pending_of(accounts.alice), 10);
+
+            // The guard is released again when a settlement fails.
+            contract.locked.set(&false);
+            assert_eq!(contract.buy(2, None), Err(Error::NotListed));
+            assert_eq!(contract.withdraw_psp22(), Err(Error::Psp22NotConfigured));
+            assert_eq!(contract.locked.get(), Some(false));
+        }
+
+        #[ink::test]
+        fn list_validates_beneficiaries() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+
+            let too_many = vec![(accounts.bob, 1); MAX_BENEFICIARIES + 1];
+            assert_eq!(contract.list(1, 10, Currency::Native, too_many, None), Err(Error::TooManyBeneficiaries));
+            let over_split = vec![(accounts.bob, 6_000), (accounts.charlie, 4_001)];
+            assert_eq!(contract.list(1, 10, Currency::Native, over_split, None), Err(Error::InvalidSplit));
+            // Shares beyond u16 when summed are still caught.
+            let overflow = vec![(accounts.bob, u16::MAX), (accounts.charlie, u16::MAX)];
+            assert_eq!(contract.list(1, 10, Currency::Native, overflow, None), Err(Error::InvalidSplit));
+        }
+
+        #[ink::test]
+        fn proceeds_split_without_losing_funds() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+
+            // 3 x 33.33% of 7 rounds every share down to 2, so the seller keeps 1.
+            let mut listed = listing(accounts.alice, 7, Currency::Native);
+            listed.beneficiaries = vec![(accounts.bob, 3_333), (accounts.charlie, 3_333), (accounts.django, 3_333)];
+            contract.credit_proceeds(1, &listed, None);
+            assert_eq!(contract.pending_of(accounts.bob), 2);
+            assert_eq!(contract.pending_of(accounts.charlie), 2);
+            assert_eq!(contract.pending_of(accounts.django), 2);
+            assert_eq!(contract.pending_of(accounts.alice), 1);
+            assert_eq!(ink::env::test::recorded_events().count(), 3);
+
+            // A full split leaves nothing to the seller, and shares too small to pay are skipped.
+            let mut listed = listing(accounts.alice, 3, Currency::Psp22);
+            listed.beneficiaries = vec![(accounts.bob, 5_000), (accounts.charlie, 4_999), (accounts.eve, 1)];
+            contract.credit_proceeds(2, &listed, None);
+            assert_eq!(contract.pending_psp22_of(accounts.bob), 1);
+            assert_eq!(contract.pending_psp22_of(accounts.charlie), 1);
+            assert_eq!(contract.pending_psp22_of(accounts.eve), 0);
+            assert_eq!(contract.pending_psp22_of(accounts.alice), 1);
+            assert_eq!(ink::env::test::recorded_events().count(), 5);
+
+            // Large prices split exactly.
+            let mut listed = listing(accounts.frank, Balance::MAX, Currency::Native);
+            listed.beneficiaries = vec![(accounts.eve, 2_500)];
+            contract.credit_proceeds(3, &listed, None);
+            assert_eq!(
+                contract.pending_of(accounts.eve) + contract.pending_of(accounts.frank),
+                Balance::MAX
+            );
+        }
+
+        #[ink::test]
+        fn royalties_are_paid_before_the_split() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+
+            // The royalty comes off the top and the beneficiaries share what is left.
+            let mut listed = listing(accounts.alice, 1_000, Currency::Native);
+            listed.beneficiaries = vec![(accounts.bob, 5_000)];
+            contract.credit_proceeds(1, &listed, Some((accounts.django, 100)));
+            assert_eq!(contract.pending_of(accounts.django), 100);
+            assert_eq!(contract.pending_of(accounts.bob), 450);
+            assert_eq!(contract.pending_of(accounts.alice), 450);
+            // RoyaltyPaid and ProceedsSplit.
+            assert_eq!(ink::env::test::recorded_events().count(), 2);
+
+            // A royalty reported above the price is capped at it, and a zero royalty is not paid.
+            let listed = listing(accounts.alice, 10, Currency::Psp22);
+            contract.credit_proceeds(2, &listed, Some((accounts.eve, 50)));
+            assert_eq!(contract.pending_psp22_of(accounts.eve), 10);
+            assert_eq!(contract.pending_psp22_of(accounts.alice), 0);
+            let events = ink::env::test::recorded_events().count();
+            contract.credit_proceeds(3, &listed, Some((accounts.eve, 0)));
+            assert_eq!(ink::env::test::recorded_events().count(), events);
+            assert_eq!(contract.pending_psp22_of(accounts.alice), 10);
+
+            // A held sale keeps the royalty it was sold with until it is released.
+            contract.hold_proceeds(4, accounts.bob, &listing(accounts.charlie, 200, Currency::Native), Some((accounts.frank, 20)));
+            assert_eq!(contract.pending_of(accounts.frank), 0);
+            ink::env::test::set_block_timestamp::<Environment>(contract.hold_period());
+            assert_eq!(contract.release_held(accounts.charlie), 1);
+            assert_eq!(contract.pending_of(accounts.frank), 20);
+            assert_eq!(contract.pending_of(accounts.charlie), 180);
+        }
+
+        #[ink::test]
+        fn held_proceeds_are_released_after_the_hold_or_by_dispute() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+            let hold = contract.hold_period();
+            let seller = accounts.alice;
+            let mut listed = listing(seller, 100, Currency::Native);
+            listed.beneficiaries = vec![(accounts.eve, 1_000)];
+            for id in 1..=3 {
+                contract.hold_proceeds(id, accounts.bob, &listed, None);
+            }
+            assert_eq!(contract.held_count_of(seller), 3);
+            assert_eq!(contract.pending_of(seller), 0);
+
+            // The buyer can dispute until the hold period is over, and nobody else can.
+            ink::env::test::set_block_timestamp::<Environment>(hold - 1);
+            set_caller(accounts.charlie);
+            assert_eq!(contract.open_dispute((seller, 0)), Err(Error::NotBuyer));
+            set_caller(accounts.bob);
+            assert_eq!(contract.open_dispute((seller, 0)), Ok(()));
+            assert_eq!(contract.open_dispute((seller, 0)), Err(Error::AlreadyDisputed));
+            assert_eq!(contract.open_dispute((seller, 1)), Ok(()));
+            assert_eq!(contract.release_held(seller), 0);
+
+            ink::env::test::set_block_timestamp::<Environment>(hold);
+            assert_eq!(contract.open_dispute((seller, 2)), Err(Error::DisputeWindowClosed));
+            assert_eq!(contract.open_dispute((seller, 3)), Err(Error::SaleNotFound));
+
+            // Releasing skips the disputed sales and splits the undisputed one.
+            assert_eq!(contract.release_held(seller), 1);
+            assert_eq!(contract.pending_of(seller), 90);
+            assert_eq!(contract.pending_of(accounts.eve), 10);
+            assert_eq!(contract.held((seller, 2)), None);
+            assert_eq!(contract.release_held(seller), 0);
+
+            // The admin refunds one disputed sale and pays the seller for the other.
+            assert_eq!(contract.resolve_dispute((seller, 0), true), Err(Error::NotAdmin));
+            set_caller(accounts.alice);
+            assert_eq!(contract.resolve_dispute((seller, 2), true), Err(Error::SaleNotFound));
+            assert_eq!(contract.resolve_dispute((seller, 0), true), Ok(()));
+            assert_eq!(contract.pending_of(accounts.bob), 100);
+            assert_eq!(contract.resolve_dispute((seller, 1), false), Ok(()));
+            assert_eq!(contract.pending_of(seller), 180);
+            assert_eq!(contract.pending_of(accounts.eve), 20);
+            assert_eq!(contract.resolve_dispute((seller, 1), false), Err(Error::SaleNotFound));
+
+            // Every sale was settled, so the queue is drained.
+            assert_eq!(contract.release_held(seller), 0);
+            assert_eq!(contract.held_head.get(seller), Some(3));
+
+            // Without a hold period, proceeds are credited right away.
+            assert_eq!(contract.set_hold_period(0), Ok(()));
+            contract.hold_proceeds(4, accounts.bob, &listing(accounts.django, 50, Currency::Native), None);
+            assert_eq!(contract.pending_of(accounts.django), 50);
+            assert_eq!(contract.held_count_of(accounts.django), 0);
+        }
+
+        #[ink::test]
+        fn a_dispute_does_not_hold_back_later_sales() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+            let hold = contract.hold_period();
+            let seller = accounts.alice;
+            let listed = listing(seller, 10, Currency::Native);
+            for id in 0..MAX_RELEASE_SCAN + 2 {
+                contract.hold_proceeds(id, accounts.bob, &listed, None);
+            }
+            ink::env::test::set_block_timestamp::<Environment>(hold - 1);
+            set_caller(accounts.bob);
+            assert_eq!(contract.open_dispute((seller, 0)), Ok(()));
+
+            // Each call moves past the disputed sale and a full scan, so the sales beyond it are released too.
+            ink::env::test::set_block_timestamp::<Environment>(hold);
+            assert_eq!(contract.release_held(seller), MAX_RELEASE_SCAN - 1);
+            assert_eq!(contract.release_held(seller), 2);
+            assert_eq!(contract.pending_of(seller), 10 * Balance::from(MAX_RELEASE_SCAN + 1));
+            assert!(contract.held((seller, 0)).is_some_and(|funds| funds.disputed));
+
+            // The disputed sale is still settled by the admin.
+            set_caller(accounts.alice);
+            assert_eq!(contract.resolve_dispute((seller, 0), false), Ok(()));
+            assert_eq!(contract.pending_of(seller), 10 * Balance::from(MAX_RELEASE_SCAN + 2));
+        }
+
+        #[ink::test]
+        fn liabilities_track_every_value_move_and_only_the_surplus_is_swept() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+            let market = ink::env::test::callee::<Environment>();
+            let seller = accounts.django;
+            let fund = |amount: Balance| {
+                let balance = ink::env::test::get_account_balance::<Environment>(market).unwrap_or(0);
+                ink::env::test::set_account_balance::<Environment>(market, balance + amount);
+            };
+            let solvent = |contract: &NftMarketplace| {
+                let balance = ink::env::test::get_account_balance::<Environment>(market).unwrap_or(0);
+                balance >= contract.liabilities().total && contract.surplus() == balance - contract.liabilities().total
+            };
+            ink::env::test::set_account_balance::<Environment>(market, 0);
+
+            // Native sales are held, PSP22 sales owe nothing in the native balance.
+            let mut listed = listing(seller, 100, Currency::Native);
+            listed.beneficiaries = vec![(accounts.eve, 1_000)];
+            fund(100);
+            contract.hold_proceeds(1, accounts.bob, &listed, None);
+            contract.hold_proceeds(2, accounts.bob, &listing(seller, 50, Currency::Psp22), None);
+            fund(40);
+            contract.hold_proceeds(3, accounts.bob, &listing(seller, 40, Currency::Native), None);
+            assert_eq!(contract.liabilities(), Liabilities { pending: 0, held: 140, disputed: 0, total: 140 });
+            assert_eq!(contract.surplus(), 0);
+
+            // A dispute moves the sale from held to disputed, and value sent directly is surplus.
+            set_caller(accounts.bob);
+            assert_eq!(contract.open_dispute((seller, 2)), Ok(()));
+            fund(7);
+            assert_eq!(contract.liabilities(), Liabilities { pending: 0, held: 100, disputed: 40, total: 140 });
+            assert_eq!(contract.surplus(), 7);
+            assert!(solvent(&contract));
+
+            // Releasing credits the proceeds and a withdrawal pays them out.
+            ink::env::test::set_block_timestamp::<Environment>(contract.hold_period());
+            set_caller(seller);
+            assert_eq!(contract.withdraw(), Ok(90));
+            assert_eq!(contract.liabilities(), Liabilities { pending: 10, held: 0, disputed: 40, total: 50 });
+            assert!(solvent(&contract));
+
+            // Refunding the dispute credits the buyer.
+            set_caller(accounts.alice);
+            assert_eq!(contract.resolve_dispute((seller, 2), true), Ok(()));
+            assert_eq!(contract.liabilities(), Liabilities { pending: 50, held: 0, disputed: 0, total: 50 });
+            assert!(solvent(&contract));
+
+            // Only the admin sweeps, and only the surplus.
+            set_caller(accounts.bob);
+            assert_eq!(contract.sweep_surplus(accounts.frank), Err(Error::NotAdmin));
+            set_caller(accounts.alice);
+            let before = ink::env::test::get_account_balance::<Environment>(accounts.frank).unwrap_or(0);
+            assert_eq!(contract.sweep_surplus(accounts.frank), Ok(7));
+            assert_eq!(ink::env::test::get_account_balance::<Environment>(accounts.frank), Ok(before + 7));
+            assert_eq!(contract.surplus(), 0);
+            assert_eq!(contract.sweep_surplus(accounts.frank), Err(Error::NothingToWithdraw));
+            assert!(solvent(&contract));
+
+            // The buyer and the beneficiary can still withdraw everything they are owed.
+            set_caller(accounts.bob);
+            assert_eq!(contract.withdraw(), Ok(40));
+            set_caller(accounts.eve);
+            assert_eq!(contract.withdraw(), Ok(10));
+            assert_eq!(contract.liabilities(), Liabilities::default());
+            assert_eq!(ink::env::test::get_account_balance::<Environment>(market), Ok(0));
+        }
+
+        #[ink::test]
+        fn pausing_the_collection_blocks_listing_and_buying_only() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+            let collection = AccountId::from([0xFF; 32]);
+            contract.add_listing(1, &listing(accounts.bob, 100, Currency::Native));
+
+            assert_eq!(contract.pause_collection(accounts.charlie), Err(Error::UnknownCollection));
+            assert_eq!(contract.pause_collection(collection), Ok(()));
+            assert!(contract.is_collection_paused(collection));
+            assert!(!contract.is_collection_paused(accounts.charlie));
+            assert_eq!(ink::env::test::recorded_events().count(), 1);
+
+            set_caller(accounts.charlie);
+            assert_eq!(contract.list(2, 100, Currency::Native, Vec::new(), None), Err(Error::CollectionPaused));
+            assert_eq!(contract.create_dutch(2, 200, 100, MIN_AUCTION_DURATION), Err(Error::CollectionPaused));
+            assert_eq!(contract.buy(1, None), Err(Error::CollectionPaused));
+            assert_eq!(contract.buy_dutch(1), Err(Error::CollectionPaused));
+            assert_eq!(contract.buy_batch(vec![1], 100), Err(Error::CollectionPaused));
+            set_caller(accounts.bob);
+            assert_eq!(contract.relist(1, 100, 1_000), Err(Error::CollectionPaused));
+
+            // Sellers can still take their listings down, and proceeds stay withdrawable.
+            assert_eq!(contract.delist(1), Ok(()));
+            assert_eq!(contract.withdraw(), Err(Error::NothingToWithdraw));
+
+            set_caller(accounts.alice);
+            assert_eq!(contract.unpause_collection(collection), Ok(()));
+            assert!(!contract.is_collection_paused(collection));
+            set_caller(accounts.bob);
+            assert_eq!(contract.buy(1, None), Err(Error::NotListed));
+        }
+
+        #[ink::test]
+        fn listings_are_extended_while_active_and_relisted_once_expired() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+            contract.add_listing(1, &listing(accounts.alice, 100, Currency::Native));
+            ink::env::test::set_block_timestamp::<Environment>(500);
+
+            // Only the seller pushes the expiry back, to a later time in the future.
+            set_caller(accounts.bob);
+            assert_eq!(contract.extend_listing(1, 1_000), Err(Error::NotSeller));
+            assert_eq!(contract.relist(1, 50, 5_000), Err(Error::NotSeller));
+            set_caller(accounts.alice);
+            assert_eq!(contract.extend_listing(2, 1_000), Err(Error::NotListed));
+            assert_eq!(contract.extend_listing(1, 500), Err(Error::InvalidExpiry));
+            assert_eq!(contract.extend_listing(1, 1_000), Ok(()));
+            assert_eq!(contract.extend_listing(1, 1_000), Err(Error::InvalidExpiry));
+            assert_eq!(contract.extend_listing(1, 2_000), Ok(()));
+            assert_eq!(contract.listing(1).and_then(|listing| listing.expires_at), Some(2_000));
+            assert_eq!(contract.relist(1, 50, 5_000), Err(Error::ListingNotExpired));
+
+            // Once expired, the listing cannot be bought or extended, only relisted.
+            ink::env::test::set_block_timestamp::<Environment>(2_000);
+            set_caller(accounts.bob);
+            ink::env::test::set_value_transferred::<Environment>(100);
+            assert_eq!(contract.buy(1, None), Err(Error::ListingExpired));
+            set_caller(accounts.alice);
+            assert_eq!(contract.extend_listing(1, 3_000), Err(Error::ListingExpired));
+            assert_eq!(contract.relist(1, 50, 2_000), Err(Error::InvalidExpiry));
+            assert_eq!(contract.set_price_bounds(60, 1_000), Ok(()));
+            assert_eq!(contract.relist(1, 50, 5_000), Err(Error::PriceTooLow));
+
+            // Anyone can purge an expired listing.
+            let mut expired = listing(accounts.alice, 100, Currency::Native);
+            expired.expires_at = Some(1_000);
+            contract.add_listing(2, &expired);
+            set_caller(accounts.bob);
+            assert_eq!(contract.validate_listing(2), Ok(Some(DelistReason::Expired)));
+            assert_eq!(contract.listing(2), None);
+        }
+
+        #[ink::test]
+        fn dutch_auctions_fall_to_their_end_price() {
+            let accounts = default_accounts();
+            let mut contract = new_marketplace();
+            let hour = MIN_AUCTION_DURATION;
+            assert_eq!(contract.create_dutch(1, 100, 100, hour), Err(Error::InvalidAuction));
+            assert_eq!(contract.create_dutch(1, 100, 0, hour), Err(Error::InvalidAuction));
+            assert_eq!(contract.create_dutch(1, 100, 10, hour - 1), Err(Error::InvalidAuction));
+            assert_eq!(contract.create_dutch(1, 100, 10, MAX_AUCTION_DURATION + 1), Err(Error::InvalidAuction));
+            assert_eq!(contract.set_price_bounds(20, 1_000), Ok(()));
+            assert_eq!(contract.create_dutch(1, 100, 10, hour), Err(Error::PriceTooLow));
+
+            let started_at = 5_000;
+            contract.add_listing(1, &listing(accounts.alice, 1_000, Currency::Native));
+            contract.auctions.insert(1, &DutchAuction { start_price: 1_000, end_price: 100, started_at, duration: hour });
+            contract.add_listing(2, &listing(accounts.alice, 500, Currency::Native));
+
+            // The price falls linearly from the start price and floors at the end price.
+            ink::env::test::set_block_timestamp::<Environment>(started_at);
+            assert_eq!(contract.current_price(1), Some(1_000));
+            ink::env::test::set_block_timestamp::<Environment>(started_at + hour / 2);
+            assert_eq!(contract.current_price(1), Some(550));
+            ink::env::test::set_block_timestamp::<Environment>(started_at + hour);
+            assert_eq!(contract.current_price(1), Some(100));
+            ink::env::test::set_block_timestamp::<Environment>(started_at + 10 * hour);
+            assert_eq!(contract.current_price(1), Some(100));
+            assert_eq!(contract.current_price(2), Some(500));
+            assert_eq!(contract.set_price(1, 50), Err(Error::InvalidAuction));
+
+            // The whole price range can be used without overflowing.
+            let wide = DutchAuction { start_price: Balance::MAX, end_price: 1, started_at: 0, duration: hour };
+            assert_eq!(wide.price_at(hour / 2), Balance::MAX - (Balance::MAX - 1) / 2);
+
+            // The value has to cover the current price, and fixed-price listings are bought with `buy`.
+            set_caller(accounts.bob);
+            ink::env::test::set_value_transferred::<Environment>(99);
+            assert_eq!(contract.buy_dutch(1), Err(Error::IncorrectPayment));
+            assert_eq!(contract.buy_dutch(2), Err(Error::NotAuction));
+            assert_eq!(contract.buy_dutch(3), Err(Error::NotListed));
+
+            // The seller cancels the auction by delisting it.
+            set_caller(accounts.alice);
+            assert_eq!(contract.delist(1), Ok(()));
+            assert_eq!(contract.dutch_auction(1), None);
+            assert_eq!(contract.current_price(1), None);
+        }
+
+        #[ink::test]
+        fn listing_vouchers_are_checked_and_single_use() {
+            let mut contract = new_marketplace();
+            let marketplace = ink::env::account_id::<Environment>();
+            let collection = AccountId::from([0xFF; 32]);
+            let signer = voucher_signer_key();
+            let voucher = ListingVoucher { collection, id: 1, price: 100, expiry: 1_000, nonce: 7 };
+            let signature = sign_listing_voucher(marketplace, &voucher);
+
+            // A voucher for another collection, or past its expiry, is rejected before its signature.
+            let elsewhere = ListingVoucher { collection: AccountId::from([0xEE; 32]), ..voucher.clone() };
+            assert_eq!(contract.redeem_listing_voucher(&elsewhere, signer, signature), Err(Error::UnknownCollection));
+            ink::env::test::set_block_timestamp::<Environment>(1_000);
+            assert_eq!(contract.redeem_listing_voucher(&voucher, signer, signature), Err(Error::VoucherExpired));
+            ink::env::test::set_block_timestamp::<Environment>(999);
+
+            // Any other term than the signed ones, or another key, fails the signature check.
+            let cheaper = ListingVoucher { price: 1, ..voucher.clone() };
+            assert_eq!(contract.redeem_listing_voucher(&cheaper, signer, signature), Err(Error::InvalidSignature));
+            let renonced = ListingVoucher { nonce: 8, ..voucher.clone() };
+            assert_eq!(contract.redeem_listing_voucher(&renonced, signer, signature), Err(Error::InvalidSignature));
+            let mut other_signer = signer;
+            other_signer[1] ^= 1;
+            assert_eq!(contract.redeem_listing_voucher(&voucher, other_signer, signature), Err(Error::InvalidSignature));
+
+            // The voucher is attributed to the account of the key and its nonce cannot be replayed.
+            let seller = voucher_seller();
+            assert!(!contract.is_nonce_used(seller, 7));
+            assert_eq!(contract.redeem_listing_voucher(&voucher, signer, signature), Ok(seller));
+            assert!(contract.is_nonce_used(seller, 7));
+            assert_eq!(contract.redeem_listing_voucher(&voucher, signer, signature), Err(Error::VoucherUsed));
+        }
+
+        // The fixed key of the seller signing listing vouchers in tests.
+        fn voucher_secret_key() -> secp256k1::SecretKey {
+            secp256k1::SecretKey::from_slice(&[0x42; 32]).expect("valid secret key")
+        }
+
+        pub(super) fn voucher_signer_key() -> SignerKey {
+            secp256k1::PublicKey::from_secret_key(secp256k1::SECP256K1, &voucher_secret_key()).serialize()
+        }
+
+        // The account of the voucher key, which the marketplace attributes voucher listings to.
+        pub(super) fn voucher_seller() -> AccountId {
+            let mut hash = [0u8; 32];
+            ink::env::hash_bytes::<Blake2x256>(&voucher_signer_key(), &mut hash);
+            AccountId::from(hash)
+        }
+
+        // Signs a listing voucher the way a seller's wallet would.
+        pub(super) fn sign_listing_voucher(marketplace: AccountId, voucher: &ListingVoucher) -> VoucherSignature {
+            let mut hash = [0u8; 32];
+            ink::env::hash_encoded::<Blake2x256, _>(&(marketplace, voucher), &mut hash);
+
+            let message = secp256k1::Message::from_slice(&hash).expect("32 byte hash");
+            let (recovery_id, compact) = secp256k1::SECP256K1
+                .sign_ecdsa_recoverable(&message, &voucher_secret_key())
+                .serialize_compact();
+
+            let mut signature = [0u8; 65];
+            signature[..64].copy_from_slice(&compact);
+            signature[64] = recovery_id.to_i32() as u8;
+            signature
+        }
+    }
+
+    /// End-to-end tests
+    #[cfg(all(test, feature = "e2e-tests"))]
+    mod e2e_tests {
+        use super::*;
+        use ink_e2e::build_message;
+        use patient::PatientRef;
+        use psp22_mock::{
+            Psp22MockRef,
+            PSP22
+        };
+        use epr::epr::EprRef;
+        use reentrant_mock::ReentrantMockRef;
+        use super::tests::{
+            sign_listing_voucher,
+            voucher_seller,
+            voucher_signer_key
+        };
+
+        type E2EResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+        #[ink_e2e::test(additional_contracts = "../patient/Cargo.toml psp22_mock/Cargo.toml")]
+        async fn buy_with_psp22(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let alice = ink_e2e::account_id(ink_e2e::AccountKeyring::Alice);
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
+
+            // Alice deploys the NFT contract and mints token 1, Bob deploys the token and holds its supply.
+            let nft = client
+                .instantiate("patient", &ink_e2e::alice(), PatientRef::new(String::from("HealthDot"), String::from("HDOT"), String::new(), String::new()), 0, None)
+                .await
+                .expect("instantiating `patient` failed")
+                .account_id;
+            let token = client
+                .instantiate("psp22_mock", &ink_e2e::bob(), Psp22MockRef::new(1_000), 0, None)
+                .await
+                .expect("instantiating `psp22_mock` failed")
+                .account_id;
+            let marketplace = client
+                .instantiate("marketplace", &ink_e2e::alice(), NftMarketplaceRef::new(nft.clone()), 0, None)
+                .await
+                .expect("instantiating `marketplace` failed")
+                .account_id;
+
+            let mint = build_message::<PatientRef>(nft.clone()).call(|patient| patient.mint(1));
+            client.call(&ink_e2e::alice(), mint, 0, None).await.expect("mint failed");
+            let approve_nft = build_message::<PatientRef>(nft.clone())
+                .call(|patient| patient.set_approval_for_all(marketplace.clone(), true));
+            client.call(&ink_e2e::alice(), approve_nft, 0, None).await.expect("nft approval failed");
+
+            let register = build_message::<NftMarketplaceRef>(marketplace.clone())
+                .call(|market| market.set_payment_token(Some(token.clone())));
+            client.call(&ink_e2e::alice(), register, 0, None).await.expect("set_payment_token failed");
+            let no_hold = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.set_hold_period(0));
+            client.call(&ink_e2e::alice(), no_hold, 0, None).await.expect("set_hold_period failed");
+            let list = build_message::<NftMarketplaceRef>(marketplace.clone())
+                .call(|market| market.list(1, 100, Currency::Psp22, Vec::new(), None));
+            client.call(&ink_e2e::alice(), list, 0, None).await.expect("list failed");
+
+            // Without an allowance the sale aborts and the listing stays.
+            let buy = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.buy(1, None));
+            let without_allowance = client.call_dry_run(&ink_e2e::bob(), &buy, 0, None).await.return_value();
+            assert_eq!(without_allowance, Err(Error::Psp22TransferFailed));
+
+            // Bob approves the marketplace on the token and buys.
+            let approve_token = build_message::<Psp22MockRef>(token.clone())
+                .call(|psp22| psp22.approve(marketplace.clone(), 100));
+            client.call(&ink_e2e::bob(), approve_token, 0, None).await.expect("token approval failed");
+            client.call(&ink_e2e::bob(), buy, 0, None).await.expect("buy failed");
+
+            let owner = build_message::<PatientRef>(nft.clone()).call(|patient| patient.owner_of(1));
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &owner, 0, None).await.return_value(), Some(bob));
+
+            // Alice withdraws her proceeds in the token.
+            let withdraw = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.withdraw_psp22());
+            client.call(&ink_e2e::alice(), withdraw, 0, None).await.expect("withdraw_psp22 failed");
+            let balance = build_message::<Psp22MockRef>(token.clone())
+                .call(|psp22| psp22.balance_of(alice));
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &balance, 0, None).await.return_value(), 100);
+
+            Ok(())
+        }
+
+        #[ink_e2e::test(additional_contracts = "../patient/Cargo.toml reentrant_mock/Cargo.toml")]
+        async fn reentrant_payment_token_cannot_buy_twice(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let alice = ink_e2e::account_id(ink_e2e::AccountKeyring::Alice);
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
+
+            let nft = client
+                .instantiate("patient", &ink_e2e::alice(), PatientRef::new(String::from("HealthDot"), String::from("HDOT"), String::new(), String::new()), 0, None)
+                .await
+                .expect("instantiating `patient` failed")
+                .account_id;
+            let token = client
+                .instantiate("reentrant_mock", &ink_e2e::alice(), ReentrantMockRef::new(), 0, None)
+                .await
+                .expect("instantiating `reentrant_mock` failed")
+                .account_id;
+            let marketplace = client
+                .instantiate("marketplace", &ink_e2e::alice(), NftMarketplaceRef::new(nft.clone()), 0, None)
+                .await
+                .expect("instantiating `marketplace` failed")
+                .account_id;
+
+            let mint = build_message::<PatientRef>(nft.clone()).call(|patient| patient.mint(1));
+            client.call(&ink_e2e::alice(), mint, 0, None).await.expect("mint failed");
+            let approve = build_message::<PatientRef>(nft.clone())
+                .call(|patient| patient.set_approval_for_all(marketplace.clone(), true));
+            client.call(&ink_e2e::alice(), approve, 0, None).await.expect("approval failed");
+            let register = build_message::<NftMarketplaceRef>(marketplace.clone())
+                .call(|market| market.set_payment_token(Some(token.clone())));
+            client.call(&ink_e2e::alice(), register, 0, None).await.expect("set_payment_token failed");
+            let no_hold = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.set_hold_period(0));
+            client.call(&ink_e2e::alice(), no_hold, 0, None).await.expect("set_hold_period failed");
+            let list = build_message::<NftMarketplaceRef>(marketplace.clone())
+                .call(|market| market.list(1, 100, Currency::Psp22, Vec::new(), None));
+            client.call(&ink_e2e::alice(), list, 0, None).await.expect("list failed");
+
+            // The token calls `buy` again while the marketplace pulls the payment.
+            let arm = build_message::<ReentrantMockRef>(token.clone())
+                .call(|mock| mock.set_target(marketplace.clone(), 1));
+            client.call(&ink_e2e::alice(), arm, 0, None).await.expect("set_target failed");
+            let buy = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.buy(1, None));
+            client.call(&ink_e2e::bob(), buy, 0, None).await.expect("buy failed");
+
+            // The nested call was refused and the sale settled exactly once.
+            let reentered = build_message::<ReentrantMockRef>(token.clone()).call(|mock| mock.reentered());
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &reentered, 0, None).await.return_value(), Some(false));
+            let owner = build_message::<PatientRef>(nft.clone()).call(|patient| patient.owner_of(1));
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &owner, 0, None).await.return_value(), Some(bob));
+            let listing = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.listing(1));
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &listing, 0, None).await.return_value(), None);
+            let pending = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.pending_psp22_of(alice));
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &pending, 0, None).await.return_value(), 100);
+
+            Ok(())
+        }
+
+        /// Deploys the EPR contract, with its Patient child, and a marketplace for the child's tokens.
+        /// Bob is a patient who turns his consent for Charlie into an access token, which Charlie lists for 100.
+        /// Returns the EPR contract, the Patient contract, the marketplace and the access token.
+        async fn access_token_setup(
+            client: &mut ink_e2e::Client<ink_e2e::PolkadotConfig, ink::env::DefaultEnvironment>
+        ) -> (AccountId, AccountId, AccountId, TokenId) {
+            let alice = ink_e2e::account_id(ink_e2e::AccountKeyring::Alice);
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
+            let charlie = ink_e2e::account_id(ink_e2e::AccountKeyring::Charlie);
+
+            let patient_hash = client
+                .upload("patient", &ink_e2e::alice(), None)
+                .await
+                .expect("uploading `patient` failed")
+                .code_hash;
+            let epr = client
+                .instantiate("epr", &ink_e2e::alice(), EprRef::new(patient_hash, String::from("HealthDot"), String::from("HDOT"), Vec::new()), 0, None)
+                .await
+                .expect("instantiating `epr` failed")
+                .account_id;
+            let grant = build_message::<EprRef>(epr.clone()).call(|epr| epr.add_user_with_permissions(alice, true));
+            client.call(&ink_e2e::alice(), grant, 0, None).await.expect("grant failed");
+            let create = build_message::<EprRef>(epr.clone()).call(|epr| epr.create_patient(alice, bob));
+            client.call(&ink_e2e::alice(), create, 0, None).await.expect("create_patient failed");
+
+            let template = build_message::<EprRef>(epr.clone())
+                .call(|epr| epr.add_template(String::from("ipfs://terms"), Hash::from([1; 32]), 1));
+            client.call(&ink_e2e::alice(), template, 0, None).await.expect("add_template failed");
+            let consent = build_message::<EprRef>(epr.clone())
+                .call(|epr| epr.grant_consent(charlie, 1, 1, None));
+            client.call(&ink_e2e::bob(), consent, 0, None).await.expect("grant_consent failed");
+            let mint = build_message::<EprRef>(epr.clone()).call(|epr| epr.mint_consent_token(charlie));
+            let token_id = client
+                .call(&ink_e2e::bob(), mint, 0, None)
+                .await
+                .expect("mint_consent_token failed")
+                .return_value()
+                .expect("minting the consent token failed");
+
+            let nft = build_message::<EprRef>(epr.clone()).call(|epr| epr.patient_contract());
+            let nft = client.call_dry_run(&ink_e2e::alice(), &nft, 0, None).await.return_value();
+            let marketplace = client
+                .instantiate("marketplace", &ink_e2e::alice(), NftMarketplaceRef::new(nft.clone()), 0, None)
+                .await
+                .expect("instantiating `marketplace` failed")
+                .account_id;
+            let set_epr = build_message::<NftMarketplaceRef>(marketplace.clone())
+                .call(|market| market.set_epr(Some(epr.clone())));
+            client.call(&ink_e2e::alice(), set_epr, 0, None).await.expect("set_epr failed");
+
+            let approve = build_message::<PatientRef>(nft.clone())
+                .call(|patient| patient.approve(marketplace.clone(), token_id, None));
+            client.call(&ink_e2e::charlie(), approve, 0, None).await.expect("approve failed");
+            let list = build_message::<NftMarketplaceRef>(marketplace.clone())
+                .call(|market| market.list(token_id, 100, Currency::Native, Vec::new(), None));
+            client.call(&ink_e2e::charlie(), list, 0, None).await.expect("list failed");
+
+            (epr, nft, marketplace, token_id)
+        }
+
+        #[ink_e2e::test(additional_contracts = "../Cargo.toml ../patient/Cargo.toml")]
+        async fn access_token_resale_moves_the_consent(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
+            let charlie = ink_e2e::account_id(ink_e2e::AccountKeyring::Charlie);
+            let dave = ink_e2e::account_id(ink_e2e::AccountKeyring::Dave);
+            let (epr, nft, marketplace, token_id) = access_token_setup(&mut client).await;
+
+            let register = build_message::<EprRef>(epr.clone()).call(|epr| epr.set_marketplace(Some(marketplace.clone())));
+            client.call(&ink_e2e::alice(), register, 0, None).await.expect("set_marketplace failed");
+
+            let buy = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.buy(token_id, None));
+            client.call(&ink_e2e::dave(), buy, 100, None).await.expect("buy failed");
+
+            let owner = build_message::<PatientRef>(nft.clone()).call(|patient| patient.owner_of(token_id));
+            assert_eq!(client.call_dry_run(&ink_e2e::dave(), &owner, 0, None).await.return_value(), Some(dave));
+            let old_consent = build_message::<EprRef>(epr.clone()).call(|epr| epr.consent_receipt(bob, charlie));
+            assert!(client.call_dry_run(&ink_e2e::dave(), &old_consent, 0, None).await.return_value().is_none());
+            let new_consent = build_message::<EprRef>(epr.clone()).call(|epr| epr.consent_receipt(bob, dave));
+            assert!(client.call_dry_run(&ink_e2e::dave(), &new_consent, 0, None).await.return_value().is_some());
+
+            Ok(())
+        }
+
+        #[ink_e2e::test(additional_contracts = "../Cargo.toml ../patient/Cargo.toml")]
+        async fn access_token_resale_rolls_back(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
+            let charlie = ink_e2e::account_id(ink_e2e::AccountKeyring::Charlie);
+            // The EPR contract never registers the marketplace, so the reassignment is refused.
+            let (epr, nft, marketplace, token_id) = access_token_setup(&mut client).await;
+
+            let buy = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.buy(token_id, None));
+            let dry_run = client.call_dry_run(&ink_e2e::dave(), &buy, 100, None).await.return_value();
+            assert_eq!(dry_run, Err(Error::ConsentReassignFailed));
+            assert!(client.call(&ink_e2e::dave(), buy, 100, None).await.is_err());
+
+            // The token, the listing and the consent are untouched.
+            let owner = build_message::<PatientRef>(nft.clone()).call(|patient| patient.owner_of(token_id));
+            assert_eq!(client.call_dry_run(&ink_e2e::dave(), &owner, 0, None).await.return_value(), Some(charlie));
+            let listing = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.listing(token_id));
+            assert!(client.call_dry_run(&ink_e2e::dave(), &listing, 0, None).await.return_value().is_some());
+            let consent = build_message::<EprRef>(epr.clone()).call(|epr| epr.consent_receipt(bob, charlie));
+            assert!(client.call_dry_run(&ink_e2e::dave(), &consent, 0, None).await.return_value().is_some());
+
+            Ok(())
+        }
+
+        #[ink_e2e::test(additional_contracts = "../patient/Cargo.toml")]
+        async fn collection_owner_can_pause_its_collection(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            // Bob's institution owns the NFT contract, Alice runs the marketplace.
+            let nft = client
+                .instantiate("patient", &ink_e2e::bob(), PatientRef::new(String::from("HealthDot"), String::from("HDOT"), String::new(), String::new()), 0, None)
+                .await
+                .expect("instantiating `patient` failed")
+                .account_id;
+            let marketplace = client
+                .instantiate("marketplace", &ink_e2e::alice(), NftMarketplaceRef::new(nft.clone()), 0, None)
+                .await
+                .expect("instantiating `marketplace` failed")
+                .account_id;
+
+            // Charlie owns neither, and cannot pause.
+            let pause = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.pause_collection(nft.clone()));
+            assert_eq!(client.call_dry_run(&ink_e2e::charlie(), &pause, 0, None).await.return_value(), Err(Error::NotAdmin));
+            client.call(&ink_e2e::bob(), pause, 0, None).await.expect("pause failed");
+
+            let mint = build_message::<PatientRef>(nft.clone()).call(|patient| patient.mint(1));
+            client.call(&ink_e2e::bob(), mint, 0, None).await.expect("mint failed");
+            let list = build_message::<NftMarketplaceRef>(marketplace.clone())
+                .call(|market| market.list(1, 100, Currency::Native, Vec::new(), None));
+            assert_eq!(client.call_dry_run(&ink_e2e::bob(), &list, 0, None).await.return_value(), Err(Error::CollectionPaused));
+
+            let unpause = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.unpause_collection(nft.clone()));
+            client.call(&ink_e2e::bob(), unpause, 0, None).await.expect("unpause failed");
+            let approve = build_message::<PatientRef>(nft.clone()).call(|patient| patient.approve(marketplace.clone(), 1, None));
+            client.call(&ink_e2e::bob(), approve, 0, None).await.expect("approve failed");
+            client.call(&ink_e2e::bob(), list, 0, None).await.expect("list failed");
+
+            Ok(())
+        }
+
+        #[ink_e2e::test(additional_contracts = "../patient/Cargo.toml")]
+        async fn badges_of_non_transferable_categories_cannot_be_listed(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let nft = client
+                .instantiate("patient", &ink_e2e::alice(), PatientRef::new(String::from("HealthDot"), String::from("HDOT"), String::new(), String::new()), 0, None)
+                .await
+                .expect("instantiating `patient` failed")
+                .account_id;
+            let marketplace = client
+                .instantiate("marketplace", &ink_e2e::alice(), NftMarketplaceRef::new(nft.clone()), 0, None)
+                .await
+                .expect("instantiating `marketplace` failed")
+                .account_id;
+
+            // Alice makes category 3 a badge category, mints herself a badge and approves the marketplace for it.
+            let badges = build_message::<PatientRef>(nft.clone()).call(|patient| patient.set_category_transferable(3, false));
+            client.call(&ink_e2e::alice(), badges, 0, None).await.expect("set_category_transferable failed");
+            let mint = build_message::<PatientRef>(nft.clone()).call(|patient| patient.mint_categorized(1, 3));
+            client.call(&ink_e2e::alice(), mint, 0, None).await.expect("mint failed");
+            let approve = build_message::<PatientRef>(nft.clone()).call(|patient| patient.approve(marketplace.clone(), 1, None));
+            client.call(&ink_e2e::alice(), approve, 0, None).await.expect("approve failed");
+
+            let list = build_message::<NftMarketplaceRef>(marketplace.clone())
+                .call(|market| market.list(1, 100, Currency::Native, Vec::new(), None));
+            let result = client.call_dry_run(&ink_e2e::alice(), &list, 0, None).await.return_value();
+            assert_eq!(result, Err(Error::TokenNotTransferable));
+
+            Ok(())
+        }
+
+        #[ink_e2e::test(additional_contracts = "../patient/Cargo.toml")]
+        async fn listing_requires_and_rechecks_approval(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let nft = client
+                .instantiate("patient", &ink_e2e::alice(), PatientRef::new(String::from("HealthDot"), String::from("HDOT"), String::new(), String::new()), 0, None)
+                .await
+                .expect("instantiating `patient` failed")
+                .account_id;
+            let marketplace = client
+                .instantiate("marketplace", &ink_e2e::alice(), NftMarketplaceRef::new(nft.clone()), 0, None)
+                .await
+                .expect("instantiating `marketplace` failed")
+                .account_id;
+            let mint = build_message::<PatientRef>(nft.clone()).call(|patient| patient.mint(1));
+            client.call(&ink_e2e::alice(), mint, 0, None).await.expect("mint failed");
+
+            // Listing without approving the marketplace is rejected.
+            let list = build_message::<NftMarketplaceRef>(marketplace.clone())
+                .call(|market| market.list(1, 100, Currency::Native, Vec::new(), None));
+            let unapproved = client.call_dry_run(&ink_e2e::alice(), &list, 0, None).await.return_value();
+            assert_eq!(unapproved, Err(Error::MarketplaceNotApproved));
+
+            // Once approved for the token, the listing goes through.
+            let approve = build_message::<PatientRef>(nft.clone())
+                .call(|patient| patient.approve(marketplace.clone(), 1, None));
+            client.call(&ink_e2e::alice(), approve, 0, None).await.expect("approve failed");
+            client.call(&ink_e2e::alice(), list, 0, None).await.expect("list failed");
+
+            // Alice revokes the approval; Bob's purchase delists the token and refunds him.
+            let revoke = build_message::<PatientRef>(nft.clone()).call(|patient| patient.revoke_approval(1));
+            client.call(&ink_e2e::alice(), revoke, 0, None).await.expect("revoke failed");
+            let buy = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.buy(1, None));
+            let result = client.call(&ink_e2e::bob(), buy, 100, None).await.expect("buy failed");
+            assert!(result.contains_event("Contracts", "ContractEmitted"));
+
+            let listing = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.listing(1));
+            assert_eq!(client.call_dry_run(&ink_e2e::bob(), &listing, 0, None).await.return_value(), None);
+            let owner = build_message::<PatientRef>(nft.clone()).call(|patient| patient.owner_of(1));
+            assert_eq!(
+                client.call_dry_run(&ink_e2e::bob(), &owner, 0, None).await.return_value(),
+                Some(ink_e2e::account_id(ink_e2e::AccountKeyring::Alice))
+            );
+            let pending = build_message::<NftMarketplaceRef>(marketplace.clone())
+                .call(|market| market.pending_of(ink_e2e::account_id(ink_e2e::AccountKeyring::Alice)));
+            assert_eq!(client.call_dry_run(&ink_e2e::bob(), &pending, 0, None).await.return_value(), 0);
+
+            Ok(())
+        }
+
+        #[ink_e2e::test(additional_contracts = "../patient/Cargo.toml")]
+        async fn reserved_listing_sells_to_the_named_buyer(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
+
+            let nft = client
+                .instantiate("patient", &ink_e2e::alice(), PatientRef::new(String::from("HealthDot"), String::from("HDOT"), String::new(), String::new()), 0, None)
+                .await
+                .expect("instantiating `patient` failed")
+                .account_id;
+            let marketplace = client
+                .instantiate("marketplace", &ink_e2e::alice(), NftMarketplaceRef::new(nft.clone()), 0, None)
+                .await
+                .expect("instantiating `marketplace` failed")
+                .account_id;
+            let mint = build_message::<PatientRef>(nft.clone()).call(|patient| patient.mint(1));
+            client.call(&ink_e2e::alice(), mint, 0, None).await.expect("mint failed");
+            let approve = build_message::<PatientRef>(nft.clone())
+                .call(|patient| patient.approve(marketplace.clone(), 1, None));
+            client.call(&ink_e2e::alice(), approve, 0, None).await.expect("approve failed");
+            let list = build_message::<NftMarketplaceRef>(marketplace.clone())
+                .call(|market| market.list(1, 100, Currency::Native, Vec::new(), Some(bob)));
+            client.call(&ink_e2e::alice(), list, 0, None).await.expect("list failed");
+
+            // Charlie is turned away, Bob buys the token.
+            let buy = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.buy(1, None));
+            let stranger = client.call_dry_run(&ink_e2e::charlie(), &buy, 100, None).await.return_value();
+            assert_eq!(stranger, Err(Error::ReservedListing));
+            client.call(&ink_e2e::bob(), buy, 100, None).await.expect("buy failed");
+
+            let owner = build_message::<PatientRef>(nft.clone()).call(|patient| patient.owner_of(1));
+            assert_eq!(client.call_dry_run(&ink_e2e::bob(), &owner, 0, None).await.return_value(), Some(bob));
+
+            Ok(())
+        }
+
+        #[ink_e2e::test(additional_contracts = "../patient/Cargo.toml")]
+        async fn buy_batch_skips_delisted_ids_and_refunds_the_rest(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let alice = ink_e2e::account_id(ink_e2e::AccountKeyring::Alice);
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
+
+            let nft = client
+                .instantiate("patient", &ink_e2e::alice(), PatientRef::new(String::from("HealthDot"), String::from("HDOT"), String::new(), String::new()), 0, None)
+                .await
+                .expect("instantiating `patient` failed")
+                .account_id;
+            let marketplace = client
+                .instantiate("marketplace", &ink_e2e::alice(), NftMarketplaceRef::new(nft.clone()), 0, None)
+                .await
+                .expect("instantiating `marketplace` failed")
+                .account_id;
+            let approve = build_message::<PatientRef>(nft.clone())
+                .call(|patient| patient.set_approval_for_all(marketplace.clone(), true));
+            client.call(&ink_e2e::alice(), approve, 0, None).await.expect("approval failed");
+            for id in [1, 2, 3] {
+                let mint = build_message::<PatientRef>(nft.clone()).call(|patient| patient.mint(id));
+                client.call(&ink_e2e::alice(), mint, 0, None).await.expect("mint failed");
+                let list = build_message::<NftMarketplaceRef>(marketplace.clone())
+                    .call(|market| market.list(id, 100, Currency::Native, Vec::new(), None));
+                client.call(&ink_e2e::alice(), list, 0, None).await.expect("list failed");
+            }
+            let delist = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.delist(2));
+            client.call(&ink_e2e::alice(), delist, 0, None).await.expect("delist failed");
+            let no_hold = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.set_hold_period(0));
+            client.call(&ink_e2e::alice(), no_hold, 0, None).await.expect("set_hold_period failed");
+
+            // Bob sends enough for all three; the delisted id is skipped and its share refunded.
+            let held_before = client.balance(marketplace.clone()).await.expect("balance failed");
+            let buy = build_message::<NftMarketplaceRef>(marketplace.clone())
+                .call(|market| market.buy_batch(vec![1, 2, 3], 300));
+            let results = client.call(&ink_e2e::bob(), buy, 300, None).await.expect("buy_batch failed").return_value();
+            assert_eq!(results, Ok(vec![Ok(()), Err(Error::NotListed), Ok(())]));
+
+            for (id, owner) in [(1, bob), (2, alice), (3, bob)] {
+                let owner_of = build_message::<PatientRef>(nft.clone()).call(|patient| patient.owner_of(id));
+                assert_eq!(client.call_dry_run(&ink_e2e::bob(), &owner_of, 0, None).await.return_value(), Some(owner));
+            }
+            let pending = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.pending_of(alice));
+            assert_eq!(client.call_dry_run(&ink_e2e::bob(), &pending, 0, None).await.return_value(), 200);
+            // The marketplace only kept what was spent.
+            let held_after = client.balance(marketplace.clone()).await.expect("balance failed");
+            assert_eq!(held_after, held_before + 200);
+
+            Ok(())
+        }
+
+        #[ink_e2e::test(additional_contracts = "../patient/Cargo.toml")]
+        async fn selling_the_floor_listing_updates_the_stats(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let nft = client
+                .instantiate("patient", &ink_e2e::alice(), PatientRef::new(String::from("HealthDot"), String::from("HDOT"), String::new(), String::new()), 0, None)
+                .await
+                .expect("instantiating `patient` failed")
+                .account_id;
+            let marketplace = client
+                .instantiate("marketplace", &ink_e2e::alice(), NftMarketplaceRef::new(nft.clone()), 0, None)
+                .await
+                .expect("instantiating `marketplace` failed")
+                .account_id;
+            let approve = build_message::<PatientRef>(nft.clone())
+                .call(|patient| patient.set_approval_for_all(marketplace.clone(), true));
+            client.call(&ink_e2e::alice(), approve, 0, None).await.expect("approval failed");
+            for (id, price) in [(1, 300), (2, 100), (3, 200)] {
+                let mint = build_message::<PatientRef>(nft.clone()).call(|patient| patient.mint(id));
+                client.call(&ink_e2e::alice(), mint, 0, None).await.expect("mint failed");
+                let list = build_message::<NftMarketplaceRef>(marketplace.clone())
+                    .call(|market| market.list(id, price, Currency::Native, Vec::new(), None));
+                client.call(&ink_e2e::alice(), list, 0, None).await.expect("list failed");
+            }
+            let floor = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.floor_price(nft.clone()));
+            assert_eq!(client.call_dry_run(&ink_e2e::bob(), &floor, 0, None).await.return_value(), Some(100));
+
+            // Bob buys the floor listing and then the next one.
+            for (id, price) in [(2, 100), (3, 200)] {
+                let buy = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.buy(id, None));
+                client.call(&ink_e2e::bob(), buy, price, None).await.expect("buy failed");
+            }
+            assert_eq!(client.call_dry_run(&ink_e2e::bob(), &floor, 0, None).await.return_value(), Some(300));
+            let stats = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.collection_stats(nft.clone()));
+            assert_eq!(
+                client.call_dry_run(&ink_e2e::bob(), &stats, 0, None).await.return_value(),
+                Stats { total_volume: 300, psp22_volume: 0, sale_count: 2, listing_count: 1 }
+            );
+
+            Ok(())
+        }
+
+        #[ink_e2e::test(additional_contracts = "../patient/Cargo.toml")]
+        async fn buy_rejects_a_token_whose_uri_changed(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
+
+            let nft = client
+                .instantiate("patient", &ink_e2e::alice(), PatientRef::new(String::from("HealthDot"), String::from("HDOT"), String::new(), String::new()), 0, None)
+                .await
+                .expect("instantiating `patient` failed")
+                .account_id;
+            let marketplace = client
+                .instantiate("marketplace", &ink_e2e::alice(), NftMarketplaceRef::new(nft.clone()), 0, None)
+                .await
+                .expect("instantiating `marketplace` failed")
+                .account_id;
+            let mint = build_message::<PatientRef>(nft.clone()).call(|patient| patient.mint(1));
+            client.call(&ink_e2e::alice(), mint, 0, None).await.expect("mint failed");
+            let set_uri = build_message::<PatientRef>(nft.clone())
+                .call(|patient| patient.set_token_uri(1, String::from("ipfs://record-v1")));
+            client.call(&ink_e2e::alice(), set_uri, 0, None).await.expect("set_token_uri failed");
+            let approve = build_message::<PatientRef>(nft.clone())
+                .call(|patient| patient.approve(marketplace.clone(), 1, None));
+            client.call(&ink_e2e::alice(), approve, 0, None).await.expect("approve failed");
+            let list = build_message::<NftMarketplaceRef>(marketplace.clone())
+                .call(|market| market.list(1, 100, Currency::Native, Vec::new(), None));
+            client.call(&ink_e2e::alice(), list, 0, None).await.expect("list failed");
+
+            // Bob evaluates the listing; then Alice swaps the record pointer.
+            let listing = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.listing(1));
+            let seen = client
+                .call_dry_run(&ink_e2e::bob(), &listing, 0, None)
+                .await
+                .return_value()
+                .expect("token is listed")
+                .uri_hash;
+            let swap = build_message::<PatientRef>(nft.clone())
+                .call(|patient| patient.set_token_uri(1, String::from("ipfs://record-v2")));
+            client.call(&ink_e2e::alice(), swap, 0, None).await.expect("set_token_uri failed");
+
+            // Holding buy to what he saw, Bob's purchase is rejected and he keeps his funds.
+            let buy = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.buy(1, Some(seen)));
+            let stale = client.call_dry_run(&ink_e2e::bob(), &buy, 100, None).await.return_value();
+            assert_eq!(stale, Err(Error::MetadataChanged));
+            assert!(client.call(&ink_e2e::bob(), buy, 100, None).await.is_err());
+
+            // With the current hash the purchase goes through.
+            let current = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.hash_of_uri(1));
+            let current = client.call_dry_run(&ink_e2e::bob(), &current, 0, None).await.return_value();
+            assert_ne!(current, seen);
+            let buy = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.buy(1, Some(current)));
+            client.call(&ink_e2e::bob(), buy, 100, None).await.expect("buy failed");
+            let owner = build_message::<PatientRef>(nft.clone()).call(|patient| patient.owner_of(1));
+            assert_eq!(client.call_dry_run(&ink_e2e::bob(), &owner, 0, None).await.return_value(), Some(bob));
+
+            Ok(())
+        }
+
+        #[ink_e2e::test(additional_contracts = "../patient/Cargo.toml")]
+        async fn buying_a_token_moved_elsewhere_refunds_and_delists(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let alice = ink_e2e::account_id(ink_e2e::AccountKeyring::Alice);
+            let charlie = ink_e2e::account_id(ink_e2e::AccountKeyring::Charlie);
+
+            let nft = client
+                .instantiate("patient", &ink_e2e::alice(), PatientRef::new(String::from("HealthDot"), String::from("HDOT"), String::new(), String::new()), 0, None)
+                .await
+                .expect("instantiating `patient` failed")
+                .account_id;
+            let marketplace = client
+                .instantiate("marketplace", &ink_e2e::alice(), NftMarketplaceRef::new(nft.clone()), 0, None)
+                .await
+                .expect("instantiating `marketplace` failed")
+                .account_id;
+            for id in [1, 2] {
+                let mint = build_message::<PatientRef>(nft.clone()).call(|patient| patient.mint(id));
+                client.call(&ink_e2e::alice(), mint, 0, None).await.expect("mint failed");
+            }
+            let approve = build_message::<PatientRef>(nft.clone())
+                .call(|patient| patient.set_approval_for_all(marketplace.clone(), true));
+            client.call(&ink_e2e::alice(), approve, 0, None).await.expect("approval failed");
+            for id in [1, 2] {
+                let list = build_message::<NftMarketplaceRef>(marketplace.clone())
+                    .call(|market| market.list(id, 100, Currency::Native, Vec::new(), None));
+                client.call(&ink_e2e::alice(), list, 0, None).await.expect("list failed");
+            }
+
+            // Alice gives both tokens to Charlie directly on the NFT contract.
+            for id in [1, 2] {
+                let transfer = build_message::<PatientRef>(nft.clone()).call(|patient| patient.transfer(charlie.clone(), id));
+                client.call(&ink_e2e::alice(), transfer, 0, None).await.expect("transfer failed");
+            }
+
+            // Bob's purchase is refunded and the listing removed; the marketplace keeps none of his payment.
+            let held = client.balance(marketplace.clone()).await.expect("reading the balance failed");
+            let buy = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.buy(1, None));
+            client.call(&ink_e2e::bob(), buy, 100, None).await.expect("buy failed");
+            assert_eq!(client.balance(marketplace.clone()).await.expect("reading the balance failed"), held);
+
+            let listing = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.listing(1));
+            assert_eq!(client.call_dry_run(&ink_e2e::bob(), &listing, 0, None).await.return_value(), None);
+            let owner = build_message::<PatientRef>(nft.clone()).call(|patient| patient.owner_of(1));
+            assert_eq!(client.call_dry_run(&ink_e2e::bob(), &owner, 0, None).await.return_value(), Some(charlie));
+            let pending = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.pending_of(alice));
+            assert_eq!(client.call_dry_run(&ink_e2e::bob(), &pending, 0, None).await.return_value(), 0);
+
+            // Anyone can clear the other stale listing.
+            let validate = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.validate_listing(2));
+            let result = client.call(&ink_e2e::bob(), validate, 0, None).await.expect("validate_listing failed");
+            assert_eq!(result.return_value(), Ok(Some(DelistReason::StaleOwner)));
+            let listing = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.listing(2));
+            assert_eq!(client.call_dry_run(&ink_e2e::bob(), &listing, 0, None).await.return_value(), None);
+
+            Ok(())
+        }
+
+        #[ink_e2e::test(additional_contracts = "../patient/Cargo.toml")]
+        async fn relisting_rechecks_the_approval(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let nft = client
+                .instantiate("patient", &ink_e2e::alice(), PatientRef::new(String::from("HealthDot"), String::from("HDOT"), String::new(), String::new()), 0, None)
+                .await
+                .expect("instantiating `patient` failed")
+                .account_id;
+            let marketplace = client
+                .instantiate("marketplace", &ink_e2e::alice(), NftMarketplaceRef::new(nft.clone()), 0, None)
+                .await
+                .expect("instantiating `marketplace` failed")
+                .account_id;
+            let mint = build_message::<PatientRef>(nft.clone()).call(|patient| patient.mint(1));
+            client.call(&ink_e2e::alice(), mint, 0, None).await.expect("mint failed");
+            let approve = build_message::<PatientRef>(nft.clone())
+                .call(|patient| patient.set_approval_for_all(marketplace.clone(), true));
+            client.call(&ink_e2e::alice(), approve, 0, None).await.expect("approve failed");
+            let list = build_message::<NftMarketplaceRef>(marketplace.clone())
+                .call(|market| market.list(1, 100, Currency::Native, Vec::new(), None));
+            client.call(&ink_e2e::alice(), list, 0, None).await.expect("list failed");
+
+            // The node stamps blocks with the wall clock, so a listing extended a few seconds ahead soon expires.
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .expect("clock before the epoch")
+                .as_millis() as Timestamp;
+            let extend = build_message::<NftMarketplaceRef>(marketplace.clone())
+                .call(|market| market.extend_listing(1, now + 3_000));
+            client.call(&ink_e2e::alice(), extend, 0, None).await.expect("extend_listing failed");
+            std::thread::sleep(std::time::Duration::from_secs(6));
+            let mint = build_message::<PatientRef>(nft.clone()).call(|patient| patient.mint(2));
+            client.call(&ink_e2e::alice(), mint, 0, None).await.expect("mint failed");
+
+            // Without the approval, the expired listing cannot be relisted.
+            let revoke = build_message::<PatientRef>(nft.clone())
+                .call(|patient| patient.set_approval_for_all(marketplace.clone(), false));
+            client.call(&ink_e2e::alice(), revoke, 0, None).await.expect("revoke failed");
+            let relist = build_message::<NftMarketplaceRef>(marketplace.clone())
+                .call(|market| market.relist(1, 80, now + 3_600_000));
+            let unapproved = client.call_dry_run(&ink_e2e::alice(), &relist, 0, None).await.return_value();
+            assert_eq!(unapproved, Err(Error::MarketplaceNotApproved));
+
+            // Approved again, it is relisted in place at the new price and Bob can buy it.
+            let approve = build_message::<PatientRef>(nft.clone())
+                .call(|patient| patient.set_approval_for_all(marketplace.clone(), true));
+            client.call(&ink_e2e::alice(), approve, 0, None).await.expect("approve failed");
+            client.call(&ink_e2e::alice(), relist, 0, None).await.expect("relist failed");
+            let set_hold = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.set_hold_period(0));
+            client.call(&ink_e2e::alice(), set_hold, 0, None).await.expect("set_hold_period failed");
+            let buy = build_message::<NftMarketplaceRef>(marketplace.clone()).call(|market| market.buy(1, None));
+            client.call(&ink_e2e::bob(), buy, 80, None).await.expect("buy failed");
+            let owner = build_message::<PatientRef>(nft.clone()).call(|patient| patient.owner_of(1));
+            assert_eq!(
+                client.call_dry_run(&ink_e2e::bob(), &owner, 0, None).await.return_value(),
+                Some(ink_e2e::account_id(ink_e2e::AccountKeyring::Bob))
+            );
+
+            Ok(())
+        }
+
+        #[ink_e2e::test(additional_contracts = "../patient/Cargo.toml")]
+        async fn listing_vouchers_need_the_token_owner(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let nft = client
+                .instantiate("patient", &ink_e2e::alice(), PatientRef::new(String::from("HealthDot"), String::from("HDOT"), String::new(), String::new()), 0, None)
+                .await
+                .expect("instantiating `patient` failed")
+                .account_id;
+            let marketplace = client
+                .instantiate("marketplace", &ink_e2e::alice(), NftMarketplaceRef::new(nft.clone()), 0, None)
+                .await
+                .expect("instantiating `marketplace` failed")
+                .account_id;
+            let mint = build_message::<PatientRef>(nft.clone()).call(|patient| patient.mint(1));
+            client.call(&ink_e2e::alice(), mint, 0, None).await.expect("mint failed");
+            let approve = build_message::<PatientRef>(nft.clone())
+                .call(|patient| patient.set_approval_for_all(marketplace.clone(), true));
+            client.call(&ink_e2e::alice(), approve, 0, None).await.expect("approval failed");
+
+            // The voucher key does not own token 1, so a relayer cannot list it, and the nonce stays unused.
+            let voucher = ListingVoucher { collection: nft.clone(), id: 1, price: 100, expiry: u64::MAX, nonce: 1 };
+            let signature = sign_listing_voucher(marketplace.clone(), &voucher);
+            let list = build_message::<NftMarketplaceRef>(marketplace.clone())
+                .call(|market| market.list_with_voucher(voucher.clone(), voucher_signer_key(), signature));
+            let result = client.call_dry_run(&ink_e2e::bob(), &list, 0, None).await.return_value();
+            assert_eq!(result, Err(Error::NotOwner));
+            let used = build_message::<NftMarketplaceRef>(marketplace.clone())
+                .call(|market| market.is_nonce_used(voucher_seller(), 1));
+            assert!(!client.call_dry_run(&ink_e2e::bob(), &used, 0, None).await.return_value());
+
+            // Once the voucher key's account owns the token, the same voucher is attributed to it.
+            let give = build_message::<PatientRef>(nft.clone()).call(|patient| patient.transfer(voucher_seller(), 1));
+            client.call(&ink_e2e::alice(), give, 0, None).await.expect("transfer failed");
+            let owner = build_message::<PatientRef>(nft.clone()).call(|patient| patient.owner_of(1));
+            assert_eq!(client.call_dry_run(&ink_e2e::bob(), &owner, 0, None).await.return_value(), Some(voucher_seller()));
+            // The ownership check passes; the key's account has not approved the marketplace, which only it can do.
+            let unapproved = client.call_dry_run(&ink_e2e::bob(), &list, 0, None).await.return_value();
+            assert_eq!(unapproved, Err(Error::MarketplaceNotApproved));
+
+            Ok(())
+        }
     }
 }
@@ -0,0 +1,133 @@
+// Required for environments that don't have a standard library (like a Wasm contract).
+#![cfg_attr(not(feature = "std"), no_std, no_main)]
+
+pub use self::psp22_mock::{
+    PSP22,
+    PSP22Error,
+    Psp22Mock,
+    Psp22MockRef
+};
+
+/// A minimal PSP22 token used by the marketplace end-to-end tests.
+/// It implements the transfer and allowance messages of the standard under their PSP22 selectors,
+/// without metadata or any of the optional extensions.
+#[ink::contract]
+mod psp22_mock {
+    use ink::prelude::{
+        string::String,
+        vec::Vec
+    };
+    use ink::storage::Mapping;
+
+    /// The errors defined by the PSP22 standard.
+    #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub enum PSP22Error {
+        Custom(String),
+        InsufficientBalance,
+        InsufficientAllowance,
+        ZeroRecipientAddress,
+        ZeroSenderAddress,
+        SafeTransferCheckFailed(String)
+    }
+
+    /// The subset of the PSP22 interface the marketplace relies on.
+    #[ink::trait_definition]
+    pub trait PSP22 {
+        #[ink(message)]
+        fn total_supply(&self) -> Balance;
+
+        #[ink(message)]
+        fn balance_of(&self, owner: AccountId) -> Balance;
+
+        #[ink(message)]
+        fn allowance(&self, owner: AccountId, spender: AccountId) -> Balance;
+
+        #[ink(message)]
+        fn transfer(&mut self, to: AccountId, value: Balance, data: Vec<u8>) -> Result<(), PSP22Error>;
+
+        #[ink(message)]
+        fn transfer_from(&mut self, from: AccountId, to: AccountId, value: Balance, data: Vec<u8>) -> Result<(), PSP22Error>;
+
+        #[ink(message)]
+        fn approve(&mut self, spender: AccountId, value: Balance) -> Result<(), PSP22Error>;
+    }
+
+    #[ink(storage)]
+    pub struct Psp22Mock {
+        /// The total number of tokens in existence.
+        supply: Balance,
+        /// The balance of each account.
+        balances: Mapping<AccountId, Balance>,
+        /// The amount each (owner, spender) may still move.
+        allowances: Mapping<(AccountId, AccountId), Balance>
+    }
+
+    impl Psp22Mock {
+        /// Creates the token and credits the whole supply to the caller.
+        #[ink(constructor)]
+        pub fn new(supply: Balance) -> Self {
+            let mut balances = Mapping::default();
+            balances.insert(Self::env().caller(), &supply);
+            Self {
+                supply,
+                balances,
+                allowances: Default::default()
+            }
+        }
+
+        /// Moves `value` tokens between two accounts.
+        fn move_tokens(&mut self, from: AccountId, to: AccountId, value: Balance) -> Result<(), PSP22Error> {
+            let from_balance = self.balance_of(from);
+            if from_balance < value {
+                return Err(PSP22Error::InsufficientBalance)
+            }
+            self.balances.insert(from, &(from_balance - value));
+            let to_balance = self.balance_of(to);
+            self.balances.insert(to, &(to_balance + value));
+            Ok(())
+        }
+    }
+
+    impl PSP22 for Psp22Mock {
+        #[ink(message)]
+        fn total_supply(&self) -> Balance {
+            self.supply
+        }
+
+        #[ink(message)]
+        fn balance_of(&self, owner: AccountId) -> Balance {
+            self.balances.get(owner).unwrap_or(0)
+        }
+
+        #[ink(message)]
+        fn allowance(&self, owner: AccountId, spender: AccountId) -> Balance {
+            self.allowances.get((owner, spender)).unwrap_or(0)
+        }
+
+        #[ink(message)]
+        fn transfer(&mut self, to: AccountId, value: Balance, _data: Vec<u8>) -> Result<(), PSP22Error> {
+            let from = self.env().caller();
+            self.move_tokens(from, to, value)
+        }
+
+        #[ink(message)]
+        fn transfer_from(&mut self, from: AccountId, to: AccountId, value: Balance, _data: Vec<u8>) -> Result<(), PSP22Error> {
+            let spender = self.env().caller();
+            let allowance = self.allowance(from, spender);
+            if allowance < value {
+                return Err(PSP22Error::InsufficientAllowance)
+            }
+            self.move_tokens(from, to, value)?;
+            self.allowances.insert((from, spender), &(allowance - value));
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn approve(&mut self, spender: AccountId, value: Balance) -> Result<(), PSP22Error> {
+            let owner = self.env().caller();
+            self.allowances.insert((owner, spender), &value);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,104 @@
+// Required for environments that don't have a standard library (like a Wasm contract).
+#![cfg_attr(not(feature = "std"), no_std, no_main)]
+
+pub use self::reentrant_mock::{
+    ReentrantMock,
+    ReentrantMockRef
+};
+
+/// A malicious PSP22 token used by the marketplace end-to-end tests.
+/// Whenever the marketplace pulls a payment with `transfer_from`, it calls back into the marketplace's `buy`
+/// for the configured token and records whether that nested call got through. The payment itself always succeeds.
+#[ink::contract]
+mod reentrant_mock {
+    use ink::env::call::{
+        build_call,
+        ExecutionInput,
+        Selector
+    };
+    use ink::prelude::vec::Vec;
+    use psp22_mock::{
+        PSP22,
+        PSP22Error
+    };
+
+    #[ink(storage)]
+    pub struct ReentrantMock {
+        /// The marketplace to call back into and the token to try to buy again.
+        target: Option<(AccountId, u32)>,
+        /// Whether the last nested call succeeded, if one was attempted.
+        reentered: Option<bool>
+    }
+
+    impl ReentrantMock {
+        #[ink(constructor)]
+        pub fn new() -> Self {
+            Self {
+                target: None,
+                reentered: None
+            }
+        }
+
+        /// Sets the marketplace and the token the next `transfer_from` tries to buy again.
+        #[ink(message)]
+        pub fn set_target(&mut self, marketplace: AccountId, id: u32) {
+            self.target = Some((marketplace, id));
+        }
+
+        /// Returns whether the last nested call into the marketplace succeeded.
+        #[ink(message)]
+        pub fn reentered(&self) -> Option<bool> {
+            self.reentered
+        }
+    }
+
+    impl Default for ReentrantMock {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl PSP22 for ReentrantMock {
+        #[ink(message)]
+        fn total_supply(&self) -> Balance {
+            0
+        }
+
+        #[ink(message)]
+        fn balance_of(&self, _owner: AccountId) -> Balance {
+            0
+        }
+
+        #[ink(message)]
+        fn allowance(&self, _owner: AccountId, _spender: AccountId) -> Balance {
+            Balance::MAX
+        }
+
+        #[ink(message)]
+        fn transfer(&mut self, _to: AccountId, _value: Balance, _data: Vec<u8>) -> Result<(), PSP22Error> {
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn transfer_from(&mut self, _from: AccountId, _to: AccountId, _value: Balance, _data: Vec<u8>) -> Result<(), PSP22Error> {
+            if let Some((marketplace, id)) = self.target {
+                let result = build_call::<Environment>()
+                    .call(marketplace)
+                    .exec_input(
+                        ExecutionInput::new(Selector::new(ink::selector_bytes!("buy")))
+                            .push_arg(id)
+                            .push_arg(None::<Hash>)
+                    )
+                    .returns::<Result<(), u8>>()
+                    .try_invoke();
+                self.reentered = Some(matches!(result, Ok(Ok(Ok(())))));
+            }
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn approve(&mut self, _spender: AccountId, _value: Balance) -> Result<(), PSP22Error> {
+            Ok(())
+        }
+    }
+}
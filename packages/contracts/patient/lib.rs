@@ -3,14 +3,26 @@
 
 pub use self::patient::{
     Patient,
-    PatientRef
+    PatientRef,
+    TokenId,
+    TokenListener,
+    FEATURE_ENUMERABLE,
+    FEATURE_METADATA,
+    FEATURE_ROYALTIES,
+    FEATURE_SOULBOUND,
+    FEATURE_VOUCHERS,
+    CONSENT_TOKEN_FLAG,
+    ERROR_NAMES
 };
 
 // We're importing the ink contract language.
 #[ink::contract]
 mod patient {
     // This trait provides an abstraction for working with storage data structures in ink.
-    use ink::storage::Mapping;
+    use ink::storage::{
+        Lazy,
+        Mapping
+    };
 
     // Importing necessary traits for encoding and decoding.
     use scale::{
@@ -19,32 +31,219 @@ mod patient {
     };
 
     use scale::alloc::string::String;
+    use scale::alloc::vec::Vec;
+
+    use ink::env::hash::Blake2x256;
+    use ink::env::call::{
+        build_call,
+        ExecutionInput,
+        Selector
+    };
 
     // Define our own types for better readability.
     // TokenId represents a unique identifier for each token.
     pub type TokenId = u32;
     // Approved represents the approval status of a token.
     pub type Approved = bool;
+    // UriRecord represents one entry of a token's URI history: the URI, who set it, and when.
+    pub type UriRecord = (String, AccountId, Timestamp);
+    // OwnershipRecord represents one entry of a token's ownership history: the owner, since when, and whether the
+    // token came to them through a recovery.
+    pub type OwnershipRecord = (AccountId, Timestamp, bool);
+    // Recovery represents a pending recovery of a token, as (new owner, time from which it can be finalized).
+    pub type Recovery = (AccountId, Timestamp);
+    // SignerKey represents a compressed secp256k1 public key allowed to sign mint vouchers.
+    pub type SignerKey = [u8; 33];
+    // VoucherSignature represents a recoverable secp256k1 signature over a mint voucher.
+    pub type VoucherSignature = [u8; 65];
+    // TokenRange represents an inclusive range of token ids, as (first, last).
+    pub type TokenRange = (TokenId, TokenId);
+    // OperatorKey represents an (owner, operator) pair.
+    pub type OperatorKey = (AccountId, AccountId);
+    // Royalty represents the receiver of a royalty and its share of a sale price, in basis points.
+    pub type Royalty = (AccountId, u16);
+    // ReservedRange represents an inclusive range of token ids reserved for a minter, as (first, last, minter).
+    pub type ReservedRange = (TokenId, TokenId, AccountId);
+    // Snapshot represents the block a snapshot was taken at and the total supply at that point, as (block, total supply).
+    pub type Snapshot = (BlockNumber, u32);
+    // BalanceSnapshot represents an account's balance when a snapshot was taken, as (snapshot id, balance).
+    pub type BalanceSnapshot = (u32, u32);
+
+    // Longest URI emitted in the data of a TokenUriUpdated event; longer ones are only identified by their hash.
+    pub const MAX_EVENT_URI_LEN: usize = 256;
+
+    // Longest URI that can be stored for a token or for the collection.
+    pub const MAX_URI_LEN: usize = 2048;
+
+    // Longest token URI kept in token_resource_locator; longer ones overflow into token_uri_long.
+    pub const MAX_SHORT_URI_LEN: usize = 256;
+
+    // Longest data payload a transfer_with_data or transfer_from_with_data call may carry.
+    pub const MAX_TRANSFER_DATA_LEN: usize = 256;
+
+    // Most token ranges an owner can have approved across all of their operators.
+    pub const MAX_APPROVED_RANGES: u32 = 16;
+
+    // Most id ranges that can be reserved for minters across the whole contract.
+    pub const MAX_RESERVED_RANGES: usize = 32;
+
+    // Most tokens a single transfer_all or transfer_all_from call moves.
+    pub const MAX_BULK_TRANSFER: u32 = 50;
+
+    // Most positions of the owner's index a single transfer_all or transfer_all_from call looks at,
+    // counting the tokens it skips.
+    pub const MAX_BULK_SCAN: u32 = 4 * MAX_BULK_TRANSFER;
+
+    // Most listener contracts notified of every mint.
+    pub const MAX_LISTENERS: usize = 3;
+
+    // Gas each listener may use to handle a mint notification.
+    pub const LISTENER_GAS_LIMIT: u64 = 5_000_000_000;
+
+    // Default time, in milliseconds, between a burn request and the earliest moment it can be executed: 7 days.
+    pub const DEFAULT_BURN_GRACE_PERIOD: Timestamp = 7 * 24 * 60 * 60 * 1000;
+
+    // Time, in milliseconds, during which the owner of a token can veto its recovery before it can be finalized: 14 days.
+    pub const RECOVERY_CHALLENGE_PERIOD: Timestamp = 14 * 24 * 60 * 60 * 1000;
+
+    // Operation codes folded into the state digest, one per kind of Transfer event: from the zero address,
+    // between two accounts, and to the zero address.
+    pub const DIGEST_MINT: u8 = 0;
+    pub const DIGEST_TRANSFER: u8 = 1;
+    pub const DIGEST_BURN: u8 = 2;
+
+    // The basis points that make up a whole sale price; no royalty can exceed it.
+    pub const MAX_ROYALTY_BPS: u16 = 10_000;
+
+    // Feature ids of the extensions a token contract may implement, as answered by supports.
+    // Other contracts check them before calling an extension's messages.
+    pub const FEATURE_ENUMERABLE: [u8; 4] = ink::selector_bytes!("Extension::Enumerable");
+    pub const FEATURE_METADATA: [u8; 4] = ink::selector_bytes!("Extension::Metadata");
+    pub const FEATURE_ROYALTIES: [u8; 4] = ink::selector_bytes!("Extension::Royalties");
+    pub const FEATURE_SOULBOUND: [u8; 4] = ink::selector_bytes!("Extension::Soulbound");
+    pub const FEATURE_VOUCHERS: [u8; 4] = ink::selector_bytes!("Extension::Vouchers");
+
+    // Ids of the access-consent tokens the EPR contract mints have this bit set. Such a token stands for a consent
+    // recorded on the EPR contract, so only the consent market, which moves the consent along, may transfer it.
+    pub const CONSENT_TOKEN_FLAG: TokenId = 0x4000_0000;
+
+    // The extensions this build of the contract implements.
+    const SUPPORTED_FEATURES: [[u8; 4]; 5] = [FEATURE_ENUMERABLE, FEATURE_METADATA, FEATURE_ROYALTIES, FEATURE_SOULBOUND, FEATURE_VOUCHERS];
+
+    /// The interface of contracts notified whenever a token is minted.
+    /// Statistics or billing contracts implement it and are registered by the owner with add_listener.
+    #[ink::trait_definition]
+    pub trait TokenListener {
+        /// Called after token `id` has been minted to `to`.
+        #[ink(message)]
+        fn on_minted(&mut self, id: TokenId, to: AccountId);
+    }
 
 
 
     // Annotate the struct as the ink contract's storage.
     // The contract's storage holds its state variables.
     #[ink(storage)]
-    #[derive(Default)] // Derive the Default trait to initialize the contract.
     pub struct Patient {
+        // The account that instantiated the contract.
+        owner: AccountId,
         // The name of the token.
         token_name: String,
         // The symbol of the token.
         token_symbol: String,
-        // A mapping from a TokenId to its resource locator (the data it points to).
+        // The prefix prepended to relative token URIs.
+        base_uri: String,
+        // The URI of the collection-level metadata (logo, description, institution).
+        collection_uri: String,
+        // A mapping from a TokenId to its resource locator (the data it points to), up to MAX_SHORT_URI_LEN bytes.
         token_resource_locator: Mapping<TokenId, String>,
+        // A mapping from a TokenId to the UTF-8 bytes of a resource locator longer than MAX_SHORT_URI_LEN.
+        token_uri_long: Mapping<TokenId, Vec<u8>>,
         // A mapping from a TokenId to its owner's AccountId.
         token_owner: Mapping<TokenId, AccountId>,
         // A mapping from a TokenId to an approved AccountId (who can manage this token).
         token_approvals: Mapping<TokenId, AccountId>,
+        // A mapping from a TokenId to the time its approval expires, for approvals granted with an expiry.
+        approval_expiry: Mapping<TokenId, Timestamp>,
         // A mapping from an AccountId to the count of tokens it owns.
-        owned_tokens_count: Mapping<AccountId, u32>
+        owned_tokens_count: Mapping<AccountId, u32>,
+        // A mapping from (owner, position) to the TokenId stored at that position of the owner's index.
+        owned_tokens: Mapping<(AccountId, u32), TokenId>,
+        // A mapping from a TokenId to its position in its owner's index.
+        owned_tokens_index: Mapping<TokenId, u32>,
+        // A mapping from (owner, operator) to whether the operator may manage all of the owner's tokens.
+        operator_approvals: Mapping<(AccountId, AccountId), ()>,
+        // A mapping from (owner, operator) to the sorted, disjoint token ranges the operator may manage.
+        range_approvals: Mapping<OperatorKey, Vec<TokenRange>>,
+        // A mapping from an owner to the number of ranges they have approved across all operators.
+        range_approval_count: Mapping<AccountId, u32>,
+        // A mapping from a global index to the TokenId stored at that position.
+        all_tokens: Mapping<u32, TokenId>,
+        // A mapping from a TokenId to its position in the global index.
+        all_tokens_index: Mapping<TokenId, u32>,
+        // The number of tokens currently in existence.
+        total_supply: u32,
+        // A mapping from a TokenId to its category, set at mint time. Category 0 is not stored.
+        category: Mapping<TokenId, u8>,
+        // A mapping from a category to the number of its tokens currently in existence.
+        category_supply: Mapping<u8, u32>,
+        // A mapping from (TokenId, position) to every URI the token has pointed to.
+        uri_history: Mapping<(TokenId, u32), UriRecord>,
+        // A mapping from a TokenId to the number of entries in its URI history.
+        uri_history_count: Mapping<TokenId, u32>,
+        // The keys the owner allows to sign mint vouchers.
+        voucher_signers: Mapping<SignerKey, ()>,
+        // The hashes of vouchers that have already been redeemed.
+        consumed_vouchers: Mapping<[u8; 32], ()>,
+        // The EPR contract notified when a token is burned, if any.
+        registry: Option<AccountId>,
+        // The contracts notified whenever a token is minted, at most MAX_LISTENERS.
+        listeners: Vec<AccountId>,
+        // The time, in milliseconds, a token has to stay with its owner before it can be transferred again. 0 disables it.
+        transfer_cooldown: Timestamp,
+        // A mapping from a TokenId to when it was last minted or transferred.
+        last_transfer_at: Mapping<TokenId, Timestamp>,
+        // The accounts, such as the marketplace, whose transfers are not held to the cooldown.
+        cooldown_exempt: Mapping<AccountId, ()>,
+        // The time, in milliseconds, a requested burn waits before anyone can execute it.
+        burn_grace_period: Timestamp,
+        // A mapping from a TokenId with a pending burn to the time from which the burn can be executed.
+        pending_burns: Mapping<TokenId, Timestamp>,
+        // A mapping from a TokenId to the royalty set for that token, which overrides the default royalty.
+        token_royalties: Mapping<TokenId, Royalty>,
+        // The royalty of every token without one of its own, if any.
+        default_royalty: Option<Royalty>,
+        // The id ranges reserved for minters, at most MAX_RESERVED_RANGES, which never overlap.
+        reserved_ranges: Vec<ReservedRange>,
+        // The id of the latest snapshot, 0 before the first one. Snapshot ids start at 1.
+        snapshot_count: u32,
+        // A mapping from a snapshot id to the block it was taken at and the total supply then.
+        snapshots: Mapping<u32, Snapshot>,
+        // A mapping from an account and a position to the account's balance when a snapshot was taken. An entry is
+        // written by the first balance change after a snapshot, so accounts that do not move keep no history,
+        // and the snapshot ids of an account's entries increase with their position.
+        balance_snapshots: Mapping<(AccountId, u32), BalanceSnapshot>,
+        // A mapping from an account to the number of its balance snapshots.
+        balance_snapshot_count: Mapping<AccountId, u32>,
+        // The categories whose tokens can never be transferred, only burned, such as clinician credential badges.
+        non_transferable: Mapping<u8, ()>,
+        // The account that may start recoveries besides the contract owner, if any.
+        recovery_council: Option<AccountId>,
+        // A mapping from a TokenId to its pending recovery.
+        recoveries: Mapping<TokenId, Recovery>,
+        // The marketplace allowed to transfer access-consent tokens, if any.
+        consent_market: Option<AccountId>,
+        // A mapping from (TokenId, position) to every account that owned the token, in order.
+        ownership_history: Mapping<(TokenId, u32), OwnershipRecord>,
+        // A mapping from a TokenId to the number of entries in its ownership history.
+        ownership_history_count: Mapping<TokenId, u32>,
+        // The rolling digest of every mint, transfer and burn, see state_digest.
+        state_digest: Hash,
+        // The block of the latest update of the state digest.
+        digest_block: BlockNumber,
+        // Set while the registry or the listeners are being notified, so they cannot mint, move or burn tokens from inside that call.
+        // It lives in its own storage cell because a nested call only sees what has already been written.
+        locked: Lazy<bool>
     }
 
     // Define an Error enum to handle errors.
@@ -56,9 +255,41 @@ mod patient {
         TokenExists,
         TokenNotFound,
         NotAllowed,
-        CannotFetchValue
+        CannotFetchValue,
+        InvalidSignature,
+        VoucherUsed,
+        InvalidRange,
+        TooManyRanges,
+        DataTooLarge,
+        ReentrancyDetected,
+        InvalidUri,
+        TooManyListeners,
+        ListenerExists,
+        ListenerNotFound,
+        ZeroAddress,
+        UriTooLarge,
+        InvalidExpiry,
+        CooldownActive,
+        IdCollision,
+        BurnPending,
+        InvalidRoyalty,
+        IdOutOfRange,
+        TokenNotTransferable,
+        RecoveryNotFound,
+        RecoveryPending,
+        RecoveryWindowClosed
     }
 
+    // The name of each Error variant, indexed by its discriminant. Kept next to the enum and checked against it by the tests.
+    pub const ERROR_NAMES: [&str; 28] = [
+        "NotOwner", "NotApproved", "TokenExists", "TokenNotFound", "NotAllowed", "CannotFetchValue",
+        "InvalidSignature", "VoucherUsed", "InvalidRange", "TooManyRanges", "DataTooLarge",
+        "ReentrancyDetected", "InvalidUri", "TooManyListeners", "ListenerExists", "ListenerNotFound",
+        "ZeroAddress", "UriTooLarge", "InvalidExpiry", "CooldownActive", "IdCollision", "BurnPending",
+        "InvalidRoyalty", "IdOutOfRange", "TokenNotTransferable", "RecoveryNotFound", "RecoveryPending",
+        "RecoveryWindowClosed"
+    ];
+
     // This is an event that will be emitted when the ownership of any NFT changes.
     #[ink(event)]
     pub struct Transfer {
@@ -73,15 +304,33 @@ mod patient {
         token_id: TokenId
     }
 
+    // This is an event that will be emitted, after the Transfer event, when a token is transferred with a data payload.
+    #[ink(event)]
+    pub struct TransferWithData {
+        // The previous owner of the token.
+        from: AccountId,
+        // The new owner of the token.
+        #[ink(topic)]
+        to: AccountId,
+        // The id of the token being transferred.
+        #[ink(topic)]
+        token_id: TokenId,
+        // The Blake2x256 hash of the data.
+        #[ink(topic)]
+        data_hash: Hash,
+        // The data itself, such as a handover document hash or a transfer reason code.
+        data: Vec<u8>
+    }
+
     // This is an event that will be emitted when the approved address for an NFT changes.
     #[ink(event)]
     pub struct Approval {
         // The current owner of the token.
         #[ink(topic)]
         owner: AccountId,
-        // The approved address that can manage the token.
+        // The approved address that can manage the token (None when the approval is cleared).
         #[ink(topic)]
-        spender: AccountId,
+        spender: Option<AccountId>,
         // The id of the token.
         #[ink(topic)]
         token_id: TokenId
@@ -101,18 +350,232 @@ mod patient {
         approved: Approved
     }
 
+    // This is an event that will be emitted when an operator is approved or disapproved for a range of tokens.
+    #[ink(event)]
+    pub struct ApprovalForRange {
+        // The owner of the tokens.
+        #[ink(topic)]
+        owner: AccountId,
+        // The operator whose approved range has changed.
+        #[ink(topic)]
+        operator: AccountId,
+        // The first token id of the range.
+        from_id: TokenId,
+        // The last token id of the range, inclusive.
+        to_id: TokenId,
+        // Whether the range was approved or revoked.
+        approved: Approved
+    }
+
+    // This is an event that will be emitted when the URI of a token changes.
+    #[ink(event)]
+    pub struct TokenUriUpdated {
+        // The id of the token.
+        #[ink(topic)]
+        token_id: TokenId,
+        // The account that changed the URI.
+        #[ink(topic)]
+        actor: AccountId,
+        // The hash of the new stored URI, matching hash_of_token_uri.
+        #[ink(topic)]
+        uri_hash: Hash,
+        // The new stored URI, or None when it is longer than MAX_EVENT_URI_LEN.
+        uri: Option<String>
+    }
+
+    // This is an event that will be emitted when the base URI of the contract changes.
+    #[ink(event)]
+    pub struct BaseUriChanged {
+        // The previous base URI.
+        old: String,
+        // The new base URI.
+        new: String
+    }
+
+    // This is an event that will be emitted when the registry could not be notified of a burn.
+    #[ink(event)]
+    pub struct RegistryNotifyFailed {
+        // The id of the burned token.
+        #[ink(topic)]
+        token_id: TokenId,
+        // The registry that could not be notified.
+        #[ink(topic)]
+        registry: AccountId
+    }
+
+    // This is an event that will be emitted when the collection metadata URI changes.
+    #[ink(event)]
+    pub struct CollectionMetadataChanged {
+        // The previous collection URI.
+        old: String,
+        // The new collection URI.
+        new: String
+    }
+
+    // This is an event that will be emitted when a listener could not be notified of a mint.
+    #[ink(event)]
+    pub struct ListenerFailed {
+        // The id of the minted token.
+        #[ink(topic)]
+        token_id: TokenId,
+        // The listener that could not be notified.
+        #[ink(topic)]
+        listener: AccountId
+    }
+
+    // This is an event that will be emitted when the name or symbol of the token contract changes.
+    #[ink(event)]
+    pub struct MetadataChanged {
+        // The name of the token after the change.
+        name: String,
+        // The symbol of the token after the change.
+        symbol: String
+    }
+
+    // This is an event that will be emitted when an id range is reserved for a minter.
+    #[ink(event)]
+    pub struct RangeReserved {
+        // The account that may mint the ids of the range.
+        #[ink(topic)]
+        minter: AccountId,
+        // The first token id of the range.
+        from_id: TokenId,
+        // The last token id of the range, inclusive.
+        to_id: TokenId
+    }
+
+    // This is an event that will be emitted when a snapshot of the balances is taken.
+    #[ink(event)]
+    pub struct SnapshotCreated {
+        // The id of the snapshot.
+        #[ink(topic)]
+        id: u32,
+        // The block the snapshot was taken at.
+        block: BlockNumber,
+        // The total supply at that point.
+        total_supply: u32
+    }
+
+    // This is an event that will be emitted when the owner of a token requests its burn.
+    #[ink(event)]
+    pub struct BurnRequested {
+        // The id of the token to be burned.
+        #[ink(topic)]
+        token_id: TokenId,
+        // The owner of the token.
+        #[ink(topic)]
+        owner: AccountId,
+        // The time from which anyone can execute the burn.
+        executable_at: Timestamp
+    }
+
+    // This is an event that will be emitted when the recovery of a token to a new owner starts.
+    #[ink(event)]
+    pub struct RecoveryInitiated {
+        // The id of the token being recovered.
+        #[ink(topic)]
+        token_id: TokenId,
+        // The account the token will be transferred to.
+        #[ink(topic)]
+        new_owner: AccountId,
+        // The contract owner or recovery council who started the recovery.
+        initiated_by: AccountId,
+        // The time from which the recovery can be finalized, until which the token's owner can veto it.
+        finalizable_at: Timestamp
+    }
+
+    // This is an event that will be emitted when the owner of a token vetoes its recovery.
+    #[ink(event)]
+    pub struct RecoveryVetoed {
+        // The id of the token that is no longer being recovered.
+        #[ink(topic)]
+        token_id: TokenId,
+        // The owner of the token.
+        #[ink(topic)]
+        owner: AccountId
+    }
+
+    // This is an event that will be emitted when a token is transferred to its new owner at the end of a recovery.
+    #[ink(event)]
+    pub struct RecoveryFinalized {
+        // The id of the recovered token.
+        #[ink(topic)]
+        token_id: TokenId,
+        // The previous owner, whose key was lost.
+        #[ink(topic)]
+        from: AccountId,
+        // The new owner.
+        #[ink(topic)]
+        to: AccountId
+    }
+
+    // This is an event that will be emitted when the owner of a token cancels its pending burn.
+    #[ink(event)]
+    pub struct BurnCancelled {
+        // The id of the token that is no longer to be burned.
+        #[ink(topic)]
+        token_id: TokenId,
+        // The owner of the token.
+        #[ink(topic)]
+        owner: AccountId
+    }
+
     // The implementation of the contract.
     impl Patient {
-        // Constructor function for the contract. It takes in the token name and symbol.
+        // Constructor function for the contract. It takes in the token name, symbol, base URI and collection URI.
+        // The collection URI is validated like token URIs and may be left empty.
         #[ink(constructor, payable)]
-        pub fn new(token_name: String, token_symbol: String) -> Self {
+        pub fn new(token_name: String, token_symbol: String, base_uri: String, collection_uri: String) -> Self {
+            assert!(check_uri(&collection_uri).is_ok(), "invalid collection URI");
             Self {
+                owner: Self::env().caller(),
                 token_name,
                 token_symbol,
+                base_uri,
+                collection_uri,
                 token_resource_locator: Default::default(),
+                token_uri_long: Default::default(),
                 token_owner: Default::default(),
                 token_approvals: Default::default(),
-                owned_tokens_count: Default::default()
+                approval_expiry: Default::default(),
+                owned_tokens_count: Default::default(),
+                owned_tokens: Default::default(),
+                owned_tokens_index: Default::default(),
+                operator_approvals: Default::default(),
+                range_approvals: Default::default(),
+                range_approval_count: Default::default(),
+                all_tokens: Default::default(),
+                all_tokens_index: Default::default(),
+                total_supply: 0,
+                category: Default::default(),
+                category_supply: Default::default(),
+                uri_history: Default::default(),
+                uri_history_count: Default::default(),
+                voucher_signers: Default::default(),
+                consumed_vouchers: Default::default(),
+                registry: None,
+                listeners: Vec::new(),
+                transfer_cooldown: 0,
+                last_transfer_at: Default::default(),
+                cooldown_exempt: Default::default(),
+                burn_grace_period: DEFAULT_BURN_GRACE_PERIOD,
+                pending_burns: Default::default(),
+                token_royalties: Default::default(),
+                default_royalty: None,
+                reserved_ranges: Vec::new(),
+                snapshot_count: 0,
+                snapshots: Default::default(),
+                balance_snapshots: Default::default(),
+                balance_snapshot_count: Default::default(),
+                non_transferable: Default::default(),
+                recovery_council: None,
+                recoveries: Default::default(),
+                consent_market: None,
+                ownership_history: Default::default(),
+                ownership_history_count: Default::default(),
+                state_digest: Hash::default(),
+                digest_block: 0,
+                locked: Default::default()
             }
         }
 
@@ -137,317 +600,3217 @@ mod patient {
         }
 
         /// This function approves an account to manage a token on behalf of its owner.
+        /// With `valid_until`, the approval counts as absent from that time on; it has to lie in the future,
+        /// otherwise the function fails with InvalidExpiry.
         /// The function first approves the address for the token ID and then returns Ok if the operation was successful.
         /// If the operation was unsuccessful, it will return an error.
         /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
         #[ink(message)]
-        pub fn approve(&mut self, address: AccountId, token_id: TokenId) -> Result<(), Error> {
-            self.approve_for(&address, token_id)?;
+        pub fn approve(&mut self, address: AccountId, token_id: TokenId, valid_until: Option<Timestamp>) -> Result<(), Error> {
+            self.approve_for(&address, token_id, valid_until)?;
             Ok(())
         }
 
         /// This function returns the account approved to manage a specific token.
-        /// If there's no account approved for the given token ID, the function will return None.
+        /// If there's no account approved for the given token ID, or its approval has expired, the function will return None.
         /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
         #[ink(message)]
         pub fn get_approved(&self, token_id: TokenId) -> Option<AccountId> {
-            self.token_approvals.get(token_id)
+            self.token_approvals.get(token_id).filter(|_| !self.is_approval_expired(token_id))
         }
 
-        /// This function transfers a token from the caller to a recipient.
-        /// First, it gets the caller's account ID, then transfers the token with the given ID from the caller to the recipient.
-        /// The function will return Ok if the operation was successful, or an error if it wasn't.
-        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
+        /// This function returns the time the approval of a specific token expires, if it was granted with an expiry.
         #[ink(message)]
-        pub fn transfer(&mut self, to: AccountId, id: TokenId) -> Result<(), Error> {
-            let caller = self.env().caller();
-            self.transfer_token_from(&caller, &to, id)?;
-            Ok(())
+        pub fn approval_expiry(&self, token_id: TokenId) -> Option<Timestamp> {
+            self.approval_expiry.get(token_id)
         }
 
-        /// This function transfers a token from a sender to a recipient.
-        /// It works similarly to the transfer function, but instead of using the caller's account ID, it uses the provided sender's account ID.
+        /// This function removes an expired approval from storage and emits an Approval event with no spender.
+        /// Anyone may call it. It fails with CannotFetchValue if the token has no approval and NotAllowed if it has not expired.
         /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
         #[ink(message)]
-        pub fn transfer_from(&mut self, from: AccountId, to: AccountId, id: TokenId) -> Result<(), Error> {
-            self.transfer_token_from(&from, &to, id)?;
+        pub fn prune_expired_approval(&mut self, token_id: TokenId) -> Result<(), Error> {
+            let owner = self.owner_of(token_id).ok_or(Error::TokenNotFound)?;
+            if !self.token_approvals.contains(token_id) {
+                return Err(Error::CannotFetchValue)
+            }
+            if !self.is_approval_expired(token_id) {
+                return Err(Error::NotAllowed)
+            }
+
+            self.clear_approval(&owner, token_id);
             Ok(())
         }
 
-        /// This function mints a new token with a specific ID.
-        /// It adds the token to the caller's account and emits a Transfer event indicating the creation of a new token.
-        /// The function will return Ok if the operation was successful, or an error if it wasn't.
+        /// This function clears the account approved to manage a specific token.
+        /// Only the owner of the token may call it. It emits an Approval event with no spender.
         /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
         #[ink(message)]
-        pub fn mint(&mut self, id: TokenId) -> Result<(), Error> {
-            let msg_sender: AccountId = self.env().caller();
-            
-            self.add_token_to(&msg_sender, id)?;
-            self.env().emit_event(Transfer {
-                from: Some(AccountId::from([0x0; 32])),
-                to: Some(msg_sender),
-                token_id: id
-            });
-            Ok(())
-        }
+        pub fn revoke_approval(&mut self, token_id: TokenId) -> Result<(), Error> {
+            let owner = self.owner_of(token_id).ok_or(Error::TokenNotFound)?;
+            if owner != self.env().caller() {
+                return Err(Error::NotOwner)
+            }
 
-        ////////////////////////////////
-        ////// Internal Functions///////
-        ////////////////////////////////
+            if self.get_approved(token_id).is_none() {
+                return Err(Error::CannotFetchValue)
+            }
 
-        
-        /// This function checks the number of tokens owned by a specific account.
-        /// It attempts to get the balance of an account from the owned_tokens_count map.
-        /// If the account does not exist in the map (i.e., it does not own any tokens), it returns 0.
-        fn balance_of_or_zero(&self, of: &AccountId) -> u32 {
-            self.owned_tokens_count.get(of).unwrap_or(0)
+            self.clear_approval(&owner, token_id);
+            Ok(())
         }
 
-        /// This function adds a token to a specific account.
-        /// It first checks if the token with the provided ID already exists, and if it does, it returns an error.
-        /// If the account to receive the token is the zero address, it also returns an error.
-        /// It then increases the token count of the receiving account and adds the token to the account's ownership.
-        /// The function will return Ok if the operation was successful, or an error if it wasn't.
-        fn add_token_to(&mut self, to: &AccountId, id: TokenId) -> Result<(), Error> {
-            let Self {
-                token_owner,
-                owned_tokens_count,
-                ..
-            } = self;
-
-            if token_owner.contains(id) {
-                return Err(Error::TokenExists)
-            };
-
-            if *to == AccountId::from([0x0; 32]) {
+        /// This function approves or disapproves an operator to manage all of the caller's tokens.
+        /// It records the operator status and emits an ApprovalForAll event.
+        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
+        #[ink(message)]
+        pub fn set_approval_for_all(&mut self, operator: AccountId, approved: bool) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if operator == caller {
                 return Err(Error::NotAllowed)
             }
 
-            let count = owned_tokens_count.get(to).map(|c| c + 1 ).unwrap_or(1);
-            
-            owned_tokens_count.insert(to, &count);
-            token_owner.insert(id, to);
-
-            Ok(())
-
-        }
-        
-        /// This function transfers a token from one account to another.
-        /// It first checks if the token exists, and if it doesn't, it returns an error.
-        /// It then removes the token from the sender's account and adds it to the recipient's account.
-        /// After transferring the token, it emits a Transfer event.
-        /// The function will return Ok if the operation was successful, or an error if it wasn't.
-        fn transfer_token_from(&mut self, from: &AccountId, to: &AccountId, id: TokenId) -> Result<(), Error> {
-            // let msg_sender: AccountId = self.env().caller();
-            
-            if !self.exists(id) {
-                return Err(Error::TokenNotFound)
-            };
-
-            self.remove_token_from(from, id)?;
-            self.add_token_to(to, id)?;
+            if approved {
+                self.operator_approvals.insert((&caller, &operator), &());
+            } else {
+                self.operator_approvals.remove((&caller, &operator));
+            }
 
-            self.env().emit_event(Transfer {
-                from: Some(*from),
-                to: Some(*to),
-                token_id: id
+            self.env().emit_event(ApprovalForAll {
+                owner: caller,
+                operator,
+                approved
             });
-
-            Ok(())
-        }
-
-        /// This function removes a token from a specific account.
-        /// It first checks if the token exists, and if it doesn't, it returns an error.
-        /// It then decreases the token count of the account and removes the token from the account's ownership.
-        /// The function will return Ok if the operation was successful, or an error if it wasn't.
-        fn remove_token_from(&mut self, from: &AccountId, id: TokenId) -> Result<(), Error> {
-            let Self {
-                token_owner,
-                owned_tokens_count,
-                ..
-            } = self;
-
-            if !token_owner.contains(id) {
-                return Err(Error::TokenNotFound)
-            };
-
-            let count = owned_tokens_count.get(from).map(|c| c - 1).ok_or(Error::CannotFetchValue)?;
-            
-            owned_tokens_count.insert(from, &count);
-            token_owner.remove(id);
-
             Ok(())
         }
 
-        /// This function checks if a token exists by checking if it has an owner.
-        fn exists(&self, id: TokenId) -> bool {
-            self.token_owner.contains(id)
+        /// This function returns whether an operator is approved to manage all of the owner's tokens.
+        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
+        #[ink(message)]
+        pub fn is_approved_for_all(&self, owner: AccountId, operator: AccountId) -> bool {
+            self.operator_approvals.contains((&owner, &operator))
         }
 
-        /// This function approves an account to manage a specific token on behalf of its owner.
-        /// It first checks if the caller is the owner of the token, and if it's not, it returns an error.
-        /// It also checks if the account to be approved is the zero address or if the token is already approved, and if either is true, it returns an error.
-        /// If everything is in order, it adds the account to the token's approvals.
-        /// After approving the account, it emits an Approval event.
-        /// The function will return Ok if the operation was successful, or an error if it wasn't.
-        fn approve_for(&mut self, address: &AccountId, token_id: TokenId) -> Result<(), Error> {
-            let msg_sender: AccountId = self.env().caller();
-            let owner: Option<AccountId> = self.owner_of(token_id);
-
-            if !(owner == Some(msg_sender)) {
+        /// This function approves or disapproves an operator for the caller's tokens with ids from `from_id` to `to_id`, inclusive.
+        /// Approving a range that overlaps or touches an already approved one merges them.
+        /// Disapproving a range removes it from whatever is approved, which may split an approved range in two.
+        /// An owner can have at most MAX_APPROVED_RANGES ranges across all operators.
+        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
+        #[ink(message)]
+        pub fn set_approval_for_range(&mut self, operator: AccountId, from_id: TokenId, to_id: TokenId, approved: bool) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if operator == caller {
                 return Err(Error::NotAllowed)
+            }
+            if from_id > to_id {
+                return Err(Error::InvalidRange)
+            }
+
+            let ranges = self.range_approvals.get((caller, operator)).unwrap_or_default();
+            let previous = ranges.len() as u32;
+            let ranges = if approved {
+                merge_range(ranges, from_id, to_id)
+            } else {
+                subtract_range(ranges, from_id, to_id)
             };
 
-            if *address == AccountId::from([0x0; 32]) {
-                return Err(Error::NotAllowed)
+            let count = self.range_approval_count.get(caller).unwrap_or(0) - previous + ranges.len() as u32;
+            if count > MAX_APPROVED_RANGES {
+                return Err(Error::TooManyRanges)
             }
-
-            if self.token_approvals.contains(token_id) {
-                return Err(Error::NotAllowed)
+            self.range_approval_count.insert(caller, &count);
+            if ranges.is_empty() {
+                self.range_approvals.remove((caller, operator));
             } else {
-                self.token_approvals.insert(token_id, address);
+                self.range_approvals.insert((caller, operator), &ranges);
             }
 
-            self.env().emit_event(Approval {
-                owner: msg_sender,
-                spender: *address,
-                token_id
+            self.env().emit_event(ApprovalForRange {
+                owner: caller,
+                operator,
+                from_id,
+                to_id,
+                approved
             });
-
             Ok(())
         }
 
-
-        ////////////////////////////////
-        ////// Metadata Extension///////
-        ////////////////////////////////
-        
-        /// This function retrieves the name of the token contract.
-        /// It clones the token name from the contract's state and returns it.
+        /// This function returns the token ranges, inclusive and sorted, an operator may manage for the owner.
+        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
         #[ink(message)]
-        pub fn name(&self) -> String {
-            self.token_name.clone()
+        pub fn approved_ranges(&self, owner: AccountId, operator: AccountId) -> Vec<TokenRange> {
+            self.range_approvals.get((owner, operator)).unwrap_or_default()
         }
 
-        /// This function retrieves the symbol of the token contract.
-        /// It clones the token symbol from the contract's state and returns it.
+        /// This function checks if a token exists by checking if it has an owner.
+        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
         #[ink(message)]
-        pub fn symbol(&self) -> String {
-            self.token_symbol.clone()
+        pub fn exists(&self, id: TokenId) -> bool {
+            self.token_owner.contains(id)
         }
 
-        /// This function retrieves the Uniform Resource Identifier (URI) of a specific token.
-        /// The URI is a unique identifier for the token in a given context.
-        /// It retrieves the URI from the token_resource_locator map using the provided token ID.
-        /// If the token does not exist (i.e., it does not have an URI), it returns None.
+        /// This function returns whether an account may move a specific token.
+        /// That is the case if the account owns the token, is the token's approved account, or is an operator of the owner
+        /// for all tokens or for a range containing the token.
+        /// The transfer path uses the same check, so the queried answer always matches on-chain behavior.
+        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
         #[ink(message)]
-        pub fn token_uri(&self, id: TokenId) -> Option<String> {
-            self.token_resource_locator.get(id)
+        pub fn is_approved_or_owner(&self, account: AccountId, id: TokenId) -> bool {
+            let owner = match self.owner_of(id) {
+                Some(owner) => owner,
+                None => return false,
+            };
+
+            account == owner
+                || self.get_approved(id) == Some(account)
+                || self.is_approved_for_all(owner, account)
+                || self
+                    .approved_ranges(owner, account)
+                    .iter()
+                    .any(|(from_id, to_id)| (*from_id..=*to_id).contains(&id))
+        }
+
+        /// This function transfers a token from the caller to a recipient.
+        /// First, it gets the caller's account ID, then transfers the token with the given ID from the caller to the recipient.
+        /// The function will return Ok if the operation was successful, or an error if it wasn't.
+        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
+        #[ink(message)]
+        pub fn transfer(&mut self, to: AccountId, id: TokenId) -> Result<(), Error> {
+            let caller = self.env().caller();
+            self.transfer_token_from(&caller, &to, id)?;
+            Ok(())
+        }
+
+        /// This function transfers a token from a sender to a recipient.
+        /// It works similarly to the transfer function, but instead of using the caller's account ID, it uses the provided sender's account ID.
+        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
+        #[ink(message)]
+        pub fn transfer_from(&mut self, from: AccountId, to: AccountId, id: TokenId) -> Result<(), Error> {
+            self.transfer_token_from(&from, &to, id)?;
+            Ok(())
+        }
+
+        /// This function moves up to MAX_BULK_TRANSFER of the caller's tokens to a recipient, such as a new custody wallet.
+        /// Each token is transferred as with transfer, clearing its approval and emitting a Transfer event.
+        /// Tokens that cannot be moved right now, such as badges, tokens pending burn or cooling down, are skipped.
+        /// It returns how many tokens were moved, so the caller can call it again until it returns 0.
+        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
+        #[ink(message)]
+        pub fn transfer_all(&mut self, to: AccountId) -> Result<u32, Error> {
+            let caller = self.env().caller();
+            self.transfer_all_tokens(&caller, &to)
+        }
+
+        /// This function moves up to MAX_BULK_TRANSFER tokens of an owner to a recipient, like transfer_all.
+        /// The caller must be the owner or an operator approved for all of the owner's tokens.
+        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
+        #[ink(message)]
+        pub fn transfer_all_from(&mut self, from: AccountId, to: AccountId) -> Result<u32, Error> {
+            let caller = self.env().caller();
+            if caller != from && !self.is_approved_for_all(from, caller) {
+                return Err(Error::NotApproved)
+            }
+            self.transfer_all_tokens(&from, &to)
+        }
+
+        /// This function transfers a token from the caller to a recipient, attaching a data payload.
+        /// It works like transfer, but also emits a TransferWithData event carrying the data and its hash.
+        /// The data can be at most MAX_TRANSFER_DATA_LEN bytes long.
+        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
+        #[ink(message)]
+        pub fn transfer_with_data(&mut self, to: AccountId, id: TokenId, data: Vec<u8>) -> Result<(), Error> {
+            let caller = self.env().caller();
+            self.transfer_token_with_data(&caller, &to, id, data)
+        }
+
+        /// This function transfers a token from a sender to a recipient, attaching a data payload.
+        /// It works like transfer_from, but also emits a TransferWithData event carrying the data and its hash.
+        /// The data can be at most MAX_TRANSFER_DATA_LEN bytes long.
+        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
+        #[ink(message)]
+        pub fn transfer_from_with_data(&mut self, from: AccountId, to: AccountId, id: TokenId, data: Vec<u8>) -> Result<(), Error> {
+            self.transfer_token_with_data(&from, &to, id, data)
+        }
+
+        /// This function mints a new token with a specific ID.
+        /// It adds the token to the caller's account and emits a Transfer event indicating the creation of a new token.
+        /// The function will return Ok if the operation was successful, or an error if it wasn't.
+        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
+        #[ink(message)]
+        pub fn mint(&mut self, id: TokenId) -> Result<(), Error> {
+            let msg_sender: AccountId = self.env().caller();
+            self.ensure_mintable(id)?;
+            self.mint_token_to(&msg_sender, id, 0)
+        }
+
+        /// This function mints a new token with a specific ID in a category, such as patient identity, consent or dataset tokens.
+        /// It works like mint, which mints tokens in category 0. The category stays with the token until it is burned.
+        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
+        #[ink(message)]
+        pub fn mint_categorized(&mut self, id: TokenId, category: u8) -> Result<(), Error> {
+            let msg_sender: AccountId = self.env().caller();
+            self.ensure_mintable(id)?;
+            self.mint_token_to(&msg_sender, id, category)
+        }
+
+        /// This function mints a new token with a specific ID directly to a recipient.
+        /// It works like mint, but the token is added to the provided account instead of the caller's.
+        /// The EPR contract uses it so each patient holds the token of their own record.
+        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
+        #[ink(message)]
+        pub fn mint_to(&mut self, to: AccountId, id: TokenId) -> Result<(), Error> {
+            self.ensure_mintable(id)?;
+            self.mint_token_to(&to, id, 0)
+        }
+
+        /// This function mints a new token with a specific ID in a category directly to a recipient.
+        /// It works like mint_to, which mints tokens in category 0.
+        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
+        #[ink(message)]
+        pub fn mint_to_categorized(&mut self, to: AccountId, id: TokenId, category: u8) -> Result<(), Error> {
+            self.ensure_mintable(id)?;
+            self.mint_token_to(&to, id, category)
+        }
+
+        /// This function mints a token to a patient under an ID derived from the patient's account and a salt,
+        /// see derive_token_id, so that observers cannot enumerate the tokens of patients by sequential IDs.
+        /// It returns the derived ID, or IdCollision if a token with that ID already exists,
+        /// in which case the caller can try again with another salt.
+        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
+        #[ink(message)]
+        pub fn mint_derived(&mut self, patient: AccountId, salt: u64) -> Result<TokenId, Error> {
+            let id = self.derive_token_id(patient, salt);
+            if self.token_owner.contains(id) {
+                return Err(Error::IdCollision)
+            }
+            self.ensure_mintable(id)?;
+            self.mint_token_to(&patient, id, 0)?;
+            Ok(id)
+        }
+
+        /// This function returns the ID mint_derived gives the token of a patient for a salt: the first four bytes,
+        /// read as a little-endian integer, of the Blake2x256 hash of the SCALE-encoded (contract address, patient, salt).
+        #[ink(message)]
+        pub fn derive_token_id(&self, patient: AccountId, salt: u64) -> TokenId {
+            let hash = self.env().hash_encoded::<Blake2x256, _>(&(self.env().account_id(), patient, salt));
+            TokenId::from_le_bytes([hash[0], hash[1], hash[2], hash[3]])
+        }
+
+        /// This function starts the burn of a token owned by the caller. The burn can be executed once the burn grace
+        /// period has passed, and until then the owner can cancel it. The token cannot be transferred meanwhile.
+        /// It emits a BurnRequested event with the time from which the burn can be executed.
+        #[ink(message)]
+        pub fn request_burn(&mut self, id: TokenId) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if self.owner_of(id).ok_or(Error::TokenNotFound)? != caller {
+                return Err(Error::NotOwner)
+            }
+            if self.pending_burns.contains(id) {
+                return Err(Error::BurnPending)
+            }
+
+            let executable_at = self.env().block_timestamp().saturating_add(self.burn_grace_period);
+            self.pending_burns.insert(id, &executable_at);
+
+            self.env().emit_event(BurnRequested {
+                token_id: id,
+                owner: caller,
+                executable_at
+            });
+            Ok(())
+        }
+
+        /// This function cancels the pending burn of a token owned by the caller and emits a BurnCancelled event.
+        /// It can be called until the burn is executed, even after the grace period has passed.
+        #[ink(message)]
+        pub fn cancel_burn(&mut self, id: TokenId) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if self.owner_of(id).ok_or(Error::TokenNotFound)? != caller {
+                return Err(Error::NotOwner)
+            }
+            self.pending_burns.take(id).ok_or(Error::CannotFetchValue)?;
+
+            self.env().emit_event(BurnCancelled {
+                token_id: id,
+                owner: caller
+            });
+            Ok(())
+        }
+
+        /// This function executes the pending burn of a token once its grace period has passed. Anyone can call it.
+        /// It removes the token from its owner, clears its approval and resource locator, drops it from the global index
+        /// and from its category's supply, and emits a Transfer event to the zero address.
+        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
+        #[ink(message)]
+        pub fn execute_burn(&mut self, id: TokenId) -> Result<(), Error> {
+            self.ensure_unlocked()?;
+            let owner = self.owner_of(id).ok_or(Error::TokenNotFound)?;
+            let executable_at = self.pending_burns.get(id).ok_or(Error::CannotFetchValue)?;
+            if self.env().block_timestamp() < executable_at {
+                return Err(Error::NotAllowed)
+            }
+
+            self.pending_burns.remove(id);
+            self.remove_token_from(&owner, id)?;
+            self.clear_approval(&owner, id);
+            self.token_resource_locator.remove(id);
+            self.token_uri_long.remove(id);
+            self.remove_from_index(id);
+            self.last_transfer_at.remove(id);
+            self.token_royalties.remove(id);
+            let category = self.category.take(id).unwrap_or(0);
+            let supply = self.supply_of_category(category);
+            self.category_supply.insert(category, &supply.saturating_sub(1));
+
+            self.env().emit_event(Transfer {
+                from: Some(owner),
+                to: Some(AccountId::from([0x0; 32])),
+                token_id: id
+            });
+            self.fold_digest(DIGEST_BURN, id, owner, AccountId::from([0x0; 32]));
+
+            // The token is fully removed before the registry is called.
+            if let Some(registry) = self.registry {
+                self.locked.set(&true);
+                self.notify_registry(registry, id);
+                self.locked.set(&false);
+            }
+            Ok(())
+        }
+
+        /// This function starts the recovery of a token whose owner lost their key, to be transferred to new_owner.
+        /// The owner can veto it with veto_recovery for RECOVERY_CHALLENGE_PERIOD, after which anyone can finalize it
+        /// with finalize_recovery. Only the contract owner or the recovery council may call it, and a token has at most
+        /// one pending recovery. The token cannot be transferred until the recovery is vetoed or finalized, so it is still
+        /// with the owner the recovery was started against when it is finalized.
+        /// It emits a RecoveryInitiated event with the time from which the recovery can be finalized.
+        #[ink(message)]
+        pub fn initiate_recovery(&mut self, id: TokenId, new_owner: AccountId) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if caller != self.owner && Some(caller) != self.recovery_council {
+                return Err(Error::NotOwner)
+            }
+            let owner = self.owner_of(id).ok_or(Error::TokenNotFound)?;
+            if new_owner == AccountId::from([0x0; 32]) {
+                return Err(Error::ZeroAddress)
+            }
+            if new_owner == owner || new_owner == self.env().account_id() {
+                return Err(Error::NotAllowed)
+            }
+            if self.recoveries.contains(id) {
+                return Err(Error::RecoveryPending)
+            }
+
+            let finalizable_at = self.env().block_timestamp().saturating_add(RECOVERY_CHALLENGE_PERIOD);
+            self.recoveries.insert(id, &(new_owner, finalizable_at));
+
+            self.env().emit_event(RecoveryInitiated {
+                token_id: id,
+                new_owner,
+                initiated_by: caller,
+                finalizable_at
+            });
+            Ok(())
+        }
+
+        /// This function cancels the pending recovery of a token owned by the caller, which still holds its key,
+        /// and emits a RecoveryVetoed event. It can only be called during the challenge period.
+        #[ink(message)]
+        pub fn veto_recovery(&mut self, id: TokenId) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if self.owner_of(id).ok_or(Error::TokenNotFound)? != caller {
+                return Err(Error::NotOwner)
+            }
+            let (_, finalizable_at) = self.recoveries.get(id).ok_or(Error::RecoveryNotFound)?;
+            if self.env().block_timestamp() >= finalizable_at {
+                return Err(Error::RecoveryWindowClosed)
+            }
+
+            self.recoveries.remove(id);
+            self.env().emit_event(RecoveryVetoed {
+                token_id: id,
+                owner: caller
+            });
+            Ok(())
+        }
+
+        /// This function finalizes the recovery of a token once its challenge period has passed. Anyone can call it.
+        /// It moves the token to the new owner, whatever its category, cooldown or approvals, clears its approval
+        /// and any pending burn, and records the new owner in the ownership history with the recovery marker.
+        /// It emits a Transfer event and a RecoveryFinalized event.
+        #[ink(message)]
+        pub fn finalize_recovery(&mut self, id: TokenId) -> Result<(), Error> {
+            self.ensure_unlocked()?;
+            let owner = self.owner_of(id).ok_or(Error::TokenNotFound)?;
+            let (new_owner, finalizable_at) = self.recoveries.get(id).ok_or(Error::RecoveryNotFound)?;
+            if self.env().block_timestamp() < finalizable_at {
+                return Err(Error::RecoveryPending)
+            }
+
+            self.recoveries.remove(id);
+            self.pending_burns.remove(id);
+            self.remove_token_from(&owner, id)?;
+            self.clear_approval(&owner, id);
+            self.add_token_to(&new_owner, id)?;
+            self.last_transfer_at.insert(id, &self.env().block_timestamp());
+            self.record_owner(id, new_owner, true);
+
+            self.env().emit_event(Transfer {
+                from: Some(owner),
+                to: Some(new_owner),
+                token_id: id
+            });
+            self.fold_digest(DIGEST_TRANSFER, id, owner, new_owner);
+            self.env().emit_event(RecoveryFinalized {
+                token_id: id,
+                from: owner,
+                to: new_owner
+            });
+            Ok(())
+        }
+
+        /// This function returns the pending recovery of a token, if it has one.
+        #[ink(message)]
+        pub fn recovery_of(&self, id: TokenId) -> Option<Recovery> {
+            self.recoveries.get(id)
+        }
+
+        /// This function sets or clears the recovery council, the account that may start recoveries besides the
+        /// contract owner. Only the contract owner may call it.
+        #[ink(message)]
+        pub fn set_recovery_council(&mut self, council: Option<AccountId>) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.recovery_council = council;
+            Ok(())
+        }
+
+        /// This function returns the recovery council, if any.
+        #[ink(message)]
+        pub fn recovery_council(&self) -> Option<AccountId> {
+            self.recovery_council
+        }
+
+        /// This function sets or clears the marketplace allowed to transfer access-consent tokens, whose ids have
+        /// CONSENT_TOKEN_FLAG set. Every other transfer of such a token fails with TokenNotTransferable.
+        /// Only the contract owner may call it.
+        #[ink(message)]
+        pub fn set_consent_market(&mut self, market: Option<AccountId>) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.consent_market = market;
+            Ok(())
+        }
+
+        /// This function returns the marketplace allowed to transfer access-consent tokens, if any.
+        #[ink(message)]
+        pub fn consent_market(&self) -> Option<AccountId> {
+            self.consent_market
+        }
+
+        /// This function returns up to `limit` entries of a token's ownership history starting at position `start`.
+        #[ink(message)]
+        pub fn ownership_history_of(&self, id: TokenId, start: u32, limit: u32) -> Vec<OwnershipRecord> {
+            let count = self.ownership_history_count.get(id).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            (start..end).filter_map(|index| self.ownership_history.get((id, index))).collect()
+        }
+
+        /// This function returns the time from which the pending burn of a token can be executed, if it has one.
+        #[ink(message)]
+        pub fn pending_burn(&self, id: TokenId) -> Option<Timestamp> {
+            self.pending_burns.get(id)
+        }
+
+        /// This function sets how long, in milliseconds, a requested burn waits before it can be executed.
+        /// Burns already requested keep the time they were given. Only the contract owner may call it.
+        #[ink(message)]
+        pub fn set_burn_grace_period(&mut self, grace_period: Timestamp) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.burn_grace_period = grace_period;
+            Ok(())
+        }
+
+        /// This function returns the burn grace period in milliseconds.
+        #[ink(message)]
+        pub fn burn_grace_period(&self) -> Timestamp {
+            self.burn_grace_period
+        }
+
+        /// This function returns the contract owner, who manages the contract and may call its owner-only functions.
+        #[ink(message)]
+        pub fn owner(&self) -> AccountId {
+            self.owner
+        }
+
+        /// This function sets the EPR contract notified when a token is burned, or stops notifications with None.
+        /// Only the contract owner may call it.
+        #[ink(message)]
+        pub fn set_registry(&mut self, registry: Option<AccountId>) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.registry = registry;
+            Ok(())
+        }
+
+        /// This function returns the EPR contract notified when a token is burned.
+        #[ink(message)]
+        pub fn registry(&self) -> Option<AccountId> {
+            self.registry
+        }
+
+        /// This function registers a contract implementing TokenListener to be notified of every mint.
+        /// Only the contract owner may call it, and at most MAX_LISTENERS contracts can be registered.
+        #[ink(message)]
+        pub fn add_listener(&mut self, listener: AccountId) -> Result<(), Error> {
+            self.ensure_owner()?;
+            if self.listeners.contains(&listener) {
+                return Err(Error::ListenerExists)
+            }
+            if self.listeners.len() >= MAX_LISTENERS {
+                return Err(Error::TooManyListeners)
+            }
+            self.listeners.push(listener);
+            Ok(())
+        }
+
+        /// This function stops notifying a listener of mints. Only the contract owner may call it.
+        #[ink(message)]
+        pub fn remove_listener(&mut self, listener: AccountId) -> Result<(), Error> {
+            self.ensure_owner()?;
+            let position = self.listeners.iter().position(|l| *l == listener).ok_or(Error::ListenerNotFound)?;
+            self.listeners.remove(position);
+            Ok(())
+        }
+
+        /// This function returns the contracts notified of every mint, in registration order.
+        #[ink(message)]
+        pub fn listeners(&self) -> Vec<AccountId> {
+            self.listeners.clone()
+        }
+
+        /// This function sets how long, in milliseconds, a token has to stay with its owner after a mint or transfer
+        /// before it can be transferred again. A cooldown of 0 disables it. Only the contract owner may call it.
+        #[ink(message)]
+        pub fn set_transfer_cooldown(&mut self, cooldown: Timestamp) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.transfer_cooldown = cooldown;
+            Ok(())
+        }
+
+        /// This function returns the transfer cooldown in milliseconds.
+        #[ink(message)]
+        pub fn transfer_cooldown(&self) -> Timestamp {
+            self.transfer_cooldown
+        }
+
+        /// This function returns when a token was last minted or transferred.
+        #[ink(message)]
+        pub fn last_transfer_at(&self, id: TokenId) -> Option<Timestamp> {
+            self.last_transfer_at.get(id)
+        }
+
+        /// This function exempts an account, such as the marketplace, from the transfer cooldown for the transfers it makes.
+        /// Only the contract owner may call it.
+        #[ink(message)]
+        pub fn exempt(&mut self, account: AccountId) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.cooldown_exempt.insert(account, &());
+            Ok(())
+        }
+
+        /// This function holds an exempted account to the transfer cooldown again. Only the contract owner may call it.
+        #[ink(message)]
+        pub fn remove_exemption(&mut self, account: AccountId) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.cooldown_exempt.remove(account);
+            Ok(())
+        }
+
+        /// This function returns whether an account's transfers bypass the transfer cooldown.
+        #[ink(message)]
+        pub fn is_exempt(&self, account: AccountId) -> bool {
+            self.cooldown_exempt.contains(account)
+        }
+
+        /// This function moves a token stranded on the contract's own address to a recipient.
+        /// Only the contract owner may call it, and only for tokens currently owned by the contract itself.
+        /// It clears any approval left on the token and emits a Transfer event.
+        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
+        #[ink(message)]
+        pub fn rescue_token(&mut self, id: TokenId, to: AccountId) -> Result<(), Error> {
+            self.ensure_owner()?;
+
+            let contract = self.env().account_id();
+            if self.owner_of(id).ok_or(Error::TokenNotFound)? != contract {
+                return Err(Error::NotAllowed)
+            }
+
+            if to == contract {
+                return Err(Error::NotAllowed)
+            }
+
+            self.remove_token_from(&contract, id)?;
+            self.add_token_to(&to, id)?;
+            self.clear_approval(&contract, id);
+            self.last_transfer_at.insert(id, &self.env().block_timestamp());
+            self.record_owner(id, to, false);
+
+            self.env().emit_event(Transfer {
+                from: Some(contract),
+                to: Some(to),
+                token_id: id
+            });
+            self.fold_digest(DIGEST_TRANSFER, id, contract, to);
+            Ok(())
+        }
+
+        ////////////////////////////////
+        //////// Reserved Ranges ///////
+        ////////////////////////////////
+
+        /// This function reserves an inclusive range of token ids for a minter, such as one EPR instance among several
+        /// writing into this contract. Only the minter can mint the ids of its ranges, and ids outside every range
+        /// can only be minted by the contract owner. Ranges cannot overlap, and at most MAX_RESERVED_RANGES exist.
+        /// Only the contract owner may call it.
+        #[ink(message)]
+        pub fn reserve_range(&mut self, minter: AccountId, from_id: TokenId, to_id: TokenId) -> Result<(), Error> {
+            self.ensure_owner()?;
+            if from_id > to_id {
+                return Err(Error::InvalidRange)
+            }
+            if self.reserved_ranges.iter().any(|(first, last, _)| from_id <= *last && *first <= to_id) {
+                return Err(Error::InvalidRange)
+            }
+            if self.reserved_ranges.len() >= MAX_RESERVED_RANGES {
+                return Err(Error::TooManyRanges)
+            }
+
+            self.reserved_ranges.push((from_id, to_id, minter));
+            self.env().emit_event(RangeReserved {
+                minter,
+                from_id,
+                to_id
+            });
+            Ok(())
+        }
+
+        /// This function returns the id ranges reserved for a minter, in the order they were reserved.
+        #[ink(message)]
+        pub fn ranges_of(&self, minter: AccountId) -> Vec<TokenRange> {
+            self.reserved_ranges
+                .iter()
+                .filter(|(_, _, owner)| *owner == minter)
+                .map(|(first, last, _)| (*first, *last))
+                .collect()
+        }
+
+        /// This function returns the minter a token id is reserved for, if any.
+        #[ink(message)]
+        pub fn range_owner(&self, id: TokenId) -> Option<AccountId> {
+            self.reserved_ranges
+                .iter()
+                .find(|(first, last, _)| *first <= id && id <= *last)
+                .map(|(_, _, minter)| *minter)
+        }
+
+        ////////////////////////////////
+        ////////// Snapshots ///////////
+        ////////////////////////////////
+
+        /// This function takes a snapshot of every balance and of the total supply, and returns its id.
+        /// Nothing is copied when the snapshot is taken: each account's balance is recorded on its first change after it.
+        /// Only the contract owner may call it.
+        #[ink(message)]
+        pub fn create_snapshot(&mut self) -> Result<u32, Error> {
+            self.ensure_owner()?;
+            let id = self.snapshot_count + 1;
+            let block = self.env().block_number();
+            self.snapshot_count = id;
+            self.snapshots.insert(id, &(block, self.total_supply));
+            self.env().emit_event(SnapshotCreated {
+                id,
+                block,
+                total_supply: self.total_supply
+            });
+            Ok(id)
+        }
+
+        /// This function returns the block a snapshot was taken at and the total supply then, or None for an unknown id.
+        #[ink(message)]
+        pub fn snapshot(&self, id: u32) -> Option<Snapshot> {
+            self.snapshots.get(id)
+        }
+
+        /// This function returns the total supply when a snapshot was taken, or 0 for an unknown id.
+        #[ink(message)]
+        pub fn total_supply_at(&self, id: u32) -> u32 {
+            self.snapshots.get(id).map(|(_, supply)| supply).unwrap_or(0)
+        }
+
+        /// This function returns the balance of an account when a snapshot was taken, or 0 for an unknown id.
+        /// The balance is the one recorded by the account's first change at or after the snapshot,
+        /// or its current balance if it has not changed since.
+        #[ink(message)]
+        pub fn balance_at(&self, id: u32, account: AccountId) -> u32 {
+            if id == 0 || id > self.snapshot_count {
+                return 0
+            }
+            // Binary search for the first entry recorded for a snapshot at or after `id`.
+            let (mut low, mut high) = (0, self.balance_snapshot_count.get(account).unwrap_or(0));
+            while low < high {
+                let middle = low + (high - low) / 2;
+                match self.balance_snapshots.get((account, middle)) {
+                    Some((snapshot, _)) if snapshot < id => low = middle + 1,
+                    _ => high = middle
+                }
+            }
+            match self.balance_snapshots.get((account, low)) {
+                Some((_, balance)) => balance,
+                None => self.balance_of_or_zero(&account)
+            }
+        }
+
+        ////////////////////////////////
+        ////////// Royalties ///////////
+        ////////////////////////////////
+
+        /// This function sets the royalty paid to a receiver on every sale of a token, in basis points of the price.
+        /// It overrides the default royalty for that token. Only the contract owner may call it.
+        #[ink(message)]
+        pub fn set_royalty(&mut self, id: TokenId, receiver: AccountId, bps: u16) -> Result<(), Error> {
+            self.ensure_owner()?;
+            if !self.exists(id) {
+                return Err(Error::TokenNotFound)
+            }
+            if bps > MAX_ROYALTY_BPS {
+                return Err(Error::InvalidRoyalty)
+            }
+            self.token_royalties.insert(id, &(receiver, bps));
+            Ok(())
+        }
+
+        /// This function removes the royalty of a token, which falls back to the default royalty.
+        /// Only the contract owner may call it.
+        #[ink(message)]
+        pub fn clear_royalty(&mut self, id: TokenId) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.token_royalties.take(id).ok_or(Error::CannotFetchValue)?;
+            Ok(())
+        }
+
+        /// This function sets the royalty of every token without a royalty of its own, in basis points of the price.
+        /// Only the contract owner may call it.
+        #[ink(message)]
+        pub fn set_default_royalty(&mut self, receiver: AccountId, bps: u16) -> Result<(), Error> {
+            self.ensure_owner()?;
+            if bps > MAX_ROYALTY_BPS {
+                return Err(Error::InvalidRoyalty)
+            }
+            self.default_royalty = Some((receiver, bps));
+            Ok(())
+        }
+
+        /// This function removes the default royalty. Only the contract owner may call it.
+        #[ink(message)]
+        pub fn clear_default_royalty(&mut self) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.default_royalty = None;
+            Ok(())
+        }
+
+        /// This function returns the default royalty, if any.
+        #[ink(message)]
+        pub fn default_royalty(&self) -> Option<Royalty> {
+            self.default_royalty
+        }
+
+        /// This function returns who receives a royalty on a sale of a token at the given price, and how much.
+        /// The token's own royalty is used if it has one, the default royalty otherwise.
+        /// It returns None for tokens that do not exist and when no royalty applies.
+        #[ink(message)]
+        pub fn royalty_info(&self, id: TokenId, sale_price: Balance) -> Option<(AccountId, Balance)> {
+            if !self.exists(id) {
+                return None
+            }
+            let (receiver, bps) = self.token_royalties.get(id).or(self.default_royalty)?;
+            let bps = Balance::from(bps);
+            let whole = Balance::from(MAX_ROYALTY_BPS);
+            Some((receiver, sale_price / whole * bps + sale_price % whole * bps / whole))
+        }
+
+        ////////////////////////////////
+        ////// Feature Detection////////
+        ////////////////////////////////
+
+        /// This function returns whether the contract implements the extension with the given feature id,
+        /// one of the FEATURE_* constants, so tooling need not probe its messages.
+        #[ink(message)]
+        pub fn supports(&self, feature: [u8; 4]) -> bool {
+            SUPPORTED_FEATURES.contains(&feature)
+        }
+
+        /// This function returns the rolling digest of every mint, transfer and burn, so an indexer replaying the
+        /// Transfer events can check its state against the contract's. It starts as the zero hash, and every Transfer
+        /// event replaces it with the Blake2x256 hash of the SCALE-encoded tuple
+        /// (previous digest: Hash, operation: u8, token_id: TokenId, from: AccountId, to: AccountId),
+        /// where from and to are the event's accounts, the zero address standing for none, and operation is
+        /// DIGEST_MINT (0) from the zero address, DIGEST_BURN (2) to it and DIGEST_TRANSFER (1) otherwise.
+        /// Events are folded in the order they were emitted.
+        #[ink(message)]
+        pub fn state_digest(&self) -> Hash {
+            self.state_digest
+        }
+
+        /// This function returns the block of the latest update of the state digest.
+        #[ink(message)]
+        pub fn digest_block(&self) -> BlockNumber {
+            self.digest_block
+        }
+
+        /// This function returns the feature ids of every extension the contract implements.
+        #[ink(message)]
+        pub fn supported_features(&self) -> Vec<[u8; 4]> {
+            SUPPORTED_FEATURES.to_vec()
+        }
+
+        /// This function returns the discriminant and name of every Error variant.
+        #[ink(message)]
+        pub fn error_catalog(&self) -> Vec<(u8, String)> {
+            ERROR_NAMES
+                .iter()
+                .enumerate()
+                .map(|(code, name)| (code as u8, String::from(*name)))
+                .collect()
+        }
+
+        ////////////////////////////////
+
+        ////////////////////////////////
+        ////// Enumeration Extension////
+        ////////////////////////////////
+
+        /// This function returns the number of tokens currently in existence.
+        #[ink(message)]
+        pub fn total_supply(&self) -> u32 {
+            self.total_supply
+        }
+
+        /// This function returns the TokenId at a position of the global index.
+        /// Positions are dense in `0..total_supply`, but the order changes when tokens are burned.
+        #[ink(message)]
+        pub fn token_by_index(&self, index: u32) -> Option<TokenId> {
+            self.all_tokens.get(index)
+        }
+
+        /// This function returns the TokenId at a position of an owner's index.
+        /// Positions are dense in `0..balance_of(owner)`, but the order changes when the owner's tokens move.
+        #[ink(message)]
+        pub fn token_of_owner_by_index(&self, owner: AccountId, index: u32) -> Option<TokenId> {
+            self.owned_tokens.get((owner, index))
+        }
+
+        /// This function returns up to `limit` TokenIds of the global index starting at position `start`.
+        #[ink(message)]
+        pub fn token_ids(&self, start: u32, limit: u32) -> Vec<TokenId> {
+            let end = start.saturating_add(limit).min(self.total_supply);
+            (start..end).filter_map(|index| self.all_tokens.get(index)).collect()
+        }
+
+        /// This function sets whether the tokens of a category can be transferred. Tokens of a non-transferable
+        /// category stay with the account they were minted to: every transfer path fails with TokenNotTransferable,
+        /// while their holder can still burn them. Categories are transferable by default.
+        /// Only the contract owner can call it.
+        #[ink(message)]
+        pub fn set_category_transferable(&mut self, category: u8, transferable: bool) -> Result<(), Error> {
+            self.ensure_owner()?;
+            if transferable {
+                self.non_transferable.remove(category);
+            } else {
+                self.non_transferable.insert(category, &());
+            }
+            Ok(())
+        }
+
+        /// This function returns whether the tokens of a category can be transferred.
+        #[ink(message)]
+        pub fn is_category_transferable(&self, category: u8) -> bool {
+            !self.non_transferable.contains(category)
+        }
+
+        /// This function returns whether a token exists and its category can be transferred.
+        /// The marketplace checks it before listing a token.
+        #[ink(message)]
+        pub fn is_transferable_token(&self, id: TokenId) -> bool {
+            self.category_of(id).is_some_and(|category| self.is_category_transferable(category))
+        }
+
+        /// This function returns the category a token was minted in, or None if the token does not exist.
+        #[ink(message)]
+        pub fn category_of(&self, id: TokenId) -> Option<u8> {
+            self.token_owner.contains(id).then(|| self.category.get(id).unwrap_or(0))
+        }
+
+        /// This function returns the number of tokens of a category currently in existence.
+        #[ink(message)]
+        pub fn supply_of_category(&self, category: u8) -> u32 {
+            self.category_supply.get(category).unwrap_or(0)
+        }
+
+        ////////////////////////////////
+        ////// Internal Functions///////
+        ////////////////////////////////
+
+        /// This function appends a token to the end of the global index.
+        fn add_to_index(&mut self, id: TokenId) {
+            let index = self.total_supply;
+            self.all_tokens.insert(index, &id);
+            self.all_tokens_index.insert(id, &index);
+            self.total_supply = index + 1;
+        }
+
+        /// This function removes a token from the global index.
+        /// The last token is moved into the freed position so the index stays dense.
+        fn remove_from_index(&mut self, id: TokenId) {
+            let Some(index) = self.all_tokens_index.get(id) else {
+                return
+            };
+            let last = self.total_supply - 1;
+
+            if index != last {
+                if let Some(moved) = self.all_tokens.get(last) {
+                    self.all_tokens.insert(index, &moved);
+                    self.all_tokens_index.insert(moved, &index);
+                }
+            }
+
+            self.all_tokens.remove(last);
+            self.all_tokens_index.remove(id);
+            self.total_supply = last;
+        }
+
+        /// This function transfers the last tokens of an owner's index, up to MAX_BULK_TRANSFER, and returns how many moved.
+        /// It walks the index downwards for at most MAX_BULK_SCAN positions and skips the tokens that cannot move right now,
+        /// so one of them does not block the rest. Moving a token fills its position with the last one of the index, which
+        /// is a skipped token or the token itself, so the positions left to walk are unchanged.
+        /// Transferring to the owner itself is rejected, since it would move the same token over and over.
+        fn transfer_all_tokens(&mut self, from: &AccountId, to: &AccountId) -> Result<u32, Error> {
+            if from == to {
+                return Err(Error::NotAllowed)
+            }
+
+            let operator = self.env().caller();
+            let mut position = self.balance_of_or_zero(from);
+            let stop = position.saturating_sub(MAX_BULK_SCAN);
+            let mut moved = 0;
+            while position > stop && moved < MAX_BULK_TRANSFER {
+                position -= 1;
+                let id = self.owned_tokens.get((from, position)).ok_or(Error::CannotFetchValue)?;
+                if self.check_movable(&operator, id).is_err() {
+                    continue
+                }
+                self.transfer_token_from(from, to, id)?;
+                moved += 1;
+            }
+            Ok(moved)
+        }
+
+        /// This function appends a new owner to a token's ownership history, marking owners who got it through a recovery.
+        fn record_owner(&mut self, id: TokenId, owner: AccountId, recovered: bool) {
+            let count = self.ownership_history_count.get(id).unwrap_or(0);
+            self.ownership_history.insert((id, count), &(owner, self.env().block_timestamp(), recovered));
+            self.ownership_history_count.insert(id, &(count + 1));
+        }
+
+        /// This function folds a Transfer event into the state digest, see state_digest.
+        fn fold_digest(&mut self, operation: u8, id: TokenId, from: AccountId, to: AccountId) {
+            self.state_digest = self.hash_of(&(self.state_digest, operation, id, from, to));
+            self.digest_block = self.env().block_number();
+        }
+
+        /// This function mints a token in a category to an account and emits a Transfer event from the zero address.
+        /// Minting to the contract's own address is rejected, since the contract cannot move its own holdings.
+        fn mint_token_to(&mut self, to: &AccountId, id: TokenId, category: u8) -> Result<(), Error> {
+            self.ensure_unlocked()?;
+            if *to == self.env().account_id() {
+                return Err(Error::NotAllowed)
+            }
+
+            self.add_token_to(to, id)?;
+            self.add_to_index(id);
+            self.last_transfer_at.insert(id, &self.env().block_timestamp());
+            self.record_owner(id, *to, false);
+            if category != 0 {
+                self.category.insert(id, &category);
+            }
+            let supply = self.supply_of_category(category);
+            self.category_supply.insert(category, &(supply + 1));
+            self.env().emit_event(Transfer {
+                from: Some(AccountId::from([0x0; 32])),
+                to: Some(*to),
+                token_id: id
+            });
+            self.fold_digest(DIGEST_MINT, id, AccountId::from([0x0; 32]), *to);
+
+            // The token is fully minted before the listeners are called.
+            if !self.listeners.is_empty() {
+                self.locked.set(&true);
+                self.notify_listeners(*to, id);
+                self.locked.set(&false);
+            }
+            Ok(())
+        }
+
+        
+        /// This function checks the number of tokens owned by a specific account.
+        /// It attempts to get the balance of an account from the owned_tokens_count map.
+        /// If the account does not exist in the map (i.e., it does not own any tokens), it returns 0.
+        fn balance_of_or_zero(&self, of: &AccountId) -> u32 {
+            self.owned_tokens_count.get(of).unwrap_or(0)
+        }
+
+        /// This function records the balance of an account for the latest snapshot, unless it has been recorded already
+        /// or there is no snapshot. It is called before every change of the account's balance.
+        fn snapshot_balance(&mut self, account: &AccountId) {
+            let latest = self.snapshot_count;
+            if latest == 0 {
+                return
+            }
+            let count = self.balance_snapshot_count.get(account).unwrap_or(0);
+            if count > 0 && self.balance_snapshots.get((account, count - 1)).is_some_and(|(id, _)| id == latest) {
+                return
+            }
+            let balance = self.balance_of_or_zero(account);
+            self.balance_snapshots.insert((account, count), &(latest, balance));
+            self.balance_snapshot_count.insert(account, &(count + 1));
+        }
+
+        /// This function adds a token to a specific account.
+        /// It first checks if the token with the provided ID already exists, and if it does, it returns an error.
+        /// If the account to receive the token is the zero address, it also returns an error.
+        /// It then records the receiving account's balance for the latest snapshot if needed, increases its token count,
+        /// adds the token to the account's ownership and appends it to the account's index.
+        /// The function will return Ok if the operation was successful, or an error if it wasn't.
+        fn add_token_to(&mut self, to: &AccountId, id: TokenId) -> Result<(), Error> {
+            if self.token_owner.contains(id) {
+                return Err(Error::TokenExists)
+            };
+
+            if *to == AccountId::from([0x0; 32]) {
+                return Err(Error::NotAllowed)
+            }
+
+            self.snapshot_balance(to);
+            let Self {
+                token_owner,
+                owned_tokens_count,
+                owned_tokens,
+                owned_tokens_index,
+                ..
+            } = self;
+            let index = owned_tokens_count.get(to).unwrap_or(0);
+            let count = index + 1;
+            
+            owned_tokens_count.insert(to, &count);
+            token_owner.insert(id, to);
+            owned_tokens.insert((to, index), &id);
+            owned_tokens_index.insert(id, &index);
+
+            Ok(())
+
+        }
+        
+        /// This function transfers a token from one account to another.
+        /// It first checks if the token exists, and if it doesn't, it returns an error.
+        /// It also checks that `from` owns the token and that the caller is the owner, approved, or an operator.
+        /// Transfers to the contract's own address are rejected, since tokens sent there could not be moved again,
+        /// and transfers to the zero address fail with ZeroAddress. Tokens with a pending burn fail with BurnPending.
+        /// All of these checks run before any storage is changed, so a failed transfer leaves the token where it was.
+        /// It then removes the token from the sender's account, clears its approval and adds it to the recipient's account.
+        /// After transferring the token, it emits a Transfer event.
+        /// The function will return Ok if the operation was successful, or an error if it wasn't.
+        fn transfer_token_from(&mut self, from: &AccountId, to: &AccountId, id: TokenId) -> Result<(), Error> {
+            self.ensure_unlocked()?;
+            let msg_sender: AccountId = self.env().caller();
+
+            if !self.exists(id) {
+                return Err(Error::TokenNotFound)
+            };
+
+            if self.owner_of(id) != Some(*from) {
+                return Err(Error::NotOwner)
+            };
+
+            if !self.is_approved_or_owner(msg_sender, id) {
+                return Err(Error::NotApproved)
+            };
+
+            if *to == self.env().account_id() {
+                return Err(Error::NotAllowed)
+            };
+
+            if *to == AccountId::from([0x0; 32]) {
+                return Err(Error::ZeroAddress)
+            };
+
+            self.check_movable(&msg_sender, id)?;
+
+            self.remove_token_from(from, id)?;
+            self.clear_approval(from, id);
+            self.add_token_to(to, id)?;
+            self.last_transfer_at.insert(id, &self.env().block_timestamp());
+            self.record_owner(id, *to, false);
+
+            self.env().emit_event(Transfer {
+                from: Some(*from),
+                to: Some(*to),
+                token_id: id
+            });
+            self.fold_digest(DIGEST_TRANSFER, id, *from, *to);
+
+            Ok(())
+        }
+
+        /// This function checks the restrictions on a token itself that keep it from moving right now: a cooldown the
+        /// operator is not exempt from, a pending burn or recovery, a non-transferable category, or an access-consent
+        /// token moved by anyone but the consent market.
+        fn check_movable(&self, operator: &AccountId, id: TokenId) -> Result<(), Error> {
+            if self.is_cooling_down(id) && !self.cooldown_exempt.contains(operator) {
+                return Err(Error::CooldownActive)
+            };
+
+            if self.pending_burns.contains(id) {
+                return Err(Error::BurnPending)
+            };
+
+            if self.recoveries.contains(id) {
+                return Err(Error::RecoveryPending)
+            };
+
+            if !self.is_category_transferable(self.category.get(id).unwrap_or(0)) {
+                return Err(Error::TokenNotTransferable)
+            };
+
+            if id & CONSENT_TOKEN_FLAG != 0 && self.consent_market != Some(*operator) {
+                return Err(Error::TokenNotTransferable)
+            };
+
+            Ok(())
+        }
+
+        /// This function transfers a token like transfer_token_from and then emits a TransferWithData event.
+        /// It rejects data longer than MAX_TRANSFER_DATA_LEN before moving the token.
+        fn transfer_token_with_data(&mut self, from: &AccountId, to: &AccountId, id: TokenId, data: Vec<u8>) -> Result<(), Error> {
+            if data.len() > MAX_TRANSFER_DATA_LEN {
+                return Err(Error::DataTooLarge)
+            }
+
+            self.transfer_token_from(from, to, id)?;
+
+            self.env().emit_event(TransferWithData {
+                from: *from,
+                to: *to,
+                token_id: id,
+                data_hash: self.hash_of(&data),
+                data
+            });
+
+            Ok(())
+        }
+
+        /// This function removes a token from a specific account.
+        /// It first checks if the token exists, and if it doesn't, it returns an error.
+        /// It then records the account's balance for the latest snapshot if needed, decreases its token count
+        /// and removes the token from the account's ownership.
+        /// The account's last token is moved into the freed position of its index so the index stays dense.
+        /// The function will return Ok if the operation was successful, or an error if it wasn't.
+        fn remove_token_from(&mut self, from: &AccountId, id: TokenId) -> Result<(), Error> {
+            if !self.token_owner.contains(id) {
+                return Err(Error::TokenNotFound)
+            };
+
+            self.snapshot_balance(from);
+            let Self {
+                token_owner,
+                owned_tokens_count,
+                owned_tokens,
+                owned_tokens_index,
+                ..
+            } = self;
+            let count = owned_tokens_count.get(from).map(|c| c - 1).ok_or(Error::CannotFetchValue)?;
+            
+            owned_tokens_count.insert(from, &count);
+            token_owner.remove(id);
+
+            if let Some(index) = owned_tokens_index.take(id) {
+                if index != count {
+                    if let Some(moved) = owned_tokens.get((from, count)) {
+                        owned_tokens.insert((from, index), &moved);
+                        owned_tokens_index.insert(moved, &index);
+                    }
+                }
+                owned_tokens.remove((from, count));
+            }
+
+            Ok(())
+        }
+
+        /// This function returns true once the approval of a token has reached its expiry.
+        fn is_approval_expired(&self, id: TokenId) -> bool {
+            self.approval_expiry.get(id).is_some_and(|valid_until| valid_until <= self.env().block_timestamp())
+        }
+
+        /// This function removes the approval of a token, if any, and emits an Approval event with no spender
+        /// so indexers can follow approvals from the event stream alone.
+        fn clear_approval(&mut self, owner: &AccountId, id: TokenId) {
+            self.approval_expiry.remove(id);
+            if self.token_approvals.take(id).is_some() {
+                self.env().emit_event(Approval {
+                    owner: *owner,
+                    spender: None,
+                    token_id: id
+                });
+            }
+        }
+
+        /// This function approves an account to manage a specific token on behalf of its owner.
+        /// It first checks if the caller is the owner of the token, and if it's not, it returns an error.
+        /// It also checks if the account to be approved is the zero address or if the token is already approved, and if either is true, it returns an error.
+        /// An expired approval counts as absent and is replaced.
+        /// If everything is in order, it adds the account to the token's approvals, along with the expiry if there is one.
+        /// After approving the account, it emits an Approval event.
+        /// The function will return Ok if the operation was successful, or an error if it wasn't.
+        fn approve_for(&mut self, address: &AccountId, token_id: TokenId, valid_until: Option<Timestamp>) -> Result<(), Error> {
+            let msg_sender: AccountId = self.env().caller();
+            let owner: Option<AccountId> = self.owner_of(token_id);
+
+            if owner != Some(msg_sender) {
+                return Err(Error::NotOwner)
+            };
+
+            if *address == AccountId::from([0x0; 32]) {
+                return Err(Error::NotAllowed)
+            }
+
+            if valid_until.is_some_and(|valid_until| valid_until <= self.env().block_timestamp()) {
+                return Err(Error::InvalidExpiry)
+            }
+
+            if self.get_approved(token_id).is_some() {
+                return Err(Error::NotAllowed)
+            } else {
+                self.token_approvals.insert(token_id, address);
+                match valid_until {
+                    Some(valid_until) => {
+                        self.approval_expiry.insert(token_id, &valid_until);
+                    }
+                    None => self.approval_expiry.remove(token_id)
+                }
+            }
+
+            self.env().emit_event(Approval {
+                owner: msg_sender,
+                spender: Some(*address),
+                token_id
+            });
+
+            Ok(())
+        }
+
+
+        ////////////////////////////////
+        ////// Metadata Extension///////
+        ////////////////////////////////
+        
+        /// This function retrieves the name of the token contract.
+        /// It clones the token name from the contract's state and returns it.
+        #[ink(message)]
+        pub fn name(&self) -> String {
+            self.token_name.clone()
+        }
+
+        /// This function retrieves the symbol of the token contract.
+        /// It clones the token symbol from the contract's state and returns it.
+        #[ink(message)]
+        pub fn symbol(&self) -> String {
+            self.token_symbol.clone()
+        }
+
+        /// This function renames the token contract.
+        /// Only the contract owner may call it. It emits a MetadataChanged event.
+        #[ink(message)]
+        pub fn set_name(&mut self, name: String) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.token_name = name;
+            self.emit_metadata_changed();
+            Ok(())
+        }
+
+        /// This function changes the symbol of the token contract.
+        /// Only the contract owner may call it. It emits a MetadataChanged event.
+        #[ink(message)]
+        pub fn set_symbol(&mut self, symbol: String) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.token_symbol = symbol;
+            self.emit_metadata_changed();
+            Ok(())
+        }
+
+        /// This function emits a MetadataChanged event carrying the current name and symbol.
+        fn emit_metadata_changed(&self) {
+            self.env().emit_event(MetadataChanged {
+                name: self.token_name.clone(),
+                symbol: self.token_symbol.clone()
+            });
+        }
+
+        /// This function retrieves the Uniform Resource Identifier (URI) of a specific token.
+        /// The URI is a unique identifier for the token in a given context.
+        /// It retrieves the stored value from the token_resource_locator map, or from token_uri_long for long values.
+        /// A relative value is treated as a suffix and returned after the base URI; an absolute value (containing "://") is returned as is.
+        /// If the token does not exist (i.e., it does not have an URI), it returns None.
+        #[ink(message)]
+        pub fn token_uri(&self, id: TokenId) -> Option<String> {
+            let stored = self.stored_uri(id)?;
+            if stored.contains("://") {
+                return Some(stored)
+            }
+
+            let mut uri = self.base_uri.clone();
+            uri.push_str(&stored);
+            Some(uri)
+        }
+
+        /// This function retrieves the base URI prepended to relative token URIs.
+        #[ink(message)]
+        pub fn base_uri(&self) -> String {
+            self.base_uri.clone()
+        }
+
+        /// This function changes the base URI prepended to relative token URIs.
+        /// Only the contract owner may change it. Composed token URIs change immediately.
+        /// It emits a BaseUriChanged event.
+        #[ink(message)]
+        pub fn set_base_uri(&mut self, base_uri: String) -> Result<(), Error> {
+            self.ensure_owner()?;
+
+            let old = core::mem::replace(&mut self.base_uri, base_uri.clone());
+            self.env().emit_event(BaseUriChanged {
+                old,
+                new: base_uri
+            });
+            Ok(())
+        }
+
+        /// This function returns the URI of the collection-level metadata, such as its logo, description and institution,
+        /// as looked up by marketplaces and explorers. It is empty when no collection metadata is set.
+        #[ink(message)]
+        pub fn contract_uri(&self) -> String {
+            self.collection_uri.clone()
+        }
+
+        /// This function changes the URI of the collection-level metadata. Only the contract owner may change it.
+        /// The URI is validated like token URIs; an empty one clears it. It emits a CollectionMetadataChanged event.
+        #[ink(message)]
+        pub fn set_collection_uri(&mut self, collection_uri: String) -> Result<(), Error> {
+            self.ensure_owner()?;
+            check_uri(&collection_uri)?;
+
+            let old = core::mem::replace(&mut self.collection_uri, collection_uri.clone());
+            self.env().emit_event(CollectionMetadataChanged {
+                old,
+                new: collection_uri
+            });
+            Ok(())
+        }
+
+        /// This function sets the Uniform Resource Identifier (URI) for a specific token.
+        /// The URI is a unique identifier for the token in a given context.
+        /// Only the owner of the token, its approved account, or an operator of the owner may set it.
+        /// It inserts the provided URI into the token_resource_locator map, or into token_uri_long when it is longer
+        /// than MAX_SHORT_URI_LEN, records the change in the token's URI history, and emits a TokenUriUpdated event.
+        /// The URI can be at most MAX_URI_LEN bytes long, otherwise it fails with UriTooLarge,
+        /// and cannot contain whitespace or control characters.
+        /// The function will return Ok if the operation was successful, or an error if it wasn't.
+        #[ink(message)]
+        pub fn set_token_uri(&mut self, id: TokenId, uri: String) -> Result<(), Error> {
+            let caller = self.env().caller();
+            check_token_uri(&uri)?;
+
+            if !self.exists(id) {
+                return Err(Error::TokenNotFound)
+            };
+
+            if !self.is_approved_or_owner(caller, id) {
+                return Err(Error::NotApproved)
+            };
+
+            self.update_token_uri(caller, id, uri);
+            Ok(())
+        }
+
+        /// This function returns up to `limit` entries of a token's URI history starting at position `start`.
+        /// Each entry holds the URI, the account that set it, and the block timestamp of the change.
+        #[ink(message)]
+        pub fn uri_history_of(&self, id: TokenId, start: u32, limit: u32) -> Vec<UriRecord> {
+            let count = self.uri_history_count.get(id).unwrap_or(0);
+            let end = start.saturating_add(limit).min(count);
+            (start..end).filter_map(|index| self.uri_history.get((id, index))).collect()
+        }
+
+        /// This function restores the URI a token had at a position of its history.
+        /// Only the owner of the token may roll back. The rollback is itself recorded in the history.
+        /// The function will return Ok if the operation was successful, or an error if it wasn't.
+        #[ink(message)]
+        pub fn rollback_uri(&mut self, id: TokenId, index: u32) -> Result<(), Error> {
+            let caller = self.env().caller();
+
+            if self.owner_of(id) != Some(caller) {
+                return Err(Error::NotOwner)
+            };
+
+            let (uri, _, _) = self.uri_history.get((id, index)).ok_or(Error::CannotFetchValue)?;
+            self.update_token_uri(caller, id, uri);
+            Ok(())
+        }
+
+        /// This function moves a token URI longer than MAX_SHORT_URI_LEN, stored before long URIs had their own mapping,
+        /// from token_resource_locator to token_uri_long. token_uri returns the same value before and after.
+        /// Anyone may call it. It returns whether a value was moved.
+        #[ink(message)]
+        pub fn migrate_uri(&mut self, id: TokenId) -> Result<bool, Error> {
+            if !self.exists(id) {
+                return Err(Error::TokenNotFound)
+            };
+
+            match self.token_resource_locator.get(id) {
+                Some(uri) if uri.len() > MAX_SHORT_URI_LEN => {
+                    self.token_resource_locator.remove(id);
+                    self.token_uri_long.insert(id, &uri.into_bytes());
+                    Ok(true)
+                }
+                _ => Ok(false)
+            }
+        }
+
+        ////////////////////////////////
+        ////// Voucher Extension////////
+        ////////////////////////////////
+
+        /// This function allows a key to sign mint vouchers. Only the contract owner may call it.
+        #[ink(message)]
+        pub fn add_voucher_signer(&mut self, signer: SignerKey) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.voucher_signers.insert(signer, &());
+            Ok(())
+        }
+
+        /// This function revokes a key's right to sign mint vouchers. Only the contract owner may call it.
+        /// Vouchers it signed earlier can no longer be redeemed.
+        #[ink(message)]
+        pub fn remove_voucher_signer(&mut self, signer: SignerKey) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.voucher_signers.remove(signer);
+            Ok(())
+        }
+
+        /// This function checks whether a key may sign mint vouchers.
+        #[ink(message)]
+        pub fn is_voucher_signer(&self, signer: SignerKey) -> bool {
+            self.voucher_signers.contains(signer)
+        }
+
+        /// This function redeems a mint voucher signed off-chain by a registered signer.
+        /// The voucher is a signature over the Blake2x256 hash of the SCALE-encoded (contract address, id, uri, caller),
+        /// so only the account it was issued to can redeem it, and only on this contract.
+        /// It mints the token to the caller, sets its URI and marks the voucher as consumed so it cannot be replayed.
+        /// A voucher signed with a key that is not registered fails with InvalidSignature, like a forged one.
+        /// This function is marked with the #[ink(message)] attribute making it callable from outside the contract.
+        #[ink(message)]
+        pub fn mint_with_voucher(
+            &mut self,
+            id: TokenId,
+            uri: String,
+            signer_pub: SignerKey,
+            signature: VoucherSignature
+        ) -> Result<(), Error> {
+            let caller = self.env().caller();
+            check_token_uri(&uri)?;
+
+            if !self.voucher_signers.contains(signer_pub) {
+                return Err(Error::InvalidSignature)
+            }
+
+            let voucher = self.env().hash_encoded::<Blake2x256, _>(&(self.env().account_id(), id, &uri, caller));
+            if self.consumed_vouchers.contains(voucher) {
+                return Err(Error::VoucherUsed)
+            }
+
+            let recovered = self.env()
+                .ecdsa_recover(&signature, &voucher)
+                .map_err(|_| Error::InvalidSignature)?;
+            if recovered != signer_pub {
+                return Err(Error::InvalidSignature)
+            }
+
+            // Vouchers are issued by the owner's signers, so they can mint unreserved ids only.
+            if self.range_owner(id).is_some() {
+                return Err(Error::IdOutOfRange)
+            }
+
+            self.mint_token_to(&caller, id, 0)?;
+            self.update_token_uri(caller, id, uri);
+            self.consumed_vouchers.insert(voucher, &());
+            Ok(())
+        }
+
+        /// This function calls on_token_burned on the registry so it can archive the record of a burned token.
+        /// The call is best effort: if it fails, the burn still succeeds and a RegistryNotifyFailed event is emitted.
+        fn notify_registry(&self, registry: AccountId, id: TokenId) {
+            let result = build_call::<Environment>()
+                .call(registry)
+                .exec_input(
+                    ExecutionInput::new(Selector::new(ink::selector_bytes!("on_token_burned")))
+                        .push_arg(id)
+                )
+                .returns::<Result<(), u8>>()
+                .try_invoke();
+
+            if !matches!(result, Ok(Ok(Ok(())))) {
+                self.env().emit_event(RegistryNotifyFailed {
+                    token_id: id,
+                    registry
+                });
+            }
+        }
+
+        /// This function returns true while a token is within the transfer cooldown of its last mint or transfer.
+        fn is_cooling_down(&self, id: TokenId) -> bool {
+            self.transfer_cooldown > 0
+                && self.last_transfer_at.get(id).is_some_and(|at| {
+                    self.env().block_timestamp() < at.saturating_add(self.transfer_cooldown)
+                })
+        }
+
+        /// This function returns ReentrancyDetected while the registry is being notified of a burn.
+        fn ensure_unlocked(&self) -> Result<(), Error> {
+            if self.locked.get().unwrap_or(false) {
+                return Err(Error::ReentrancyDetected)
+            }
+            Ok(())
+        }
+
+        /// This function calls on_minted on every listener, each with at most LISTENER_GAS_LIMIT gas.
+        /// The calls are best effort: a failing listener does not stop the mint or the other listeners,
+        /// and a ListenerFailed event is emitted for it.
+        fn notify_listeners(&self, to: AccountId, id: TokenId) {
+            for listener in &self.listeners {
+                let result = build_call::<Environment>()
+                    .call(*listener)
+                    .gas_limit(LISTENER_GAS_LIMIT)
+                    .exec_input(
+                        ExecutionInput::new(Selector::new(ink::selector_bytes!("TokenListener::on_minted")))
+                            .push_arg(id)
+                            .push_arg(to)
+                    )
+                    .returns::<()>()
+                    .try_invoke();
+
+                if !matches!(result, Ok(Ok(()))) {
+                    self.env().emit_event(ListenerFailed {
+                        token_id: id,
+                        listener: *listener
+                    });
+                }
+            }
+        }
+
+        /// This function returns IdOutOfRange unless the caller may mint the id: a reserved id by the minter it is
+        /// reserved for, an unreserved id by the contract owner.
+        fn ensure_mintable(&self, id: TokenId) -> Result<(), Error> {
+            let caller = self.env().caller();
+            let allowed = match self.range_owner(id) {
+                Some(minter) => minter == caller,
+                None => caller == self.owner
+            };
+            if !allowed {
+                return Err(Error::IdOutOfRange)
+            }
+            Ok(())
+        }
+
+        /// This function returns NotOwner unless the caller is the contract owner.
+        fn ensure_owner(&self) -> Result<(), Error> {
+            if self.env().caller() != self.owner {
+                return Err(Error::NotOwner)
+            }
+            Ok(())
+        }
+
+        /// This function returns the Blake2x256 hash of the SCALE-encoded URI stored for a token,
+        /// as carried in the uri_hash topic of TokenUriUpdated. The base URI is not part of the hash.
+        #[ink(message)]
+        pub fn hash_of_token_uri(&self, id: TokenId) -> Option<Hash> {
+            self.stored_uri(id).map(|uri| self.hash_of(&uri))
+        }
+
+        /// This function returns the URI stored for a token, from whichever mapping holds it.
+        fn stored_uri(&self, id: TokenId) -> Option<String> {
+            self.token_resource_locator
+                .get(id)
+                .or_else(|| self.token_uri_long.get(id).and_then(|bytes| String::from_utf8(bytes).ok()))
+        }
+
+        /// This function returns the Blake2x256 hash of a SCALE-encoded value.
+        fn hash_of<T: scale::Encode>(&self, value: &T) -> Hash {
+            Hash::from(self.env().hash_encoded::<Blake2x256, _>(value))
+        }
+
+        /// This function stores a token's URI, appends it to the URI history and emits a TokenUriUpdated event.
+        fn update_token_uri(&mut self, actor: AccountId, id: TokenId, uri: String) {
+            let count = self.uri_history_count.get(id).unwrap_or(0);
+            let now = self.env().block_timestamp();
+
+            if uri.len() > MAX_SHORT_URI_LEN {
+                self.token_resource_locator.remove(id);
+                self.token_uri_long.insert(id, &uri.as_bytes().to_vec());
+            } else {
+                self.token_uri_long.remove(id);
+                self.token_resource_locator.insert(id, &uri);
+            }
+            self.uri_history.insert((id, count), &(uri.clone(), actor, now));
+            self.uri_history_count.insert(id, &(count + 1));
+
+            self.env().emit_event(TokenUriUpdated {
+                token_id: id,
+                actor,
+                uri_hash: self.hash_of(&uri),
+                uri: Some(uri).filter(|uri| uri.len() <= MAX_EVENT_URI_LEN)
+            });
+        }
+    }
+
+    /// This function rejects URIs longer than MAX_URI_LEN bytes or containing whitespace or control characters.
+    fn check_uri(uri: &str) -> Result<(), Error> {
+        if uri.len() > MAX_URI_LEN || uri.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err(Error::InvalidUri)
+        }
+        Ok(())
+    }
+
+    /// This function checks a token URI like check_uri, but reports one longer than MAX_URI_LEN as UriTooLarge.
+    fn check_token_uri(uri: &str) -> Result<(), Error> {
+        if uri.len() > MAX_URI_LEN {
+            return Err(Error::UriTooLarge)
+        }
+        check_uri(uri)
+    }
+
+    /// This function adds a range to sorted, disjoint ranges, merging it with every range it overlaps or touches.
+    fn merge_range(ranges: Vec<TokenRange>, from_id: TokenId, to_id: TokenId) -> Vec<TokenRange> {
+        let (mut low, mut high) = (from_id, to_id);
+        let mut merged: Vec<TokenRange> = ranges
+            .into_iter()
+            .filter(|&(start, end)| {
+                let apart = end.saturating_add(1) < low || high.saturating_add(1) < start;
+                if !apart {
+                    low = low.min(start);
+                    high = high.max(end);
+                }
+                apart
+            })
+            .collect();
+        merged.push((low, high));
+        merged.sort_unstable();
+        merged
+    }
+
+    /// This function removes a range from sorted, disjoint ranges, trimming or splitting the ranges it overlaps.
+    fn subtract_range(ranges: Vec<TokenRange>, from_id: TokenId, to_id: TokenId) -> Vec<TokenRange> {
+        let mut remaining = Vec::new();
+        for (start, end) in ranges {
+            if end < from_id || start > to_id {
+                remaining.push((start, end));
+                continue
+            }
+            if start < from_id {
+                remaining.push((start, from_id - 1));
+            }
+            if end > to_id {
+                remaining.push((to_id + 1, end));
+            }
+        }
+        remaining
+    }
+
+    /// Unit tests
+    #[cfg(test)]
+    mod tests {
+        /// Imports all the definitions from the outer scope so we can use them here.
+        use super::*;
+
+        #[ink::test]
+        fn mint_works() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            // Token 1 does not exists.
+            assert_eq!(patient.owner_of(1), None);
+            // Alice does not owns tokens.
+            assert_eq!(patient.balance_of(accounts.alice), 0);
+            // Create token Id 1.
+            assert_eq!(patient.mint(1), Ok(()));
+            // Alice owns 1 token.
+            assert_eq!(patient.balance_of(accounts.alice), 1);
+        }
+
+        #[ink::test]
+        fn mint_existing_should_fail() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            // Create token Id 1.
+            assert_eq!(patient.mint(1), Ok(()));
+            // The first Transfer event takes place
+            assert_eq!(1, ink::env::test::recorded_events().count());
+            // Alice owns 1 token.
+            assert_eq!(patient.balance_of(accounts.alice), 1);
+            // Alice owns token Id 1.
+            assert_eq!(patient.owner_of(1), Some(accounts.alice));
+            // Cannot create  token Id if it exists.
+            // Bob cannot own token Id 1.
+            assert_eq!(patient.mint(1), Err(Error::TokenExists));
+        }
+
+        #[ink::test]
+        fn transfer_works() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            // Create token Id 1 for Alice
+            assert_eq!(patient.mint(1), Ok(()));
+            // Alice owns token 1
+            assert_eq!(patient.balance_of(accounts.alice), 1);
+            // Bob does not owns any token
+            assert_eq!(patient.balance_of(accounts.bob), 0);
+            // The first Transfer event takes place
+            assert_eq!(1, ink::env::test::recorded_events().count());
+            // Alice transfers token 1 to Bob
+            assert_eq!(patient.transfer(accounts.bob, 1), Ok(()));
+            // The second Transfer event takes place
+            assert_eq!(2, ink::env::test::recorded_events().count());
+            // Bob owns token 1
+            assert_eq!(patient.balance_of(accounts.bob), 1);
+        }
+
+        #[ink::test]
+        fn invalid_transfer_should_fail() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            // Transfer token fails if it does not exists.
+            assert_eq!(patient.transfer(accounts.bob, 2), Err(Error::TokenNotFound));
+            // Token Id 2 does not exists.
+            assert_eq!(patient.owner_of(2), None);
+            // Create token Id 2.
+            assert_eq!(patient.mint(2), Ok(()));
+            // Alice owns 1 token.
+            assert_eq!(patient.balance_of(accounts.alice), 1);
+            // Token Id 2 is owned by Alice.
+            assert_eq!(patient.owner_of(2), Some(accounts.alice));
+            // Set Bob as caller
+            set_caller(accounts.bob);
+        }
+
+        #[ink::test]
+        fn transfer_to_zero_address_leaves_storage_untouched() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut patient = new_patient();
+            let zero = AccountId::from([0x0; 32]);
+            assert_eq!(patient.mint(1), Ok(()));
+            assert_eq!(patient.mint(2), Ok(()));
+            assert_eq!(patient.approve(accounts.bob, 1, None), Ok(()));
+
+            assert_eq!(patient.transfer(zero, 1), Err(Error::ZeroAddress));
+            assert_eq!(patient.transfer_all(zero), Err(Error::ZeroAddress));
+            // Alice still owns both tokens, and Bob's approval survives.
+            assert_eq!(patient.owner_of(1), Some(accounts.alice));
+            assert_eq!(patient.owner_of(2), Some(accounts.alice));
+            assert_eq!(patient.balance_of(accounts.alice), 2);
+            assert_eq!(patient.balance_of(zero), 0);
+            assert_eq!(patient.get_approved(1), Some(accounts.bob));
+            assert_eq!(patient.token_of_owner_by_index(accounts.alice, 1), Some(2));
+        }
+
+        #[ink::test]
+        fn exists_works() {
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            // Token 1 does not exist yet.
+            assert!(!patient.exists(1));
+            // Create token Id 1.
+            assert_eq!(patient.mint(1), Ok(()));
+            // Token 1 now exists.
+            assert!(patient.exists(1));
+        }
+
+        #[ink::test]
+        fn is_approved_or_owner_covers_every_role() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            // Nobody may move a token that does not exist.
+            assert!(!patient.is_approved_or_owner(accounts.alice, 1));
+            // Alice mints token 1, approves Bob for it and makes Charlie an operator.
+            assert_eq!(patient.mint(1), Ok(()));
+            assert_eq!(patient.approve(accounts.bob, 1, None), Ok(()));
+            assert_eq!(patient.set_approval_for_all(accounts.charlie, true), Ok(()));
+            // Owner, approved account and operator may move it, a stranger may not.
+            assert!(patient.is_approved_or_owner(accounts.alice, 1));
+            assert!(patient.is_approved_or_owner(accounts.bob, 1));
+            assert!(patient.is_approved_or_owner(accounts.charlie, 1));
+            assert!(!patient.is_approved_or_owner(accounts.eve, 1));
+            // Revoking the operator removes Charlie's access only.
+            assert_eq!(patient.set_approval_for_all(accounts.charlie, false), Ok(()));
+            assert!(!patient.is_approved_or_owner(accounts.charlie, 1));
+            assert!(patient.is_approved_or_owner(accounts.bob, 1));
+        }
+
+        #[ink::test]
+        fn range_approvals_limit_the_operator() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            // Alice mints tokens 999, 1000, 1999 and 2000 and approves Bob for 1000 to 1999.
+            for id in [999, 1000, 1999, 2000] {
+                assert_eq!(patient.mint(id), Ok(()));
+            }
+            assert_eq!(patient.set_approval_for_range(accounts.bob, 1000, 1999, true), Ok(()));
+            assert_eq!(patient.approved_ranges(accounts.alice, accounts.bob), vec![(1000, 1999)]);
+            // Bob can move a token inside the range but not outside it.
+            set_caller(accounts.bob);
+            assert_eq!(patient.transfer_from(accounts.alice, accounts.bob, 999), Err(Error::NotApproved));
+            assert_eq!(patient.transfer_from(accounts.alice, accounts.bob, 2000), Err(Error::NotApproved));
+            assert_eq!(patient.transfer_from(accounts.alice, accounts.bob, 1000), Ok(()));
+            assert_eq!(patient.owner_of(1000), Some(accounts.bob));
+            // Revoking the range removes access.
+            set_caller(accounts.alice);
+            assert_eq!(patient.set_approval_for_range(accounts.bob, 1000, 1999, false), Ok(()));
+            assert_eq!(patient.approved_ranges(accounts.alice, accounts.bob), Vec::new());
+            set_caller(accounts.bob);
+            assert_eq!(patient.transfer_from(accounts.alice, accounts.bob, 1999), Err(Error::NotApproved));
+        }
+
+        #[ink::test]
+        fn range_approvals_merge_and_split() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            assert_eq!(patient.set_approval_for_range(accounts.bob, 5, 1, true), Err(Error::InvalidRange));
+            assert_eq!(patient.set_approval_for_range(accounts.alice, 1, 5, true), Err(Error::NotAllowed));
+            // Overlapping and adjacent ranges are merged.
+            assert_eq!(patient.set_approval_for_range(accounts.bob, 10, 20, true), Ok(()));
+            assert_eq!(patient.set_approval_for_range(accounts.bob, 30, 40, true), Ok(()));
+            assert_eq!(patient.set_approval_for_range(accounts.bob, 15, 29, true), Ok(()));
+            assert_eq!(patient.approved_ranges(accounts.alice, accounts.bob), vec![(10, 40)]);
+            // Revoking the middle of a range splits it.
+            assert_eq!(patient.set_approval_for_range(accounts.bob, 20, 25, false), Ok(()));
+            assert_eq!(patient.approved_ranges(accounts.alice, accounts.bob), vec![(10, 19), (26, 40)]);
+            // The cap counts the ranges of every operator.
+            for i in 0..14 {
+                assert_eq!(patient.set_approval_for_range(accounts.charlie, i * 100, i * 100, true), Ok(()));
+            }
+            assert_eq!(patient.set_approval_for_range(accounts.charlie, 5000, 5000, true), Err(Error::TooManyRanges));
+            assert_eq!(patient.set_approval_for_range(accounts.bob, 30, 30, false), Err(Error::TooManyRanges));
+            // Merging into an existing range does not count as a new one.
+            assert_eq!(patient.set_approval_for_range(accounts.charlie, 1, 1, true), Ok(()));
+            assert_eq!(patient.set_approval_for_range(accounts.bob, 20, 25, true), Ok(()));
+            assert_eq!(patient.set_approval_for_range(accounts.charlie, 5000, 5000, true), Ok(()));
+        }
+
+        #[ink::test]
+        fn transfer_from_matches_is_approved_or_owner() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            // Alice mints tokens 1, 2 and 3.
+            assert_eq!(patient.mint(1), Ok(()));
+            assert_eq!(patient.mint(2), Ok(()));
+            assert_eq!(patient.mint(3), Ok(()));
+            // Bob is approved for token 1 and Charlie is Alice's operator.
+            assert_eq!(patient.approve(accounts.bob, 1, None), Ok(()));
+            assert_eq!(patient.set_approval_for_all(accounts.charlie, true), Ok(()));
+            // A stranger cannot move token 2.
+            set_caller(accounts.eve);
+            assert!(!patient.is_approved_or_owner(accounts.eve, 2));
+            assert_eq!(patient.transfer_from(accounts.alice, accounts.eve, 2), Err(Error::NotApproved));
+            // Bob is approved for token 1 but not for token 2.
+            set_caller(accounts.bob);
+            assert_eq!(patient.transfer_from(accounts.alice, accounts.bob, 2), Err(Error::NotApproved));
+            assert_eq!(patient.transfer_from(accounts.alice, accounts.bob, 1), Ok(()));
+            assert_eq!(patient.owner_of(1), Some(accounts.bob));
+            // Charlie, as operator, can move any of Alice's tokens.
+            set_caller(accounts.charlie);
+            assert_eq!(patient.transfer_from(accounts.alice, accounts.charlie, 2), Ok(()));
+            assert_eq!(patient.owner_of(2), Some(accounts.charlie));
+            // But `from` has to be the current owner.
+            assert_eq!(patient.transfer_from(accounts.bob, accounts.charlie, 3), Err(Error::NotOwner));
+            // The owner can always move her own token.
+            set_caller(accounts.alice);
+            assert_eq!(patient.transfer_from(accounts.alice, accounts.django, 3), Ok(()));
+            assert_eq!(patient.owner_of(3), Some(accounts.django));
+        }
+
+        #[ink::test]
+        fn authorization_failures_name_their_reason() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut patient = new_patient();
+            assert_eq!(patient.mint(1), Ok(()));
+            // Owner-only actions fail with NotOwner.
+            set_caller(accounts.bob);
+            assert_eq!(patient.approve(accounts.charlie, 1, None), Err(Error::NotOwner));
+            assert_eq!(patient.revoke_approval(1), Err(Error::NotOwner));
+            assert_eq!(patient.rollback_uri(1, 0), Err(Error::NotOwner));
+            assert_eq!(patient.request_burn(1), Err(Error::NotOwner));
+            assert_eq!(patient.set_base_uri(String::from("https://evil.example/")), Err(Error::NotOwner));
+            // Actions open to approved accounts fail with NotApproved.
+            assert_eq!(patient.transfer_from(accounts.alice, accounts.bob, 1), Err(Error::NotApproved));
+            assert_eq!(patient.set_token_uri(1, String::from("ipfs://evil")), Err(Error::NotApproved));
+            assert_eq!(patient.transfer_all_from(accounts.alice, accounts.bob), Err(Error::NotApproved));
+            // Policy violations keep failing with NotAllowed, even for the owner.
+            set_caller(accounts.alice);
+            assert_eq!(patient.approve(AccountId::from([0x0; 32]), 1, None), Err(Error::NotAllowed));
+            assert_eq!(patient.set_approval_for_all(accounts.alice, true), Err(Error::NotAllowed));
+            assert_eq!(patient.approve(accounts.bob, 1, None), Ok(()));
+            assert_eq!(patient.approve(accounts.charlie, 1, None), Err(Error::NotAllowed));
+        }
+
+        #[ink::test]
+        fn approvals_lapse_at_their_expiry() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut patient = new_patient();
+            assert_eq!(patient.mint(1), Ok(()));
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(1_000);
+            // The expiry has to lie in the future.
+            assert_eq!(patient.approve(accounts.bob, 1, Some(1_000)), Err(Error::InvalidExpiry));
+            assert_eq!(patient.approve(accounts.bob, 1, Some(2_000)), Ok(()));
+            assert_eq!(patient.get_approved(1), Some(accounts.bob));
+            assert_eq!(patient.approval_expiry(1), Some(2_000));
+            // Before the expiry the approval cannot be pruned.
+            assert_eq!(patient.prune_expired_approval(1), Err(Error::NotAllowed));
+            // From the expiry on it counts as absent.
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(2_000);
+            assert_eq!(patient.get_approved(1), None);
+            assert!(!patient.is_approved_or_owner(accounts.bob, 1));
+            set_caller(accounts.bob);
+            assert_eq!(patient.transfer_from(accounts.alice, accounts.bob, 1), Err(Error::NotApproved));
+            // Anyone can clean it up, which emits an Approval event with no spender.
+            set_caller(accounts.eve);
+            let events = ink::env::test::recorded_events().count();
+            assert_eq!(patient.prune_expired_approval(1), Ok(()));
+            assert_eq!(ink::env::test::recorded_events().count(), events + 1);
+            assert_eq!(patient.approval_expiry(1), None);
+            assert_eq!(patient.prune_expired_approval(1), Err(Error::CannotFetchValue));
+            // An expired approval does not block a new one, and approvals without expiry stay.
+            set_caller(accounts.alice);
+            assert_eq!(patient.approve(accounts.charlie, 1, Some(3_000)), Ok(()));
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(3_000);
+            assert_eq!(patient.approve(accounts.django, 1, None), Ok(()));
+            assert_eq!(patient.approval_expiry(1), None);
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(u64::MAX);
+            assert_eq!(patient.get_approved(1), Some(accounts.django));
+        }
+
+        #[ink::test]
+        fn mint_to_works() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            // Alice mints token 1 for Bob.
+            assert_eq!(patient.mint_to(accounts.bob, 1), Ok(()));
+            // Bob owns the token, Alice does not.
+            assert_eq!(patient.owner_of(1), Some(accounts.bob));
+            assert_eq!(patient.balance_of(accounts.bob), 1);
+            assert_eq!(patient.balance_of(accounts.alice), 0);
+            // The same id cannot be minted twice.
+            assert_eq!(patient.mint_to(accounts.charlie, 1), Err(Error::TokenExists));
+            // Minting to the zero address is rejected.
+            assert_eq!(patient.mint_to(AccountId::from([0x0; 32]), 2), Err(Error::NotAllowed));
+        }
+
+        #[ink::test]
+        fn burn_works() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            // Alice mints token 1.
+            assert_eq!(patient.mint(1), Ok(()));
+            // Bob cannot request the burn of Alice's token.
+            set_caller(accounts.bob);
+            assert_eq!(patient.request_burn(1), Err(Error::NotOwner));
+            // Alice burns her token.
+            set_caller(accounts.alice);
+            burn(&mut patient, 1);
+            assert_eq!(patient.owner_of(1), None);
+            assert_eq!(patient.balance_of(accounts.alice), 0);
+            assert_eq!(patient.total_supply(), 0);
+            // A burned token cannot be burned again.
+            assert_eq!(patient.request_burn(1), Err(Error::TokenNotFound));
+            assert_eq!(patient.execute_burn(1), Err(Error::TokenNotFound));
+            // Mint, burn request and burn events took place.
+            assert_eq!(3, ink::env::test::recorded_events().count());
+        }
+
+        #[ink::test]
+        fn categories_are_counted_until_burn() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut patient = new_patient();
+            // Alice mints an identity token, two consent tokens and a dataset token for Bob.
+            assert_eq!(patient.mint(1), Ok(()));
+            assert_eq!(patient.mint_categorized(2, 1), Ok(()));
+            assert_eq!(patient.mint_categorized(3, 1), Ok(()));
+            assert_eq!(patient.mint_to_categorized(accounts.bob, 4, 2), Ok(()));
+            assert_eq!(patient.mint_categorized(2, 2), Err(Error::TokenExists));
+            assert_eq!(
+                (patient.supply_of_category(0), patient.supply_of_category(1), patient.supply_of_category(2)),
+                (1, 2, 1)
+            );
+            assert_eq!(patient.category_of(1), Some(0));
+            assert_eq!(patient.category_of(4), Some(2));
+            assert_eq!(patient.category_of(5), None);
+            // The category moves with the token.
+            assert_eq!(patient.transfer(accounts.charlie, 3), Ok(()));
+            assert_eq!(patient.category_of(3), Some(1));
+            assert_eq!(patient.supply_of_category(1), 2);
+            // Burning clears it and decrements its supply.
+            set_caller(accounts.charlie);
+            burn(&mut patient, 3);
+            assert_eq!(patient.category_of(3), None);
+            assert!(!patient.category.contains(3));
+            assert_eq!(patient.supply_of_category(1), 1);
+            assert_eq!(patient.total_supply(), 3);
+        }
+
+        #[ink::test]
+        fn badges_of_non_transferable_categories_can_only_be_burned() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut patient = new_patient();
+            // Category 3 holds clinician credential badges. Only the owner decides what is transferable.
+            set_caller(accounts.bob);
+            assert_eq!(patient.set_category_transferable(3, false), Err(Error::NotOwner));
+            set_caller(accounts.alice);
+            assert_eq!(patient.set_category_transferable(3, false), Ok(()));
+            assert!(!patient.is_category_transferable(3));
+            assert!(patient.is_category_transferable(0));
+
+            assert_eq!(patient.mint_to_categorized(accounts.bob, 1, 3), Ok(()));
+            assert_eq!(patient.mint_to_categorized(accounts.bob, 2, 0), Ok(()));
+            assert!(!patient.is_transferable_token(1));
+            assert!(patient.is_transferable_token(2));
+            assert!(!patient.is_transferable_token(3));
+
+            // No transfer path moves the badge, even for an approved operator.
+            set_caller(accounts.bob);
+            assert_eq!(patient.transfer(accounts.charlie, 1), Err(Error::TokenNotTransferable));
+            assert_eq!(patient.transfer_with_data(accounts.charlie, 1, Vec::new()), Err(Error::TokenNotTransferable));
+            assert_eq!(patient.set_approval_for_all(accounts.charlie, true), Ok(()));
+            set_caller(accounts.charlie);
+            assert_eq!(patient.transfer_from(accounts.bob, accounts.charlie, 1), Err(Error::TokenNotTransferable));
+            assert_eq!(patient.transfer_from(accounts.bob, accounts.charlie, 2), Ok(()));
+            set_caller(accounts.bob);
+            assert_eq!(patient.transfer_all(accounts.charlie), Ok(0));
+            assert_eq!(patient.owner_of(1), Some(accounts.bob));
+
+            // The holder can still burn it.
+            burn(&mut patient, 1);
+            assert_eq!(patient.owner_of(1), None);
+
+            // Making the category transferable again lifts the restriction.
+            set_caller(accounts.alice);
+            assert_eq!(patient.set_category_transferable(3, true), Ok(()));
+            assert_eq!(patient.mint_categorized(3, 3), Ok(()));
+            assert_eq!(patient.transfer(accounts.bob, 3), Ok(()));
+        }
+
+        #[ink::test]
+        fn lost_keys_are_recovered_after_an_unvetoed_challenge_period() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut patient = new_patient();
+            assert_eq!(patient.mint_to(accounts.bob, 1), Ok(()));
+            assert_eq!(patient.set_recovery_council(Some(accounts.eve)), Ok(()));
+
+            // Neither the token's owner nor a stranger can start a recovery.
+            set_caller(accounts.bob);
+            assert_eq!(patient.initiate_recovery(1, accounts.charlie), Err(Error::NotOwner));
+            set_caller(accounts.django);
+            assert_eq!(patient.initiate_recovery(1, accounts.django), Err(Error::NotOwner));
+
+            // The owner vetoes a recovery while they still hold their key.
+            set_caller(accounts.eve);
+            assert_eq!(patient.initiate_recovery(1, accounts.bob), Err(Error::NotAllowed));
+            assert_eq!(patient.initiate_recovery(2, accounts.charlie), Err(Error::TokenNotFound));
+            assert_eq!(patient.initiate_recovery(1, accounts.charlie), Ok(()));
+            assert_eq!(patient.initiate_recovery(1, accounts.django), Err(Error::RecoveryPending));
+            assert_eq!(patient.finalize_recovery(1), Err(Error::RecoveryPending));
+            // Whoever holds the old key cannot move the token out of the recovery's way.
+            set_caller(accounts.bob);
+            assert_eq!(patient.transfer(accounts.django, 1), Err(Error::RecoveryPending));
+            assert_eq!(patient.transfer_all(accounts.django), Ok(0));
+            set_caller(accounts.charlie);
+            assert_eq!(patient.veto_recovery(1), Err(Error::NotOwner));
+            set_caller(accounts.bob);
+            assert_eq!(patient.veto_recovery(1), Ok(()));
+            assert_eq!(patient.recovery_of(1), None);
+            assert_eq!(patient.veto_recovery(1), Err(Error::RecoveryNotFound));
+
+            // Once the challenge period passes unvetoed, anyone finalizes it, even over a pending burn and approval.
+            assert_eq!(patient.approve(accounts.django, 1, None), Ok(()));
+            assert_eq!(patient.request_burn(1), Ok(()));
+            set_caller(accounts.alice);
+            assert_eq!(patient.initiate_recovery(1, accounts.charlie), Ok(()));
+            let (_, finalizable_at) = patient.recovery_of(1).expect("recovery is pending");
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(finalizable_at);
+            set_caller(accounts.bob);
+            assert_eq!(patient.veto_recovery(1), Err(Error::RecoveryWindowClosed));
+            set_caller(accounts.django);
+            assert_eq!(patient.finalize_recovery(1), Ok(()));
+            assert_eq!(patient.owner_of(1), Some(accounts.charlie));
+            assert_eq!(patient.get_approved(1), None);
+            assert_eq!(patient.pending_burn(1), None);
+            assert_eq!(patient.recovery_of(1), None);
+
+            // The history tells the minted owner from the recovered one.
+            let history = patient.ownership_history_of(1, 0, 10);
+            assert_eq!(history.iter().map(|(owner, _, recovered)| (*owner, *recovered)).collect::<Vec<_>>(),
+                vec![(accounts.bob, false), (accounts.charlie, true)]);
+            assert_eq!(history[1].1, finalizable_at);
+
+            // After a veto the owner can transfer the token again.
+            set_caller(accounts.alice);
+            assert_eq!(patient.initiate_recovery(1, accounts.bob), Ok(()));
+            set_caller(accounts.charlie);
+            assert_eq!(patient.veto_recovery(1), Ok(()));
+            assert_eq!(patient.transfer(accounts.django, 1), Ok(()));
+        }
+
+        #[ink::test]
+        fn only_the_consent_market_moves_consent_tokens() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut patient = new_patient();
+            let token = 1 | CONSENT_TOKEN_FLAG;
+            assert_eq!(patient.mint_to(accounts.bob, token), Ok(()));
+            assert_eq!(patient.mint_to(accounts.bob, 2), Ok(()));
+
+            // Without a consent market nobody moves the token, and transfer_all leaves it behind.
+            set_caller(accounts.bob);
+            assert_eq!(patient.transfer(accounts.charlie, token), Err(Error::TokenNotTransferable));
+            assert_eq!(patient.set_approval_for_all(accounts.django, true), Ok(()));
+            assert_eq!(patient.transfer_all(accounts.charlie), Ok(1));
+            assert_eq!(patient.owner_of(token), Some(accounts.bob));
+
+            // Only the owner sets the market, which then moves the token as an approved operator.
+            assert_eq!(patient.set_consent_market(Some(accounts.django)), Err(Error::NotOwner));
+            set_caller(accounts.alice);
+            assert_eq!(patient.set_consent_market(Some(accounts.django)), Ok(()));
+            assert_eq!(patient.consent_market(), Some(accounts.django));
+            set_caller(accounts.bob);
+            assert_eq!(patient.transfer(accounts.charlie, token), Err(Error::TokenNotTransferable));
+            set_caller(accounts.django);
+            assert_eq!(patient.transfer_from(accounts.bob, accounts.charlie, token), Ok(()));
+            assert_eq!(patient.owner_of(token), Some(accounts.charlie));
+        }
+
+        #[ink::test]
+        fn transfers_wait_for_the_cooldown_unless_exempt() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut patient = new_patient();
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(1_000);
+            assert_eq!(patient.mint(1), Ok(()));
+            assert_eq!(patient.mint(2), Ok(()));
+            assert_eq!(patient.last_transfer_at(1), Some(1_000));
+
+            // Without a cooldown a token moves as often as its owner likes.
+            assert_eq!(patient.transfer(accounts.bob, 2), Ok(()));
+            set_caller(accounts.bob);
+            assert_eq!(patient.transfer(accounts.alice, 2), Ok(()));
+
+            set_caller(accounts.alice);
+            assert_eq!(patient.set_transfer_cooldown(500), Ok(()));
+            assert_eq!(patient.transfer(accounts.bob, 1), Err(Error::CooldownActive));
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(1_500);
+            assert_eq!(patient.transfer(accounts.bob, 1), Ok(()));
+            assert_eq!(patient.last_transfer_at(1), Some(1_500));
+
+            // Bob cannot flip it right away, but an exempted operator can move it for him.
+            set_caller(accounts.bob);
+            assert_eq!(patient.transfer(accounts.charlie, 1), Err(Error::CooldownActive));
+            assert_eq!(patient.set_approval_for_all(accounts.django, true), Ok(()));
+            assert_eq!(patient.exempt(accounts.django), Err(Error::NotOwner));
+            set_caller(accounts.alice);
+            assert_eq!(patient.exempt(accounts.django), Ok(()));
+            assert!(patient.is_exempt(accounts.django));
+            set_caller(accounts.django);
+            assert_eq!(patient.transfer_from(accounts.bob, accounts.charlie, 1), Ok(()));
+            assert_eq!(patient.owner_of(1), Some(accounts.charlie));
+
+            // Back to no cooldown, the usual rules apply again.
+            set_caller(accounts.alice);
+            assert_eq!(patient.set_transfer_cooldown(0), Ok(()));
+            set_caller(accounts.charlie);
+            assert_eq!(patient.transfer(accounts.bob, 1), Ok(()));
+        }
+
+        #[ink::test]
+        fn advertised_features_match_the_messages() {
+            let mut patient = new_patient();
+            assert_eq!(patient.mint(1), Ok(()));
+
+            // Enumerable and metadata are advertised and answer.
+            assert!(patient.supports(FEATURE_ENUMERABLE));
+            assert_eq!((patient.total_supply(), patient.token_by_index(0)), (1, Some(1)));
+            assert!(patient.supports(FEATURE_METADATA));
+            assert_eq!(patient.name(), "HealthDot");
+            assert_eq!(patient.set_token_uri(1, String::from("ipfs://record")), Ok(()));
+            assert_eq!(patient.token_uri(1), Some(String::from("ipfs://record")));
+            assert!(patient.supports(FEATURE_ROYALTIES));
+            assert_eq!(patient.royalty_info(1, 100), None);
+            assert!(patient.supports(FEATURE_SOULBOUND));
+            assert!(patient.is_transferable_token(1));
+            assert!(!patient.supports([0; 4]));
+
+            let features = patient.supported_features();
+            assert_eq!(features, vec![FEATURE_ENUMERABLE, FEATURE_METADATA, FEATURE_ROYALTIES, FEATURE_SOULBOUND, FEATURE_VOUCHERS]);
+            assert!(features.iter().all(|feature| patient.supports(*feature)));
+        }
+
+        #[ink::test]
+        fn token_moves_are_rejected_while_notifying_the_registry() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            assert_eq!(patient.mint(1), Ok(()));
+            assert_eq!(patient.mint(3), Ok(()));
+            assert_eq!(patient.request_burn(3), Ok(()));
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(DEFAULT_BURN_GRACE_PERIOD);
+            // While the registry is being notified, tokens cannot be minted, moved or burned.
+            patient.locked.set(&true);
+            assert_eq!(patient.mint(2), Err(Error::ReentrancyDetected));
+            assert_eq!(patient.transfer(accounts.bob, 1), Err(Error::ReentrancyDetected));
+            assert_eq!(patient.execute_burn(3), Err(Error::ReentrancyDetected));
+            assert_eq!(patient.owner_of(3), Some(accounts.alice));
+            // Once the call returns, the token can be burned again.
+            patient.locked.set(&false);
+            assert_eq!(patient.execute_burn(3), Ok(()));
+        }
+
+        #[ink::test]
+        fn transfer_all_drains_in_batches() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            for id in 1..=120 {
+                assert_eq!(patient.mint(id), Ok(()));
+            }
+            assert_eq!(patient.approve(accounts.eve, 7, None), Ok(()));
+            // Alice's custody wallet moves to Bob over three calls.
+            assert_eq!(patient.transfer_all(accounts.bob), Ok(MAX_BULK_TRANSFER));
+            assert_eq!(patient.transfer_all(accounts.bob), Ok(MAX_BULK_TRANSFER));
+            assert_eq!(patient.transfer_all(accounts.bob), Ok(20));
+            assert_eq!(patient.transfer_all(accounts.bob), Ok(0));
+            assert_eq!(patient.balance_of(accounts.alice), 0);
+            assert_eq!(patient.balance_of(accounts.bob), 120);
+            assert_eq!(patient.owner_of(7), Some(accounts.bob));
+            assert_eq!(patient.get_approved(7), None);
+            // Bob's index holds every token exactly once.
+            let mut owned: Vec<TokenId> = (0..120).filter_map(|index| patient.token_of_owner_by_index(accounts.bob, index)).collect();
+            owned.sort_unstable();
+            assert_eq!(owned, (1..=120).collect::<Vec<TokenId>>());
+            assert_eq!(patient.token_of_owner_by_index(accounts.alice, 0), None);
+            assert_eq!(patient.transfer_all(accounts.alice), Err(Error::NotAllowed));
+        }
+
+        #[ink::test]
+        fn transfer_all_skips_tokens_that_cannot_move() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut patient = new_patient();
+            assert_eq!(patient.set_category_transferable(3, false), Ok(()));
+            for id in 1..=3 {
+                assert_eq!(patient.mint(id), Ok(()));
+            }
+            // The badge is last in Alice's index, where transfer_all starts.
+            assert_eq!(patient.mint_categorized(4, 3), Ok(()));
+            assert_eq!(patient.token_of_owner_by_index(accounts.alice, 3), Some(4));
+
+            assert_eq!(patient.transfer_all(accounts.bob), Ok(3));
+            assert_eq!(patient.balance_of(accounts.bob), 3);
+            assert_eq!(patient.owner_of(4), Some(accounts.alice));
+            assert_eq!(patient.token_of_owner_by_index(accounts.alice, 0), Some(4));
+            assert_eq!(patient.transfer_all(accounts.bob), Ok(0));
         }
 
-        /// This function sets the Uniform Resource Identifier (URI) for a specific token.
-        /// The URI is a unique identifier for the token in a given context.
-        /// It inserts the provided URI into the token_resource_locator map with the provided token ID as the key.
-        /// The function will return Ok if the operation was successful, or an error if it wasn't.
-        #[ink(message)]
-        pub fn set_token_uri(&mut self, id: TokenId, uri: String) -> Result<(), Error> {
-            let Self {
-                token_resource_locator,
-                ..
-            } = self;
+        #[ink::test]
+        fn transfer_all_from_needs_an_operator() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            for id in 1..=3 {
+                assert_eq!(patient.mint(id), Ok(()));
+            }
+            // Charlie is not an operator of Alice.
+            set_caller(accounts.charlie);
+            assert_eq!(patient.transfer_all_from(accounts.alice, accounts.charlie), Err(Error::NotApproved));
+            set_caller(accounts.alice);
+            assert_eq!(patient.set_approval_for_all(accounts.charlie, true), Ok(()));
+            set_caller(accounts.charlie);
+            assert_eq!(patient.transfer_all_from(accounts.alice, accounts.django), Ok(3));
+            assert_eq!(patient.balance_of(accounts.django), 3);
+            // Three mints, three transfers and the operator approval.
+            assert_eq!(7, ink::env::test::recorded_events().count());
+        }
 
-            token_resource_locator.insert(id, &uri);
+        #[ink::test]
+        fn global_index_tracks_live_tokens() {
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            // Mint 5 sparse, caller-chosen ids.
+            for id in [10, 42, 7, 1000, 3] {
+                assert_eq!(patient.mint(id), Ok(()));
+            }
+            assert_eq!(patient.total_supply(), 5);
+            assert_eq!(patient.token_ids(0, 10), vec![10, 42, 7, 1000, 3]);
+            // Burn two tokens from the middle.
+            burn(&mut patient, 42);
+            burn(&mut patient, 7);
+            assert_eq!(patient.total_supply(), 3);
+            // The index enumerates exactly the live set.
+            let mut live = patient.token_ids(0, 10);
+            live.sort();
+            assert_eq!(live, vec![3, 10, 1000]);
+            assert_eq!(patient.token_by_index(3), None);
+            // Pagination covers every position once.
+            let mut paged = patient.token_ids(0, 2);
+            paged.extend(patient.token_ids(2, 2));
+            assert_eq!(paged, patient.token_ids(0, 3));
+            // Minting after burns appends to the dense index.
+            assert_eq!(patient.mint(42), Ok(()));
+            assert_eq!(patient.token_by_index(3), Some(42));
+            // Burning the last and the first positions keeps the index consistent.
+            burn(&mut patient, 42);
+            let first = patient.token_by_index(0).unwrap();
+            burn(&mut patient, first);
+            let mut live = patient.token_ids(0, 10);
+            live.sort();
+            let mut expected = vec![3, 10, 1000];
+            expected.retain(|id| *id != first);
+            assert_eq!(live, expected);
+            assert_eq!(patient.total_supply(), 2);
+        }
 
-            Ok(())
+        #[ink::test]
+        fn set_token_uri_is_restricted() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            // URIs cannot be set on tokens that do not exist.
+            assert_eq!(patient.set_token_uri(1, String::from("ipfs://a")), Err(Error::TokenNotFound));
+            // Alice mints token 1 and sets its URI.
+            assert_eq!(patient.mint(1), Ok(()));
+            assert_eq!(patient.set_token_uri(1, String::from("ipfs://a")), Ok(()));
+            // A stranger cannot change it.
+            set_caller(accounts.eve);
+            assert_eq!(patient.set_token_uri(1, String::from("ipfs://evil")), Err(Error::NotApproved));
+            assert_eq!(patient.token_uri(1), Some(String::from("ipfs://a")));
         }
-    }
 
-    /// Unit tests
-    #[cfg(test)]
-    mod tests {
-        /// Imports all the definitions from the outer scope so we can use them here.
-        use super::*;
+        #[ink::test]
+        fn operator_updates_uri_and_owner_rolls_back() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            // Alice mints token 1, sets its URI and makes Charlie her operator.
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(100);
+            assert_eq!(patient.mint(1), Ok(()));
+            assert_eq!(patient.set_token_uri(1, String::from("ipfs://v1")), Ok(()));
+            assert_eq!(patient.set_approval_for_all(accounts.charlie, true), Ok(()));
+            // The integration service (Charlie) updates the pointer twice.
+            set_caller(accounts.charlie);
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(200);
+            assert_eq!(patient.set_token_uri(1, String::from("ipfs://v2")), Ok(()));
+            assert_eq!(patient.set_token_uri(1, String::from("ipfs://bad")), Ok(()));
+            // The history accumulates with actor and timestamp.
+            assert_eq!(
+                patient.uri_history_of(1, 0, 10),
+                vec![
+                    (String::from("ipfs://v1"), accounts.alice, 100),
+                    (String::from("ipfs://v2"), accounts.charlie, 200),
+                    (String::from("ipfs://bad"), accounts.charlie, 200),
+                ]
+            );
+            assert_eq!(patient.uri_history_of(1, 1, 1), vec![(String::from("ipfs://v2"), accounts.charlie, 200)]);
+            // Only the owner can roll back.
+            assert_eq!(patient.rollback_uri(1, 0), Err(Error::NotOwner));
+            set_caller(accounts.alice);
+            assert_eq!(patient.rollback_uri(1, 7), Err(Error::CannotFetchValue));
+            assert_eq!(patient.rollback_uri(1, 0), Ok(()));
+            assert_eq!(patient.token_uri(1), Some(String::from("ipfs://v1")));
+            // The rollback itself is part of the trail.
+            assert_eq!(patient.uri_history_of(1, 3, 10), vec![(String::from("ipfs://v1"), accounts.alice, 200)]);
+            // Mint, operator approval and four URI updates were emitted.
+            assert_eq!(6, ink::env::test::recorded_events().count());
+        }
 
         #[ink::test]
-        fn mint_works() {
+        fn approved_account_updates_uri() {
             let accounts =
                 ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
             // Create a new contract instance.
-            let mut patient = Patient::new(String::from("HealthDot"), String::from("HDOT"));
-            // Token 1 does not exists.
-            assert_eq!(patient.owner_of(1), None);
-            // Alice does not owns tokens.
-            assert_eq!(patient.balance_of(accounts.alice), 0);
-            // Create token Id 1.
+            let mut patient = new_patient();
+            // Alice mints token 1 and approves Bob for it.
             assert_eq!(patient.mint(1), Ok(()));
-            // Alice owns 1 token.
-            assert_eq!(patient.balance_of(accounts.alice), 1);
+            assert_eq!(patient.approve(accounts.bob, 1, None), Ok(()));
+            // Bob updates the URI.
+            set_caller(accounts.bob);
+            assert_eq!(patient.set_token_uri(1, String::from("ipfs://bob")), Ok(()));
+            assert_eq!(patient.token_uri(1), Some(String::from("ipfs://bob")));
         }
 
         #[ink::test]
-        fn mint_existing_should_fail() {
+        fn token_uri_composes_base_and_suffix() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance with a gateway base URI.
+            set_contract_address();
+            let mut patient = Patient::new(String::from("HealthDot"), String::from("HDOT"), String::from("https://gateway.example/records/"), String::new());
+            assert_eq!(patient.mint(1), Ok(()));
+            assert_eq!(patient.mint(2), Ok(()));
+            // A relative suffix is composed with the base URI.
+            assert_eq!(patient.set_token_uri(1, String::from("1.json")), Ok(()));
+            assert_eq!(patient.token_uri(1), Some(String::from("https://gateway.example/records/1.json")));
+            // An absolute URI is passed through unchanged.
+            assert_eq!(patient.set_token_uri(2, String::from("ipfs://Qm123")), Ok(()));
+            assert_eq!(patient.token_uri(2), Some(String::from("ipfs://Qm123")));
+            // Tokens without a stored value have no URI.
+            assert_eq!(patient.token_uri(3), None);
+            // Changing the base affects composed URIs immediately.
+            assert_eq!(patient.set_base_uri(String::from("https://mirror.example/")), Ok(()));
+            assert_eq!(patient.base_uri(), String::from("https://mirror.example/"));
+            assert_eq!(patient.token_uri(1), Some(String::from("https://mirror.example/1.json")));
+            assert_eq!(patient.token_uri(2), Some(String::from("ipfs://Qm123")));
+            // Only the contract owner can change the base.
+            set_caller(accounts.bob);
+            assert_eq!(patient.set_base_uri(String::from("https://evil.example/")), Err(Error::NotOwner));
+            assert_eq!(patient.base_uri(), String::from("https://mirror.example/"));
+        }
+
+        #[ink::test]
+        fn mint_with_voucher_works_once_for_its_holder() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance and register the back office key.
+            let mut patient = new_patient();
+            let signer = voucher_signer_key();
+            let uri = String::from("ipfs://record-7");
+            let signature = sign_voucher(7, &uri, accounts.bob);
+            // Vouchers from unregistered keys are rejected.
+            set_caller(accounts.bob);
+            assert_eq!(patient.mint_with_voucher(7, uri.clone(), signer, signature), Err(Error::InvalidSignature));
+            // Only the owner can register signers.
+            assert_eq!(patient.add_voucher_signer(signer), Err(Error::NotOwner));
+            set_caller(accounts.alice);
+            assert_eq!(patient.add_voucher_signer(signer), Ok(()));
+            assert!(patient.is_voucher_signer(signer));
+            // A voucher issued to Bob cannot be redeemed by Charlie.
+            set_caller(accounts.charlie);
+            assert_eq!(patient.mint_with_voucher(7, uri.clone(), signer, signature), Err(Error::InvalidSignature));
+            assert!(!patient.exists(7));
+            // Bob redeems his voucher: the token is his and carries the signed URI.
+            set_caller(accounts.bob);
+            assert_eq!(patient.mint_with_voucher(7, uri.clone(), signer, signature), Ok(()));
+            assert_eq!(patient.owner_of(7), Some(accounts.bob));
+            assert_eq!(patient.token_uri(7), Some(uri.clone()));
+            // The voucher cannot be replayed, even after the token is burned.
+            assert_eq!(patient.mint_with_voucher(7, uri.clone(), signer, signature), Err(Error::VoucherUsed));
+            burn(&mut patient, 7);
+            assert_eq!(patient.mint_with_voucher(7, uri, signer, signature), Err(Error::VoucherUsed));
+        }
+
+        #[ink::test]
+        fn tokens_cannot_be_sent_to_the_contract() {
             let accounts =
                 ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
             // Create a new contract instance.
-            let mut patient = Patient::new(String::from("HealthDot"), String::from("HDOT"));
-            // Create token Id 1.
+            let mut patient = new_patient();
+            let contract = ink::env::account_id::<ink::env::DefaultEnvironment>();
+            // Minting to the contract's own address is rejected.
+            assert_eq!(patient.mint_to(contract, 1), Err(Error::NotAllowed));
+            assert!(!patient.exists(1));
+            // Transfers to the contract's own address are rejected.
+            assert_eq!(patient.mint(2), Ok(()));
+            assert_eq!(patient.transfer(contract, 2), Err(Error::NotAllowed));
+            assert_eq!(patient.transfer_from(accounts.alice, contract, 2), Err(Error::NotAllowed));
+            assert_eq!(patient.owner_of(2), Some(accounts.alice));
+            assert_eq!(patient.balance_of(contract), 0);
+        }
+
+        #[ink::test]
+        fn owner_rescues_stranded_token() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance with a token stranded on the contract's address.
+            let mut patient = new_patient();
+            let contract = ink::env::account_id::<ink::env::DefaultEnvironment>();
+            assert_eq!(patient.add_token_to(&contract, 5), Ok(()));
+            patient.add_to_index(5);
+            assert_eq!(patient.mint(6), Ok(()));
+            // Only the contract owner can rescue tokens.
+            set_caller(accounts.bob);
+            assert_eq!(patient.rescue_token(5, accounts.bob), Err(Error::NotOwner));
+            // Tokens not owned by the contract cannot be rescued.
+            set_caller(accounts.alice);
+            assert_eq!(patient.rescue_token(6, accounts.bob), Err(Error::NotAllowed));
+            assert_eq!(patient.rescue_token(7, accounts.bob), Err(Error::TokenNotFound));
+            // The owner moves the stranded token out.
+            assert_eq!(patient.rescue_token(5, accounts.bob), Ok(()));
+            assert_eq!(patient.owner_of(5), Some(accounts.bob));
+            assert_eq!(patient.balance_of(contract), 0);
+            assert_eq!(patient.balance_of(accounts.bob), 1);
+        }
+
+        #[ink::test]
+        fn owner_renames_collection() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            assert_eq!(patient.name(), String::from("HealthDot"));
+            assert_eq!(patient.symbol(), String::from("HDOT"));
+            // Only the contract owner can rename the collection.
+            set_caller(accounts.bob);
+            assert_eq!(patient.set_name(String::from("Bob's Clinic")), Err(Error::NotOwner));
+            assert_eq!(patient.set_symbol(String::from("BOB")), Err(Error::NotOwner));
+            // The owner renames it and each change is announced.
+            set_caller(accounts.alice);
+            assert_eq!(patient.set_name(String::from("St. Mary Records")), Ok(()));
+            assert_eq!(patient.set_symbol(String::from("SMR")), Ok(()));
+            assert_eq!(patient.name(), String::from("St. Mary Records"));
+            assert_eq!(patient.symbol(), String::from("SMR"));
+            assert_eq!(ink::env::test::recorded_events().count(), 2);
+        }
+
+        type Event = <Patient as ink::reflect::ContractEventBase>::Type;
+
+        // Decodes the approvals recorded so far as (owner, spender, token) triples.
+        fn recorded_approvals() -> Vec<(AccountId, Option<AccountId>, TokenId)> {
+            ink::env::test::recorded_events()
+                .map(|event| <Event as scale::Decode>::decode(&mut &event.data[..]).expect("invalid event data"))
+                .filter_map(|event| match event {
+                    Event::Approval(Approval { owner, spender, token_id }) => Some((owner, spender, token_id)),
+                    _ => None
+                })
+                .collect()
+        }
+
+        #[ink::test]
+        fn replaying_transfer_events_reproduces_the_state_digest() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut patient = new_patient();
+            assert_eq!((patient.state_digest(), patient.digest_block()), (Hash::default(), 0));
+
+            // A scripted history of mints, transfers and burns, over a few blocks.
+            assert_eq!(patient.mint_to(accounts.bob, 1), Ok(()));
+            assert_eq!(patient.mint_to(accounts.bob, 2), Ok(()));
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            set_caller(accounts.bob);
+            assert_eq!(patient.transfer(accounts.charlie, 1), Ok(()));
+            assert_eq!(patient.set_approval_for_all(accounts.django, true), Ok(()));
+            ink::env::test::advance_block::<ink::env::DefaultEnvironment>();
+            burn(&mut patient, 2);
+            set_caller(accounts.charlie);
+            assert_eq!(patient.transfer(accounts.bob, 1), Ok(()));
+
+            // An indexer folds the Transfer events it saw, and nothing else, with the documented formula.
+            let zero = AccountId::from([0x0; 32]);
+            let replayed = ink::env::test::recorded_events()
+                .map(|event| <Event as scale::Decode>::decode(&mut &event.data[..]).expect("invalid event data"))
+                .filter_map(|event| match event {
+                    Event::Transfer(Transfer { from, to, token_id }) => Some((from.unwrap_or(zero), to.unwrap_or(zero), token_id)),
+                    _ => None
+                })
+                .fold(Hash::default(), |digest, (from, to, token_id)| {
+                    let operation = match (from == zero, to == zero) {
+                        (true, _) => DIGEST_MINT,
+                        (_, true) => DIGEST_BURN,
+                        _ => DIGEST_TRANSFER
+                    };
+                    let mut output = [0u8; 32];
+                    ink::env::hash_encoded::<Blake2x256, _>(&(digest, operation, token_id, from, to), &mut output);
+                    Hash::from(output)
+                });
+            assert_eq!(patient.state_digest(), replayed);
+            assert_eq!(patient.digest_block(), 2);
+        }
+
+        #[ink::test]
+        fn transfer_with_data_emits_both_events() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance.
+            let mut patient = new_patient();
             assert_eq!(patient.mint(1), Ok(()));
-            // The first Transfer event takes place
-            assert_eq!(1, ink::env::test::recorded_events().count());
-            // Alice owns 1 token.
-            assert_eq!(patient.balance_of(accounts.alice), 1);
-            // Alice owns token Id 1.
+            // Data longer than the cap is rejected and the token stays put.
+            let too_long = vec![0; MAX_TRANSFER_DATA_LEN + 1];
+            assert_eq!(patient.transfer_with_data(accounts.bob, 1, too_long), Err(Error::DataTooLarge));
             assert_eq!(patient.owner_of(1), Some(accounts.alice));
-            // Cannot create  token Id if it exists.
-            // Bob cannot own token Id 1.
-            assert_eq!(patient.mint(1), Err(Error::TokenExists));
+            // A transfer with data emits the plain Transfer event and then TransferWithData.
+            let data = vec![7; MAX_TRANSFER_DATA_LEN];
+            let before = ink::env::test::recorded_events().count();
+            assert_eq!(patient.transfer_with_data(accounts.bob, 1, data.clone()), Ok(()));
+            let events: Vec<Event> = ink::env::test::recorded_events()
+                .skip(before)
+                .map(|event| <Event as scale::Decode>::decode(&mut &event.data[..]).expect("invalid event data"))
+                .collect();
+            assert!(matches!(
+                events.first(),
+                Some(Event::Transfer(Transfer { from: Some(from), to: Some(to), token_id: 1 }))
+                    if *from == accounts.alice && *to == accounts.bob
+            ));
+            match events.last() {
+                Some(Event::TransferWithData(event)) => {
+                    assert_eq!((event.from, event.to, event.token_id), (accounts.alice, accounts.bob, 1));
+                    assert_eq!(event.data_hash, patient.hash_of(&data));
+                    assert_eq!(event.data, data);
+                }
+                _ => panic!("expected a TransferWithData event")
+            }
         }
 
         #[ink::test]
-        fn transfer_works() {
+        fn transfer_from_with_data_matches_transfer_from() {
             let accounts =
                 ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
             // Create a new contract instance.
-            let mut patient = Patient::new(String::from("HealthDot"), String::from("HDOT"));
-            // Create token Id 1 for Alice
+            let mut patient = new_patient();
+            // Alice mints tokens 1 and 2 and approves Bob for token 1 only.
             assert_eq!(patient.mint(1), Ok(()));
-            // Alice owns token 1
-            assert_eq!(patient.balance_of(accounts.alice), 1);
-            // Bob does not owns any token
-            assert_eq!(patient.balance_of(accounts.bob), 0);
-            // The first Transfer event takes place
-            assert_eq!(1, ink::env::test::recorded_events().count());
-            // Alice transfers token 1 to Bob
-            assert_eq!(patient.transfer(accounts.bob, 1), Ok(()));
-            // The second Transfer event takes place
-            assert_eq!(2, ink::env::test::recorded_events().count());
-            // Bob owns token 1
-            assert_eq!(patient.balance_of(accounts.bob), 1);
+            assert_eq!(patient.mint(2), Ok(()));
+            assert_eq!(patient.approve(accounts.bob, 1, None), Ok(()));
+            // A stranger, a wrong `from` and a missing token fail the same way as with transfer_from.
+            set_caller(accounts.bob);
+            assert_eq!(patient.transfer_from_with_data(accounts.alice, accounts.bob, 2, vec![1]), Err(Error::NotApproved));
+            assert_eq!(patient.transfer_from_with_data(accounts.charlie, accounts.bob, 1, vec![1]), Err(Error::NotOwner));
+            assert_eq!(patient.transfer_from_with_data(accounts.alice, accounts.bob, 3, vec![1]), Err(Error::TokenNotFound));
+            assert_eq!(patient.transfer_with_data(accounts.charlie, 1, vec![1]), Err(Error::NotOwner));
+            // The approved account can move its token, and the approval is cleared.
+            assert_eq!(patient.transfer_from_with_data(accounts.alice, accounts.charlie, 1, vec![1]), Ok(()));
+            assert_eq!(patient.owner_of(1), Some(accounts.charlie));
+            assert_eq!(patient.get_approved(1), None);
         }
 
         #[ink::test]
-        fn invalid_transfer_should_fail() {
+        fn clearing_approvals_emits_events() {
             let accounts =
                 ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
             // Create a new contract instance.
-            let mut patient = Patient::new(String::from("HealthDot"), String::from("HDOT"));
-            // Transfer token fails if it does not exists.
-            assert_eq!(patient.transfer(accounts.bob, 2), Err(Error::TokenNotFound));
-            // Token Id 2 does not exists.
-            assert_eq!(patient.owner_of(2), None);
-            // Create token Id 2.
+            let mut patient = new_patient();
+            assert_eq!(patient.mint(1), Ok(()));
+            // Alice approves Bob, then transfers the token to Charlie, which clears Bob's approval.
+            assert_eq!(patient.approve(accounts.bob, 1, None), Ok(()));
+            assert_eq!(patient.transfer(accounts.charlie, 1), Ok(()));
+            assert_eq!(patient.get_approved(1), None);
+            // Charlie approves Django, and is not blocked by the previous owner's approval.
+            set_caller(accounts.charlie);
+            assert_eq!(patient.approve(accounts.django, 1, None), Ok(()));
+            // Only the owner can revoke, and only an existing approval.
+            set_caller(accounts.django);
+            assert_eq!(patient.revoke_approval(1), Err(Error::NotOwner));
+            set_caller(accounts.charlie);
+            assert_eq!(patient.revoke_approval(1), Ok(()));
+            assert_eq!(patient.get_approved(1), None);
+            assert_eq!(patient.revoke_approval(1), Err(Error::CannotFetchValue));
+            // Burning a token without an approval does not emit a clear.
+            burn(&mut patient, 1);
+            // Mint, approve, clear, transfer, approve, clear, burn request, burn.
+            assert_eq!(ink::env::test::recorded_events().count(), 8);
+            assert_eq!(recorded_approvals(), vec![
+                (accounts.alice, Some(accounts.bob), 1),
+                (accounts.alice, None, 1),
+                (accounts.charlie, Some(accounts.django), 1),
+                (accounts.charlie, None, 1)
+            ]);
+        }
+
+        #[ink::test]
+        fn uri_events_carry_the_uri_hash() {
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            assert_eq!(patient.mint(1), Ok(()));
+            assert_eq!(patient.set_token_uri(1, String::from("ipfs://record-1")), Ok(()));
+            let hash = patient.hash_of_token_uri(1).expect("token has a URI");
+            assert_eq!(patient.hash_of_token_uri(2), None);
+            // The third topic is the hash of the stored URI.
+            let event = ink::env::test::recorded_events().last().expect("event recorded");
+            let encoded = scale::Encode::encode(&ink::env::topics::PrefixedValue { prefix: b"Patient::TokenUriUpdated::uri_hash", value: &hash });
+            let mut topic = [0u8; 32];
+            ink::env::hash_bytes::<Blake2x256>(&encoded, &mut topic);
+            assert_eq!(event.topics[3], topic.to_vec());
+            match <Event as scale::Decode>::decode(&mut &event.data[..]).expect("invalid event data") {
+                Event::TokenUriUpdated(TokenUriUpdated { uri, .. }) => assert_eq!(uri, Some(String::from("ipfs://record-1"))),
+                _ => panic!("expected TokenUriUpdated")
+            }
+            // An oversized URI is only identified by its hash.
+            let long = "x".repeat(MAX_EVENT_URI_LEN + 1);
+            assert_eq!(patient.set_token_uri(1, long), Ok(()));
+            let event = ink::env::test::recorded_events().last().expect("event recorded");
+            match <Event as scale::Decode>::decode(&mut &event.data[..]).expect("invalid event data") {
+                Event::TokenUriUpdated(TokenUriUpdated { uri, .. }) => assert_eq!(uri, None),
+                _ => panic!("expected TokenUriUpdated")
+            }
+        }
+
+        #[ink::test]
+        fn long_uris_overflow_into_their_own_mapping() {
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            assert_eq!(patient.mint(1), Ok(()));
+            let at_cap = format!("did:web:{}", "a".repeat(MAX_SHORT_URI_LEN - 8));
+            let over_cap = format!("{at_cap}b");
+            let at_limit = "c".repeat(MAX_URI_LEN);
+
+            // A URI of exactly the short cap stays in the short mapping.
+            assert_eq!(patient.set_token_uri(1, at_cap.clone()), Ok(()));
+            assert_eq!(patient.token_resource_locator.get(1), Some(at_cap.clone()));
+            assert_eq!(patient.token_uri_long.get(1), None);
+            assert_eq!(patient.token_uri(1), Some(at_cap.clone()));
+
+            // One byte more moves it to the long mapping, and reads follow it there.
+            assert_eq!(patient.set_token_uri(1, over_cap.clone()), Ok(()));
+            assert_eq!(patient.token_resource_locator.get(1), None);
+            assert_eq!(patient.token_uri(1), Some(over_cap.clone()));
+            assert_eq!(patient.hash_of_token_uri(1), Some(patient.hash_of(&over_cap)));
+
+            // The hard limit is accepted, anything beyond it is not.
+            assert_eq!(patient.set_token_uri(1, at_limit.clone()), Ok(()));
+            assert_eq!(patient.token_uri_long.get(1), Some(at_limit.clone().into_bytes()));
+            assert_eq!(patient.set_token_uri(1, "c".repeat(MAX_URI_LEN + 1)), Err(Error::UriTooLarge));
+            assert_eq!(patient.base_uri(), String::new());
+            assert_eq!(patient.token_uri(1), Some(at_limit));
+
+            // Going back to a short URI clears the long mapping.
+            assert_eq!(patient.rollback_uri(1, 0), Ok(()));
+            assert_eq!(patient.token_uri_long.get(1), None);
+            assert_eq!(patient.token_uri(1), Some(at_cap));
+
+            // A long URI stored before the overflow mapping existed is readable, and migrate_uri moves it.
             assert_eq!(patient.mint(2), Ok(()));
-            // Alice owns 1 token.
-            assert_eq!(patient.balance_of(accounts.alice), 1);
-            // Token Id 2 is owned by Alice.
-            assert_eq!(patient.owner_of(2), Some(accounts.alice));
-            // Set Bob as caller
+            patient.token_resource_locator.insert(2, &over_cap);
+            assert_eq!(patient.token_uri(2), Some(over_cap.clone()));
+            assert_eq!(patient.migrate_uri(2), Ok(true));
+            assert_eq!(patient.token_resource_locator.get(2), None);
+            assert_eq!(patient.token_uri(2), Some(over_cap));
+            assert_eq!(patient.migrate_uri(2), Ok(false));
+            assert_eq!(patient.migrate_uri(1), Ok(false));
+            assert_eq!(patient.migrate_uri(3), Err(Error::TokenNotFound));
+
+            // Burning clears both mappings.
+            burn(&mut patient, 2);
+            assert_eq!(patient.token_uri_long.get(2), None);
+        }
+
+        #[ink::test]
+        fn registry_is_owner_managed() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            assert_eq!(patient.registry(), None);
+            // Without a registry, burning notifies nobody.
+            assert_eq!(patient.mint(1), Ok(()));
+            burn(&mut patient, 1);
+            // Only the contract owner can set the registry.
+            set_caller(accounts.bob);
+            assert_eq!(patient.set_registry(Some(accounts.django)), Err(Error::NotOwner));
+            set_caller(accounts.alice);
+            assert_eq!(patient.set_registry(Some(accounts.django)), Ok(()));
+            assert_eq!(patient.registry(), Some(accounts.django));
+            assert_eq!(patient.set_registry(None), Ok(()));
+            assert_eq!(patient.registry(), None);
+        }
+
+        #[ink::test]
+        fn collection_uri_is_owner_managed() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance with collection metadata.
+            set_contract_address();
+            let mut patient = Patient::new(String::from("HealthDot"), String::from("HDOT"), String::new(), String::from("ipfs://QmCollection"));
+            assert_eq!(patient.contract_uri(), String::from("ipfs://QmCollection"));
+            // Only the contract owner can change it.
+            set_caller(accounts.bob);
+            assert_eq!(patient.set_collection_uri(String::from("ipfs://QmOther")), Err(Error::NotOwner));
+            set_caller(accounts.alice);
+            assert_eq!(patient.set_collection_uri(String::from("https://hospital.example/collection.json")), Ok(()));
+            assert_eq!(patient.contract_uri(), String::from("https://hospital.example/collection.json"));
+            assert_eq!(1, ink::env::test::recorded_events().count());
+            // URIs are validated like token URIs.
+            assert_eq!(patient.set_collection_uri(String::from("ipfs://Qm bad")), Err(Error::InvalidUri));
+            assert_eq!(patient.set_collection_uri("u".repeat(MAX_URI_LEN + 1)), Err(Error::InvalidUri));
+            assert_eq!(patient.mint(1), Ok(()));
+            assert_eq!(patient.set_token_uri(1, String::from("ipfs://Qm\nbad")), Err(Error::InvalidUri));
+            assert_eq!(patient.contract_uri(), String::from("https://hospital.example/collection.json"));
+            // An empty URI clears it.
+            assert_eq!(patient.set_collection_uri(String::new()), Ok(()));
+            assert_eq!(patient.contract_uri(), String::new());
+        }
+
+        #[ink::test]
+        fn listeners_are_owner_managed() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            // Create a new contract instance.
+            let mut patient = new_patient();
+            // Only the contract owner can add or remove listeners.
             set_caller(accounts.bob);
+            assert_eq!(patient.add_listener(accounts.charlie), Err(Error::NotOwner));
+            set_caller(accounts.alice);
+            assert_eq!(patient.add_listener(accounts.charlie), Ok(()));
+            assert_eq!(patient.add_listener(accounts.charlie), Err(Error::ListenerExists));
+            assert_eq!(patient.add_listener(accounts.django), Ok(()));
+            assert_eq!(patient.add_listener(accounts.eve), Ok(()));
+            // At most MAX_LISTENERS can be registered.
+            assert_eq!(patient.add_listener(accounts.frank), Err(Error::TooManyListeners));
+            set_caller(accounts.bob);
+            assert_eq!(patient.remove_listener(accounts.charlie), Err(Error::NotOwner));
+            set_caller(accounts.alice);
+            assert_eq!(patient.remove_listener(accounts.charlie), Ok(()));
+            assert_eq!(patient.remove_listener(accounts.charlie), Err(Error::ListenerNotFound));
+            assert_eq!(patient.listeners(), vec![accounts.django, accounts.eve]);
+            assert_eq!(patient.add_listener(accounts.frank), Ok(()));
         }
 
         fn set_caller(sender: AccountId) {
             ink::env::test::set_caller::<ink::env::DefaultEnvironment>(sender);
         }
 
+        // The off-chain environment runs the contract as Alice by default; give it an address of its own
+        // so minting to the default caller is not mistaken for minting to the contract.
+        fn set_contract_address() {
+            ink::env::test::set_callee::<ink::env::DefaultEnvironment>(AccountId::from([0xC0; 32]));
+        }
+
+        fn new_patient() -> Patient {
+            set_contract_address();
+            Patient::new(String::from("HealthDot"), String::from("HDOT"), String::new(), String::new())
+        }
+
+        // Requests the burn of a token as the current caller and executes it once the grace period has passed.
+        fn burn(patient: &mut Patient, id: TokenId) {
+            assert_eq!(patient.request_burn(id), Ok(()));
+            let executable_at = patient.pending_burn(id).expect("burn is pending");
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(executable_at);
+            assert_eq!(patient.execute_burn(id), Ok(()));
+        }
+
+        #[ink::test]
+        fn minters_mint_only_inside_their_reserved_ranges() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut patient = new_patient();
+            // Two EPR instances get adjacent ranges.
+            set_caller(accounts.bob);
+            assert_eq!(patient.reserve_range(accounts.bob, 1, 100), Err(Error::NotOwner));
+            set_caller(accounts.alice);
+            assert_eq!(patient.reserve_range(accounts.bob, 100, 1), Err(Error::InvalidRange));
+            assert_eq!(patient.reserve_range(accounts.bob, 1, 100), Ok(()));
+            assert_eq!(patient.reserve_range(accounts.charlie, 101, 200), Ok(()));
+            // Ranges are exclusive, even by a single id.
+            assert_eq!(patient.reserve_range(accounts.django, 200, 300), Err(Error::InvalidRange));
+            assert_eq!(patient.reserve_range(accounts.django, 50, 60), Err(Error::InvalidRange));
+            assert_eq!(patient.ranges_of(accounts.bob), vec![(1, 100)]);
+            assert_eq!(patient.ranges_of(accounts.django), Vec::new());
+            assert_eq!((patient.range_owner(100), patient.range_owner(101), patient.range_owner(201)), (Some(accounts.bob), Some(accounts.charlie), None));
+
+            // Each minter mints up to the boundaries of its own range, and not across.
+            set_caller(accounts.bob);
+            assert_eq!(patient.mint(1), Ok(()));
+            assert_eq!(patient.mint_to(accounts.eve, 100), Ok(()));
+            assert_eq!(patient.mint(101), Err(Error::IdOutOfRange));
+            assert_eq!(patient.mint_categorized(0, 1), Err(Error::IdOutOfRange));
+            set_caller(accounts.charlie);
+            assert_eq!(patient.mint(101), Ok(()));
+            assert_eq!(patient.mint_to(accounts.eve, 200), Ok(()));
+            assert_eq!(patient.mint_to_categorized(accounts.eve, 100, 1), Err(Error::IdOutOfRange));
+            assert_eq!(patient.mint(201), Err(Error::IdOutOfRange));
+
+            // Unreserved ids are left to the contract owner, who cannot mint into reserved ranges either.
+            set_caller(accounts.django);
+            assert_eq!(patient.mint(500), Err(Error::IdOutOfRange));
+            set_caller(accounts.alice);
+            assert_eq!(patient.mint(500), Ok(()));
+            assert_eq!(patient.mint(50), Err(Error::IdOutOfRange));
+            assert_eq!(patient.total_supply(), 5);
+        }
+
+        #[ink::test]
+        fn snapshots_keep_the_balances_from_before_later_transfers() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut patient = new_patient();
+            assert_eq!(patient.mint_to(accounts.bob, 1), Ok(()));
+            assert_eq!(patient.mint_to(accounts.bob, 2), Ok(()));
+            assert_eq!(patient.mint_to(accounts.charlie, 3), Ok(()));
+
+            set_caller(accounts.bob);
+            assert_eq!(patient.create_snapshot(), Err(Error::NotOwner));
+            set_caller(accounts.alice);
+            let first = patient.create_snapshot().unwrap();
+            assert_eq!(patient.snapshot(first), Some((ink::env::block_number::<ink::env::DefaultEnvironment>(), 3)));
+
+            // Transfers, mints and burns after the snapshot leave its balances untouched.
+            set_caller(accounts.bob);
+            assert_eq!(patient.transfer(accounts.django, 1), Ok(()));
+            assert_eq!(patient.transfer(accounts.django, 2), Ok(()));
+            set_caller(accounts.alice);
+            assert_eq!(patient.mint_to(accounts.django, 4), Ok(()));
+            let second = patient.create_snapshot().unwrap();
+            set_caller(accounts.charlie);
+            burn(&mut patient, 3);
+
+            assert_eq!((patient.balance_of(accounts.bob), patient.balance_at(first, accounts.bob)), (0, 2));
+            assert_eq!((patient.balance_of(accounts.django), patient.balance_at(first, accounts.django)), (3, 0));
+            assert_eq!(patient.balance_at(second, accounts.django), 3);
+            assert_eq!((patient.balance_at(first, accounts.charlie), patient.balance_at(second, accounts.charlie)), (1, 1));
+            assert_eq!(patient.balance_of(accounts.charlie), 0);
+            assert_eq!((patient.total_supply_at(first), patient.total_supply_at(second), patient.total_supply()), (3, 4, 3));
+            assert_eq!((patient.balance_at(0, accounts.bob), patient.balance_at(3, accounts.bob), patient.total_supply_at(3)), (0, 0, 0));
+        }
+
+        #[ink::test]
+        fn error_catalog_names_every_variant_in_order() {
+            let patient = new_patient();
+            let catalog = patient.error_catalog();
+            assert_eq!(catalog.len(), ERROR_NAMES.len());
+            for (code, name) in &catalog {
+                let error = Error::decode(&mut &[*code][..]).expect("catalog entry without a variant");
+                assert_eq!(format!("{:?}", error), *name);
+            }
+            assert!(Error::decode(&mut &[catalog.len() as u8][..]).is_err());
+        }
+
+        #[ink::test]
+        fn token_royalties_override_the_collection_default() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut patient = new_patient();
+            assert_eq!(patient.mint(1), Ok(()));
+            assert_eq!(patient.mint(2), Ok(()));
+            // Without any royalty configured, none is reported.
+            assert_eq!(patient.royalty_info(1, 1_000), None);
+            assert_eq!(patient.default_royalty(), None);
+
+            // Only the contract owner configures royalties, within the whole of the price.
+            set_caller(accounts.bob);
+            assert_eq!(patient.set_default_royalty(accounts.eve, 500), Err(Error::NotOwner));
+            assert_eq!(patient.set_royalty(1, accounts.bob, 500), Err(Error::NotOwner));
+            set_caller(accounts.alice);
+            assert_eq!(patient.set_default_royalty(accounts.eve, MAX_ROYALTY_BPS + 1), Err(Error::InvalidRoyalty));
+            assert_eq!(patient.set_royalty(3, accounts.django, 100), Err(Error::TokenNotFound));
+
+            // The default covers every token.
+            assert_eq!(patient.set_default_royalty(accounts.eve, 250), Ok(()));
+            assert_eq!(patient.royalty_info(1, 1_000), Some((accounts.eve, 25)));
+            assert_eq!(patient.royalty_info(2, 1_000), Some((accounts.eve, 25)));
+            assert_eq!(patient.royalty_info(3, 1_000), None);
+
+            // A token's own royalty wins, and clearing it falls back to the default rather than to nothing.
+            assert_eq!(patient.set_royalty(1, accounts.django, 1_000), Ok(()));
+            assert_eq!(patient.royalty_info(1, 1_000), Some((accounts.django, 100)));
+            assert_eq!(patient.royalty_info(2, 1_000), Some((accounts.eve, 25)));
+            assert_eq!(patient.clear_royalty(1), Ok(()));
+            assert_eq!(patient.royalty_info(1, 1_000), Some((accounts.eve, 25)));
+            assert_eq!(patient.clear_royalty(1), Err(Error::CannotFetchValue));
+
+            // Large prices are split exactly.
+            assert_eq!(patient.set_royalty(2, accounts.django, MAX_ROYALTY_BPS), Ok(()));
+            assert_eq!(patient.royalty_info(2, Balance::MAX), Some((accounts.django, Balance::MAX)));
+            assert_eq!(patient.clear_default_royalty(), Ok(()));
+            assert_eq!(patient.royalty_info(1, 1_000), None);
+        }
+
+        #[ink::test]
+        fn burns_wait_for_the_grace_period_and_can_be_cancelled() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut patient = new_patient();
+            assert_eq!(patient.mint(1), Ok(()));
+            assert_eq!(patient.burn_grace_period(), DEFAULT_BURN_GRACE_PERIOD);
+            assert_eq!(patient.execute_burn(1), Err(Error::CannotFetchValue));
+            assert_eq!(patient.cancel_burn(1), Err(Error::CannotFetchValue));
+
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(1_000);
+            assert_eq!(patient.request_burn(1), Ok(()));
+            let executable_at = 1_000 + DEFAULT_BURN_GRACE_PERIOD;
+            assert_eq!(patient.pending_burn(1), Some(executable_at));
+            assert_eq!(patient.request_burn(1), Err(Error::BurnPending));
+            // The token cannot leave its owner while the burn is pending, not even through an operator.
+            assert_eq!(patient.transfer(accounts.bob, 1), Err(Error::BurnPending));
+            assert_eq!(patient.set_approval_for_all(accounts.charlie, true), Ok(()));
+            set_caller(accounts.charlie);
+            assert_eq!(patient.transfer_from(accounts.alice, accounts.charlie, 1), Err(Error::BurnPending));
+            // Anyone can execute it, but not a moment before the grace period ends, and only the owner can cancel it.
+            set_caller(accounts.eve);
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(executable_at - 1);
+            assert_eq!(patient.execute_burn(1), Err(Error::NotAllowed));
+            assert_eq!(patient.cancel_burn(1), Err(Error::NotOwner));
+            set_caller(accounts.alice);
+            assert_eq!(patient.cancel_burn(1), Ok(()));
+            assert_eq!(patient.pending_burn(1), None);
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(executable_at);
+            assert_eq!(patient.execute_burn(1), Err(Error::CannotFetchValue));
+            assert_eq!(patient.transfer(accounts.bob, 1), Ok(()));
+
+            // The contract owner shortens the grace period for new requests.
+            set_caller(accounts.bob);
+            assert_eq!(patient.set_burn_grace_period(0), Err(Error::NotOwner));
+            assert_eq!(patient.request_burn(1), Ok(()));
+            set_caller(accounts.alice);
+            assert_eq!(patient.set_burn_grace_period(1_000), Ok(()));
+            assert_eq!(patient.pending_burn(1), Some(executable_at + DEFAULT_BURN_GRACE_PERIOD));
+            set_caller(accounts.eve);
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(executable_at + DEFAULT_BURN_GRACE_PERIOD);
+            assert_eq!(patient.execute_burn(1), Ok(()));
+            assert!(!patient.exists(1));
+            assert_eq!(patient.pending_burn(1), None);
+        }
+
+        #[ink::test]
+        fn derived_ids_are_deterministic_and_collide_on_reuse() {
+            let accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut patient = new_patient();
+            let id = patient.derive_token_id(accounts.bob, 7);
+            assert_eq!(patient.derive_token_id(accounts.bob, 7), id);
+            assert_ne!(patient.derive_token_id(accounts.bob, 8), id);
+            assert_ne!(patient.derive_token_id(accounts.charlie, 7), id);
+
+            // The ID is the truncated hash of the contract address, the patient and the salt.
+            let contract = ink::env::account_id::<ink::env::DefaultEnvironment>();
+            let mut hash = [0u8; 32];
+            ink::env::hash_encoded::<Blake2x256, _>(&(contract, accounts.bob, 7u64), &mut hash);
+            assert_eq!(id, TokenId::from_le_bytes([hash[0], hash[1], hash[2], hash[3]]));
+
+            // Anyone can mint it to the patient, once.
+            assert_eq!(patient.mint_derived(accounts.bob, 7), Ok(id));
+            assert_eq!(patient.owner_of(id), Some(accounts.bob));
+            assert_eq!(patient.mint_derived(accounts.bob, 7), Err(Error::IdCollision));
+            assert_eq!(patient.mint_derived(accounts.bob, 8), Ok(patient.derive_token_id(accounts.bob, 8)));
+            assert_eq!(patient.balance_of(accounts.bob), 2);
+        }
+
+        // The fixed back office key used to sign vouchers in tests.
+        fn voucher_secret_key() -> secp256k1::SecretKey {
+            secp256k1::SecretKey::from_slice(&[0x42; 32]).expect("valid secret key")
+        }
+
+        fn voucher_signer_key() -> SignerKey {
+            secp256k1::PublicKey::from_secret_key(secp256k1::SECP256K1, &voucher_secret_key()).serialize()
+        }
+
+        // Signs a voucher for `holder` the same way the back office would.
+        fn sign_voucher(id: TokenId, uri: &String, holder: AccountId) -> VoucherSignature {
+            let contract = ink::env::account_id::<ink::env::DefaultEnvironment>();
+            let mut hash = [0u8; 32];
+            ink::env::hash_encoded::<Blake2x256, _>(&(contract, id, uri, holder), &mut hash);
+
+            let message = secp256k1::Message::from_slice(&hash).expect("32 byte hash");
+            let (recovery_id, compact) = secp256k1::SECP256K1
+                .sign_ecdsa_recoverable(&message, &voucher_secret_key())
+                .serialize_compact();
+
+            let mut signature = [0u8; 65];
+            signature[..64].copy_from_slice(&compact);
+            signature[64] = recovery_id.to_i32() as u8;
+            signature
+        }
+
+    }
+
+    /// End-to-end tests
+    #[cfg(all(test, feature = "e2e-tests"))]
+    mod e2e_tests {
+        use super::*;
+        use ink_e2e::build_message;
+        use listener_mock::ListenerMockRef;
+
+        type E2EResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+        #[ink_e2e::test(additional_contracts = "listener_mock/Cargo.toml")]
+        async fn listeners_are_notified_of_mints(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
+
+            let patient = client
+                .instantiate("patient", &ink_e2e::alice(), PatientRef::new(String::from("HealthDot"), String::from("HDOT"), String::new(), String::new()), 0, None)
+                .await
+                .expect("instantiating `patient` failed")
+                .account_id;
+            let counting = client
+                .instantiate("listener_mock", &ink_e2e::alice(), ListenerMockRef::new(false), 0, None)
+                .await
+                .expect("instantiating the counting listener failed")
+                .account_id;
+            let failing = client
+                .instantiate("listener_mock", &ink_e2e::alice(), ListenerMockRef::new(true), 0, None)
+                .await
+                .expect("instantiating the failing listener failed")
+                .account_id;
+
+            // The failing listener is registered first, so it must not keep the counting one from being notified.
+            for listener in [failing.clone(), counting.clone()] {
+                let add = build_message::<PatientRef>(patient.clone()).call(|patient| patient.add_listener(listener.clone()));
+                client.call(&ink_e2e::alice(), add, 0, None).await.expect("add_listener failed");
+            }
+
+            let mint = build_message::<PatientRef>(patient.clone()).call(|patient| patient.mint(1));
+            client.call(&ink_e2e::alice(), mint, 0, None).await.expect("mint failed");
+            let mint_to = build_message::<PatientRef>(patient.clone()).call(|patient| patient.mint_to(bob.clone(), 2));
+            client.call(&ink_e2e::alice(), mint_to, 0, None).await.expect("mint_to failed");
+
+            // Both tokens were minted despite the failing listener.
+            let owner = build_message::<PatientRef>(patient.clone()).call(|patient| patient.owner_of(2));
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &owner, 0, None).await.return_value(), Some(bob.clone()));
+            let supply = build_message::<PatientRef>(patient.clone()).call(|patient| patient.total_supply());
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &supply, 0, None).await.return_value(), 2);
+
+            // The counting listener saw both mints; the failing one recorded nothing.
+            let minted = build_message::<ListenerMockRef>(counting.clone()).call(|listener| listener.minted());
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &minted, 0, None).await.return_value(), 2);
+            let last = build_message::<ListenerMockRef>(counting.clone()).call(|listener| listener.last());
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &last, 0, None).await.return_value(), Some((2, bob)));
+            let minted = build_message::<ListenerMockRef>(failing.clone()).call(|listener| listener.minted());
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &minted, 0, None).await.return_value(), 0);
+
+            Ok(())
+        }
     }
 }
\ No newline at end of file
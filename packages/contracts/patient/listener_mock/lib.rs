@@ -0,0 +1,59 @@
+// Required for environments that don't have a standard library (like a Wasm contract).
+#![cfg_attr(not(feature = "std"), no_std, no_main)]
+
+pub use self::listener_mock::{
+    ListenerMock,
+    ListenerMockRef
+};
+
+/// A mint listener used by the Patient end-to-end tests.
+/// It counts the mints it is notified of, or traps on every notification when deployed as failing.
+#[ink::contract]
+mod listener_mock {
+    use patient::{
+        TokenId,
+        TokenListener
+    };
+
+    #[ink(storage)]
+    pub struct ListenerMock {
+        /// Whether every notification traps.
+        failing: bool,
+        /// The number of mints notified so far.
+        minted: u32,
+        /// The last minted token and its recipient.
+        last: Option<(TokenId, AccountId)>
+    }
+
+    impl ListenerMock {
+        #[ink(constructor)]
+        pub fn new(failing: bool) -> Self {
+            Self {
+                failing,
+                minted: 0,
+                last: None
+            }
+        }
+
+        /// Returns the number of mints notified so far.
+        #[ink(message)]
+        pub fn minted(&self) -> u32 {
+            self.minted
+        }
+
+        /// Returns the last minted token and its recipient.
+        #[ink(message)]
+        pub fn last(&self) -> Option<(TokenId, AccountId)> {
+            self.last
+        }
+    }
+
+    impl TokenListener for ListenerMock {
+        #[ink(message)]
+        fn on_minted(&mut self, id: TokenId, to: AccountId) {
+            assert!(!self.failing, "listener failure");
+            self.minted += 1;
+            self.last = Some((id, to));
+        }
+    }
+}